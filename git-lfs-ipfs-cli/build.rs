@@ -0,0 +1,31 @@
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+}
+
+/// Records the commit being built, for `version --verbose`
+fn main() {
+    if let Some(commit) = git(&["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=GIT_LFS_IPFS_COMMIT={}", commit);
+    }
+    // HEAD only changes on checkout, so commits are picked up through the branch it points at,
+    // which lives in its own file or, once git packs it, in packed-refs. Missing files are
+    // left out, since cargo would rebuild every time for them.
+    let mut watched = vec!["HEAD".to_string(), "packed-refs".to_string()];
+    watched.extend(git(&["symbolic-ref", "-q", "HEAD"]));
+    for file in watched {
+        let path = git(&["rev-parse", "--git-path", &file]).filter(|path| Path::new(path).exists());
+        if let Some(path) = path {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    println!("cargo:rerun-if-env-changed=GIT_LFS_IPFS_COMMIT");
+}
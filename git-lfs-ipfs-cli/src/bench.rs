@@ -0,0 +1,350 @@
+use actix::prelude::*;
+use actix_web::{error::PayloadError, HttpMessage};
+use bytes::Bytes;
+use cid::Cid;
+use futures::{future, prelude::*, stream};
+use rand::Rng;
+use serde_derive::Serialize;
+
+use std::time::{Duration, Instant};
+
+use crate::error::{self, CliError};
+use crate::{messages, output};
+use git_lfs_ipfs_lib::{error::Error, ipfs, pin, spec::ipfs::Path};
+
+/// Object sizes benchmarked when `--sizes` isn't given: a small asset, one a full block wide
+/// and one spanning many blocks
+const DEFAULT_SIZES: &[u64] = &[64 * 1024, 1024 * 1024, 64 * 1024 * 1024];
+/// Objects of each size transferred when `--count` isn't given
+const DEFAULT_COUNT: usize = 5;
+/// Objects in flight at once when `--jobs` isn't given, as many as git-lfs' default
+/// `lfs.concurrenttransfers`
+const DEFAULT_JOBS: usize = 8;
+/// Synthetic objects are generated and uploaded in chunks of this size
+const CHUNK_SIZE: u64 = 256 * 1024;
+
+/// Random content of `size` bytes, generated as it is sent
+fn synthetic(size: u64) -> impl Stream<Item = Bytes, Error = PayloadError> {
+    let chunks = (size + CHUNK_SIZE - 1) / CHUNK_SIZE;
+    stream::iter_ok((0..chunks).map(move |i| {
+        let mut chunk = vec![0; CHUNK_SIZE.min(size - i * CHUNK_SIZE) as usize];
+        rand::thread_rng().fill(&mut chunk[..]);
+        Bytes::from(chunk)
+    }))
+}
+
+/// Times `operation` from when it is first polled, not from when it is created, so that
+/// objects waiting for a free job aren't counted as slow.
+fn timed<F, T>(operation: F) -> impl Future<Item = (T, Duration), Error = Error>
+where
+    F: FnOnce() -> Box<dyn Future<Item = T, Error = Error>>,
+{
+    future::lazy(move || {
+        let start = Instant::now();
+        operation().map(move |item| (item, start.elapsed()))
+    })
+}
+
+/// Reads `res` to the end, returning how many bytes it had
+fn drain(res: actix_web::client::ClientResponse) -> impl Future<Item = u64, Error = Error> {
+    res.payload()
+        .map_err(Error::IpfsApiPayloadError)
+        .fold(0, |read, chunk| Ok::<_, Error>(read + chunk.len() as u64))
+}
+
+/// The `p`th percentile of `sorted`, by nearest rank
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_secs(0);
+    }
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+/// Throughput and latencies of one operation on objects of one size
+#[derive(Debug, Serialize)]
+pub struct Measurement {
+    pub operation: &'static str,
+    pub size: u64,
+    pub objects: usize,
+    /// Bytes per second over the whole run, with `jobs` objects in flight
+    pub throughput: f64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+}
+
+impl Measurement {
+    fn new(
+        operation: &'static str,
+        size: u64,
+        wall: Duration,
+        mut latencies: Vec<Duration>,
+    ) -> Self {
+        latencies.sort();
+        let seconds = wall.as_secs() as f64 + f64::from(wall.subsec_nanos()) / 1e9;
+        let millis =
+            |latency: Duration| latency.as_secs() * 1000 + u64::from(latency.subsec_millis());
+        Self {
+            operation,
+            size,
+            objects: latencies.len(),
+            throughput: if seconds > 0.0 {
+                (size * latencies.len() as u64) as f64 / seconds
+            } else {
+                0.0
+            },
+            p50_ms: millis(percentile(&latencies, 50)),
+            p90_ms: millis(percentile(&latencies, 90)),
+            p99_ms: millis(percentile(&latencies, 99)),
+        }
+    }
+}
+
+/// What `bench` reports
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub jobs: usize,
+    pub measurements: Vec<Measurement>,
+    /// Where the numbers suggest the bottleneck is
+    pub hints: Vec<String>,
+}
+
+fn find<'a>(
+    measurements: &'a [Measurement],
+    operation: &str,
+    size: u64,
+) -> Option<&'a Measurement> {
+    measurements
+        .iter()
+        .find(|measurement| measurement.operation == operation && measurement.size == size)
+}
+
+/// Reads the measurements for signs of a bottleneck.
+fn hints(measurements: &[Measurement]) -> Vec<String> {
+    let mut hints = vec![];
+    let latency_bound = measurements
+        .iter()
+        .any(|measurement| measurement.size <= 1024 * 1024 && measurement.p50_ms >= 100);
+    if latency_bound {
+        hints.push(messages::text("bench.latency-bound", &[]));
+    }
+    let slow_downloads = measurements
+        .iter()
+        .filter(|upload| upload.operation == "upload")
+        .filter_map(|upload| Some((upload, find(measurements, "download", upload.size)?)))
+        .any(|(upload, download)| download.throughput * 2.0 < upload.throughput);
+    if slow_downloads {
+        hints.push(messages::text("bench.slow-downloads", &[]));
+    }
+    let slow_gateway = measurements
+        .iter()
+        .filter(|gateway| gateway.operation == "gateway")
+        .filter_map(|gateway| Some((gateway, find(measurements, "download", gateway.size)?)))
+        .any(|(gateway, download)| gateway.throughput * 2.0 < download.throughput);
+    if slow_gateway {
+        hints.push(messages::text("bench.slow-gateway", &[]));
+    }
+    let uneven = measurements
+        .iter()
+        .any(|measurement| measurement.p99_ms > 4 * measurement.p50_ms.max(1));
+    if uneven {
+        hints.push(messages::text("bench.uneven", &[]));
+    }
+    hints
+}
+
+/// Uploads `count` synthetic objects of `size`, reads them back through the API, and through
+/// the gateways if `gateway` is set, and unpins them again.
+fn bench_size(
+    size: u64,
+    count: usize,
+    jobs: usize,
+    gateway: bool,
+) -> impl Future<Item = Vec<Measurement>, Error = Error> {
+    let start = Instant::now();
+    stream::iter_ok::<_, Error>(0..count)
+        .map(move |_| {
+            timed(move || {
+                Box::new(ipfs::add(synthetic(size), Some(size)).map(|added| added.hash))
+            })
+        })
+        .buffer_unordered(jobs)
+        .collect()
+        .and_then(move |uploads: Vec<(Cid, Duration)>| {
+            let upload = Measurement::new(
+                "upload",
+                size,
+                start.elapsed(),
+                uploads.iter().map(|(_, latency)| *latency).collect(),
+            );
+            let cids: Vec<Cid> = uploads.into_iter().map(|(cid, _)| cid).collect();
+            read_back("download", size, cids.clone(), jobs, |cid| {
+                Box::new(ipfs::cat(Path::ipfs(cid)).and_then(drain))
+            })
+            .and_then(move |download| {
+                let measurements = vec![upload, download];
+                if !gateway {
+                    return future::Either::A(future::ok((measurements, cids)));
+                }
+                future::Either::B(
+                    read_back("gateway", size, cids.clone(), jobs, |cid| {
+                        Box::new(ipfs::gateway_cat(Path::ipfs(cid)).and_then(drain))
+                    })
+                    .map(move |gateway| {
+                        let mut measurements = measurements;
+                        measurements.push(gateway);
+                        (measurements, cids)
+                    }),
+                )
+            })
+        })
+        .and_then(|(measurements, cids)| {
+            future::join_all(cids.into_iter().map(|cid| {
+                pin::rm(cid.clone()).then(move |result| {
+                    if let Err(err) = result {
+                        warn!("Could not unpin benchmark object {}: {}", cid, err);
+                    }
+                    Ok::<_, Error>(())
+                })
+            }))
+            .map(|_| measurements)
+        })
+}
+
+/// Times reading every one of `cids` with `read`, `jobs` at a time.
+fn read_back<F>(
+    operation: &'static str,
+    size: u64,
+    cids: Vec<Cid>,
+    jobs: usize,
+    read: F,
+) -> impl Future<Item = Measurement, Error = Error>
+where
+    F: Fn(Cid) -> Box<dyn Future<Item = u64, Error = Error>> + Clone + 'static,
+{
+    future::lazy(move || {
+        let start = Instant::now();
+        stream::iter_ok::<_, Error>(cids)
+            .map(move |cid| {
+                let read = read.clone();
+                timed(move || read(cid))
+            })
+            .buffer_unordered(jobs)
+            .map(|(_, latency)| latency)
+            .collect()
+            .map(move |latencies| Measurement::new(operation, size, start.elapsed(), latencies))
+    })
+}
+
+/// Measures how fast synthetic objects of a few sizes go to and come back from the configured
+/// IPFS API, and optionally the gateways.
+pub struct Bench {
+    sizes: Vec<u64>,
+    count: usize,
+    jobs: usize,
+    gateway: bool,
+    json: bool,
+}
+
+impl Bench {
+    pub fn new(
+        sizes: Option<Vec<u64>>,
+        count: Option<usize>,
+        jobs: Option<usize>,
+        gateway: bool,
+        json: bool,
+    ) -> Self {
+        Self {
+            sizes: sizes.unwrap_or_else(|| DEFAULT_SIZES.to_vec()),
+            count: count.unwrap_or(DEFAULT_COUNT).max(1),
+            jobs: jobs.unwrap_or(DEFAULT_JOBS).max(1),
+            gateway,
+            json,
+        }
+    }
+}
+
+impl Actor for Bench {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Bench as Actor>::Context) {
+        let (count, jobs, gateway) = (self.count, self.jobs, self.gateway);
+        let bench = stream::iter_ok::<_, Error>(self.sizes.clone())
+            .and_then(move |size| {
+                info!("Benchmarking {} objects of {} bytes", count, size);
+                bench_size(size, count, jobs, gateway)
+            })
+            .concat2()
+            .map_err(CliError::IpfsApiError);
+        ctx.wait(actix::fut::wrap_future(bench).then(
+            |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                let measurements = result.unwrap_or_else(|err| error::exit(err));
+                let report = Report {
+                    jobs: actor.jobs,
+                    hints: hints(&measurements),
+                    measurements,
+                };
+                if actor.json {
+                    output::print_json("bench", &report);
+                    return actix::fut::ok(());
+                }
+                println!(
+                    "{:<10} {:>12} {:>8} {:>12} {:>8} {:>8} {:>8}",
+                    messages::text("bench.operation", &[]),
+                    messages::text("bench.size", &[]),
+                    messages::text("bench.objects", &[]),
+                    "MiB/s",
+                    "p50 ms",
+                    "p90 ms",
+                    "p99 ms"
+                );
+                for measurement in &report.measurements {
+                    println!(
+                        "{:<10} {:>12} {:>8} {:>12.1} {:>8} {:>8} {:>8}",
+                        measurement.operation,
+                        measurement.size,
+                        measurement.objects,
+                        measurement.throughput / (1024.0 * 1024.0),
+                        measurement.p50_ms,
+                        measurement.p90_ms,
+                        measurement.p99_ms
+                    );
+                }
+                for hint in &report.hints {
+                    println!("{}", messages::text("exit.hint", &[hint]));
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let latencies: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(Duration::from_millis(5), percentile(&latencies, 50));
+        assert_eq!(Duration::from_millis(9), percentile(&latencies, 90));
+        assert_eq!(Duration::from_millis(10), percentile(&latencies, 99));
+        assert_eq!(Duration::from_secs(0), percentile(&[], 50));
+    }
+
+    #[test]
+    fn slow_small_objects_suggest_more_jobs() {
+        let measurements = vec![Measurement {
+            operation: "upload",
+            size: 64 * 1024,
+            objects: 5,
+            throughput: 1.0,
+            p50_ms: 150,
+            p90_ms: 160,
+            p99_ms: 170,
+        }];
+        assert_eq!(1, hints(&measurements).len());
+        assert!(hints(&measurements)[0].contains("lfs.concurrenttransfers"));
+    }
+}
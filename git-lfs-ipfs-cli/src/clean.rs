@@ -1,67 +1,245 @@
-use std::io::{self, BufRead, Write};
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix::prelude::*;
 use actix_web::HttpMessage;
-use futures::{future, prelude::*, sync::mpsc};
+use futures::{future, prelude::*, stream, sync::mpsc};
 
-use crate::error::CliError;
-use git_lfs_ipfs_lib::{ipfs, spec};
+use crate::error::{self, CliError};
+use crate::hooks::{self, HookEvent};
+use crate::policy;
+use git_lfs_ipfs_lib::{
+    config::{self, Config},
+    ipfs, spec, storage,
+};
 
 pub struct Clean {
+    /// The file being cleaned, relative to the root of the working tree
+    filename: PathBuf,
     raw_block_data: Option<Result<bytes::Bytes, CliError>>,
 }
 
-impl Default for Clean {
-    fn default() -> Self {
+impl Clean {
+    pub fn new(filename: PathBuf) -> Self {
         Self {
+            filename,
             raw_block_data: None,
         }
     }
 }
 
+/// Smallest chunk read from stdin, so that small files reach the daemon without waiting
+const MIN_CHUNK_SIZE: usize = 8 * 1024;
+/// Largest chunk read from stdin, big enough that multi-GB files aren't slowed down by
+/// per-chunk overhead
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How long filling one chunk should take: long enough that per-chunk overhead doesn't add
+/// up, short enough that a slow input still reaches the daemon steadily
+const CHUNK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Size of the next chunk to read: as much as stdin delivered in [`CHUNK_INTERVAL`], going by
+/// the throughput measured while filling the previous chunks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChunkSize {
+    size: usize,
+    /// Bytes per second, averaged over the last few chunks
+    throughput: Option<f64>,
+}
+
+impl Default for ChunkSize {
+    fn default() -> Self {
+        ChunkSize {
+            size: MIN_CHUNK_SIZE,
+            throughput: None,
+        }
+    }
+}
+
+impl ChunkSize {
+    fn get(self) -> usize {
+        self.size
+    }
+
+    /// Records that reading `read` bytes took `elapsed`
+    fn adapt(&mut self, read: usize, elapsed: Duration) {
+        if read == 0 {
+            return;
+        }
+        let interval = seconds(CHUNK_INTERVAL);
+        // Anything faster than filling the largest chunk in an interval sizes chunks the same,
+        // and reads too quick to time are at least that fast
+        let fastest = MAX_CHUNK_SIZE as f64 / interval;
+        let measured = match seconds(elapsed) {
+            secs if secs > 0.0 => (read as f64 / secs).min(fastest),
+            _ => fastest,
+        };
+        let throughput = match self.throughput {
+            Some(average) => (average + measured) / 2.0,
+            None => measured,
+        };
+        self.throughput = Some(throughput);
+        self.size = ((throughput * interval).round() as usize)
+            .max(MIN_CHUNK_SIZE)
+            .min(MAX_CHUNK_SIZE);
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// Reads into `buf` until it is full or `input` ends, returning how much was read. Pipes
+/// return at most what they buffer per read, so a large chunk takes several.
+fn fill<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Checks `chunks` against the upload policy as they pass: the first one for a denied MIME
+/// type, and the running total against the size limit. The first violation ends the stream;
+/// since the stream can only fail with an I/O error, the violation itself is left in
+/// `violation`.
+fn checked<S>(
+    config: Arc<Config>,
+    chunks: S,
+    violation: Rc<RefCell<Option<CliError>>>,
+) -> impl Stream<Item = bytes::Bytes, Error = io::Error>
+where
+    S: Stream<Item = bytes::Bytes, Error = io::Error>,
+{
+    let mut size = 0;
+    chunks.and_then(move |chunk| {
+        let head_checked = if size == 0 {
+            policy::check_mime_type(&config, &chunk)
+        } else {
+            Ok(())
+        };
+        size += chunk.len() as u64;
+        head_checked
+            .and_then(|_| policy::check_size(&config, size))
+            .map(|_| chunk)
+            .map_err(|err| {
+                let io_err = io::Error::new(io::ErrorKind::Other, err.to_string());
+                *violation.borrow_mut() = Some(err);
+                io_err
+            })
+    })
+}
+
+/// Adds the file to IPFS as it is read, checking it against the upload policy on the way.
+/// Only clamd has to see all of the file before it is added, so only then is it read whole.
+fn add<S>(
+    config: Arc<Config>,
+    chunks: S,
+) -> impl Future<Item = spec::ipfs::AddResponse, Error = CliError>
+where
+    S: Stream<Item = bytes::Bytes, Error = io::Error> + 'static,
+{
+    let violation = Rc::new(RefCell::new(None));
+    let chunks = checked(config.clone(), chunks, violation.clone());
+    if config.clamd.is_none() {
+        return future::Either::A(ipfs::add(chunks, None).map_err(move |err| {
+            violation
+                .borrow_mut()
+                .take()
+                .unwrap_or(CliError::IpfsApiError(err))
+        }));
+    }
+    future::Either::B(
+        chunks
+            .collect()
+            .map_err(move |err| violation.borrow_mut().take().unwrap_or(CliError::Io(err)))
+            .and_then(move |chunks| policy::check_content(&config, &chunks).map(|_| chunks))
+            .and_then(|chunks| {
+                ipfs::add(stream::iter_ok::<_, io::Error>(chunks), None)
+                    .map_err(CliError::IpfsApiError)
+            }),
+    )
+}
+
 impl Actor for Clean {
     type Context = Context<Self>;
     fn started(&mut self, ctx: &mut <Clean as Actor>::Context) {
+        let config = config::get();
+        let allowed = policy::check_path(&config, &self.filename).and_then(|_| {
+            hooks::pre_upload(&HookEvent {
+                path: Some(&self.filename),
+                ..HookEvent::default()
+            })
+        });
+        if let Err(err) = allowed {
+            self.raw_block_data = Some(Err(err));
+            System::current().stop();
+            return;
+        }
         let (tx, rx) = mpsc::channel(4);
         let stdin = io::stdin();
+        // Chunks are read into one buffer, and only what was read is copied out of it
+        let buf = vec![0; MAX_CHUNK_SIZE];
         actix::spawn(
-            future::loop_fn(tx, move |tx| {
-                let mut lock = stdin.lock();
-                let buf = lock.fill_buf().map(bytes::Bytes::from);
-                let mut should_break = false;
-                if let Ok(buf) = &buf {
-                    lock.consume(buf.len());
-                    if buf.len() == 0 {
-                        should_break = true
-                    }
-                } else {
-                    should_break = true;
-                }
-                tx.send(buf).map(move |tx| {
-                    if should_break {
-                        future::Loop::Break(tx)
-                    } else {
-                        future::Loop::Continue(tx)
-                    }
-                })
-            })
+            future::loop_fn(
+                (tx, ChunkSize::default(), buf),
+                move |(tx, mut chunk_size, mut buf)| {
+                    let started = Instant::now();
+                    let chunk = fill(&mut stdin.lock(), &mut buf[..chunk_size.get()]).map(|read| {
+                        chunk_size.adapt(read, started.elapsed());
+                        bytes::Bytes::from(&buf[..read])
+                    });
+                    let should_break = match &chunk {
+                        Ok(chunk) => chunk.is_empty(),
+                        Err(_) => true,
+                    };
+                    tx.send(chunk).map(move |tx| {
+                        if should_break {
+                            future::Loop::Break(tx)
+                        } else {
+                            future::Loop::Continue((tx, chunk_size, buf))
+                        }
+                    })
+                },
+            )
             .then(|_| Ok(())),
         );
+        // Git runs the filter from the top of the working tree, where the file usually is too;
+        // if it isn't, the upload isn't checked
+        let size = std::fs::metadata(&self.filename)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
         ctx.wait(
             actix::fut::wrap_future(
-                ipfs::add(
-                    rx.then(|x| x.expect("mpsc unwrap panicked, but never should"))
-                        .filter(|x| x.len() != 0),
-                    None,
-                )
-                .and_then(|add_response| ipfs::block_get(add_response.hash))
-                .and_then(|res| {
-                    res.body()
-                        .map_err(git_lfs_ipfs_lib::error::Error::IpfsApiPayloadError)
-                }),
+                storage::ensure_space(size)
+                    .map_err(CliError::IpfsApiError)
+                    .and_then(move |_| {
+                        add(
+                            config,
+                            rx.then(|x| x.expect("mpsc unwrap panicked, but never should"))
+                                .filter(|x| x.len() != 0),
+                        )
+                    })
+                    .and_then(|add_response| {
+                        ipfs::block_get(add_response.hash)
+                            .and_then(|res| {
+                                res.body()
+                                    .map_err(git_lfs_ipfs_lib::error::Error::IpfsApiPayloadError)
+                            })
+                            .map_err(CliError::IpfsApiError)
+                    }),
             )
             .then(|result, actor: &mut Self, _ctx| {
-                actor.raw_block_data = Some(result.map_err(CliError::IpfsApiError));
+                actor.raw_block_data = Some(result);
                 System::current().stop();
                 actix::fut::ok(())
             }),
@@ -69,12 +247,104 @@ impl Actor for Clean {
     }
 
     fn stopped(&mut self, _ctx: &mut <Clean as Actor>::Context) {
-        match &self.raw_block_data {
+        match self.raw_block_data.take() {
             Some(Ok(raw_block_data)) => io::stdout()
-                .write_all(raw_block_data)
-                .expect("unable to write to stdout"),
-            Some(Err(err)) => panic!("{:?}", err),
-            _ => panic!("clean stopped before completion"),
+                .write_all(&raw_block_data)
+                .unwrap_or_else(|err| error::exit(CliError::Io(err))),
+            Some(Err(err)) => error::exit(err),
+            None => panic!("clean stopped before completion"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn chunk_size_follows_throughput() {
+        let mut chunk_size = ChunkSize::default();
+        for _ in 0..32 {
+            let size = chunk_size.get();
+            chunk_size.adapt(size, Duration::from_millis(1));
+        }
+        assert_eq!(MAX_CHUNK_SIZE, chunk_size.get());
+        chunk_size.adapt(MAX_CHUNK_SIZE, Duration::from_secs(0));
+        assert_eq!(MAX_CHUNK_SIZE, chunk_size.get());
+        // 1 MB/s fills about 10 kB per interval
+        for _ in 0..32 {
+            chunk_size.adapt(1000, Duration::from_millis(1));
         }
+        assert_eq!(10_000, chunk_size.get());
+        for _ in 0..32 {
+            chunk_size.adapt(1, Duration::from_secs(1));
+        }
+        assert_eq!(MIN_CHUNK_SIZE, chunk_size.get());
+        chunk_size.adapt(0, Duration::from_secs(1));
+        assert_eq!(MIN_CHUNK_SIZE, chunk_size.get());
+    }
+
+    /// Reads at most 3 bytes at a time, like a pipe that holds little
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = buf.len().min(3).min(self.0.len());
+            buf[..read].copy_from_slice(&self.0[..read]);
+            self.0 = &self.0[read..];
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn chunks_are_filled_across_short_reads() {
+        let mut input = Trickle(b"abcdefghij");
+        let mut buf = [0; 8];
+        assert_eq!(8, fill(&mut input, &mut buf).unwrap());
+        assert_eq!(b"abcdefgh", &buf);
+        assert_eq!(2, fill(&mut input, &mut buf).unwrap());
+        assert_eq!(b"ij", &buf[..2]);
+        assert_eq!(0, fill(&mut input, &mut buf).unwrap());
+    }
+
+    #[test]
+    fn checks_end_the_stream_at_the_first_violation() {
+        let config = Arc::new(Config {
+            denied_mime_types: vec!["application/zip".to_string()],
+            max_upload_size: Some(5),
+            ..Config::default()
+        });
+        let chunks = |chunks: Vec<&'static str>| {
+            stream::iter_ok::<_, io::Error>(chunks.into_iter().map(Bytes::from))
+        };
+
+        let violation = Rc::new(RefCell::new(None));
+        let passed = checked(config.clone(), chunks(vec!["abc", "de"]), violation.clone())
+            .collect()
+            .wait();
+        assert_eq!(vec![Bytes::from("abc"), Bytes::from("de")], passed.unwrap());
+        assert!(violation.borrow().is_none());
+
+        let violation = Rc::new(RefCell::new(None));
+        let mut passed = checked(
+            config.clone(),
+            chunks(vec!["abc", "def", "g"]),
+            violation.clone(),
+        )
+        .wait();
+        assert_eq!(Bytes::from("abc"), passed.next().unwrap().unwrap());
+        assert!(passed.next().unwrap().is_err());
+        match violation.borrow_mut().take() {
+            Some(CliError::PolicyViolation(_)) => {}
+            other => panic!("expected a policy violation, got {:?}", other),
+        }
+
+        let violation = Rc::new(RefCell::new(None));
+        let passed = checked(config, chunks(vec!["PK\x03\x04"]), violation.clone())
+            .collect()
+            .wait();
+        assert!(passed.is_err());
+        assert!(violation.borrow().is_some());
     }
 }
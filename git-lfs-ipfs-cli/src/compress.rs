@@ -0,0 +1,75 @@
+//! The `gzip` LFS extension, which compresses files before the `ipfs` extension adds them.
+//! git-lfs records it on pointers as `ext-0-gzip`, ahead of `ext-1-ipfs`, and runs its smudge
+//! filter after that of `ipfs`, so every machine that checks the pointer out knows to reverse
+//! it. The extension name pins all of the transform's parameters: gzip at a fixed level with an
+//! empty header, so the same file always compresses to the same bytes and the same OID.
+use std::io::{self, Read, Write};
+
+use actix::prelude::*;
+use flate2::{read::GzDecoder, Compression, GzBuilder};
+
+use crate::error::{self, CliError};
+
+/// Name of the extension on pointers and in the git config
+pub const EXTENSION: &str = "gzip";
+/// Never configurable, since objects compressed at another level would get another OID
+const LEVEL: u32 = 6;
+
+/// Compresses `input` into `output`. The header has no file name and a zero modification
+/// time, so that nothing but the content ends up in the compressed bytes.
+fn compress<R: Read, W: Write>(mut input: R, output: W) -> io::Result<W> {
+    let mut encoder = GzBuilder::new().write(output, Compression::new(LEVEL));
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()
+}
+
+fn decompress<R: Read, W: Write>(input: R, mut output: W) -> io::Result<W> {
+    io::copy(&mut GzDecoder::new(input), &mut output)?;
+    Ok(output)
+}
+
+/// The clean or smudge filter of the extension, streaming stdin to stdout
+pub struct Gzip {
+    clean: bool,
+}
+
+impl Gzip {
+    pub fn clean() -> Self {
+        Self { clean: true }
+    }
+
+    pub fn smudge() -> Self {
+        Self { clean: false }
+    }
+}
+
+impl Actor for Gzip {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <Gzip as Actor>::Context) {
+        System::current().stop();
+        let (stdin, stdout) = (io::stdin(), io::stdout());
+        let result = if self.clean {
+            compress(stdin.lock(), stdout.lock())
+        } else {
+            decompress(stdin.lock(), stdout.lock())
+        };
+        if let Err(err) = result.and_then(|mut stdout| stdout.flush()) {
+            error::exit(CliError::Io(err));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compression_round_trips_to_the_same_bytes_everywhere() {
+        let content = b"git-lfs-ipfs ".repeat(1000);
+        let compressed = compress(&content[..], vec![]).unwrap();
+        assert!(compressed.len() < content.len());
+        assert_eq!(compressed, compress(&content[..], vec![]).unwrap());
+        assert_eq!(content, decompress(&compressed[..], vec![]).unwrap());
+        assert!(decompress(&content[..], vec![]).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use url::Url;
+
+use crate::error::CliError;
+use git_lfs_ipfs_lib::config::Credentials;
+
+/// Asks git's credential helpers for the credentials of `url`, without prompting.
+/// https://git-scm.com/docs/git-credential
+pub fn fill(url: &Url) -> Result<Option<Credentials>, CliError> {
+    let mut description = format!("protocol={}\n", url.scheme());
+    if let Some(host) = url.host_str() {
+        match url.port() {
+            Some(port) => description.push_str(&format!("host={}:{}\n", host, port)),
+            None => description.push_str(&format!("host={}\n", host)),
+        }
+    }
+    description.push('\n');
+    let mut git = Command::new("git")
+        .args(&["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(CliError::Io)?;
+    git.stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(description.as_bytes())
+        .map_err(CliError::Io)?;
+    let output = git.wait_with_output().map_err(CliError::Io)?;
+    // git exits unsuccessfully when no helper knows the URL and it can't prompt
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse(output: &str) -> Option<Credentials> {
+    let mut username = None;
+    let mut password = None;
+    for line in output.lines() {
+        let mut parts = line.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("username"), Some(value)) => username = Some(value.to_string()),
+            (Some("password"), Some(value)) => password = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    password.map(|password| Credentials {
+        username: username.unwrap_or_default(),
+        password,
+    })
+}
+
+/// Asks git for the credentials of HTTPS endpoints, see
+/// [`set_credential_helper`](git_lfs_ipfs_lib::config::set_credential_helper)
+pub fn helper(url: &Url) -> Option<Credentials> {
+    if url.scheme() != "https" {
+        return None;
+    }
+    let origin = url.origin().ascii_serialization();
+    match fill(url) {
+        Ok(Some(credentials)) => {
+            debug!("Using credentials from git for {}", origin);
+            Some(credentials)
+        }
+        Ok(None) => {
+            debug!("git has no credentials for {}", origin);
+            None
+        }
+        Err(err) => {
+            warn!("Could not ask git for credentials for {}: {}", origin, err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn credentials_parse_from_git_output() {
+        let credentials = parse(
+            "protocol=https\nhost=ipfs.example.com\nusername=ci\npassword=s3cr=t\n",
+        )
+        .unwrap();
+        assert_eq!("ci", credentials.username);
+        assert_eq!("s3cr=t", credentials.password);
+        assert!(parse("protocol=https\nhost=ipfs.example.com\n").is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use actix::prelude::*;
+use futures::prelude::*;
+
+use crate::{error, history, key, prune, tier, verify};
+use git_lfs_ipfs_lib::config;
+
+/// How often maintenance runs, unless overridden. Matches the IPFS daemon's own republish interval.
+const DEFAULT_INTERVAL_MINUTES: u64 = 4 * 60;
+
+/// Long-running mode that periodically keeps the repository's IPFS state healthy:
+/// it republishes the latest root so the IPNS record does not expire, keeps rotated-out keys
+/// pointing at their replacements during their grace period, re-pins local
+/// objects the node lost, prunes pending deletes whose grace period is over, and moves objects
+/// that weren't used for a while to the cold tier if tiering is enabled.
+pub struct Daemon {
+    interval: Duration,
+    grace: chrono::Duration,
+}
+
+impl Daemon {
+    pub fn new(interval_minutes: Option<u64>, grace_days: Option<i64>) -> Self {
+        Self {
+            interval: Duration::from_secs(
+                60 * interval_minutes.unwrap_or(DEFAULT_INTERVAL_MINUTES),
+            ),
+            grace: prune::grace_period(grace_days),
+        }
+    }
+
+    fn maintain(&mut self, ctx: &mut <Self as Actor>::Context) {
+        debug!("Running maintenance");
+        ctx.spawn(actix::fut::wrap_future(
+            history::republish_latest().then(|result| {
+                match result {
+                    Ok(Some(entry)) => info!("Republished {} under {}", entry.root, entry.key),
+                    Ok(None) => debug!("Nothing has been published yet"),
+                    Err(err) => error!("Republishing failed: {}", error::describe(&err)),
+                }
+                Ok(())
+            }),
+        ));
+        ctx.spawn(actix::fut::wrap_future(key::republish_redirects().then(|result| {
+            match result {
+                Ok(redirects) => redirects
+                    .iter()
+                    .for_each(|redirect| info!("Republished {} as {}", redirect.from, redirect.to)),
+                Err(err) => error!("Republishing redirects failed: {}", error::describe(&err)),
+            }
+            Ok(())
+        })));
+        ctx.spawn(actix::fut::wrap_future(verify::reconcile(true).then(|result| {
+            match result {
+                Ok(report) => {
+                    report
+                        .repinned
+                        .iter()
+                        .for_each(|oid| info!("Re-pinned {}", oid));
+                    report
+                        .failed
+                        .iter()
+                        .for_each(|(oid, err)| error!("Could not re-pin {}: {}", oid, err));
+                }
+                Err(err) => error!("Pin verification failed: {}", error::describe(&err)),
+            }
+            Ok(())
+        })));
+        ctx.spawn(actix::fut::wrap_future(
+            prune::prune_expired(self.grace).then(|result| {
+                match result {
+                    Ok(unpinned) => unpinned
+                        .iter()
+                        .for_each(|oid| info!("Unpinned {}", oid)),
+                    Err(err) => error!("Pruning failed: {}", error::describe(&err)),
+                }
+                Ok(())
+            }),
+        ));
+        if let Some(days) = config::get().cold_after_days {
+            ctx.spawn(actix::fut::wrap_future(tier::demote(days).then(|result| {
+                match result {
+                    Ok(demoted) => demoted
+                        .iter()
+                        .for_each(|oid| info!("Moved {} to the cold tier", oid)),
+                    Err(err) => error!("Tiering failed: {}", error::describe(&err)),
+                }
+                Ok(())
+            })));
+        }
+    }
+}
+
+impl Actor for Daemon {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Daemon as Actor>::Context) {
+        self.maintain(ctx);
+        ctx.run_interval(self.interval, Self::maintain);
+    }
+}
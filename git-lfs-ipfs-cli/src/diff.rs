@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use actix::prelude::*;
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*, stream};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{messages, output, restore};
+use git_lfs_ipfs_lib::{
+    pin::{self, PinType},
+    signature,
+    spec::{
+        ipfs::Path,
+        manifest::{Diff, Entry},
+    },
+    LfsIpfs,
+};
+
+fn parse_root(root: &str) -> Cid {
+    root.to_cid()
+        .unwrap_or_else(|_| error::exit(CliError::InvalidCid(root.to_string())))
+}
+
+/// How the manifest of `to` differs from that of `from`, both verified if a trusted key is
+/// configured
+pub fn diff_roots(from: Cid, to: Cid) -> impl Future<Item = Diff, Error = CliError> {
+    signature::trusted_manifest(from)
+        .join(signature::trusted_manifest(to))
+        .map(|(from, to)| from.diff(&to))
+        .map_err(CliError::IpfsApiError)
+}
+
+fn print_entries(sign: char, entries: &BTreeMap<String, Entry>) {
+    for (oid, entry) in entries {
+        println!("{} {} {} {}", sign, oid, entry.size, entry.cid.cid);
+    }
+}
+
+fn print_diff(diff: &Diff) {
+    print_entries('+', &diff.added);
+    print_entries('-', &diff.removed);
+    print_entries('~', &diff.relinked);
+}
+
+/// Lists the objects added, removed and relinked between two roots, e.g. to audit what changed
+/// from one release to the next.
+pub struct DiffRoots {
+    from: String,
+    to: String,
+    json: bool,
+}
+
+impl DiffRoots {
+    pub fn new(from: String, to: String, json: bool) -> Self {
+        Self { from, to, json }
+    }
+}
+
+impl Actor for DiffRoots {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <DiffRoots as Actor>::Context) {
+        let (from, to) = (parse_root(&self.from), parse_root(&self.to));
+        ctx.wait(actix::fut::wrap_future(diff_roots(from, to)).then(
+            |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(diff) if actor.json => output::print_json("diff", &diff),
+                    Ok(diff) => print_diff(&diff),
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
+
+/// What `sync` did
+#[derive(Debug, Serialize)]
+pub struct SyncReport {
+    #[serde(flatten)]
+    pub diff: Diff,
+    /// Whether the pin on the old root was moved to the new one, rather than the new one
+    /// pinned from scratch because the old one wasn't pinned
+    pub pin_updated: bool,
+    /// Objects of the delta written to the local LFS store
+    pub fetched: usize,
+}
+
+/// Catches this node up from one root to a newer one, transferring only what changed: the
+/// recursive pin is moved from the old root to the new one, so the node only fetches the
+/// blocks the old root doesn't have, and with `fetch`, the added and relinked objects are
+/// downloaded into the local LFS store, checked against their OIDs.
+pub fn sync(
+    from: Cid,
+    to: Cid,
+    fetch: bool,
+    unpin: bool,
+) -> impl Future<Item = SyncReport, Error = CliError> {
+    diff_roots(from.clone(), to.clone()).and_then(move |diff| {
+        let pinned = pin::ls_cid(&from).map(|pin| {
+            pin.map_or(false, |pin| pin.pin_type == PinType::Recursive.as_str())
+        });
+        let pin = pinned
+            .and_then({
+                let to = to.clone();
+                move |pinned| {
+                    if pinned {
+                        future::Either::A(pin::update(from, to, unpin).map(|_| true))
+                    } else {
+                        warn!("{} isn't pinned recursively, pinning {} from scratch", from, to);
+                        future::Either::B(pin::add(to).map(|_| false))
+                    }
+                }
+            })
+            .map_err(CliError::IpfsApiError);
+        let delta: Vec<(String, u64)> = if fetch {
+            diff.delta()
+                .map(|(oid, entry)| (oid.clone(), entry.size))
+                .collect()
+        } else {
+            vec![]
+        };
+        let lfs = LfsIpfs::with_root(Path::ipfs(to));
+        pin.and_then(move |pin_updated| {
+            stream::iter_ok::<_, CliError>(delta)
+                .map(move |(oid, size)| restore::restore_object(lfs.clone(), oid, size))
+                .buffer_unordered(8)
+                .filter(|(_, restored)| *restored != restore::Restored::Present)
+                .fold(0, |fetched, _| future::ok::<_, CliError>(fetched + 1))
+                .map(move |fetched| SyncReport {
+                    diff,
+                    pin_updated,
+                    fetched,
+                })
+        })
+    })
+}
+
+/// Catches this node up from one root to a newer one, see [`sync`]
+pub struct SyncRoots {
+    from: String,
+    to: String,
+    fetch: bool,
+    unpin: bool,
+    json: bool,
+}
+
+impl SyncRoots {
+    pub fn new(from: String, to: String, fetch: bool, unpin: bool, json: bool) -> Self {
+        Self {
+            from,
+            to,
+            fetch,
+            unpin,
+            json,
+        }
+    }
+}
+
+impl Actor for SyncRoots {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <SyncRoots as Actor>::Context) {
+        let (from, to) = (parse_root(&self.from), parse_root(&self.to));
+        ctx.wait(
+            actix::fut::wrap_future(sync(from, to, self.fetch, self.unpin)).then(
+                |result, actor: &mut Self, _ctx| {
+                    System::current().stop();
+                    match result {
+                        Ok(report) if actor.json => output::print_json("sync", &report),
+                        Ok(report) => {
+                            print_diff(&report.diff);
+                            println!(
+                                "{}",
+                                messages::text(
+                                    if report.pin_updated {
+                                        "sync.pin-moved"
+                                    } else {
+                                        "sync.pinned"
+                                    },
+                                    &[
+                                        &actor.to,
+                                        &report.diff.added.len(),
+                                        &report.diff.relinked.len(),
+                                        &report.diff.removed.len()
+                                    ]
+                                )
+                            );
+                            if actor.fetch {
+                                println!("{}", messages::text("sync.fetched", &[&report.fetched]));
+                            }
+                        }
+                        Err(err) => error::exit(err),
+                    }
+                    actix::fut::ok(())
+                },
+            ),
+        );
+    }
+}
@@ -1,15 +1,170 @@
+use std::fmt;
+
 use failure::Fail;
 
+use crate::messages;
 use git_lfs_ipfs_lib::{error, spec::transfer::custom};
 
+/// Errors are displayed through the message catalog, see [`messages`]
 #[derive(Fail, Debug)]
 pub enum CliError {
-    #[fail(display = "{}", _0)]
     SerdeJsonError(#[cause] serde_json::error::Error),
-    #[fail(display = "{}", _0)]
     Io(#[cause] std::io::Error),
-    #[fail(display = "Input was an unexpected event {:?}", _0)]
     UnexpectedEvent(custom::Event),
-    #[fail(display = "Error with a request to the IPFS API {:?}", _0)]
     IpfsApiError(error::Error),
+    InvalidConfig(error::Error),
+    GitCommandFailed(std::process::ExitStatus),
+    HookFailed(&'static str, std::process::ExitStatus),
+    PolicyViolation(String),
+    LfsServerError(String),
+    AddFromUrlFailed(String, String),
+    NoPinTargets,
+    StateLocked(std::path::PathBuf),
+    NoWorkspace,
+    WorkspaceFailed(usize, usize),
+    InvalidCid(String),
+    InvalidOid(String),
+    InvalidUrl(String),
+    NothingPublished,
+    SameKey(String),
+    NoSuchTag(String),
+    NoTrustedKey,
+    NoGateway,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            CliError::SerdeJsonError(err) => messages::text("error.json", &[err]),
+            CliError::Io(err) => messages::text("error.io", &[err]),
+            CliError::UnexpectedEvent(event) => messages::text(
+                "error.unexpected-event",
+                &[&serde_json::to_string(event).unwrap_or_else(|_| format!("{:?}", event))],
+            ),
+            CliError::IpfsApiError(err) => messages::text("error.ipfs-api", &[err]),
+            CliError::InvalidConfig(err) => messages::text("error.invalid-config", &[err]),
+            CliError::GitCommandFailed(status) => {
+                messages::text("error.git-command-failed", &[status])
+            }
+            CliError::HookFailed(hook, status) => {
+                messages::text("error.hook-failed", &[hook, status])
+            }
+            CliError::PolicyViolation(reason) => {
+                messages::text("error.policy-violation", &[reason])
+            }
+            CliError::LfsServerError(reason) => messages::text("error.lfs-server", &[reason]),
+            CliError::AddFromUrlFailed(url, reason) => {
+                messages::text("error.add-from-url-failed", &[url, reason])
+            }
+            CliError::NoPinTargets => messages::text("error.no-pin-targets", &[]),
+            CliError::StateLocked(path) => {
+                messages::text("error.state-locked", &[&path.display()])
+            }
+            CliError::NoWorkspace => messages::text("error.no-workspace", &[]),
+            CliError::WorkspaceFailed(failed, total) => {
+                messages::text("error.workspace-failed", &[failed, total])
+            }
+            CliError::InvalidCid(cid) => messages::text("error.invalid-cid", &[cid]),
+            CliError::InvalidOid(oid) => messages::text("error.invalid-oid", &[oid]),
+            CliError::InvalidUrl(url) => messages::text("error.invalid-url", &[url]),
+            CliError::NothingPublished => messages::text("error.nothing-published", &[]),
+            CliError::SameKey(key) => messages::text("error.same-key", &[key]),
+            CliError::NoSuchTag(tag) => messages::text("error.no-such-tag", &[tag]),
+            CliError::NoTrustedKey => messages::text("error.no-trusted-key", &[]),
+            CliError::NoGateway => messages::text("error.no-gateway", &[]),
+        };
+        f.write_str(&message)
+    }
+}
+
+impl CliError {
+    /// What the user could try to resolve the error. Errors without a more specific hint get
+    /// one on reporting them.
+    pub fn hint(&self) -> String {
+        let id = match self {
+            CliError::IpfsApiError(err) => {
+                return match (err.hint_id(), err.hint()) {
+                    (Some(id), Some(hint)) => {
+                        messages::text_or(&format!("hint.ipfs.{}", id), hint)
+                    }
+                    _ => messages::text("hint.unknown", &[]),
+                };
+            }
+            CliError::SerdeJsonError(_) => "hint.json",
+            CliError::UnexpectedEvent(_) => "hint.unexpected-event",
+            CliError::InvalidConfig(_) => "hint.invalid-config",
+            CliError::GitCommandFailed(_) => "hint.git-command-failed",
+            CliError::HookFailed(_, _) => "hint.hook-failed",
+            CliError::PolicyViolation(_) => "hint.policy-violation",
+            CliError::LfsServerError(_) => "hint.lfs-server",
+            CliError::AddFromUrlFailed(_, _) => "hint.add-from-url-failed",
+            CliError::NoPinTargets => "hint.no-pin-targets",
+            CliError::StateLocked(_) => "hint.state-locked",
+            CliError::NoWorkspace => "hint.no-workspace",
+            CliError::WorkspaceFailed(_, _) => "hint.workspace-failed",
+            CliError::InvalidCid(_) => "hint.invalid-cid",
+            CliError::InvalidOid(_) => "hint.invalid-oid",
+            CliError::InvalidUrl(_) => "hint.invalid-url",
+            CliError::NothingPublished => "hint.nothing-published",
+            CliError::SameKey(_) => "hint.same-key",
+            CliError::NoSuchTag(_) => "hint.no-such-tag",
+            CliError::NoTrustedKey => "hint.no-trusted-key",
+            CliError::NoGateway => "hint.no-gateway",
+            CliError::Io(_) => "hint.unknown",
+        };
+        messages::text(id, &[])
+    }
+}
+
+/// The error and what to do about it on one line, for logs and the errors git-lfs shows
+pub fn describe(err: &CliError) -> String {
+    messages::text("describe", &[err, &err.hint()])
+}
+
+/// Reports a fatal error, with a hint on how to resolve it, and exits.
+pub fn exit(err: CliError) -> ! {
+    eprintln!("{}", messages::text("exit.error", &[&err]));
+    eprintln!("{}", messages::text("exit.hint", &[&err.hint()]));
+    debug!("{:?}", err);
+    crate::timings::write();
+    std::process::exit(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_error_has_a_catalog_message_and_hint() {
+        let errors = vec![
+            CliError::Io(std::io::Error::new(std::io::ErrorKind::Other, "disk on fire")),
+            CliError::IpfsApiError(error::Error::HashError),
+            CliError::IpfsApiError(error::Error::SerializeJsonError),
+            CliError::InvalidConfig(error::Error::InvalidConfig {
+                var: "GIT_LFS_IPFS_TRUSTLESS",
+                value: "yes".to_string(),
+                expected: "true, 1, false or 0",
+            }),
+            CliError::PolicyViolation("too large".to_string()),
+            CliError::LfsServerError("401".to_string()),
+            CliError::AddFromUrlFailed("https://example.com".to_string(), "404".to_string()),
+            CliError::NoPinTargets,
+            CliError::StateLocked("staged.lock".into()),
+            CliError::NoWorkspace,
+            CliError::WorkspaceFailed(1, 2),
+            CliError::InvalidCid("QmNotACid".to_string()),
+            CliError::InvalidOid("ab/../..".to_string()),
+            CliError::InvalidUrl("not a url".to_string()),
+            CliError::NothingPublished,
+            CliError::SameKey("self".to_string()),
+            CliError::NoSuchTag("v1".to_string()),
+            CliError::NoTrustedKey,
+            CliError::NoGateway,
+        ];
+        for err in errors {
+            let (message, hint) = (err.to_string(), err.hint());
+            assert!(!message.starts_with("error."), "{} has no message", message);
+            assert!(!hint.starts_with("hint."), "{:?} has no hint", err);
+        }
+    }
 }
@@ -0,0 +1,227 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use actix::prelude::*;
+use actix_web::HttpMessage;
+use cid::ToCid;
+use futures::{future, prelude::*, stream};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{history, messages, output, repo};
+use git_lfs_ipfs_lib::{error::Error, ipfs, signature};
+
+/// Objects referenced by refs older than this are skipped unless `--all` is given.
+/// Mirrors the default of `lfs.fetchrecentrefsdays`.
+const DEFAULT_RECENT_REFS_DAYS: u32 = 7;
+
+/// Fetch policy wrapping `git lfs fetch`, so that repositories with long
+/// large-binary histories only pull objects referenced by recent commits by default.
+pub struct Fetch {
+    all: bool,
+    recent_refs_days: u32,
+    recurse_submodules: bool,
+    /// Settings the git config of the superproject put into the environment, which must not
+    /// override the submodules' own
+    applied: Vec<String>,
+    json: bool,
+}
+
+/// What `fetch --json` reports
+#[derive(Debug, Serialize)]
+struct Fetched {
+    /// Where objects were fetched, `.` for the current repository, then its submodules
+    repositories: Vec<String>,
+}
+
+impl Default for Fetch {
+    fn default() -> Self {
+        Self {
+            all: false,
+            recent_refs_days: DEFAULT_RECENT_REFS_DAYS,
+            recurse_submodules: false,
+            applied: vec![],
+            json: false,
+        }
+    }
+}
+
+impl Fetch {
+    pub fn new(
+        all: bool,
+        recent_refs_days: Option<u32>,
+        recurse_submodules: bool,
+        applied: Vec<String>,
+        json: bool,
+    ) -> Self {
+        Self {
+            all,
+            recent_refs_days: recent_refs_days.unwrap_or(DEFAULT_RECENT_REFS_DAYS),
+            recurse_submodules,
+            applied,
+            json,
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("git");
+        if self.all {
+            command.args(&["lfs", "fetch", "--all"]);
+        } else {
+            command
+                .arg("-c")
+                .arg(format!("lfs.fetchrecentrefsdays={}", self.recent_refs_days))
+                .args(&["lfs", "fetch", "--recent"]);
+        }
+        command
+    }
+
+    /// Fetches in the current repository, then in each initialized submodule if asked to, with
+    /// the endpoint and key configured there. Returns where it fetched.
+    fn fetch(&self) -> Result<Fetched, CliError> {
+        let mut commands = vec![(".".to_string(), self.command())];
+        if self.recurse_submodules {
+            let submodules = repo::git(&["submodule", "foreach", "--quiet", "--recursive", "pwd"])?;
+            for path in submodules.lines().filter(|line| !line.is_empty()) {
+                let mut command = self.command();
+                command.current_dir(path);
+                for var in &self.applied {
+                    command.env_remove(var);
+                }
+                commands.push((path.to_string(), command));
+            }
+        }
+        let mut repositories = vec![];
+        for (path, mut command) in commands {
+            debug!("Running {:?}", command);
+            // git-lfs' own report goes to stderr, so that stdout only holds the JSON
+            let status = if self.json {
+                let output = command
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .map_err(CliError::Io)?;
+                std::io::stderr()
+                    .write_all(&output.stdout)
+                    .map_err(CliError::Io)?;
+                output.status
+            } else {
+                command.status().map_err(CliError::Io)?
+            };
+            if !status.success() {
+                return Err(CliError::GitCommandFailed(status));
+            }
+            repositories.push(path);
+        }
+        Ok(Fetched { repositories })
+    }
+}
+
+impl Actor for Fetch {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <Fetch as Actor>::Context) {
+        let result = self.fetch();
+        System::current().stop();
+        match result {
+            Ok(fetched) if self.json => output::print_json("fetch", &fetched),
+            Ok(_) => {}
+            Err(err) => error::exit(err),
+        }
+    }
+}
+
+/// Downloads every object in the manifest of a tagged root into the local LFS store, so that
+/// a checkout can materialize exactly the assets of a past release.
+pub struct FetchAtTag {
+    tag: String,
+    json: bool,
+}
+
+impl FetchAtTag {
+    pub fn new(tag: String, json: bool) -> Self {
+        Self { tag, json }
+    }
+}
+
+/// What `fetch --at-tag --json` reports
+#[derive(Debug, Serialize)]
+struct FetchedTag {
+    tag: String,
+    root: String,
+    /// How many objects were downloaded, leaving out those the local store had already
+    fetched: usize,
+}
+
+/// Fetches the objects listed in `root`'s manifest that aren't in the local store yet,
+/// returning how many were downloaded.
+pub fn fetch_root(root: cid::Cid) -> impl Future<Item = usize, Error = CliError> {
+    signature::trusted_manifest(root)
+        .map_err(CliError::IpfsApiError)
+        .and_then(|manifest| {
+            let missing: Vec<(String, String)> = manifest
+                .objects
+                .into_iter()
+                .filter(|(oid, _)| {
+                    repo::lfs_object_path(oid)
+                        .map(|path| !path.exists())
+                        .unwrap_or(true)
+                })
+                .map(|(oid, entry)| (oid, entry.cid.cid))
+                .collect();
+            stream::iter_ok::<_, CliError>(missing)
+                .map(|(oid, cid)| {
+                    future::result(cid.to_cid().map_err(|_| CliError::InvalidCid(cid.clone())))
+                        .and_then(|cid| {
+                            ipfs::block_get(cid)
+                                .and_then(|res| res.body().map_err(Error::IpfsApiPayloadError))
+                                .map_err(CliError::IpfsApiError)
+                        })
+                        .and_then(move |block| {
+                            let path = repo::lfs_object_path(&oid)?;
+                            if let Some(dir) = path.parent() {
+                                std::fs::create_dir_all(dir).map_err(CliError::Io)?;
+                            }
+                            std::fs::write(&path, &block).map_err(CliError::Io)?;
+                            debug!("Fetched {}", oid);
+                            Ok(())
+                        })
+                })
+                .buffer_unordered(8)
+                .fold(0, |fetched, _| future::ok::<_, CliError>(fetched + 1))
+        })
+}
+
+impl Actor for FetchAtTag {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <FetchAtTag as Actor>::Context) {
+        let tag = self.tag.clone();
+        let root = history::find_tag(&tag).and_then(|found| {
+            found
+                .ok_or_else(|| CliError::NoSuchTag(tag.clone()))
+                .and_then(|found| {
+                    found
+                        .root
+                        .to_cid()
+                        .map_err(|_| CliError::InvalidCid(found.root.clone()))
+                })
+        });
+        let fetched = future::result(root).and_then(|root| {
+            let root_cid = root.to_string();
+            fetch_root(root).map(move |fetched| (root_cid, fetched))
+        });
+        ctx.wait(
+            actix::fut::wrap_future(fetched).then(move |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok((root, fetched)) if actor.json => {
+                        output::print_json("fetch", &FetchedTag { tag, root, fetched })
+                    }
+                    Ok((_, fetched)) => {
+                        println!("{}", messages::text("fetch.fetched-tag", &[&fetched, &tag]))
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
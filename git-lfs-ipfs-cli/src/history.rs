@@ -0,0 +1,313 @@
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use cid::Cid;
+use futures::{future, prelude::*};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{self, CliError};
+use crate::{lock, messages, output, repo};
+use git_lfs_ipfs_lib::{config, error::Error, ipfs};
+
+/// The IPNS key to publish under when nothing was published from the repository yet
+const DEFAULT_KEY: &str = "self";
+
+/// A root CID that was published under an IPNS key
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Entry {
+    pub root: String,
+    pub key: String,
+    pub published_at: DateTime<Utc>,
+}
+
+/// Published roots are appended one JSON entry per line to `.git/lfs/ipfs/history`
+fn history_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("history"))
+}
+
+pub fn record(entry: &Entry) -> Result<(), CliError> {
+    lock::append_json_line(&history_path()?, entry)
+}
+
+/// Oldest first
+pub fn load() -> Result<Vec<Entry>, CliError> {
+    let path = history_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    BufReader::new(std::fs::File::open(path).map_err(CliError::Io)?)
+        .lines()
+        .map(|line| {
+            line.map_err(CliError::Io).and_then(|line| {
+                serde_json::from_str(&line).map_err(CliError::SerdeJsonError)
+            })
+        })
+        .collect()
+}
+
+/// A label, like a release version, given to a published root
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Tag {
+    pub name: String,
+    pub root: String,
+    pub tagged_at: DateTime<Utc>,
+}
+
+/// Tags are appended one JSON entry per line to `.git/lfs/ipfs/tags`, later ones overriding
+/// earlier tags of the same name
+fn tags_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("tags"))
+}
+
+pub fn record_tag(tag: &Tag) -> Result<(), CliError> {
+    lock::append_json_line(&tags_path()?, tag)
+}
+
+/// The current tags, oldest first
+pub fn load_tags() -> Result<Vec<Tag>, CliError> {
+    let path = tags_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let mut tags: Vec<Tag> = vec![];
+    for line in BufReader::new(std::fs::File::open(path).map_err(CliError::Io)?).lines() {
+        let tag: Tag =
+            serde_json::from_str(&line.map_err(CliError::Io)?).map_err(CliError::SerdeJsonError)?;
+        tags.retain(|existing| existing.name != tag.name);
+        tags.push(tag);
+    }
+    Ok(tags)
+}
+
+pub fn find_tag(name: &str) -> Result<Option<Tag>, CliError> {
+    load_tags().map(|tags| tags.into_iter().find(|tag| tag.name == name))
+}
+
+/// Prints every recorded publish, newest first.
+#[derive(Default)]
+pub struct History {
+    json: bool,
+}
+
+impl History {
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+}
+
+impl Actor for History {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <History as Actor>::Context) {
+        let mut entries = load().unwrap_or_else(|err| error::exit(err));
+        entries.reverse();
+        if self.json {
+            output::print_json("history", &entries);
+            System::current().stop();
+            return;
+        }
+        for entry in &entries {
+            println!(
+                "{} {} {}",
+                entry.published_at.to_rfc3339(),
+                entry.key,
+                entry.root
+            );
+        }
+        System::current().stop();
+    }
+}
+
+/// Republishes a previously published root under its IPNS key: `key`, or else the one last
+/// published under, like `publish`.
+pub struct Rollback {
+    root: String,
+    key: Option<String>,
+    json: bool,
+}
+
+impl Rollback {
+    pub fn new(root: String, key: Option<String>, json: bool) -> Self {
+        Self { root, key, json }
+    }
+}
+
+impl Actor for Rollback {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Rollback as Actor>::Context) {
+        use cid::ToCid;
+        let root = self.root.clone();
+        let history = load().unwrap_or_else(|err| error::exit(err));
+        let key_name = self
+            .key
+            .clone()
+            .or_else(|| history.last().map(|latest| latest.key.clone()))
+            .unwrap_or_else(|| DEFAULT_KEY.to_string());
+        if !history.iter().any(|entry| entry.root == root) {
+            warn!("{} was never published from this repository", root);
+        }
+        ctx.wait(
+            actix::fut::wrap_future(
+                future::result(
+                    root.to_cid()
+                        .map_err(|_| CliError::InvalidCid(root.clone())),
+                )
+                .and_then(move |cid| publish(cid, key_name)),
+            )
+            .then(|result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(entry) if actor.json => output::print_json("rollback", &entry),
+                    Ok(entry) => println!(
+                        "{}",
+                        messages::text("republish.republished", &[&entry.root, &entry.key])
+                    ),
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
+
+/// Tags a root, by default the one most recently published from this repository.
+pub struct TagRoot {
+    name: String,
+    root: Option<String>,
+    json: bool,
+}
+
+impl TagRoot {
+    pub fn new(name: String, root: Option<String>, json: bool) -> Self {
+        Self { name, root, json }
+    }
+}
+
+impl Actor for TagRoot {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <TagRoot as Actor>::Context) {
+        use cid::ToCid;
+        let result = match &self.root {
+            Some(root) => Ok(Some(root.clone())),
+            None => load().map(|entries| entries.last().map(|latest| latest.root.clone())),
+        }
+        .and_then(|root| root.ok_or(CliError::NothingPublished))
+        .and_then(|root| match root.to_cid() {
+            Ok(_) => Ok(root),
+            Err(_) => Err(CliError::InvalidCid(root)),
+        })
+        .and_then(|root| {
+            let tag = Tag {
+                name: self.name.clone(),
+                root,
+                tagged_at: Utc::now(),
+            };
+            record_tag(&tag).map(|_| tag)
+        });
+        System::current().stop();
+        match result {
+            Ok(tag) if self.json => output::print_json("tag add", &tag),
+            Ok(tag) => println!("{}", messages::text("tag.tagged", &[&tag.root, &tag.name])),
+            Err(err) => error::exit(err),
+        }
+    }
+}
+
+/// Prints every tag, newest first.
+#[derive(Default)]
+pub struct Tags {
+    json: bool,
+}
+
+impl Tags {
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+}
+
+impl Actor for Tags {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <Tags as Actor>::Context) {
+        let mut tags = load_tags().unwrap_or_else(|err| error::exit(err));
+        tags.reverse();
+        if self.json {
+            output::print_json("tag list", &tags);
+            System::current().stop();
+            return;
+        }
+        for tag in &tags {
+            println!("{} {} {}", tag.tagged_at.to_rfc3339(), tag.name, tag.root);
+        }
+        System::current().stop();
+    }
+}
+
+/// Publishes `root` under the local IPNS key named `key_name` and records it in the history.
+/// If announcements are enabled, the root is also sent to watchers of the key's pubsub topic.
+pub fn publish(root: Cid, key_name: String) -> impl Future<Item = Entry, Error = CliError> {
+    publish_unrecorded(root, key_name, config::get().announce)
+        .and_then(|entry| record(&entry).map(|_| entry))
+}
+
+/// Publishes the most recently recorded root again, so its IPNS record does not expire.
+/// Returns `None` if nothing was ever published from this repository.
+pub fn republish_latest() -> impl Future<Item = Option<Entry>, Error = CliError> {
+    use cid::ToCid;
+    future::result(load()).and_then(|entries| match entries.last() {
+        Some(latest) => future::Either::A(
+            future::result(
+                latest
+                    .root
+                    .to_cid()
+                    .map_err(|_| CliError::InvalidCid(latest.root.clone())),
+            )
+            .and_then({
+                let key_name = latest.key.clone();
+                move |cid| publish_unrecorded(cid, key_name, false)
+            })
+            .map(Some),
+        ),
+        None => future::Either::B(future::ok(None)),
+    })
+}
+
+fn publish_unrecorded(
+    root: Cid,
+    key_name: String,
+    announce: bool,
+) -> impl Future<Item = Entry, Error = CliError> {
+    ipfs::key_list()
+        .and_then(move |key_list| {
+            key_list
+                .keys
+                .into_iter()
+                .find(|key| key.name == key_name)
+                .ok_or(Error::IpfsUploadNotPossible)
+        })
+        .and_then(move |key| {
+            let entry = Entry {
+                root: root.to_string(),
+                key: key.name.clone(),
+                published_at: Utc::now(),
+            };
+            let topic = ipfs::announce_topic(&key.id.to_string());
+            ipfs::name_publish(root, key).and_then(move |_| {
+                if announce {
+                    future::Either::A(
+                        ipfs::pubsub_pub(&topic, Bytes::from(entry.root.as_bytes()))
+                            .then(|result| {
+                                if let Err(err) = result {
+                                    warn!("Could not announce the new root: {}", err);
+                                }
+                                Ok(entry)
+                            }),
+                    )
+                } else {
+                    future::Either::B(future::ok(entry))
+                }
+            })
+        })
+        .map_err(CliError::IpfsApiError)
+}
@@ -0,0 +1,288 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use actix::prelude::*;
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{messages, output, repo};
+use git_lfs_ipfs_lib::config;
+
+/// What a hook is told about the object, through `GIT_LFS_IPFS_HOOK_*` environment variables.
+/// Fields that aren't known yet at that point, like the OID before an upload, are left unset.
+#[derive(Debug, Default)]
+pub struct HookEvent<'a> {
+    pub oid: Option<&'a str>,
+    pub cid: Option<String>,
+    pub path: Option<&'a Path>,
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(&["/C", command]);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(&["-c", command]);
+    shell
+}
+
+/// Runs `command` through the shell. Its output is passed on to stderr, because stdout carries
+/// the filter output or transfer protocol.
+fn run(name: &'static str, command: &str, event: &HookEvent) -> Result<(), CliError> {
+    let mut hook = shell(command);
+    hook.env("GIT_LFS_IPFS_HOOK", name).stdin(std::process::Stdio::null());
+    if let Some(oid) = event.oid {
+        hook.env("GIT_LFS_IPFS_HOOK_OID", oid);
+    }
+    if let Some(cid) = &event.cid {
+        hook.env("GIT_LFS_IPFS_HOOK_CID", cid);
+    }
+    if let Some(path) = event.path {
+        hook.env("GIT_LFS_IPFS_HOOK_PATH", path);
+    }
+    debug!("Running {} hook: {}", name, command);
+    let output = hook.output().map_err(CliError::Io)?;
+    let mut stderr = std::io::stderr();
+    stderr.write_all(&output.stdout).map_err(CliError::Io)?;
+    stderr.write_all(&output.stderr).map_err(CliError::Io)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(CliError::HookFailed(name, output.status))
+    }
+}
+
+/// Runs the configured pre-upload hook, if any, before a file is added to IPFS.
+pub fn pre_upload(event: &HookEvent) -> Result<(), CliError> {
+    match &config::get().pre_upload_hook {
+        Some(command) => run("pre-upload", command, event),
+        None => Ok(()),
+    }
+}
+
+/// Runs the configured post-download hook, if any, once an object is written to disk.
+pub fn post_download(event: &HookEvent) -> Result<(), CliError> {
+    match &config::get().post_download_hook {
+        Some(command) => run("post-download", command, event),
+        None => Ok(()),
+    }
+}
+
+/// Marks git hooks written by `hooks install`, so that reinstalling replaces them
+const GIT_HOOK_MARKER: &str = "# Installed by git-lfs-ipfs-cli hooks install";
+
+/// Part of the message in the hooks that `git lfs install` writes, which are safe to replace
+/// since every installed hook still runs git-lfs first
+const GIT_LFS_HOOK_MARKER: &str = "This repository is configured for Git LFS";
+
+/// The git hooks to install, with what each does after running git-lfs' own:
+/// pre-push publishes the objects git-lfs just uploaded, and post-checkout and post-merge
+/// prefetch the objects of recent refs.
+/// https://git-scm.com/docs/githooks
+fn git_hooks(cli: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("pre-push", format!("\"{}\" publish", cli)),
+        (
+            "post-checkout",
+            format!("[ \"$3\" = 1 ] && \"{}\" fetch || true", cli),
+        ),
+        ("post-merge", format!("\"{}\" fetch || true", cli)),
+    ]
+}
+
+fn git_hook_script(name: &str, command: &str) -> String {
+    format!(
+        "#!/bin/sh\n{}\ncommand -v git-lfs >/dev/null 2>&1 || {{ echo >&2 \"git-lfs was not found on your path, remove .git/hooks/{} to stop using it\"; exit 2; }}\ngit lfs {} \"$@\" || exit $?\n{}\n",
+        GIT_HOOK_MARKER, name, name, command
+    )
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), CliError> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).map_err(CliError::Io)
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), CliError> {
+    Ok(())
+}
+
+/// Outcome of installing the git hooks
+#[derive(Debug, Default, Serialize)]
+pub struct InstallReport {
+    pub installed: Vec<PathBuf>,
+    /// Hooks that were left alone, since they weren't written by git-lfs or git-lfs-ipfs
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Writes the git hooks into the hooks directory of the current repository, honoring
+/// `core.hooksPath`. Hooks written by someone else are only replaced with `force`.
+pub fn install_git_hooks(force: bool) -> Result<InstallReport, CliError> {
+    let cli = std::env::current_exe().map_err(CliError::Io)?;
+    let dir = PathBuf::from(repo::git(&["rev-parse", "--git-path", "hooks"])?);
+    install_into(&dir, &cli.display().to_string(), force)
+}
+
+/// Writes hooks that run `cli` into `dir`, see [`install_git_hooks`]
+fn install_into(dir: &Path, cli: &str, force: bool) -> Result<InstallReport, CliError> {
+    std::fs::create_dir_all(dir).map_err(CliError::Io)?;
+    let mut report = InstallReport::default();
+    for (name, command) in git_hooks(cli) {
+        let path = dir.join(name);
+        if !force && path.exists() {
+            let existing = std::fs::read_to_string(&path).map_err(CliError::Io)?;
+            if !existing.contains(GIT_HOOK_MARKER) && !existing.contains(GIT_LFS_HOOK_MARKER) {
+                warn!("Leaving {} alone, since it was not written by git-lfs", path.display());
+                report.skipped.push(path);
+                continue;
+            }
+        }
+        std::fs::write(&path, git_hook_script(name, &command)).map_err(CliError::Io)?;
+        make_executable(&path)?;
+        report.installed.push(path);
+    }
+    Ok(report)
+}
+
+/// Installs git hooks that publish after pushing and prefetch after checkouts and merges.
+pub struct Install {
+    force: bool,
+    json: bool,
+}
+
+impl Install {
+    pub fn new(force: bool, json: bool) -> Self {
+        Self { force, json }
+    }
+}
+
+impl Actor for Install {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <Install as Actor>::Context) {
+        System::current().stop();
+        let report = install_git_hooks(self.force).unwrap_or_else(|err| error::exit(err));
+        if self.json {
+            output::print_json("hooks install", &report);
+            return;
+        }
+        for path in &report.installed {
+            println!("{}", messages::text("hooks.installed", &[&path.display()]));
+        }
+        for path in &report.skipped {
+            println!("{}", messages::text("hooks.skipped", &[&path.display()]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLI: &str = "/usr/local/bin/git-lfs-ipfs-cli";
+
+    fn hooks_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "git-lfs-ipfs-hooks-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn fresh_installs_write_every_hook() {
+        let dir = hooks_dir("fresh");
+        let report = install_into(&dir, CLI, false).unwrap();
+        assert_eq!(
+            vec!["pre-push", "post-checkout", "post-merge"],
+            names(&report.installed)
+        );
+        assert!(report.skipped.is_empty());
+        let pre_push = std::fs::read_to_string(dir.join("pre-push")).unwrap();
+        assert!(pre_push.starts_with("#!/bin/sh\n"));
+        assert!(pre_push.contains(GIT_HOOK_MARKER));
+        assert!(pre_push.contains("git lfs pre-push \"$@\""));
+        assert!(pre_push.contains(&format!("\"{}\" publish", CLI)));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.join("pre-push"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(0o755, mode & 0o777);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn foreign_hooks_are_only_replaced_with_force() {
+        let dir = hooks_dir("foreign");
+        std::fs::create_dir_all(&dir).unwrap();
+        let foreign = "#!/bin/sh\nmake lint\n";
+        std::fs::write(dir.join("pre-push"), foreign).unwrap();
+        // Written by `git lfs install`, so safe to replace
+        std::fs::write(
+            dir.join("post-merge"),
+            format!(
+                "#!/bin/sh\necho \"{}\"\ngit lfs post-merge \"$@\"\n",
+                GIT_LFS_HOOK_MARKER
+            ),
+        )
+        .unwrap();
+
+        let report = install_into(&dir, CLI, false).unwrap();
+        assert_eq!(vec!["pre-push"], names(&report.skipped));
+        assert_eq!(
+            vec!["post-checkout", "post-merge"],
+            names(&report.installed)
+        );
+        assert_eq!(
+            foreign,
+            std::fs::read_to_string(dir.join("pre-push")).unwrap()
+        );
+        assert!(std::fs::read_to_string(dir.join("post-merge"))
+            .unwrap()
+            .contains(GIT_HOOK_MARKER));
+
+        let report = install_into(&dir, CLI, true).unwrap();
+        assert!(report.skipped.is_empty());
+        assert!(std::fs::read_to_string(dir.join("pre-push"))
+            .unwrap()
+            .contains(GIT_HOOK_MARKER));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reinstalling_is_idempotent() {
+        let dir = hooks_dir("reinstall");
+        install_into(&dir, CLI, false).unwrap();
+        let read = || {
+            ["pre-push", "post-checkout", "post-merge"]
+                .iter()
+                .map(|name| std::fs::read_to_string(dir.join(name)).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let installed = read();
+        let report = install_into(&dir, CLI, false).unwrap();
+        assert_eq!(3, report.installed.len());
+        assert!(report.skipped.is_empty());
+        assert_eq!(installed, read());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
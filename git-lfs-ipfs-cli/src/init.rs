@@ -0,0 +1,201 @@
+use std::io::{self, BufRead, Write};
+
+use actix::prelude::*;
+use actix_web::{error::PayloadError, HttpMessage};
+use bytes::Bytes;
+use futures::{future, prelude::*, stream};
+
+use crate::error::{self, CliError};
+use crate::{messages, repo};
+use git_lfs_ipfs_lib::{
+    error::Error,
+    ipfs,
+    spec::{
+        ipfs::{Key, Path},
+        manifest::Layout,
+    },
+};
+
+/// The IPNS key used when none is chosen, as everywhere else in the CLI
+const DEFAULT_KEY: &str = "self";
+
+/// Payload of the round trip test, small enough to fit in a single block
+const ROUND_TRIP_PAYLOAD: &[u8] = b"git-lfs-ipfs init round trip";
+
+/// The settings from the README, pointing at this executable. With `compress`, the gzip
+/// extension runs first, so that the ipfs extension adds the compressed file.
+fn lfs_config(cli: &str, compress: bool) -> Vec<(&'static str, String)> {
+    let mut config = vec![
+        ("lfs.customtransfer.ipfs.path", cli.to_string()),
+        ("lfs.customtransfer.ipfs.args", "transfer".to_string()),
+        ("lfs.customtransfer.ipfs.concurrent", "true".to_string()),
+        ("lfs.customtransfer.ipfs.direction", "both".to_string()),
+        ("lfs.extension.ipfs.clean", format!("{} clean %f", cli)),
+        ("lfs.extension.ipfs.smudge", format!("{} smudge %f", cli)),
+        (
+            "lfs.extension.ipfs.priority",
+            if compress { "1" } else { "0" }.to_string(),
+        ),
+    ];
+    if compress {
+        config.extend(vec![
+            ("lfs.extension.gzip.clean", format!("{} gzip clean %f", cli)),
+            ("lfs.extension.gzip.smudge", format!("{} gzip smudge %f", cli)),
+            ("lfs.extension.gzip.priority", "0".to_string()),
+        ]);
+    }
+    config
+}
+
+fn prompt(question: &str, default: &str) -> Result<String, CliError> {
+    print!("{} [{}]: ", question, default);
+    io::stdout().flush().map_err(CliError::Io)?;
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(CliError::Io)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+/// Finds the IPNS key named `name`, generating it if the node doesn't have one yet.
+pub(crate) fn find_or_generate_key(name: String) -> impl Future<Item = Key, Error = Error> {
+    ipfs::key_list().and_then(move |key_list| {
+        match key_list.keys.into_iter().find(|key| key.name == name) {
+            Some(key) => future::Either::A(future::ok(key)),
+            None => {
+                println!("{}", messages::text("init.generating-key", &[&name]));
+                future::Either::B(ipfs::key_gen(&name))
+            }
+        }
+    })
+}
+
+/// Adds a small payload and reads it back, to check that the node accepts and serves objects.
+fn round_trip() -> impl Future<Item = (), Error = Error> {
+    ipfs::add(
+        stream::once::<Bytes, PayloadError>(Ok(Bytes::from(ROUND_TRIP_PAYLOAD))),
+        None,
+    )
+    .and_then(|added| ipfs::cat(Path::ipfs(added.hash)))
+    .and_then(|res| res.body().map_err(Error::IpfsApiPayloadError))
+    .and_then(|body| {
+        if body == ROUND_TRIP_PAYLOAD {
+            Ok(())
+        } else {
+            Err(Error::VerifyFailed)
+        }
+    })
+}
+
+/// Sets up git-lfs-ipfs for the current repository, or for every repository with `global`:
+/// generates the IPNS key, checks that objects round trip and then writes the git config.
+pub struct Init {
+    key: Option<String>,
+    /// Stored in the git config, outside any profile, for roots published from here on
+    layout: Option<Layout>,
+    /// Stored in the git config like `layout`, to read back every upload through a gateway
+    paranoid: bool,
+    /// Sets up the gzip extension ahead of the ipfs one
+    compress: bool,
+    global: bool,
+    interactive: bool,
+    test: bool,
+}
+
+impl Init {
+    pub fn new(
+        key: Option<String>,
+        layout: Option<Layout>,
+        paranoid: bool,
+        compress: bool,
+        global: bool,
+        interactive: bool,
+        test: bool,
+    ) -> Self {
+        Self {
+            key,
+            layout,
+            paranoid,
+            compress,
+            global,
+            interactive,
+            test,
+        }
+    }
+
+    fn write_config(&self) -> Result<(), CliError> {
+        let cli = std::env::current_exe().map_err(CliError::Io)?;
+        let scope = if self.global { "--global" } else { "--local" };
+        for (key, value) in lfs_config(&cli.display().to_string(), self.compress) {
+            repo::git(&["config", scope, key, &value])?;
+        }
+        if let Some(layout) = self.layout {
+            repo::git(&["config", scope, "git-lfs-ipfs.layout", layout.as_str()])?;
+        }
+        if self.paranoid {
+            repo::git(&["config", scope, "git-lfs-ipfs.paranoid", "true"])?;
+        }
+        if !self.global {
+            repo::git(&["lfs", "install", "--local"])?;
+        }
+        Ok(())
+    }
+}
+
+impl Actor for Init {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Init as Actor>::Context) {
+        let key_name = match &self.key {
+            Some(key) => key.clone(),
+            None if self.interactive => {
+                prompt(&messages::text("init.key-prompt", &[]), DEFAULT_KEY)
+                    .unwrap_or_else(|err| error::exit(err))
+            }
+            None => DEFAULT_KEY.to_string(),
+        };
+        let test = self.test;
+        ctx.wait(
+            actix::fut::wrap_future(
+                find_or_generate_key(key_name)
+                    .and_then(move |key| {
+                        if test {
+                            future::Either::A(round_trip().map(|_| {
+                                println!("{}", messages::text("init.round-trip", &[]));
+                                key
+                            }))
+                        } else {
+                            future::Either::B(future::ok(key))
+                        }
+                    })
+                    .map_err(CliError::IpfsApiError),
+            )
+            // The config is only written once the node checks out, so that a failed init
+            // leaves the repository as it was
+            .then(|result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                let key = result
+                    .and_then(|key| actor.write_config().map(|_| key))
+                    .unwrap_or_else(|err| error::exit(err));
+                println!(
+                    "{}",
+                    messages::text(
+                        if actor.global {
+                            "init.configured-global"
+                        } else {
+                            "init.configured-local"
+                        },
+                        &[]
+                    )
+                );
+                println!("{}", messages::text("init.published-under", &[&key.id]));
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*, stream};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{self, CliError};
+use crate::{history, init, lock, messages, output, repo};
+use git_lfs_ipfs_lib::{
+    config::{self, Config},
+    error::Error,
+    ipfs,
+    spec::ipfs::{Key, Path, Prefix, Root},
+};
+
+/// An old IPNS key that is kept pointing at the name of the key that replaced it
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Redirect {
+    /// Name of the old key on this node
+    pub from: String,
+    /// `/ipns/<new key ID>`
+    pub to: String,
+    /// The redirect is no longer republished after this, and its record expires with time
+    pub until: DateTime<Utc>,
+}
+
+/// Redirects are appended one JSON entry per line to `.git/lfs/ipfs/redirects`
+fn redirects_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("redirects"))
+}
+
+fn record_redirect(redirect: &Redirect) -> Result<(), CliError> {
+    lock::append_json_line(&redirects_path()?, redirect)
+}
+
+/// Redirects whose grace period isn't over yet, the latest for each old key
+pub fn active_redirects() -> Result<Vec<Redirect>, CliError> {
+    let path = redirects_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let redirects = BufReader::new(std::fs::File::open(path).map_err(CliError::Io)?)
+        .lines()
+        .map(|line| {
+            line.map_err(CliError::Io).and_then(|line| {
+                serde_json::from_str(&line).map_err(CliError::SerdeJsonError)
+            })
+        })
+        .collect::<Result<Vec<Redirect>, CliError>>()?;
+    Ok(active(redirects, Utc::now()))
+}
+
+/// A later redirect of the same key replaces an earlier one
+fn active(redirects: Vec<Redirect>, now: DateTime<Utc>) -> Vec<Redirect> {
+    let mut latest: Vec<Redirect> = vec![];
+    for redirect in redirects {
+        latest.retain(|existing| existing.from != redirect.from);
+        latest.push(redirect);
+    }
+    latest.retain(|redirect| redirect.until > now);
+    latest
+}
+
+fn find_key(name: String) -> impl Future<Item = Key, Error = Error> {
+    ipfs::key_list().and_then(move |key_list| {
+        key_list
+            .keys
+            .into_iter()
+            .find(|key| key.name == name)
+            .ok_or(Error::IpfsUploadNotPossible)
+    })
+}
+
+fn ipns_path(key: &Key) -> Path {
+    Path {
+        prefix: Prefix::Ipns,
+        root: Root::Cid(key.id.clone()),
+        suffix: None,
+    }
+}
+
+/// Publishes every active redirect again, so that old names keep leading to the new ones
+/// until their grace period is over. Returns the redirects that were republished.
+pub fn republish_redirects() -> impl Future<Item = Vec<Redirect>, Error = CliError> {
+    future::result(active_redirects()).and_then(|redirects| {
+        stream::iter_ok::<_, CliError>(redirects)
+            .and_then(|redirect| {
+                let to = redirect.to.parse::<Path>();
+                find_key(redirect.from.clone())
+                    .and_then(move |key| {
+                        future::result(to).and_then(|to| ipfs::name_publish_path(to, key))
+                    })
+                    .map(move |_| redirect)
+                    .map_err(CliError::IpfsApiError)
+            })
+            .collect()
+    })
+}
+
+/// The repository's git config settings that name the old key, and what they become
+fn config_updates(old: &Key, new: &Key) -> Vec<(&'static str, String, String)> {
+    vec![
+        ("git-lfs-ipfs.root", ipns_path(old).to_string(), ipns_path(new).to_string()),
+        ("git-lfs-ipfs.signing-key", old.name.clone(), new.name.clone()),
+        ("git-lfs-ipfs.trusted-key", old.id.to_string(), new.id.to_string()),
+    ]
+}
+
+/// Points the repository's git config at the new key wherever it named the old one, returning
+/// the settings that changed.
+fn update_config(old: &Key, new: &Key) -> Result<Vec<String>, CliError> {
+    let mut updated = vec![];
+    for (setting, old_value, new_value) in config_updates(old, new) {
+        match repo::git(&["config", "--local", setting]) {
+            Ok(value) if value.trim() == old_value => {
+                repo::git(&["config", "--local", setting, &new_value])?;
+                updated.push(setting.to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok(updated)
+}
+
+/// What `key rotate` reports
+#[derive(Debug, Serialize)]
+pub struct Rotation {
+    pub old_key: String,
+    pub new_key: String,
+    /// `/ipns/<new key ID>`, where the repository's objects are published from now on
+    pub name: String,
+    pub root: String,
+    /// Until when the old key is kept pointing at the new name, if it is
+    pub redirect_until: Option<DateTime<Utc>>,
+    /// Git config settings that named the old key
+    pub updated_config: Vec<String>,
+}
+
+/// Replaces the IPNS key the repository publishes under: generates the new key if needed,
+/// publishes the latest root under it, optionally points the old key at the new name for a
+/// while, and updates the git config settings that named the old key.
+pub struct Rotate {
+    new_key: String,
+    redirect_days: Option<i64>,
+    json: bool,
+}
+
+impl Rotate {
+    pub fn new(new_key: String, redirect_days: Option<i64>, json: bool) -> Self {
+        Self {
+            new_key,
+            redirect_days,
+            json,
+        }
+    }
+}
+
+impl Actor for Rotate {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Rotate as Actor>::Context) {
+        let latest = history::load()
+            .unwrap_or_else(|err| error::exit(err))
+            .pop()
+            .unwrap_or_else(|| error::exit(CliError::NothingPublished));
+        if latest.key == self.new_key {
+            error::exit(CliError::SameKey(latest.key.clone()));
+        }
+        let root = latest
+            .root
+            .to_cid()
+            .unwrap_or_else(|_| error::exit(CliError::InvalidCid(latest.root.clone())));
+        // A manifest signed with the old key is signed again with the new one
+        let config = config::get();
+        let resign = config.signing_key.as_ref() == Some(&latest.key);
+        if resign {
+            config::set(Config {
+                signing_key: Some(self.new_key.clone()),
+                ..(*config).clone()
+            });
+        }
+        let redirect_until = self
+            .redirect_days
+            .map(|days| Utc::now() + chrono::Duration::days(days));
+        let rotate = find_key(latest.key.clone())
+            .join(init::find_or_generate_key(self.new_key.clone()))
+            .and_then(move |(old, new)| {
+                let root: Box<Future<Item = Cid, Error = Error>> = if resign {
+                    Box::new(
+                        ipfs::manifest_get(root)
+                            .and_then(|manifest| ipfs::root_put(&manifest, config::get().layout)),
+                    )
+                } else {
+                    Box::new(future::ok(root))
+                };
+                root.map(|root| (old, new, root))
+            })
+            .map_err(CliError::IpfsApiError)
+            .and_then(|(old, new, root)| {
+                history::publish(root, new.name.clone()).map(|entry| (old, new, entry))
+            })
+            .and_then(move |(old, new, entry)| {
+                let redirect = match redirect_until {
+                    Some(until) => {
+                        let redirect = Redirect {
+                            from: old.name.clone(),
+                            to: ipns_path(&new).to_string(),
+                            until,
+                        };
+                        let (path, key) = (ipns_path(&new), find_key(old.name.clone()));
+                        future::Either::A(
+                            key.and_then(|key| ipfs::name_publish_path(path, key))
+                                .map_err(CliError::IpfsApiError)
+                                .and_then(move |_| record_redirect(&redirect)),
+                        )
+                    }
+                    None => future::Either::B(future::ok(())),
+                };
+                redirect.and_then(move |_| {
+                    Ok(Rotation {
+                        updated_config: update_config(&old, &new)?,
+                        old_key: old.name,
+                        new_key: new.name,
+                        name: ipns_path(&new).to_string(),
+                        root: entry.root,
+                        redirect_until,
+                    })
+                })
+            });
+        ctx.wait(
+            actix::fut::wrap_future(rotate).then(|result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(rotation) if actor.json => output::print_json("key rotate", &rotation),
+                    Ok(rotation) => {
+                        println!(
+                            "{}",
+                            messages::text(
+                                "key-rotate.published",
+                                &[&rotation.root, &rotation.new_key, &rotation.name]
+                            )
+                        );
+                        if let Some(until) = rotation.redirect_until {
+                            println!(
+                                "{}",
+                                messages::text(
+                                    "key-rotate.redirect",
+                                    &[&rotation.old_key, &rotation.name, &until.to_rfc3339()]
+                                )
+                            );
+                        }
+                        for setting in &rotation.updated_config {
+                            println!("{}", messages::text("key-rotate.updated", &[setting]));
+                        }
+                        println!(
+                            "{}",
+                            messages::text(
+                                "key-rotate.retire",
+                                &[&rotation.name, &rotation.old_key]
+                            )
+                        );
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn redirect(from: &str, until: DateTime<Utc>) -> Redirect {
+        Redirect {
+            from: from.to_string(),
+            to: "/ipns/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string(),
+            until,
+        }
+    }
+
+    #[test]
+    fn only_the_latest_unexpired_redirect_of_a_key_is_active() {
+        let now = Utc::now();
+        let day = chrono::Duration::days(1);
+        let redirects = vec![
+            redirect("extended", now + day),
+            redirect("shortened", now + day),
+            redirect("expired", now - day),
+            redirect("extended", now + day * 30),
+            redirect("shortened", now - day),
+        ];
+        let active: Vec<(String, DateTime<Utc>)> = active(redirects, now)
+            .into_iter()
+            .map(|redirect| (redirect.from, redirect.until))
+            .collect();
+        assert_eq!(active, vec![("extended".to_string(), now + day * 30)]);
+    }
+}
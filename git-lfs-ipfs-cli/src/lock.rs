@@ -0,0 +1,222 @@
+//! Locks on the state in `.git/lfs/ipfs`, which the transfer agent of every `git push`,
+//! `publish`, `prune`, `daemon` and the other commands may change at the same time. Like git's
+//! own `index.lock`, a lock is a file next to the state it guards that only one process can
+//! create, so it works across processes and on every platform.
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::{prelude::*, sync::oneshot};
+use serde::Serialize;
+
+use crate::error::CliError;
+
+/// How long to wait for another process to release a lock
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// How often to check whether it did
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+/// Locks are only held to read and rewrite a small file, so one this old was left behind by a
+/// process that crashed, and is taken over
+const STALE_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Held while reading and changing a state file, released when dropped
+#[derive(Debug)]
+pub struct StateLock {
+    path: PathBuf,
+}
+
+impl StateLock {
+    /// Locks the state file at `state` by creating `<state>.lock`, waiting for any other process
+    /// that holds it. Fails with [`CliError::StateLocked`] if it isn't released in time. It blocks
+    /// while it waits, so on the event loop it is taken through [`off_reactor`].
+    pub fn acquire(state: &Path) -> Result<Self, CliError> {
+        let mut path = OsString::from(state.as_os_str());
+        path.push(".lock");
+        Self::acquire_at(PathBuf::from(path), TIMEOUT)
+    }
+
+    fn acquire_at(path: PathBuf, timeout: Duration) -> Result<Self, CliError> {
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // Only read by whoever finds the lock left behind
+                    let _ = writeln!(file, "{}", std::process::id());
+                    return Ok(Self { path });
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    if take_over(&path, STALE_AFTER) {
+                        continue;
+                    }
+                    if started.elapsed() >= timeout {
+                        return Err(CliError::StateLocked(path));
+                    }
+                    std::thread::sleep(RETRY_INTERVAL);
+                }
+                Err(err) => return Err(CliError::Io(err)),
+            }
+        }
+    }
+}
+
+fn is_stale(path: &Path, stale_after: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map_or(false, |age| age > stale_after)
+}
+
+/// Removes the lock at `path` if it was left behind, returning whether it did. Checking its
+/// age and then removing it would remove the fresh lock of a process that took it over in
+/// between, so it is first renamed to a name of this process' own, which only one process can
+/// do, and checked again there. A lock that turns out to be fresh is put back.
+fn take_over(path: &Path, stale_after: Duration) -> bool {
+    if !is_stale(path, stale_after) {
+        return false;
+    }
+    let mut aside = OsString::from(path.as_os_str());
+    aside.push(format!(".stale.{}", std::process::id()));
+    let aside = PathBuf::from(aside);
+    // Fails if another process moved it first
+    if std::fs::rename(path, &aside).is_err() {
+        return false;
+    }
+    let stale = is_stale(&aside, stale_after);
+    if stale {
+        warn!("Taking over {}, which was left behind", path.display());
+    } else if let Err(err) = std::fs::hard_link(&aside, path) {
+        // Linking fails rather than replace a lock taken in the meantime
+        warn!("Could not put back {}: {}", path.display(), err);
+    }
+    let _ = std::fs::remove_file(&aside);
+    stale
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!("Could not release {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Runs `locked`, which takes state locks, on a thread of its own. Waiting for a lock another
+/// process holds blocks, and on the event loop it would stall every transfer running there.
+pub fn off_reactor<T, F>(locked: F) -> impl Future<Item = T, Error = CliError>
+where
+    F: FnOnce() -> Result<T, CliError> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(locked());
+    });
+    rx.then(|result| {
+        result.unwrap_or_else(|_| {
+            Err(CliError::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "the thread changing the state panicked",
+            )))
+        })
+    })
+}
+
+/// Appends `entry` as a JSON line to the state file at `path`, holding its lock and writing
+/// the line at once, so that readers never see half of it
+pub fn append_json_line<T: Serialize>(path: &Path, entry: &T) -> Result<(), CliError> {
+    let lock = StateLock::acquire(path)?;
+    append_json_line_held(&lock, path, entry)
+}
+
+/// Like [`append_json_line`], for callers that already hold the lock of `path`, such as to
+/// read the file and append to it as one change
+pub fn append_json_line_held<T: Serialize>(
+    _lock: &StateLock,
+    path: &Path,
+    entry: &T,
+) -> Result<(), CliError> {
+    let mut line = serde_json::to_vec(entry).map_err(CliError::SerdeJsonError)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(CliError::Io)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_one_holder_at_a_time() {
+        let path = std::env::temp_dir().join(format!(
+            "git-lfs-ipfs-lock-test-{}.lock",
+            std::process::id()
+        ));
+        let held = StateLock::acquire_at(path.clone(), Duration::from_millis(0)).unwrap();
+        match StateLock::acquire_at(path.clone(), Duration::from_millis(100)) {
+            Err(CliError::StateLocked(locked)) => assert_eq!(path, locked),
+            result => panic!("expected the lock to be held, got {:?}", result),
+        }
+        drop(held);
+        assert!(!path.exists());
+        drop(StateLock::acquire_at(path.clone(), Duration::from_millis(0)).unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn only_locks_left_behind_are_taken_over() {
+        let path = std::env::temp_dir().join(format!(
+            "git-lfs-ipfs-stale-test-{}.lock",
+            std::process::id()
+        ));
+        let held = StateLock::acquire_at(path.clone(), Duration::from_millis(0)).unwrap();
+        assert!(!take_over(&path, STALE_AFTER));
+        assert!(path.exists());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(take_over(&path, Duration::from_millis(10)));
+        assert!(!path.exists());
+        std::mem::forget(held);
+        let leftovers = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(&format!("git-lfs-ipfs-stale-test-{}", std::process::id()))
+            })
+            .count();
+        assert_eq!(0, leftovers);
+    }
+
+    #[test]
+    fn lines_are_appended_under_a_lock_already_held() {
+        let path =
+            std::env::temp_dir().join(format!("git-lfs-ipfs-append-test-{}", std::process::id()));
+        let lock = StateLock::acquire_at(
+            PathBuf::from(format!("{}.lock", path.display())),
+            Duration::from_millis(0),
+        )
+        .unwrap();
+        append_json_line_held(&lock, &path, &1).unwrap();
+        append_json_line_held(&lock, &path, &"two").unwrap();
+        drop(lock);
+        append_json_line(&path, &3).unwrap();
+        assert_eq!("1\n\"two\"\n3\n", std::fs::read_to_string(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn locked_work_runs_off_the_reactor() {
+        assert_eq!(42, off_reactor(|| Ok(42)).wait().unwrap());
+        assert!(off_reactor(|| -> Result<(), CliError> { panic!("lost") })
+            .wait()
+            .is_err());
+    }
+}
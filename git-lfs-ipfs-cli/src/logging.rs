@@ -0,0 +1,168 @@
+use std::cell::Cell;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::error::CliError;
+use crate::lock::StateLock;
+
+/// Where to log when `--log-file` isn't given, for the filters and transfer agent that git runs
+const LOG_FILE_ENV: &str = "GIT_LFS_IPFS_LOG_FILE";
+/// Level of the log file when `--log-level` isn't given
+const LOG_LEVEL_ENV: &str = "GIT_LFS_IPFS_LOG_LEVEL";
+
+/// The log file is rotated once it grows past this size, or at midnight
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+/// How many rotated log files are kept, as `<file>.1` (newest) to `<file>.5`
+const ROTATED_LOGS: usize = 5;
+
+/// Appends records to a file, rotating it by size and by day. Every process that git runs
+/// may log to the same file, so each record is appended to whatever file is at the path at the
+/// time, and rotation happens under the file's [`StateLock`], once.
+struct FileLog {
+    path: PathBuf,
+    level: LevelFilter,
+}
+
+thread_local! {
+    /// Set while rotating, so that records logged meanwhile, such as by the lock, are appended
+    /// without trying to rotate again
+    static ROTATING: Cell<bool> = Cell::new(false);
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", n));
+    PathBuf::from(rotated)
+}
+
+fn rotate(path: &Path) -> std::io::Result<()> {
+    for n in (1..ROTATED_LOGS).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            std::fs::rename(from, rotated_path(path, n + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated_path(path, 1))
+}
+
+/// Whether the file at `path` is due for rotation before `len` more bytes are added to it
+fn is_due(path: &Path, len: u64) -> bool {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    let written_on = metadata
+        .modified()
+        .map(|modified| {
+            chrono::DateTime::<Local>::from(modified)
+                .date()
+                .naive_local()
+        })
+        .unwrap_or_else(|_| Local::today().naive_local());
+    metadata.len() > 0
+        && (metadata.len() + len > MAX_LOG_SIZE || written_on != Local::today().naive_local())
+}
+
+impl FileLog {
+    /// Rotates the log file if it is due, unless another process got to it first
+    fn rotate_if_due(&self, len: u64) -> Result<(), CliError> {
+        if !is_due(&self.path, len) || ROTATING.with(Cell::get) {
+            return Ok(());
+        }
+        ROTATING.with(|rotating| rotating.set(true));
+        let rotated = StateLock::acquire(&self.path).and_then(|_lock| {
+            if is_due(&self.path, len) {
+                rotate(&self.path).map_err(CliError::Io)?;
+            }
+            Ok(())
+        });
+        ROTATING.with(|rotating| rotating.set(false));
+        rotated
+    }
+
+    fn write(&self, record: &Record) -> Result<(), CliError> {
+        let line = format!(
+            "{} {} {} {}: {}\n",
+            Local::now().to_rfc3339(),
+            std::process::id(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(CliError::Io)?;
+        }
+        self.rotate_if_due(line.len() as u64)?;
+        // A whole line in one append, so that lines of processes logging at the same time
+        // don't interleave
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(CliError::Io)
+    }
+}
+
+/// Logs to stderr as configured by `RUST_LOG`, and to a file at its own level
+struct Tee {
+    stderr: env_logger::Logger,
+    file: Option<FileLog>,
+}
+
+impl Log for Tee {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata)
+            || self
+                .file
+                .as_ref()
+                .map_or(false, |file| metadata.level() <= file.level)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.stderr.matches(record) {
+            self.stderr.log(record);
+        }
+        if let Some(file) = &self.file {
+            if record.level() <= file.level {
+                // Nowhere left to report this to but stderr
+                if let Err(err) = file.write(record) {
+                    eprintln!("Could not write to {}: {}", file.path.display(), err);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        // Records go to the log file as they are written
+        self.stderr.flush();
+    }
+}
+
+/// Sets up logging to stderr and, if given here or through `GIT_LFS_IPFS_LOG_FILE`, to a
+/// rotated log file, since git swallows the stderr of the filters and transfer agent.
+pub fn init(log_file: Option<PathBuf>, level: Option<LevelFilter>) {
+    let stderr = env_logger::Builder::from_default_env().build();
+    let file = log_file
+        .or_else(|| std::env::var_os(LOG_FILE_ENV).map(PathBuf::from))
+        .map(|path| FileLog {
+            path,
+            level: level
+                .or_else(|| {
+                    std::env::var(LOG_LEVEL_ENV)
+                        .ok()
+                        .and_then(|level| level.parse().ok())
+                })
+                .unwrap_or(LevelFilter::Info),
+        });
+    let max_level = file
+        .as_ref()
+        .map_or(stderr.filter(), |file| file.level.max(stderr.filter()));
+    if log::set_boxed_logger(Box::new(Tee { stderr, file })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
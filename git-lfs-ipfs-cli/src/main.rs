@@ -5,12 +5,14 @@ extern crate clap;
 extern crate dirs;
 extern crate env_logger;
 extern crate failure;
+extern crate flate2;
 extern crate futures;
 extern crate hex;
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
 extern crate multihash;
+extern crate openssl;
 extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
@@ -24,18 +26,66 @@ extern crate git_lfs_ipfs_lib;
 extern crate pretty_assertions;
 
 use actix::prelude::*;
+use git_lfs_ipfs_lib::{config, ipfs};
 
+mod bench;
 mod clean;
+mod compress;
+mod credential;
+mod daemon;
+mod diff;
 mod error;
+mod fetch;
+mod history;
+mod hooks;
+mod init;
+mod key;
+mod lock;
+mod logging;
+mod messages;
+mod migrate;
+mod mirror;
+mod output;
+mod policy;
+mod profile;
+mod prune;
+mod receipt;
+mod repo;
+mod resolve;
+mod restore;
+mod seed;
+mod share;
 mod smudge;
+mod stage;
+mod stats;
+mod status;
+mod tier;
+mod timings;
 mod transfer;
+mod verify;
+mod version;
+mod watch;
+mod workspace;
 
 fn main() {
-    env_logger::init();
     let app_matches = clap_app!(myapp =>
         (version: crate_version!())
         (author: crate_authors!())
         (about: crate_description!())
+        (@arg json: --json +global "print results as JSON, for scripts")
+        (@arg profile: --profile +takes_value +global "settings profile from the git config to use")
+        (@arg log_file: --("log-file") +takes_value +global "also log to this file, rotated daily or at 10 MiB")
+        (@arg log_level: --("log-level") +takes_value +global "level of the log file: error, warn, info, debug or trace, defaults to info")
+        (@subcommand init =>
+            (about: "set up git-lfs-ipfs: create the IPNS key, write the git config and test the IPFS node")
+            (@arg key: --key +takes_value "IPNS key to publish under, created if missing; asked for unless given")
+            (@arg layout: --layout +takes_value possible_value[flat fanout manifest] "how published roots link objects: by OID, fanned out by OID prefix, or only in the manifest")
+            (@arg paranoid: --paranoid "read every upload back through a gateway and re-hash it before it succeeds")
+            (@arg global: --global "configure every repository in ~/.gitconfig instead of the current one")
+            (@arg yes: -y --yes "don't ask anything, use defaults")
+            (@arg skip_test: --("skip-test") "skip the upload and download round trip")
+            (@arg compress: --compress "also set up the gzip extension, compressing files before they are added")
+        )
         (@subcommand smudge =>
             (about: "git-lfs smudge filter extension for ipfs")
             (@arg filename: +required "name of the file")
@@ -47,25 +97,548 @@ fn main() {
         (@subcommand transfer =>
             (about: "git-lfs custom transfer for ipfs")
         )
+        (@subcommand gzip =>
+            (about: "git-lfs extension compressing files before the ipfs extension adds them")
+            (@subcommand clean =>
+                (about: "compress stdin to stdout")
+                (@arg filename: +required "name of the file")
+            )
+            (@subcommand smudge =>
+                (about: "decompress stdin to stdout")
+                (@arg filename: +required "name of the file")
+            )
+        )
+        (@subcommand fetch =>
+            (about: "fetch LFS objects referenced by recent refs, or all of them")
+            (@arg all: --all "fetch objects for every ref in history")
+            (@arg recent_days: --("recent-days") +takes_value "how many days back a ref counts as recent")
+            (@arg at_tag: --("at-tag") +takes_value conflicts_with[all recent_days] "fetch every object in the manifest of a tagged root")
+            (@arg recurse_submodules: --("recurse-submodules") conflicts_with[at_tag] "also fetch in every initialized submodule, with its own configuration")
+        )
+        (@subcommand version =>
+            (about: "print the version, and with --verbose what this build supports, for bug reports")
+            (@arg verbose: -v --verbose "also print the commit, supported protocols, IPFS daemon and features")
+        )
+        (@subcommand bench =>
+            (about: "measure upload and download throughput and latency of synthetic objects against the configured IPFS API")
+            (@arg sizes: --sizes +takes_value +use_delimiter "object sizes in bytes, defaults to 65536,1048576,67108864")
+            (@arg count: --count +takes_value "objects of each size, defaults to 5")
+            (@arg jobs: -j --jobs +takes_value "objects transferred concurrently, defaults to 8")
+            (@arg gateway: --gateway "also time reads through GIT_LFS_IPFS_GATEWAY")
+        )
+        (@subcommand status =>
+            (about: "show which local LFS objects are pinned on the IPFS node and the pin targets, what waits to be published or unpinned, and the last published root")
+        )
+        (@subcommand daemon =>
+            (about: "periodically republish the latest root, re-pin lost objects and prune expired pending deletes")
+            (@arg interval: --("interval-minutes") +takes_value "minutes between maintenance runs, defaults to 240")
+            (@arg grace_days: --("grace-days") +takes_value "days an object stays pinned after being marked, defaults to 14")
+        )
+        (@subcommand stats =>
+            (about: "show object counts, sizes, deduplicated size, growth of the local LFS store and published roots, and where objects are pinned")
+        )
+        (@subcommand history =>
+            (about: "list root CIDs published from this repository, newest first")
+        )
+        (@subcommand publish =>
+            (about: "link the objects uploaded since the last publish into a new root and publish it under an IPNS key")
+            (@arg key: --key +takes_value "IPNS key to publish under, defaults to the one last published under, or self")
+            (@arg dry_run: --("dry-run") "only list the objects that would be published")
+        )
+        (@subcommand rollback =>
+            (about: "republish a previously published root CID")
+            (@arg root: +required "root CID to republish")
+            (@arg key: --key +takes_value "IPNS key to publish under, defaults to the one last published under, or self")
+        )
+        (@subcommand restore =>
+            (about: "rebuild the local LFS store, pins and history from a published root, checking every object")
+            (@arg from_root: --("from-root") +takes_value +required "root CID to restore from")
+            (@arg key: --key +takes_value "IPNS key to record the root under, defaults to self")
+        )
+        (@subcommand diff =>
+            (about: "list the objects added, removed and relinked from one root to another")
+            (@arg from: +required "older root CID")
+            (@arg to: +required "newer root CID")
+        )
+        (@subcommand sync =>
+            (about: "move this node's pin from one root to a newer one, transferring only what changed")
+            (@arg from: +required "root CID the node has")
+            (@arg to: +required "root CID to catch up to")
+            (@arg fetch: --fetch "also download the added and relinked objects into the local LFS store")
+            (@arg unpin: --unpin "unpin the older root once the newer one is pinned")
+        )
+        (@subcommand tier =>
+            (about: "unpin objects that weren't used for a while from the local node, once the pin targets have them")
+            (@arg days: --days +takes_value "days since last use, defaults to GIT_LFS_IPFS_COLD_AFTER_DAYS, or 90")
+        )
+        (@subcommand seed =>
+            (about: "print what a collaborator's node needs to seed a root: its CID, key, this node's addresses and size")
+            (@arg root: +takes_value "root CID to seed, defaults to the latest published from this repository")
+            (@arg pin_request: --("pin-request") +takes_value "also write a pinning service API request for the root to this file")
+        )
+        (@subcommand tag =>
+            (about: "label published roots, e.g. with release versions")
+            (@subcommand add =>
+                (about: "tag a root CID, by default the most recently published one")
+                (@arg name: +required "name of the tag")
+                (@arg root: "root CID to tag")
+            )
+            (@subcommand list =>
+                (about: "list tags, newest first")
+            )
+        )
+        (@subcommand key =>
+            (about: "manage the IPNS key this repository publishes under")
+            (@subcommand rotate =>
+                (about: "publish the latest root under a new key and point this repository's config at it")
+                (@arg name: +required "name of the new key, generated if it doesn't exist")
+                (@arg redirect_days: --("redirect-days") +takes_value "keep the old key pointing at the new name for this many days")
+            )
+        )
+        (@subcommand share =>
+            (about: "share single objects through a gateway with links that expire")
+            (@subcommand create =>
+                (about: "print a signed link to an object, without revealing the rest of the repository")
+                (@arg oid: +required "OID of the object to share")
+                (@arg hours: --hours +takes_value "hours until the link expires, defaults to 24")
+                (@arg gateway: --gateway +takes_value "gateway the link points at, defaults to the first in GIT_LFS_IPFS_GATEWAY")
+                (@arg key: --key +takes_value "IPNS key to sign the link with, defaults to the signing key, or self")
+            )
+            (@subcommand check =>
+                (about: "check that a link hasn't expired and was signed by the trusted key")
+                (@arg url: +required "the link")
+                (@arg key: --key +takes_value "ID of the key links must be signed with, defaults to GIT_LFS_IPFS_TRUSTED_KEY, or this node's signing key")
+            )
+        )
+        (@subcommand receipt =>
+            (about: "check receipts that publish wrote to GIT_LFS_IPFS_RECEIPTS")
+            (@subcommand verify =>
+                (about: "check that receipts are signed by the trusted key and match the roots they name")
+                (@arg receipts: +required +multiple "receipt files")
+                (@arg key: --key +takes_value "ID of the key receipts must be signed with, defaults to GIT_LFS_IPFS_TRUSTED_KEY")
+            )
+        )
+        (@subcommand resolve =>
+            (about: "resolve an /ipfs/ or /ipns/ path")
+            (@arg path: +required "path to resolve")
+            (@arg no_recursive: --("no-recursive") "only resolve a single step")
+            (@arg nocache: --nocache "skip cached IPNS records")
+            (@arg dht_timeout: --("dht-timeout") +takes_value "seconds to spend looking for IPNS records")
+            (@arg dht_record_count: --("dht-record-count") +takes_value "stop after finding this many IPNS records")
+        )
+        (@subcommand watch =>
+            (about: "pin roots announced over pubsub for an IPNS name as they are published")
+            (@arg name: +required "IPNS name whose announcements to follow")
+            (@arg from: --from +takes_value +multiple "only trust announcements from these peer IDs")
+        )
+        (@subcommand migrate =>
+            (about: "upload every object in the local LFS store that the IPFS node hasn't pinned")
+            (@arg jobs: -j --jobs +takes_value "objects read and uploaded concurrently, defaults to 8")
+        )
+        (@subcommand mirror_from =>
+            (name: "mirror-from")
+            (about: "copy every object in the history from another LFS server into IPFS, and build a root of them")
+            (@arg endpoint: +required "LFS endpoint to copy from, usually <repository URL>/info/lfs")
+            (@arg jobs: -j --jobs +takes_value "objects downloaded concurrently, defaults to 4")
+        )
+        (@subcommand add_from_url =>
+            (name: "add-from-url")
+            (about: "stream an object's content from a URL into IPFS, checked against its pointer, and stage it for the next publish")
+            (@arg url: +required "where the content is published, such as a dataset's own mirror")
+            (@arg pointer: +required "pointer file of the object, or - to read it from stdin")
+        )
+        (@subcommand hooks =>
+            (about: "manage the git hooks that run git-lfs-ipfs at the right points of the git workflow")
+            (@subcommand install =>
+                (about: "install pre-push, post-checkout and post-merge hooks that publish after pushing and prefetch recent objects")
+                (@arg force: -f --force "replace hooks that weren't written by git-lfs or git-lfs-ipfs")
+            )
+        )
+        (@subcommand workspace =>
+            (about: "run a command in every repository listed in a workspace file")
+            (@arg file: --file +takes_value "workspace file listing one repository per line, defaults to GIT_LFS_IPFS_WORKSPACE")
+            (@arg command: +required possible_value[fetch verify prune publish] "command to run: fetch, pin verify, prune or publish")
+            (@arg args: +multiple +last "arguments passed on to the command")
+        )
+        (@subcommand pin =>
+            (about: "manage pins of LFS objects on the IPFS node")
+            (@subcommand verify =>
+                (about: "check that every local LFS object is pinned, re-pinning missing ones")
+                (@arg dry_run: --("dry-run") "only report objects that are not pinned")
+            )
+        )
+        (@subcommand unpin =>
+            (about: "mark objects for unpinning once their grace period is over")
+            (@arg oids: +required +multiple "OIDs of the objects to unpin")
+        )
+        (@subcommand undelete =>
+            (about: "keep objects that were marked for unpinning")
+            (@arg oids: +required +multiple "OIDs of the objects to keep")
+        )
+        (@subcommand prune =>
+            (about: "unpin objects whose grace period is over")
+            (@arg grace_days: --("grace-days") +takes_value "days an object stays pinned after being marked, defaults to 14")
+        )
     )
     .get_matches();
 
+    let applied =
+        profile::load(app_matches.value_of("profile")).unwrap_or_else(|err| error::exit(err));
+    logging::init(
+        app_matches.value_of("log_file").map(Into::into),
+        optional_value(&app_matches, "log_level"),
+    );
     let sys = System::new("git-lfs-ipfs");
+    ipfs::install_client_connector();
+    config::set_credential_helper(credential::helper);
 
     match app_matches.subcommand() {
+        ("init", Some(matches)) => {
+            init::Init::new(
+                matches.value_of("key").map(str::to_string),
+                matches.value_of("layout").and_then(|layout| layout.parse().ok()),
+                matches.is_present("paranoid"),
+                matches.is_present("compress"),
+                matches.is_present("global"),
+                !matches.is_present("yes"),
+                !matches.is_present("skip_test"),
+            )
+            .start();
+        }
         ("smudge", _) => {
             smudge::Smudge::default().start();
         }
-        ("clean", _) => {
-            clean::Clean::default().start();
+        ("clean", Some(matches)) => {
+            clean::Clean::new(matches.value_of("filename").unwrap().into()).start();
         }
         ("transfer", _) => {
             transfer::Transfer::default().start();
         }
+        ("gzip", Some(matches)) => match matches.subcommand() {
+            ("clean", _) => {
+                compress::Gzip::clean().start();
+            }
+            ("smudge", _) => {
+                compress::Gzip::smudge().start();
+            }
+            _ => {
+                info!("Unknown gzip command");
+                return;
+            }
+        },
+        ("fetch", Some(matches)) => match matches.value_of("at_tag") {
+            Some(tag) => {
+                fetch::FetchAtTag::new(tag.to_string(), matches.is_present("json")).start();
+            }
+            None => {
+                fetch::Fetch::new(
+                    matches.is_present("all"),
+                    optional_value(matches, "recent_days"),
+                    matches.is_present("recurse_submodules"),
+                    applied,
+                    matches.is_present("json"),
+                )
+                .start();
+            }
+        },
+        ("version", Some(matches)) => {
+            version::Version::new(matches.is_present("verbose"), matches.is_present("json"))
+                .start();
+        }
+        ("bench", Some(matches)) => {
+            bench::Bench::new(
+                optional_values(matches, "sizes"),
+                optional_value(matches, "count"),
+                optional_value(matches, "jobs"),
+                matches.is_present("gateway"),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("status", Some(matches)) => {
+            status::Status::new(matches.is_present("json")).start();
+        }
+        ("daemon", Some(matches)) => {
+            if matches.is_present("json") {
+                clap::Error::with_description(
+                    &messages::text("daemon.no-json", &[]),
+                    clap::ErrorKind::ArgumentConflict,
+                )
+                .exit();
+            }
+            daemon::Daemon::new(
+                optional_value(matches, "interval"),
+                optional_value(matches, "grace_days"),
+            )
+            .start();
+        }
+        ("stats", Some(matches)) => {
+            stats::Stats::new(matches.is_present("json")).start();
+        }
+        ("history", Some(matches)) => {
+            history::History::new(matches.is_present("json")).start();
+        }
+        ("rollback", Some(matches)) => {
+            history::Rollback::new(
+                matches.value_of("root").unwrap().to_string(),
+                matches.value_of("key").map(str::to_string),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("diff", Some(matches)) => {
+            diff::DiffRoots::new(
+                matches.value_of("from").unwrap().to_string(),
+                matches.value_of("to").unwrap().to_string(),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("sync", Some(matches)) => {
+            diff::SyncRoots::new(
+                matches.value_of("from").unwrap().to_string(),
+                matches.value_of("to").unwrap().to_string(),
+                matches.is_present("fetch"),
+                matches.is_present("unpin"),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("tier", Some(matches)) => {
+            tier::Demote::new(
+                optional_value(matches, "days"),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("restore", Some(matches)) => {
+            restore::Restore::new(
+                matches.value_of("from_root").unwrap().to_string(),
+                matches.value_of("key").map(str::to_string),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("tag", Some(matches)) => match matches.subcommand() {
+            ("add", Some(matches)) => {
+                history::TagRoot::new(
+                    matches.value_of("name").unwrap().to_string(),
+                    matches.value_of("root").map(str::to_string),
+                    matches.is_present("json"),
+                )
+                .start();
+            }
+            ("list", Some(matches)) => {
+                history::Tags::new(matches.is_present("json")).start();
+            }
+            _ => {
+                info!("Unknown tag command");
+                return;
+            }
+        },
+        ("key", Some(matches)) => match matches.subcommand() {
+            ("rotate", Some(matches)) => {
+                key::Rotate::new(
+                    matches.value_of("name").unwrap().to_string(),
+                    optional_value(matches, "redirect_days"),
+                    matches.is_present("json"),
+                )
+                .start();
+            }
+            _ => {
+                info!("Unknown key command");
+                return;
+            }
+        },
+        ("share", Some(matches)) => match matches.subcommand() {
+            ("create", Some(matches)) => {
+                share::Create::new(
+                    matches.value_of("oid").unwrap().to_string(),
+                    optional_value(matches, "hours"),
+                    matches.value_of("gateway").map(str::to_string),
+                    matches.value_of("key").map(str::to_string),
+                    matches.is_present("json"),
+                )
+                .start();
+            }
+            ("check", Some(matches)) => {
+                share::Check::new(
+                    matches.value_of("url").unwrap().to_string(),
+                    matches.value_of("key").map(str::to_string),
+                    matches.is_present("json"),
+                )
+                .start();
+            }
+            _ => {
+                info!("Unknown share command");
+                return;
+            }
+        },
+        ("receipt", Some(matches)) => match matches.subcommand() {
+            ("verify", Some(matches)) => {
+                receipt::Verify::new(
+                    matches
+                        .values_of("receipts")
+                        .unwrap()
+                        .map(std::path::PathBuf::from)
+                        .collect(),
+                    matches.value_of("key").map(str::to_string),
+                    matches.is_present("json"),
+                )
+                .start();
+            }
+            _ => {
+                info!("Unknown receipt command");
+                return;
+            }
+        },
+        ("resolve", Some(matches)) => {
+            let defaults = ipfs::ResolveOptions::from_config();
+            resolve::Resolve::new(
+                matches.value_of("path").unwrap().to_string(),
+                ipfs::ResolveOptions {
+                    recursive: !matches.is_present("no_recursive"),
+                    nocache: matches.is_present("nocache"),
+                    dht_timeout: optional_value(matches, "dht_timeout")
+                        .map(std::time::Duration::from_secs)
+                        .or(defaults.dht_timeout),
+                    dht_record_count: optional_value(matches, "dht_record_count")
+                        .or(defaults.dht_record_count),
+                },
+            )
+            .start();
+        }
+        ("watch", Some(matches)) => {
+            watch::Watch::new(
+                matches.value_of("name").unwrap().to_string(),
+                matches
+                    .values_of("from")
+                    .map(|from| from.map(str::to_string).collect())
+                    .unwrap_or_default(),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("migrate", Some(matches)) => {
+            migrate::Migrate::new(
+                optional_value(matches, "jobs"),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("publish", Some(matches)) => {
+            stage::Publish::new(
+                matches.value_of("key").map(str::to_string),
+                matches.is_present("dry_run"),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("seed", Some(matches)) => {
+            seed::Seed::new(
+                matches.value_of("root").map(str::to_string),
+                matches.value_of("pin_request").map(std::path::PathBuf::from),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("mirror-from", Some(matches)) => {
+            mirror::MirrorFrom::new(
+                matches.value_of("endpoint").unwrap().to_string(),
+                optional_value(matches, "jobs"),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("add-from-url", Some(matches)) => {
+            mirror::AddFromUrl::new(
+                matches.value_of("url").unwrap().to_string(),
+                matches.value_of("pointer").unwrap().to_string(),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("hooks", Some(matches)) => match matches.subcommand() {
+            ("install", Some(matches)) => {
+                hooks::Install::new(matches.is_present("force"), matches.is_present("json"))
+                    .start();
+            }
+            _ => {
+                info!("Unknown hooks command");
+                return;
+            }
+        },
+        ("workspace", Some(matches)) => {
+            workspace::Workspace::new(
+                matches.value_of("file").map(std::path::PathBuf::from),
+                matches.value_of("command").unwrap().to_string(),
+                matches
+                    .values_of("args")
+                    .map(|args| args.map(str::to_string).collect())
+                    .unwrap_or_default(),
+                applied,
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("pin", Some(matches)) => match matches.subcommand() {
+            ("verify", Some(matches)) => {
+                verify::Verify::new(!matches.is_present("dry_run"), matches.is_present("json"))
+                    .start();
+            }
+            _ => {
+                info!("Unknown pin command");
+                return;
+            }
+        },
+        ("unpin", Some(matches)) => {
+            prune::Unpin::new(
+                values_t!(matches, "oids", String).unwrap(),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("undelete", Some(matches)) => {
+            prune::Undelete::new(
+                values_t!(matches, "oids", String).unwrap(),
+                matches.is_present("json"),
+            )
+            .start();
+        }
+        ("prune", Some(matches)) => {
+            prune::Prune::new(
+                optional_value(matches, "grace_days"),
+                matches.is_present("json"),
+            )
+            .start();
+        }
         _ => {
             info!("Unknown command");
             return;
         }
     };
     sys.run();
+    timings::write();
+}
+
+/// The value of an optional argument. A value that doesn't parse exits with clap's usage error
+/// instead of silently falling back to the default.
+fn optional_value<T: std::str::FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> {
+    match value_t!(matches, name, T) {
+        Ok(value) => Some(value),
+        Err(ref err) if err.kind == clap::ErrorKind::ArgumentNotFound => None,
+        Err(err) => err.exit(),
+    }
+}
+
+/// Like [`optional_value`] for arguments taking several values
+fn optional_values<T: std::str::FromStr>(
+    matches: &clap::ArgMatches,
+    name: &str,
+) -> Option<Vec<T>> {
+    match values_t!(matches, name, T) {
+        Ok(values) => Some(values),
+        Err(ref err) if err.kind == clap::ErrorKind::ArgumentNotFound => None,
+        Err(err) => err.exit(),
+    }
 }
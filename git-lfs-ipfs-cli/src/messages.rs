@@ -0,0 +1,152 @@
+//! What the CLI tells its user, looked up by ID in a message catalog so that it can be
+//! translated. English is built in from `messages/en.json`. A translation is a JSON object
+//! of the same IDs in `<language>.json`, in the directory named by `GIT_LFS_IPFS_MESSAGES`;
+//! messages it leaves out stay English. The language is taken from `GIT_LFS_IPFS_LANG`, else
+//! `LC_ALL`, `LC_MESSAGES` or `LANG`, like git's own.
+//!
+//! Output meant for programs stays as it is: `--json`, the transfer protocol and listings of
+//! OIDs, CIDs and paths. So do logs, clap's usage text, and the logger's own complaint that it
+//! couldn't write its file, since loading a catalog may log.
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use lazy_static::lazy_static;
+
+/// Directory of translated catalogs
+const MESSAGES_ENV: &str = "GIT_LFS_IPFS_MESSAGES";
+/// Language to use, if not the one of the locale
+const LANG_ENV: &str = "GIT_LFS_IPFS_LANG";
+
+type Catalog = BTreeMap<String, String>;
+
+lazy_static! {
+    static ref ENGLISH: Catalog = serde_json::from_str(include_str!("messages/en.json"))
+        .expect("the English catalog is valid");
+    static ref TRANSLATION: Catalog = load_translation();
+}
+
+/// The user's language, or `None` for the C locale
+fn language() -> Option<String> {
+    [LANG_ENV, "LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .filter(|language| language != "C" && language != "POSIX")
+}
+
+/// Catalogs to try for a locale like `pt_BR.UTF-8`, most specific first: `pt_BR`, then `pt`
+fn catalog_names(locale: &str) -> Vec<String> {
+    let language = locale.split(|c| c == '.' || c == '@').next().unwrap_or_default();
+    let mut names = vec![language.to_string()];
+    if let Some(base) = language.split('_').next().filter(|base| *base != language) {
+        names.push(base.to_string());
+    }
+    names.retain(|name| !name.is_empty());
+    names
+}
+
+fn load_translation() -> Catalog {
+    let (dir, locale) = match (std::env::var_os(MESSAGES_ENV), language()) {
+        (Some(dir), Some(locale)) => (PathBuf::from(dir), locale),
+        _ => return Catalog::new(),
+    };
+    for name in catalog_names(&locale) {
+        let path = dir.join(format!("{}.json", name));
+        if !path.exists() {
+            continue;
+        }
+        match std::fs::read(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|json| serde_json::from_slice(&json).map_err(|err| err.to_string()))
+        {
+            Ok(catalog) => return catalog,
+            Err(err) => warn!("Ignoring the catalog {}: {}", path.display(), err),
+        }
+    }
+    Catalog::new()
+}
+
+/// How many arguments `template` takes
+fn placeholders(template: &str) -> usize {
+    template.matches("{}").count()
+}
+
+/// Replaces each `{}` in `template` with the next of `args`
+fn format(template: &str, args: &[&dyn Display]) -> String {
+    let mut parts = template.split("{}");
+    let mut formatted = parts.next().unwrap_or_default().to_string();
+    for (i, part) in parts.enumerate() {
+        if let Some(arg) = args.get(i) {
+            formatted.push_str(&arg.to_string());
+        }
+        formatted.push_str(part);
+    }
+    formatted
+}
+
+/// The message `id` in the user's language, with its `{}` replaced by `args` in order. A
+/// translation that doesn't take the same arguments as the English message is ignored.
+pub fn text(id: &str, args: &[&dyn Display]) -> String {
+    let english = ENGLISH.get(id).map(String::as_str);
+    let template = TRANSLATION
+        .get(id)
+        .map(String::as_str)
+        .filter(|translated| {
+            english.map_or(true, |english| placeholders(translated) == placeholders(english))
+        })
+        .or(english)
+        .unwrap_or(id);
+    format(template, args)
+}
+
+/// Like [`text`] for messages whose English comes from elsewhere, like the library's hints
+pub fn text_or(id: &str, english: &str) -> String {
+    TRANSLATION
+        .get(id)
+        .or_else(|| ENGLISH.get(id))
+        .map_or_else(|| english.to_string(), String::clone)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn english_messages_take_their_arguments_in_order() {
+        assert_eq!(
+            "Published QmRoot with 2 new objects under self",
+            text("publish.published", &[&"QmRoot", &2, &"self"])
+        );
+        assert_eq!("missing.id", text("missing.id", &[]));
+        assert_eq!(1, placeholders(&ENGLISH["exit.hint"]));
+    }
+
+    #[test]
+    fn every_message_used_is_in_the_english_catalog() {
+        let used = regex::Regex::new(r#"messages::text\(\s*"([^"]+)""#).unwrap();
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        for entry in std::fs::read_dir(src).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().map_or(true, |extension| extension != "rs") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            for id in used.captures_iter(&source) {
+                assert!(
+                    ENGLISH.contains_key(&id[1]),
+                    "{} uses {}, which en.json lacks",
+                    path.display(),
+                    &id[1]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locales_fall_back_to_their_language() {
+        assert_eq!(vec!["pt_BR", "pt"], catalog_names("pt_BR.UTF-8"));
+        assert_eq!(vec!["de"], catalog_names("de"));
+        assert_eq!(vec!["sr_RS", "sr"], catalog_names("sr_RS@latin"));
+    }
+}
@@ -0,0 +1,189 @@
+use actix::prelude::*;
+use bytes::Bytes;
+use futures::{future, prelude::*, stream, sync::mpsc};
+use serde_derive::Serialize;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use crate::error::{self, CliError};
+use crate::{messages, output, repo};
+use git_lfs_ipfs_lib::{ipfs, spec::Object, LfsIpfs};
+
+/// Objects read or uploaded at once when `--jobs` isn't given
+const DEFAULT_JOBS: usize = 8;
+
+/// Outcome of copying the local LFS store to IPFS
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub uploaded: usize,
+    pub skipped: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Size of the chunks objects are read and uploaded in
+const CHUNK_SIZE: usize = 256 * 1024;
+/// Chunks of an object read ahead of its upload
+const READ_AHEAD: usize = 4;
+
+/// Streams an object from disk, read on its own thread so that disk reads run in parallel
+/// with other objects' uploads instead of blocking the event loop. Only a few chunks of it are
+/// held at a time, however large it is. It isn't hashed here: the daemon hashes it while
+/// storing it, and the upload fails if that doesn't match the OID, so the content only passes
+/// through once.
+fn read_object(object: &Object) -> impl Stream<Item = Bytes, Error = io::Error> {
+    read_file(
+        repo::lfs_object_path(&object.oid)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string())),
+    )
+}
+
+fn read_file(path: io::Result<PathBuf>) -> impl Stream<Item = Bytes, Error = io::Error> {
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    std::thread::spawn(move || {
+        let file = path.and_then(File::open);
+        let mut file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                let _ = tx.send(Err(err)).wait();
+                return;
+            }
+        };
+        let mut tx = tx;
+        loop {
+            let mut buf = vec![0; CHUNK_SIZE];
+            let chunk = file.read(&mut buf).map(|read| {
+                buf.truncate(read);
+                Bytes::from(buf)
+            });
+            let done = chunk.as_ref().map_or(true, Bytes::is_empty);
+            // The upload stopped listening, e.g. because it failed
+            tx = match tx.send(chunk).wait() {
+                Ok(tx) => tx,
+                Err(_) => return,
+            };
+            if done {
+                return;
+            }
+        }
+    });
+    rx.then(|chunk| match chunk {
+        Ok(chunk) => chunk,
+        Err(()) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "reading was cancelled",
+        )),
+    })
+    .filter(|chunk| !chunk.is_empty())
+}
+
+/// Uploads every local LFS object that the node hasn't pinned yet, as a pipeline of
+/// walking the store, skipping pinned objects, reading and uploading, with at most
+/// `jobs` objects in flight.
+pub fn migrate(jobs: usize) -> impl Future<Item = Report, Error = CliError> {
+    future::result(repo::local_objects()).and_then(move |objects| {
+        ipfs::pin_ls()
+            .map_err(CliError::IpfsApiError)
+            .and_then(move |pins| {
+                let pinned: HashSet<String> = pins.keys.keys().cloned().collect();
+                let total = objects.len();
+                let (pending, already_pinned): (Vec<Object>, Vec<Object>) =
+                    objects.into_iter().partition(|object| {
+                        ipfs::oid_to_cid(cid::Codec::DagProtobuf, &object.oid)
+                            .wait()
+                            .map(|cid| !pinned.contains(&cid.to_string()))
+                            .unwrap_or(true)
+                    });
+                info!(
+                    "Migrating {} of {} objects, {} already pinned",
+                    pending.len(),
+                    total,
+                    already_pinned.len()
+                );
+                let report = Report {
+                    skipped: already_pinned.len(),
+                    ..Report::default()
+                };
+                stream::iter_ok::<_, CliError>(pending)
+                    .map(|object| {
+                        LfsIpfs::new()
+                            .upload_object(&object.oid, object.size, read_object(&object))
+                            .then(move |result| match result {
+                                Ok(_) => Ok(object),
+                                Err(err) => Err((object, err.to_string())),
+                            })
+                            .then(Ok::<_, CliError>)
+                    })
+                    .buffer_unordered(jobs.max(1))
+                    .fold(report, |mut report, result| {
+                        match result {
+                            Ok(object) => {
+                                debug!("Uploaded {}", object.oid);
+                                report.uploaded += 1;
+                            }
+                            Err((object, err)) => report.failed.push((object.oid, err)),
+                        }
+                        future::ok::<_, CliError>(report)
+                    })
+            })
+    })
+}
+
+pub struct Migrate {
+    jobs: usize,
+    json: bool,
+}
+
+impl Migrate {
+    pub fn new(jobs: Option<usize>, json: bool) -> Self {
+        Self {
+            jobs: jobs.unwrap_or(DEFAULT_JOBS),
+            json,
+        }
+    }
+}
+
+impl Actor for Migrate {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Migrate as Actor>::Context) {
+        ctx.wait(
+            actix::fut::wrap_future(migrate(self.jobs)).then(|result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(report) if actor.json => output::print_json("migrate", &report),
+                    Ok(report) => {
+                        println!(
+                            "{}",
+                            messages::text("migrate.uploaded", &[&report.uploaded])
+                        );
+                        println!("{}", messages::text("migrate.skipped", &[&report.skipped]));
+                        for (oid, err) in &report.failed {
+                            println!("{}", messages::text("migrate.failed", &[oid, err]));
+                        }
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn files_are_streamed_in_chunks() {
+        let path = std::env::temp_dir().join(format!("migrate-test-{}", std::process::id()));
+        let content: Vec<u8> = (0..CHUNK_SIZE * 2 + 3).map(|i| i as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+        let chunks: Vec<Bytes> = read_file(Ok(path.clone())).collect().wait().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(3, chunks.len());
+        assert_eq!(content, chunks.concat());
+        assert!(read_file(Ok(path)).collect().wait().is_err());
+    }
+}
@@ -0,0 +1,516 @@
+use actix::prelude::*;
+use actix_web::{client, http::header, HttpMessage};
+use futures::{future, prelude::*, stream};
+use serde_derive::Serialize;
+use url::Url;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::Duration;
+
+use crate::credential;
+use crate::error::{self, CliError};
+use crate::{messages, output, repo, stage};
+use git_lfs_ipfs_lib::{
+    config,
+    error::Error,
+    ipfs,
+    pointer::{HashAlgorithm, Mode, Pointer},
+    proxy,
+    spec::{
+        lfs::{Action, Actions, BatchRequest, BatchResponse, ObjectResponse, Operation},
+        manifest::Manifest,
+        Object, GIT_LFS_CONTENT_TYPE,
+    },
+    LfsIpfs,
+};
+
+/// Objects downloaded at once when `--jobs` isn't given
+const DEFAULT_JOBS: usize = 4;
+
+/// Objects asked for per batch request, as many as LFS servers commonly accept
+const BATCH_SIZE: usize = 100;
+
+/// Redirects a download follows before it fails
+const MAX_REDIRECTS: usize = 10;
+
+/// Outcome of mirroring another LFS server
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// Root linking the manifest of every mirrored object, if any were
+    pub root: Option<String>,
+    pub mirrored: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Asks the LFS server at `endpoint` where to download `objects` from.
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md
+fn batch(
+    endpoint: &Url,
+    authorization: Option<String>,
+    objects: Vec<Object>,
+) -> impl Future<Item = BatchResponse, Error = CliError> {
+    let url = format!("{}/objects/batch", endpoint.as_str().trim_end_matches('/'));
+    debug!("Sending batch request for {} objects to {}", objects.len(), url);
+    proxy::route(endpoint);
+    let mut request = client::post(url);
+    request
+        .header(header::ACCEPT, GIT_LFS_CONTENT_TYPE)
+        .header(header::CONTENT_TYPE, GIT_LFS_CONTENT_TYPE);
+    if let Some(authorization) = authorization {
+        request.header(header::AUTHORIZATION, authorization);
+    }
+    future::result(
+        serde_json::to_vec(&BatchRequest::new(Operation::Download, objects))
+            .map_err(CliError::SerdeJsonError),
+    )
+    .and_then(move |body| {
+        request
+            .body(body)
+            .unwrap()
+            .send()
+            .timeout(Duration::from_secs(600))
+            .map_err(|err| CliError::LfsServerError(err.to_string()))
+    })
+    .and_then(|res| {
+        if res.status().is_success() {
+            future::Either::A(
+                res.json()
+                    .limit(usize::max_value())
+                    .map_err(|err| CliError::LfsServerError(err.to_string())),
+            )
+        } else {
+            future::Either::B(future::err(CliError::LfsServerError(format!(
+                "batch request failed with {}",
+                res.status()
+            ))))
+        }
+    })
+}
+
+/// Sends a GET request for `url` with `headers`, following up to `redirects` redirects, like
+/// those of servers that hand downloads off to separate storage. Headers, which may hold
+/// credentials, are only sent on to the origin they were given for.
+fn download(
+    url: Url,
+    headers: HashMap<String, String>,
+    redirects: usize,
+) -> Box<Future<Item = client::ClientResponse, Error = String>> {
+    proxy::route(&url);
+    let mut request = client::get(url.as_str());
+    for (name, value) in &headers {
+        request.header(name.as_str(), value.as_str());
+    }
+    Box::new(
+        request
+            .finish()
+            .unwrap()
+            .send()
+            .timeout(Duration::from_secs(3600))
+            .map_err(|err| err.to_string())
+            .and_then(move |res| {
+                if !res.status().is_redirection() {
+                    return future::Either::A(future::ok(res));
+                }
+                let location = res
+                    .headers()
+                    .get(header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok());
+                match location {
+                    Some(_) if redirects == 0 => future::Either::A(future::err(format!(
+                        "the download was redirected more than {} times",
+                        MAX_REDIRECTS
+                    ))),
+                    Some(location) => {
+                        debug!("Following the redirect from {} to {}", url, location);
+                        let headers = if location.origin() == url.origin() {
+                            headers
+                        } else {
+                            HashMap::new()
+                        };
+                        future::Either::B(download(location, headers, redirects - 1))
+                    }
+                    None => future::Either::A(future::err(format!(
+                        "the download was redirected with {}, but not to a valid location",
+                        res.status()
+                    ))),
+                }
+            }),
+    )
+}
+
+/// Downloads `object` as `action` says and adds it to IPFS, returning its UnixFS CID, and
+/// passing how much of it was added so far to `on_progress` as it goes, if given.
+fn mirror_object(
+    object: Object,
+    action: Action,
+    on_progress: Option<Box<FnMut(u64)>>,
+) -> impl Future<Item = (Object, cid::Cid), Error = (Object, String)> {
+    let headers = action.header.unwrap_or_default();
+    download(action.href, headers, MAX_REDIRECTS)
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(res)
+            } else {
+                Err(format!("download failed with {}", res.status()))
+            }
+        })
+        .then(move |res| match res {
+            Ok(res) => future::Either::A(
+                match on_progress {
+                    Some(mut on_progress) => {
+                        future::Either::A(LfsIpfs::new().import_file_with_progress(
+                            &object,
+                            res.payload(),
+                            move |bytes| on_progress(bytes),
+                        ))
+                    }
+                    None => future::Either::B(LfsIpfs::new().import_file(&object, res.payload())),
+                }
+                .then(move |result| match result {
+                    Ok(cid) => Ok((object, cid)),
+                    Err(err) => Err((object, err.to_string())),
+                }),
+            ),
+            Err(err) => future::Either::B(future::err((object, err))),
+        })
+}
+
+/// Copies every object referenced in this repository's history from the LFS server at
+/// `endpoint` into IPFS, with at most `jobs` objects in flight, and builds a root whose
+/// manifest maps their OIDs to where they are stored.
+pub fn mirror(endpoint: Url, jobs: usize) -> impl Future<Item = Report, Error = CliError> {
+    let authorization = match config::get().credentials_for(&endpoint) {
+        Some(credentials) => Some(credentials.basic_authorization()),
+        None => credential::fill(&endpoint)
+            .unwrap_or_else(|err| {
+                warn!("Could not ask git for credentials for {}: {}", endpoint, err);
+                None
+            })
+            .map(|credentials| credentials.basic_authorization()),
+    };
+    future::result(repo::history_objects()).and_then(move |objects| {
+        info!("Mirroring {} objects from {}", objects.len(), endpoint);
+        let batches: Vec<Vec<Object>> = objects
+            .chunks(BATCH_SIZE)
+            .map(|batch| batch.to_vec())
+            .collect();
+        stream::iter_ok::<_, CliError>(batches)
+            .and_then(move |objects| batch(&endpoint, authorization.clone(), objects))
+            .map(|response| stream::iter_ok(response.objects))
+            .flatten()
+            .map(|response| {
+                match response {
+                    ObjectResponse::Success {
+                        object,
+                        actions: Actions::Download { download },
+                        ..
+                    } => future::Either::A(mirror_object(object, download, None)),
+                    ObjectResponse::Success { object, .. } => future::Either::B(future::err((
+                        object,
+                        "the server offered no download".to_string(),
+                    ))),
+                    ObjectResponse::Error { object, error } => {
+                        future::Either::B(future::err((object, error.message)))
+                    }
+                }
+                .then(Ok::<_, CliError>)
+            })
+            .buffer_unordered(jobs.max(1))
+            .fold(
+                (Manifest::new(), Report::default()),
+                |(mut manifest, mut report), result| {
+                    match result {
+                        Ok((object, cid)) => {
+                            match manifest.insert_unixfs(&object, cid.to_string()) {
+                                Ok(()) => {
+                                    debug!("Mirrored {} as {}", object.oid, cid);
+                                    report.mirrored += 1;
+                                }
+                                Err(err) => report.failed.push((object.oid, err.to_string())),
+                            }
+                        }
+                        Err((object, err)) => report.failed.push((object.oid, err)),
+                    }
+                    future::ok::<_, CliError>((manifest, report))
+                },
+            )
+            .and_then(|(manifest, mut report)| {
+                if manifest.objects.is_empty() {
+                    return future::Either::A(future::ok(report));
+                }
+                future::Either::B(
+                    ipfs::root_put(&manifest, config::get().layout)
+                        .map_err(CliError::IpfsApiError)
+                        .map(move |root| {
+                            report.root = Some(root.to_string());
+                            report
+                        }),
+                )
+            })
+    })
+}
+
+/// Mirrors the objects of an existing LFS server onto IPFS.
+pub struct MirrorFrom {
+    endpoint: String,
+    jobs: usize,
+    json: bool,
+}
+
+impl MirrorFrom {
+    pub fn new(endpoint: String, jobs: Option<usize>, json: bool) -> Self {
+        Self {
+            endpoint,
+            jobs: jobs.unwrap_or(DEFAULT_JOBS),
+            json,
+        }
+    }
+}
+
+impl Actor for MirrorFrom {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <MirrorFrom as Actor>::Context) {
+        let endpoint = match Url::parse(&self.endpoint) {
+            Ok(endpoint) => endpoint,
+            Err(err) => error::exit(CliError::LfsServerError(format!(
+                "{} is not a valid endpoint: {}",
+                self.endpoint, err
+            ))),
+        };
+        ctx.wait(
+            actix::fut::wrap_future(mirror(endpoint, self.jobs)).then(
+                |result, actor: &mut Self, _ctx| {
+                    System::current().stop();
+                    match result {
+                        Ok(report) if actor.json => output::print_json("mirror-from", &report),
+                        Ok(report) => {
+                            println!(
+                                "{}",
+                                messages::text("mirror-from.mirrored", &[&report.mirrored])
+                            );
+                            for (oid, err) in &report.failed {
+                                println!("{}", messages::text("mirror-from.failed", &[oid, err]));
+                            }
+                            if let Some(root) = &report.root {
+                                println!("{}", messages::text("mirror-from.root", &[root]));
+                                println!("{}", messages::text("mirror-from.download", &[root]));
+                            }
+                        }
+                        Err(err) => error::exit(err),
+                    }
+                    actix::fut::ok(())
+                },
+            ),
+        );
+    }
+}
+
+/// What `add-from-url` reports
+#[derive(Debug, Serialize)]
+pub struct Added {
+    pub oid: String,
+    pub size: u64,
+    pub url: String,
+    pub cid: String,
+}
+
+/// Streams the content of the object a pointer describes from a URL, such as a public dataset's
+/// own mirror, into IPFS as a UnixFS file, and stages it for the next publish. The content is
+/// checked against the pointer on the way, and never stored on this machine's disk.
+pub struct AddFromUrl {
+    url: String,
+    /// Path of the pointer file, or `-` for stdin
+    pointer: String,
+    json: bool,
+}
+
+impl AddFromUrl {
+    pub fn new(url: String, pointer: String, json: bool) -> Self {
+        Self { url, pointer, json }
+    }
+
+    fn object(&self) -> Result<Object, CliError> {
+        let mut text = String::new();
+        if self.pointer == "-" {
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .map_err(CliError::Io)?;
+        } else {
+            text = std::fs::read_to_string(&self.pointer).map_err(CliError::Io)?;
+        }
+        let pointer = Pointer::parse(&text, Mode::Strict).map_err(CliError::IpfsApiError)?;
+        if pointer.algorithm != HashAlgorithm::Sha256 {
+            return Err(CliError::IpfsApiError(Error::PointerParseError(
+                "Only sha256 objects can be added from a URL",
+            )));
+        }
+        Ok(Object {
+            oid: pointer.oid,
+            size: pointer.size,
+        })
+    }
+}
+
+impl Actor for AddFromUrl {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <AddFromUrl as Actor>::Context) {
+        let object = self.object().unwrap_or_else(|err| error::exit(err));
+        let url = Url::parse(&self.url).unwrap_or_else(|err| {
+            error::exit(CliError::AddFromUrlFailed(
+                self.url.clone(),
+                format!("it is not a valid URL: {}", err),
+            ))
+        });
+        let mut action = Action::new(url.clone());
+        if let Some(credentials) = config::credentials_for(&url) {
+            action = action.header(
+                header::AUTHORIZATION.as_str(),
+                &credentials.basic_authorization(),
+            );
+        }
+        info!("Adding {} from {}", object.oid, url);
+        // The daemon reports every chunk it reads, so the line is rewritten in place
+        let on_progress: Option<Box<FnMut(u64)>> = if self.json {
+            None
+        } else {
+            let size = object.size;
+            Some(Box::new(move |bytes| {
+                eprint!("\r{}", messages::text("add-from-url.progress", &[&bytes, &size]))
+            }))
+        };
+        let add = mirror_object(object, action, on_progress)
+            .map_err({
+                let url = url.to_string();
+                move |(_, err)| CliError::AddFromUrlFailed(url, err)
+            })
+            .and_then(move |(object, cid)| {
+                stage::stage_unixfs(&object, &cid).map(move |_| Added {
+                    oid: object.oid,
+                    size: object.size,
+                    url: url.to_string(),
+                    cid: cid.to_string(),
+                })
+            });
+        ctx.wait(actix::fut::wrap_future(add).then(
+            |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                if !actor.json {
+                    eprintln!();
+                }
+                match result {
+                    Ok(added) if actor.json => output::print_json("add-from-url", &added),
+                    Ok(added) => {
+                        println!(
+                            "{}",
+                            messages::text(
+                                "add-from-url.added",
+                                &[&added.oid, &added.url, &added.cid]
+                            )
+                        );
+                        println!("{}", messages::text("add-from-url.publish", &[]));
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{test::TestServer, HttpRequest, HttpResponse};
+
+    /// Redirects `/start` to `/content` on the same origin, and `/elsewhere` to `/content` on
+    /// another one, `localhost` rather than `127.0.0.1`. `/content` answers whether the
+    /// request had credentials.
+    fn storage(req: &HttpRequest) -> HttpResponse {
+        let port = req
+            .connection_info()
+            .host()
+            .rsplit(':')
+            .next()
+            .unwrap()
+            .to_string();
+        match req.path() {
+            "/start" => HttpResponse::Found()
+                .header(header::LOCATION, "/content")
+                .finish(),
+            "/elsewhere" => HttpResponse::TemporaryRedirect()
+                .header(
+                    header::LOCATION,
+                    format!("http://localhost:{}/content", port),
+                )
+                .finish(),
+            "/loop" => HttpResponse::Found()
+                .header(header::LOCATION, "/loop")
+                .finish(),
+            "/content" if req.headers().contains_key(header::AUTHORIZATION) => {
+                HttpResponse::Ok().body("with credentials")
+            }
+            "/content" => HttpResponse::Ok().body("without credentials"),
+            _ => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    #[test]
+    fn downloads_follow_redirects() {
+        let mut srv = TestServer::new(|app| app.handler(storage));
+        let credentials: HashMap<String, String> = vec![(
+            header::AUTHORIZATION.to_string(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_string(),
+        )]
+        .into_iter()
+        .collect();
+        let mut body = |path: &str| {
+            let url = Url::parse(&srv.url(path)).unwrap();
+            srv.execute(
+                download(url, credentials.clone(), MAX_REDIRECTS).and_then(|res| {
+                    res.body()
+                        .map(|body| String::from_utf8_lossy(&body).into_owned())
+                        .map_err(|err| err.to_string())
+                }),
+            )
+        };
+        assert_eq!(Ok("with credentials".to_string()), body("/start"));
+        assert_eq!(Ok("without credentials".to_string()), body("/elsewhere"));
+        assert!(body("/loop").unwrap_err().contains("redirected more than"));
+    }
+
+    #[test]
+    fn pointers_are_read_from_a_file() {
+        let path = std::env::temp_dir().join(format!("add-from-url-test-{}", std::process::id()));
+        let add = |pointer: String| {
+            std::fs::write(&path, pointer).unwrap();
+            let object = AddFromUrl::new(
+                "https://example.com/dataset".to_string(),
+                path.to_string_lossy().into_owned(),
+                false,
+            )
+            .object();
+            std::fs::remove_file(&path).unwrap();
+            object
+        };
+        let sha256 = add(format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12\n",
+            "a".repeat(64)
+        ))
+        .unwrap();
+        assert_eq!("a".repeat(64), sha256.oid);
+        assert_eq!(12, sha256.size);
+        let sha512 = add(format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha512:{}\nsize 12\n",
+            "a".repeat(128)
+        ));
+        assert!(sha512.is_err());
+        assert!(add("not a pointer".to_string()).is_err());
+    }
+
+    #[test]
+    fn the_publish_hint_names_the_binary() {
+        assert!(messages::text("add-from-url.publish", &[]).contains("`git-lfs-ipfs-cli publish`"));
+    }
+}
@@ -0,0 +1,27 @@
+use serde::Serialize;
+use serde_derive::Serialize;
+
+/// Version of the `--json` output schemas. Fields may be added within a version, but are
+/// only renamed or removed along with a bump.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// Every `--json` output is a single object naming the command and schema version it follows
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    version: u32,
+    command: &'a str,
+    data: &'a T,
+}
+
+/// Prints the result of `command` as a single line of JSON on stdout.
+pub fn print_json<T: Serialize>(command: &str, data: &T) {
+    println!(
+        "{}",
+        serde_json::to_string(&Envelope {
+            version: JSON_SCHEMA_VERSION,
+            command,
+            data,
+        })
+        .expect("Failed to serialize output")
+    );
+}
@@ -0,0 +1,207 @@
+use bytes::Bytes;
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::error::CliError;
+use crate::messages;
+use git_lfs_ipfs_lib::config::Config;
+
+/// clamd refuses streams chunked larger than its StreamMaxLength, so stay well below the default
+const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Leading bytes of the formats most often kept out of repositories, and their MIME types.
+/// https://en.wikipedia.org/wiki/List_of_file_signatures
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"%PDF-", "application/pdf"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"OggS", "application/ogg"),
+    (b"ID3", "audio/mpeg"),
+];
+
+/// Refuses files with a denied extension, before any of their content is read.
+pub fn check_path(config: &Config, path: &Path) -> Result<(), CliError> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension {
+        Some(extension) if config.denied_extensions.contains(&extension) => Err(
+            CliError::PolicyViolation(messages::text("policy.denied-extension", &[&extension])),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// The MIME type of content starting with `head`, if it is one of the known [`SIGNATURES`]
+fn sniff_mime_type(head: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Refuses content whose first bytes mark it as a denied MIME type.
+pub fn check_mime_type(config: &Config, head: &[u8]) -> Result<(), CliError> {
+    let mime_type = match sniff_mime_type(head) {
+        Some(mime_type) => mime_type,
+        None => return Ok(()),
+    };
+    let denied = config.denied_mime_types.iter().any(|denied| {
+        denied == mime_type
+            || (denied.ends_with("/*") && mime_type.starts_with(&denied[..denied.len() - 1]))
+    });
+    if denied {
+        Err(CliError::PolicyViolation(messages::text(
+            "policy.denied-mime-type",
+            &[&mime_type],
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Refuses content once it grows past the maximum upload size.
+pub fn check_size(config: &Config, size: u64) -> Result<(), CliError> {
+    match config.max_upload_size {
+        Some(max_upload_size) if size > max_upload_size => Err(CliError::PolicyViolation(
+            messages::text("policy.too-large", &[&max_upload_size]),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Refuses content that clamd doesn't report as clean.
+pub fn check_content(config: &Config, chunks: &[Bytes]) -> Result<(), CliError> {
+    let clamd = match &config.clamd {
+        Some(clamd) => clamd,
+        None => return Ok(()),
+    };
+    let verdict = if clamd.parse::<std::net::SocketAddr>().is_ok() {
+        std::net::TcpStream::connect(clamd.as_str())
+            .and_then(|mut stream| clamd_instream(&mut stream, chunks))
+    } else {
+        clamd_unix(clamd, chunks)
+    }
+    .map_err(CliError::Io)?;
+    if verdict.ends_with("OK") {
+        Ok(())
+    } else {
+        Err(CliError::PolicyViolation(messages::text(
+            "policy.clamd",
+            &[&verdict],
+        )))
+    }
+}
+
+#[cfg(unix)]
+fn clamd_unix(path: &str, chunks: &[Bytes]) -> std::io::Result<String> {
+    std::os::unix::net::UnixStream::connect(path)
+        .and_then(|mut stream| clamd_instream(&mut stream, chunks))
+}
+
+#[cfg(not(unix))]
+fn clamd_unix(path: &str, _chunks: &[Bytes]) -> std::io::Result<String> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{} is not a host:port, and Unix sockets are unsupported here", path),
+    ))
+}
+
+/// Scans `chunks` with clamd's INSTREAM command and returns its verdict, e.g. `stream: OK`.
+/// https://linux.die.net/man/8/clamd
+fn clamd_instream<S: Read + Write>(stream: &mut S, chunks: &[Bytes]) -> std::io::Result<String> {
+    stream.write_all(b"zINSTREAM\0")?;
+    for chunk in chunks.iter().flat_map(|chunk| chunk.chunks(CLAMD_CHUNK_SIZE)) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        stream.write_all(chunk)?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+    stream.flush()?;
+    let mut verdict = vec![];
+    for byte in stream.bytes() {
+        match byte? {
+            0 => break,
+            byte => verdict.push(byte),
+        }
+    }
+    Ok(String::from_utf8_lossy(&verdict).trim().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Records what is written, and replays a canned clamd reply
+    struct Clamd {
+        written: Vec<u8>,
+        reply: std::io::Cursor<&'static [u8]>,
+    }
+
+    impl Read for Clamd {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reply.read(buf)
+        }
+    }
+
+    impl Write for Clamd {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn instream_frames_chunks_and_reads_the_verdict() {
+        let mut clamd = Clamd {
+            written: vec![],
+            reply: std::io::Cursor::new(b"stream: Eicar-Test-Signature FOUND\0"),
+        };
+        let verdict =
+            clamd_instream(&mut clamd, &[Bytes::from("ab"), Bytes::from("c")]).unwrap();
+        assert_eq!("stream: Eicar-Test-Signature FOUND", verdict);
+        assert_eq!(
+            &b"zINSTREAM\0\0\0\0\x02ab\0\0\0\x01c\0\0\0\0"[..],
+            &clamd.written[..]
+        );
+    }
+
+    #[test]
+    fn denied_extensions_are_matched_case_insensitively() {
+        let config = Config {
+            denied_extensions: vec!["exe".to_string()],
+            ..Config::default()
+        };
+        assert!(check_path(&config, Path::new("bin/Setup.EXE")).is_err());
+        assert!(check_path(&config, Path::new("assets/logo.png")).is_ok());
+        assert!(check_path(&config, Path::new("exe")).is_ok());
+    }
+
+    #[test]
+    fn denied_mime_types_are_sniffed_from_the_first_bytes() {
+        let config = Config {
+            denied_mime_types: vec![
+                "application/x-executable".to_string(),
+                "image/*".to_string(),
+            ],
+            ..Config::default()
+        };
+        assert!(check_mime_type(&config, b"\x7fELF\x02\x01\x01").is_err());
+        assert!(check_mime_type(&config, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").is_err());
+        assert!(check_mime_type(&config, b"%PDF-1.7").is_ok());
+        assert!(check_mime_type(&config, b"plain text").is_ok());
+        assert!(check_mime_type(&config, b"").is_ok());
+    }
+}
@@ -0,0 +1,84 @@
+use crate::error::CliError;
+use crate::repo;
+use git_lfs_ipfs_lib::config;
+
+/// Selects a profile for commands that git runs, like the filters and the transfer agent
+const PROFILE_ENV: &str = "GIT_LFS_IPFS_PROFILE";
+
+/// Settings of the profile named `name`, read from `git-lfs-ipfs.<name>.<setting>` in the git
+/// config, or without a name the settings outside any profile, `git-lfs-ipfs.<setting>`.
+/// Each setting is named after its environment variable without the prefix, so
+/// `git-lfs-ipfs.work.api` holds `GIT_LFS_IPFS_API`.
+fn settings(name: Option<&str>) -> Result<Vec<(String, String)>, CliError> {
+    let prefix = match name {
+        Some(name) => format!("git-lfs-ipfs.{}.", name),
+        None => "git-lfs-ipfs.".to_string(),
+    };
+    let output = match repo::git(&[
+        "config",
+        "--get-regexp",
+        &format!("^{}", regex::escape(&prefix)),
+    ]) {
+        Ok(output) => output,
+        // git config exits with 1 if nothing matched
+        Err(CliError::GitCommandFailed(status)) if status.code() == Some(1) => String::new(),
+        Err(err) => return Err(err),
+    };
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next()?.get(prefix.len()..)?;
+            // Outside profiles, skip the profile settings and the profile selection itself
+            if name.is_none() && (key.contains('.') || key == "profile") {
+                return None;
+            }
+            let value = parts.next().unwrap_or("");
+            Some((
+                format!("GIT_LFS_IPFS_{}", key.to_uppercase().replace('-', "_")),
+                value.to_string(),
+            ))
+        })
+        .collect())
+}
+
+/// Applies the selected profile, then the settings outside any profile, like the root layout
+/// chosen by `init`, on top of the environment. The profile is `name` if given, else
+/// `$GIT_LFS_IPFS_PROFILE`, else `git-lfs-ipfs.profile` from the git config.
+/// Variables set in the environment, directly or through a `_FILE`, take precedence over the
+/// profile's settings, which take precedence over the others.
+/// Returns the variables that were set from the git config.
+pub fn load(name: Option<&str>) -> Result<Vec<String>, CliError> {
+    let name = match name {
+        Some(name) => Some(name.to_string()),
+        None => match std::env::var(PROFILE_ENV)
+            .ok()
+            .or_else(|| repo::git(&["config", "git-lfs-ipfs.profile"]).ok())
+        {
+            Some(name) if !name.is_empty() => Some(name),
+            _ => None,
+        },
+    };
+    let mut settings = match name {
+        Some(name) => {
+            let settings = self::settings(Some(&name))?;
+            if settings.is_empty() {
+                warn!("Profile {} has no settings", name);
+            }
+            settings
+        }
+        None => vec![],
+    };
+    settings.extend(self::settings(None)?);
+    let mut applied = vec![];
+    for (var, value) in settings {
+        let unset = std::env::var_os(&var).is_none()
+            && std::env::var_os(format!("{}_FILE", var)).is_none();
+        if unset {
+            std::env::set_var(&var, value);
+            applied.push(var);
+        }
+    }
+    config::load().map_err(CliError::InvalidConfig)?;
+    Ok(applied)
+}
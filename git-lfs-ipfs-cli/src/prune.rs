@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use futures::{future, prelude::*};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{self, CliError};
+use crate::lock::StateLock;
+use crate::{messages, output, repo, stage};
+use git_lfs_ipfs_lib::ipfs;
+
+/// How long an object stays pinned after it was marked for deletion, unless overridden
+const DEFAULT_GRACE_DAYS: i64 = 14;
+
+/// An object marked for unpinning, which stays pinned until its grace period runs out
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PendingDelete {
+    pub oid: String,
+    pub requested_at: DateTime<Utc>,
+}
+
+/// The pending delete set is kept as a JSON array in `.git/lfs/ipfs/pending-delete`
+fn pending_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("pending-delete"))
+}
+
+pub fn load_pending() -> Result<Vec<PendingDelete>, CliError> {
+    let path = pending_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    serde_json::from_reader(std::fs::File::open(path).map_err(CliError::Io)?)
+        .map_err(CliError::SerdeJsonError)
+}
+
+fn save_pending(pending: &[PendingDelete]) -> Result<(), CliError> {
+    let path = pending_path()?;
+    let tmp = path.with_extension("tmp");
+    serde_json::to_writer(std::fs::File::create(&tmp).map_err(CliError::Io)?, pending)
+        .map_err(CliError::SerdeJsonError)?;
+    std::fs::rename(tmp, path).map_err(CliError::Io)
+}
+
+/// Changes the pending delete set while holding its lock, so that concurrent changes aren't lost
+fn update_pending<F>(update: F) -> Result<(), CliError>
+where
+    F: FnOnce(&mut Vec<PendingDelete>),
+{
+    let _lock = StateLock::acquire(&pending_path()?)?;
+    let mut pending = load_pending()?;
+    update(&mut pending);
+    save_pending(&pending)
+}
+
+/// Objects prune unpinned are kept as a JSON object from OID to when in `.git/lfs/ipfs/pruned`,
+/// until they are uploaded or undeleted again
+fn pruned_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("pruned"))
+}
+
+fn load_pruned() -> Result<BTreeMap<String, DateTime<Utc>>, CliError> {
+    let path = pruned_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    serde_json::from_reader(std::fs::File::open(path).map_err(CliError::Io)?)
+        .map_err(CliError::SerdeJsonError)
+}
+
+/// Changes the pruned set while holding its lock, like [`update_pending`]
+fn update_pruned<F>(update: F) -> Result<(), CliError>
+where
+    F: FnOnce(&mut BTreeMap<String, DateTime<Utc>>),
+{
+    let path = pruned_path()?;
+    let _lock = StateLock::acquire(&path)?;
+    let mut pruned = load_pruned()?;
+    update(&mut pruned);
+    let tmp = path.with_extension("tmp");
+    serde_json::to_writer(std::fs::File::create(&tmp).map_err(CliError::Io)?, &pruned)
+        .map_err(CliError::SerdeJsonError)?;
+    std::fs::rename(tmp, path).map_err(CliError::Io)
+}
+
+/// Forgets that `oids` were pruned, for objects uploaded or undeleted again, so that
+/// `pin verify` pins them once more
+pub fn unprune(oids: &[String]) -> Result<(), CliError> {
+    let pruned = load_pruned()?;
+    if !oids.iter().any(|oid| pruned.contains_key(oid)) {
+        return Ok(());
+    }
+    update_pruned(|pruned| {
+        for oid in oids {
+            pruned.remove(oid);
+        }
+    })
+}
+
+/// OIDs of the objects that prune is about to unpin or has unpinned, which must not be
+/// pinned again behind its back
+pub fn removed_oids() -> Result<BTreeSet<String>, CliError> {
+    let mut removed: BTreeSet<String> = load_pruned()?.into_iter().map(|(oid, _)| oid).collect();
+    removed.extend(load_pending()?.into_iter().map(|entry| entry.oid));
+    Ok(removed)
+}
+
+/// Adds objects to the pending delete set without unpinning anything yet.
+pub struct Unpin {
+    oids: Vec<String>,
+    json: bool,
+}
+
+impl Unpin {
+    pub fn new(oids: Vec<String>, json: bool) -> Self {
+        Self { oids, json }
+    }
+}
+
+impl Actor for Unpin {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <Unpin as Actor>::Context) {
+        let oids = self.oids.clone();
+        let mut marked = vec![];
+        let result = update_pending(|pending| {
+            for oid in oids {
+                if !pending.iter().any(|entry| entry.oid == oid) {
+                    pending.push(PendingDelete {
+                        oid,
+                        requested_at: Utc::now(),
+                    });
+                }
+            }
+            // Objects marked earlier keep when they were first marked
+            marked = pending
+                .iter()
+                .filter(|entry| self.oids.contains(&entry.oid))
+                .cloned()
+                .collect();
+        });
+        System::current().stop();
+        match result {
+            Ok(()) if self.json => output::print_json("unpin", &marked),
+            Ok(()) => {}
+            Err(err) => error::exit(err),
+        }
+    }
+}
+
+/// Removes objects from the pending delete set, keeping them pinned. Objects that were already
+/// pruned are pinned again by the next `pin verify`.
+pub struct Undelete {
+    oids: Vec<String>,
+    json: bool,
+}
+
+impl Undelete {
+    pub fn new(oids: Vec<String>, json: bool) -> Self {
+        Self { oids, json }
+    }
+}
+
+impl Actor for Undelete {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <Undelete as Actor>::Context) {
+        let oids = self.oids.clone();
+        let result = update_pending(|pending| pending.retain(|entry| !oids.contains(&entry.oid)))
+            .and_then(|_| unprune(&self.oids));
+        System::current().stop();
+        match result {
+            Ok(()) if self.json => output::print_json("undelete", &self.oids),
+            Ok(()) => {}
+            Err(err) => error::exit(err),
+        }
+    }
+}
+
+/// Unpins every pending object whose grace period has run out.
+pub struct Prune {
+    grace: Duration,
+    json: bool,
+}
+
+impl Prune {
+    pub fn new(grace_days: Option<i64>, json: bool) -> Self {
+        Self {
+            grace: grace_period(grace_days),
+            json,
+        }
+    }
+}
+
+pub fn grace_period(grace_days: Option<i64>) -> Duration {
+    Duration::days(grace_days.unwrap_or(DEFAULT_GRACE_DAYS))
+}
+
+impl Actor for Prune {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Prune as Actor>::Context) {
+        ctx.wait(
+            actix::fut::wrap_future(prune_expired(self.grace)).then(
+                |result, actor: &mut Self, _ctx| {
+                    System::current().stop();
+                    match result {
+                        Ok(unpinned) if actor.json => output::print_json("prune", &unpinned),
+                        Ok(unpinned) => unpinned
+                            .iter()
+                            .for_each(|oid| {
+                                println!("{}", messages::text("prune.unpinned", &[oid]))
+                            }),
+                        Err(err) => error::exit(err),
+                    }
+                    actix::fut::ok(())
+                },
+            ),
+        );
+    }
+}
+
+/// Unpins every pending object older than `grace` and returns their OIDs.
+/// Objects that fail to unpin stay in the pending set for the next run, and so do objects
+/// staged by a push since they were marked, until they are published.
+pub fn prune_expired(grace: Duration) -> impl Future<Item = Vec<String>, Error = CliError> {
+    let expired = || -> Result<Vec<PendingDelete>, CliError> {
+        let staged = stage::load()?;
+        let cutoff = Utc::now() - grace;
+        Ok(load_pending()?
+            .into_iter()
+            .filter(|entry| entry.requested_at <= cutoff)
+            .filter(|entry| {
+                let restaged = staged.iter().any(|staged| staged.object.oid == entry.oid);
+                if restaged {
+                    debug!("Not unpinning {}, which was staged again", entry.oid);
+                }
+                !restaged
+            })
+            .collect())
+    };
+    future::result(expired()).and_then(|expired| {
+        future::join_all(expired.into_iter().map(|entry| {
+            ipfs::oid_to_cid(cid::Codec::DagProtobuf, &entry.oid)
+                .and_then(ipfs::pin_rm)
+                .then(move |result| Ok::<_, CliError>((entry, result)))
+        }))
+        .and_then(|results| {
+            let mut unpinned = vec![];
+            for (entry, result) in results {
+                match result {
+                    Ok(_) => unpinned.push(entry),
+                    Err(err) => error!("Could not unpin {}: {}", entry.oid, err),
+                }
+            }
+            let now = Utc::now();
+            update_pruned(|pruned| {
+                for entry in &unpinned {
+                    pruned.insert(entry.oid.clone(), now);
+                }
+            })?;
+            // Objects undeleted and marked again meanwhile have a newer request, which stays
+            update_pending(|pending| {
+                pending.retain(|entry| {
+                    !unpinned.iter().any(|unpinned| {
+                        unpinned.oid == entry.oid && unpinned.requested_at == entry.requested_at
+                    })
+                })
+            })?;
+            Ok(unpinned.into_iter().map(|entry| entry.oid).collect())
+        })
+    })
+}
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use actix::prelude::*;
+use cid::Cid;
+use futures::{future, prelude::*, stream};
+
+use crate::error::{self, CliError};
+use crate::stage::Staged;
+use crate::{messages, output};
+use git_lfs_ipfs_lib::{
+    config,
+    receipt::{self, Receipt},
+};
+
+/// Issues a receipt for every object in `staged`, just published in `root`, and writes each to
+/// `<dir>/<oid>-<root>.json`, returning the paths written.
+pub fn write_receipts(
+    dir: PathBuf,
+    staged: Vec<Staged>,
+    root: Cid,
+    key_name: String,
+) -> impl Future<Item = Vec<PathBuf>, Error = CliError> {
+    future::result(std::fs::create_dir_all(&dir).map_err(CliError::Io)).and_then(move |_| {
+        stream::iter_ok::<_, CliError>(staged)
+            .and_then(move |staged| {
+                receipt::issue(&staged.object, &staged.cid, &root, &key_name)
+                    .map_err(CliError::IpfsApiError)
+            })
+            .and_then(move |receipt| {
+                let path = dir.join(format!("{}-{}.json", receipt.oid, receipt.root));
+                serde_json::to_vec_pretty(&receipt)
+                    .map_err(CliError::SerdeJsonError)
+                    .and_then(|json| std::fs::write(&path, json).map_err(CliError::Io))
+                    .map(|_| path)
+            })
+            .collect()
+    })
+}
+
+fn read_receipt(path: &Path) -> Result<Receipt, CliError> {
+    let json = std::fs::read(path).map_err(CliError::Io)?;
+    serde_json::from_slice(&json).map_err(CliError::SerdeJsonError)
+}
+
+/// Checks receipts against the roots they name and the trusted key, exiting with an error at
+/// the first one that doesn't hold.
+pub struct Verify {
+    paths: Vec<PathBuf>,
+    key: Option<String>,
+    json: bool,
+}
+
+impl Verify {
+    pub fn new(paths: Vec<PathBuf>, key: Option<String>, json: bool) -> Self {
+        Self { paths, key, json }
+    }
+}
+
+impl Actor for Verify {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Verify as Actor>::Context) {
+        let trusted_key = self
+            .key
+            .clone()
+            .or_else(|| config::get().trusted_key.clone())
+            .unwrap_or_else(|| error::exit(CliError::NoTrustedKey));
+        let receipts: Vec<Receipt> = self
+            .paths
+            .iter()
+            .map(|path| read_receipt(path))
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|err| error::exit(err));
+        let verified = stream::iter_ok::<_, CliError>(receipts)
+            .and_then(move |receipt| {
+                receipt::verify(&receipt, &trusted_key)
+                    .map(move |_| receipt)
+                    .map_err(CliError::IpfsApiError)
+            })
+            .collect();
+        ctx.wait(actix::fut::wrap_future(verified).then(
+            |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(receipts) if actor.json => output::print_json("receipt verify", &receipts),
+                    Ok(receipts) => {
+                        for receipt in receipts {
+                            println!(
+                                "{}",
+                                messages::text(
+                                    "receipt.valid",
+                                    &[
+                                        &receipt.oid,
+                                        &receipt.size,
+                                        &receipt.cid,
+                                        &receipt.root,
+                                        &receipt.key,
+                                        &receipt.issued_at.to_rfc3339()
+                                    ]
+                                )
+                            );
+                        }
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
@@ -0,0 +1,155 @@
+use serde_derive::Deserialize;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::error::CliError;
+use git_lfs_ipfs_lib::{pointer, spec::Object};
+
+/// Runs a git command in the current directory and returns its trimmed stdout.
+pub fn git(args: &[&str]) -> Result<String, CliError> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(CliError::Io)?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(CliError::GitCommandFailed(output.status))
+    }
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/spec.md#intercepting-git
+pub fn lfs_objects_dir() -> Result<PathBuf, CliError> {
+    let git_dir = git(&["rev-parse", "--git-common-dir"])?;
+    let mut dir = std::env::current_dir().map_err(CliError::Io)?.join(git_dir);
+    dir.push("lfs");
+    dir.push("objects");
+    Ok(long_path(dir))
+}
+
+/// Object paths in deep checkouts easily exceed Windows' 260 character limit, which the
+/// verbatim `\\?\` prefix lifts. Windows does not normalize verbatim paths, so separators
+/// and `..` components are resolved here first.
+#[cfg(windows)]
+fn long_path(path: PathBuf) -> PathBuf {
+    use std::path::{Component, Prefix};
+    let mut components = path.components();
+    let mut long = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(drive) => PathBuf::from(format!(r"\\?\{}:\", drive as char)),
+            Prefix::UNC(server, share) => PathBuf::from(format!(
+                r"\\?\UNC\{}\{}\",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            )),
+            _ => return path,
+        },
+        _ => return path,
+    };
+    for component in components {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                long.pop();
+            }
+            component => long.push(component),
+        }
+    }
+    long
+}
+
+#[cfg(not(windows))]
+fn long_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// State kept by git-lfs-ipfs itself, next to git-lfs' own, in `.git/lfs/ipfs`
+pub fn ipfs_state_dir() -> Result<PathBuf, CliError> {
+    lfs_objects_dir().and_then(|mut dir| {
+        dir.pop();
+        dir.push("ipfs");
+        std::fs::create_dir_all(&dir).map_err(CliError::Io)?;
+        Ok(dir)
+    })
+}
+
+/// Where git-lfs stores an object locally, i.e. `.git/lfs/objects/OI/D/OID`. Fails for
+/// anything but a hex OID, which could lead outside the store.
+pub fn lfs_object_path(oid: &str) -> Result<PathBuf, CliError> {
+    if !pointer::is_oid(oid) {
+        return Err(CliError::InvalidOid(oid.to_string()));
+    }
+    lfs_objects_dir().map(|mut dir| {
+        dir.push(&oid[0..2]);
+        dir.push(&oid[2..4]);
+        dir.push(oid);
+        dir
+    })
+}
+
+/// Every object in the local LFS store
+pub fn local_objects() -> Result<Vec<Object>, CliError> {
+    let objects_dir = lfs_objects_dir()?;
+    if !objects_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut objects = vec![];
+    for first in std::fs::read_dir(&objects_dir).map_err(CliError::Io)? {
+        let first = first.map_err(CliError::Io)?;
+        if !first.path().is_dir() {
+            continue;
+        }
+        for second in std::fs::read_dir(first.path()).map_err(CliError::Io)? {
+            let second = second.map_err(CliError::Io)?;
+            if !second.path().is_dir() {
+                continue;
+            }
+            for object in std::fs::read_dir(second.path()).map_err(CliError::Io)? {
+                let object = object.map_err(CliError::Io)?;
+                let metadata = object.metadata().map_err(CliError::Io)?;
+                if metadata.is_file() {
+                    objects.push(Object {
+                        oid: object.file_name().to_string_lossy().to_string(),
+                        size: metadata.len(),
+                    });
+                }
+            }
+        }
+    }
+    objects.sort_by(|a, b| a.oid.cmp(&b.oid));
+    Ok(objects)
+}
+
+/// Output of `git lfs ls-files --json`
+#[derive(Deserialize)]
+struct LsFiles {
+    #[serde(default)]
+    files: Option<Vec<Object>>,
+}
+
+/// Every object that a pointer anywhere in the history refers to, whether or not it is in
+/// the local LFS store
+pub fn history_objects() -> Result<Vec<Object>, CliError> {
+    let output = git(&["lfs", "ls-files", "--all", "--json"])?;
+    let listed: LsFiles = serde_json::from_str(&output).map_err(CliError::SerdeJsonError)?;
+    let mut objects = listed.files.unwrap_or_default();
+    objects.sort_by(|a, b| a.oid.cmp(&b.oid));
+    objects.dedup_by(|a, b| a.oid == b.oid);
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_oids_have_a_place_in_the_store() {
+        for oid in &["ab/../../..", "a", "ab\u{e9}d", ""] {
+            match lfs_object_path(oid) {
+                Err(CliError::InvalidOid(_)) => {}
+                other => panic!("expected {:?} to be rejected, got {:?}", oid, other),
+            }
+        }
+    }
+}
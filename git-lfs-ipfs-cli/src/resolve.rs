@@ -0,0 +1,42 @@
+use actix::prelude::*;
+use futures::{future, prelude::*};
+
+use crate::error::{self, CliError};
+use git_lfs_ipfs_lib::{
+    ipfs::{self, ResolveOptions},
+    spec::ipfs::Path,
+};
+
+/// Resolves an IPFS or IPNS path and prints the result.
+pub struct Resolve {
+    path: String,
+    options: ResolveOptions,
+}
+
+impl Resolve {
+    pub fn new(path: String, options: ResolveOptions) -> Self {
+        Self { path, options }
+    }
+}
+
+impl Actor for Resolve {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Resolve as Actor>::Context) {
+        use std::str::FromStr;
+        let options = self.options.clone();
+        ctx.wait(
+            actix::fut::wrap_future(
+                future::result(Path::from_str(&self.path))
+                    .and_then(move |path| ipfs::resolve_with(path, options)),
+            )
+            .then(|result, _actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(path) => println!("{}", path),
+                    Err(err) => error::exit(CliError::IpfsApiError(err)),
+                }
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path as FsPath, PathBuf};
+
+use actix::prelude::*;
+use bytes::Bytes;
+use chrono::Utc;
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*, stream};
+use openssl::hash::{Hasher, MessageDigest};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{history, messages, output, repo};
+use git_lfs_ipfs_lib::{
+    error::Error, pin, pointer::HashAlgorithm, signature, spec::ipfs::Path, LfsIpfs,
+};
+
+/// Key the restored root is recorded under when `--key` isn't given
+const DEFAULT_KEY: &str = "self";
+
+/// What happened to one object of the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Restored {
+    /// It was in the local store already, with the right content
+    Present,
+    /// It was missing from the local store
+    Downloaded,
+    /// The local copy didn't hash to its OID, and was replaced
+    Replaced,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub root: String,
+    pub objects: usize,
+    pub present: usize,
+    pub downloaded: usize,
+    pub replaced: Vec<String>,
+}
+
+/// A hasher for the algorithm `oid` is a digest of, `None` if it isn't a hex OID
+fn hasher_for(oid: &str) -> Option<Hasher> {
+    let digest = match HashAlgorithm::from_hex(oid)? {
+        HashAlgorithm::Sha256 => MessageDigest::sha256(),
+        HashAlgorithm::Sha512 => MessageDigest::sha512(),
+    };
+    Hasher::new(digest).ok()
+}
+
+/// Whether what `hasher` was fed is what `oid` is the digest of
+fn matches_oid(oid: &str, mut hasher: Hasher) -> bool {
+    match (hasher.finish(), hex::decode(oid)) {
+        (Ok(digest), Ok(expected)) => digest[..] == expected[..],
+        _ => false,
+    }
+}
+
+/// Whether the file at `path` is `size` bytes long and hashes to `oid`. It is hashed as it is
+/// read, so only a small buffer of it is held at a time.
+fn is_intact(path: &FsPath, oid: &str, size: u64) -> io::Result<bool> {
+    if std::fs::metadata(path)?.len() != size {
+        return Ok(false);
+    }
+    let mut hasher = match hasher_for(oid) {
+        Some(hasher) => hasher,
+        None => return Ok(false),
+    };
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(matches_oid(oid, hasher))
+}
+
+fn size_mismatch(oid: &str, expected: u64, received: u64) -> CliError {
+    CliError::IpfsApiError(Error::SizeMismatch {
+        oid: oid.to_string(),
+        expected,
+        received,
+    })
+}
+
+/// Streams `content` into the local store as `oid`, hashing it on the way. It is written to a
+/// temporary file that only takes the object's place once it turns out to be `size` bytes long
+/// and to hash to the OID, so that an interrupted or corrupt download never leaves a bad object
+/// behind.
+fn write_object<S>(oid: String, size: u64, content: S) -> impl Future<Item = (), Error = CliError>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    let open = repo::lfs_object_path(&oid).and_then(|path| {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(CliError::Io)?;
+        }
+        let hasher = hasher_for(&oid).ok_or(CliError::IpfsApiError(Error::HashError))?;
+        let partial = path.with_extension("part");
+        let file = File::create(&partial).map_err(CliError::Io)?;
+        Ok((path, partial, file, hasher))
+    });
+    future::result(open).and_then(move |(path, partial, file, hasher)| {
+        let cleanup = partial.clone();
+        let checked = oid.clone();
+        content
+            .map_err(CliError::IpfsApiError)
+            .fold(
+                (file, hasher, 0),
+                move |(mut file, mut hasher, received), chunk| {
+                    let received = received + chunk.len() as u64;
+                    if received > size {
+                        return Err(size_mismatch(&checked, size, received));
+                    }
+                    file.write_all(&chunk)
+                        .and_then(|_| hasher.write_all(&chunk))
+                        .map_err(CliError::Io)?;
+                    Ok((file, hasher, received))
+                },
+            )
+            .and_then(move |(file, hasher, received)| {
+                drop(file);
+                if received != size {
+                    return Err(size_mismatch(&oid, size, received));
+                }
+                if !matches_oid(&oid, hasher) {
+                    return Err(CliError::IpfsApiError(Error::HashError));
+                }
+                std::fs::rename(&partial, &path).map_err(CliError::Io)
+            })
+            .map_err(move |err| {
+                let _ = std::fs::remove_file(cleanup);
+                err
+            })
+    })
+}
+
+/// Makes sure the local store holds `oid` with the right content, downloading it from `root`
+/// if it doesn't, and checking what was downloaded against `size` and the OID.
+pub(crate) fn restore_object(
+    lfs: LfsIpfs,
+    oid: String,
+    size: u64,
+) -> impl Future<Item = (String, Restored), Error = CliError> {
+    let local: Result<Option<PathBuf>, CliError> =
+        repo::lfs_object_path(&oid).map(|path| Some(path).filter(|path| path.exists()));
+    future::result(local).and_then(move |local| {
+        let restored = match local {
+            Some(path) => {
+                if is_intact(&path, &oid, size).unwrap_or(false) {
+                    return future::Either::A(future::ok((oid, Restored::Present)));
+                }
+                warn!("{} in the local store is corrupt, replacing it", oid);
+                Restored::Replaced
+            }
+            None => Restored::Downloaded,
+        };
+        future::Either::B(
+            write_object(oid.clone(), size, lfs.download_object(&oid)).map(move |_| {
+                debug!("Restored {}", oid);
+                (oid, restored)
+            }),
+        )
+    })
+}
+
+/// Rebuilds the local state of the repository from a published root alone: every object its
+/// manifest lists is put in the local LFS store, checked against its OID, the root is pinned,
+/// and it is recorded in the history so that the next `publish` builds on it.
+pub fn restore(root: Cid, key_name: String) -> impl Future<Item = Report, Error = CliError> {
+    let lfs = LfsIpfs::with_root(Path::ipfs(root.clone()));
+    signature::trusted_manifest(root.clone())
+        .map_err(CliError::IpfsApiError)
+        .and_then(move |manifest| {
+            let objects: Vec<(String, u64)> = manifest
+                .objects
+                .into_iter()
+                .map(|(oid, entry)| (oid, entry.size))
+                .collect();
+            let report = Report {
+                root: root.to_string(),
+                objects: objects.len(),
+                ..Report::default()
+            };
+            stream::iter_ok::<_, CliError>(objects)
+                .map(move |(oid, size)| restore_object(lfs.clone(), oid, size))
+                .buffer_unordered(8)
+                .fold(report, |mut report, (oid, restored)| {
+                    match restored {
+                        Restored::Present => report.present += 1,
+                        Restored::Downloaded => report.downloaded += 1,
+                        Restored::Replaced => report.replaced.push(oid),
+                    }
+                    future::ok::<_, CliError>(report)
+                })
+                .and_then(move |report| {
+                    pin::add(root)
+                        .map_err(CliError::IpfsApiError)
+                        .map(|_| report)
+                })
+        })
+        .and_then(move |report| {
+            let recorded = history::load()?
+                .iter()
+                .any(|entry| entry.root == report.root);
+            if !recorded {
+                history::record(&history::Entry {
+                    root: report.root.clone(),
+                    key: key_name,
+                    published_at: Utc::now(),
+                })?;
+            }
+            Ok(report)
+        })
+}
+
+/// Restores the repository's local state from a root CID, e.g. after losing the machine it
+/// was published from.
+pub struct Restore {
+    root: String,
+    key: Option<String>,
+    json: bool,
+}
+
+impl Restore {
+    pub fn new(root: String, key: Option<String>, json: bool) -> Self {
+        Self { root, key, json }
+    }
+}
+
+impl Actor for Restore {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Restore as Actor>::Context) {
+        let root = self
+            .root
+            .to_cid()
+            .unwrap_or_else(|_| error::exit(CliError::InvalidCid(self.root.clone())));
+        let key_name = self.key.clone().unwrap_or_else(|| DEFAULT_KEY.to_string());
+        ctx.wait(actix::fut::wrap_future(restore(root, key_name)).then(
+            |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(report) if actor.json => output::print_json("restore", &report),
+                    Ok(report) => {
+                        println!(
+                            "{}",
+                            messages::text(
+                                "restore.restored",
+                                &[
+                                    &report.objects,
+                                    &report.root,
+                                    &(report.downloaded + report.replaced.len()),
+                                    &report.present
+                                ]
+                            )
+                        );
+                        for oid in &report.replaced {
+                            println!("{}", messages::text("restore.replaced", &[oid]));
+                        }
+                        println!("{}", messages::text("restore.pinned", &[&report.root]));
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(oid: &str, content: &[u8]) -> bool {
+        let mut hasher = match hasher_for(oid) {
+            Some(hasher) => hasher,
+            None => return false,
+        };
+        hasher.update(content).unwrap();
+        matches_oid(oid, hasher)
+    }
+
+    #[test]
+    fn only_content_that_hashes_to_the_oid_matches() {
+        let oid = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(matches(oid, b"hello"));
+        assert!(!matches(oid, b"hello\n"));
+        assert!(!matches(&oid[1..], b"hello"));
+    }
+
+    #[test]
+    fn local_copies_are_checked_against_size_and_oid() {
+        let oid = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let path = std::env::temp_dir().join(format!("restore-test-{}", std::process::id()));
+        std::fs::write(&path, b"hello").unwrap();
+        let intact = (is_intact(&path, oid, 5), is_intact(&path, oid, 6));
+        std::fs::write(&path, b"hallo").unwrap();
+        let corrupt = is_intact(&path, oid, 5);
+        std::fs::remove_file(&path).unwrap();
+        assert!(intact.0.unwrap());
+        assert!(!intact.1.unwrap());
+        assert!(!corrupt.unwrap());
+        assert!(is_intact(&path, oid, 5).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{history, messages, output};
+use git_lfs_ipfs_lib::{dag, error::Error, ipfs, swarm};
+
+/// What a collaborator's node needs to seed the latest root
+#[derive(Debug, Serialize)]
+pub struct Bundle {
+    pub root: String,
+    /// The IPNS key the root was published under, and the name it resolves from, if known
+    pub key: Option<String>,
+    pub name: Option<String>,
+    /// Where to reach this node, to fetch the root from it directly
+    pub peers: Vec<String>,
+    pub size: u64,
+    pub blocks: u64,
+}
+
+impl Bundle {
+    /// Shell commands that connect to this node and pin the root
+    fn commands(&self) -> Vec<String> {
+        self.peers
+            .iter()
+            .map(|peer| format!("ipfs swarm connect {}", peer))
+            .chain(std::iter::once(format!(
+                "ipfs pin add --progress /ipfs/{}",
+                self.root
+            )))
+            .collect()
+    }
+
+    /// A pin request of the IPFS Pinning Service API, as accepted by ipfs-cluster and hosted
+    /// pinning services, listing this node as where to fetch the root from.
+    /// https://ipfs.github.io/pinning-services-api-spec/
+    fn pin_request(&self) -> serde_json::Value {
+        serde_json::json!({
+            "cid": self.root,
+            "name": self.key.as_ref().map(|key| format!("git-lfs-ipfs {}", key)),
+            "origins": self.peers,
+        })
+    }
+}
+
+/// Gathers the bundle for `root`, published under `key` if known.
+fn bundle(root: Cid, key: Option<String>) -> impl Future<Item = Bundle, Error = Error> {
+    let name = key.clone().map(|key| {
+        ipfs::key_list().map(move |key_list| {
+            key_list
+                .keys
+                .into_iter()
+                .find(|candidate| candidate.name == key)
+                .map(|key| key.id.to_string())
+        })
+    });
+    dag::stat(&root)
+        .join3(
+            swarm::local_addrs(),
+            match name {
+                Some(name) => future::Either::A(name),
+                None => future::Either::B(future::ok(None)),
+            },
+        )
+        .map(move |(stat, peers, name)| Bundle {
+            root: root.to_string(),
+            key,
+            name,
+            peers,
+            size: stat.size,
+            blocks: stat.num_blocks,
+        })
+}
+
+/// Prints everything a collaborator needs to seed a root, by default the one most recently
+/// published from this repository, and optionally writes a pin request for it.
+pub struct Seed {
+    root: Option<String>,
+    pin_request: Option<PathBuf>,
+    json: bool,
+}
+
+impl Seed {
+    pub fn new(root: Option<String>, pin_request: Option<PathBuf>, json: bool) -> Self {
+        Self {
+            root,
+            pin_request,
+            json,
+        }
+    }
+}
+
+impl Actor for Seed {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Seed as Actor>::Context) {
+        let latest = history::load()
+            .unwrap_or_else(|err| error::exit(err))
+            .pop();
+        let (root, key) = match (&self.root, latest) {
+            (Some(root), latest) => {
+                let key = latest
+                    .filter(|latest| &latest.root == root)
+                    .map(|latest| latest.key);
+                (root.clone(), key)
+            }
+            (None, Some(latest)) => (latest.root, Some(latest.key)),
+            (None, None) => error::exit(CliError::NothingPublished),
+        };
+        let root = root
+            .to_cid()
+            .unwrap_or_else(|_| error::exit(CliError::InvalidCid(root.clone())));
+        ctx.wait(
+            actix::fut::wrap_future(bundle(root, key).map_err(CliError::IpfsApiError)).then(
+                |result, actor: &mut Self, _ctx| {
+                    System::current().stop();
+                    let bundle = result.unwrap_or_else(|err| error::exit(err));
+                    if let Some(path) = &actor.pin_request {
+                        let written = serde_json::to_vec_pretty(&bundle.pin_request())
+                            .map_err(CliError::SerdeJsonError)
+                            .and_then(|json| std::fs::write(path, json).map_err(CliError::Io));
+                        if let Err(err) = written {
+                            error::exit(err);
+                        }
+                    }
+                    if actor.json {
+                        output::print_json("seed", &bundle);
+                        return actix::fut::ok(());
+                    }
+                    println!(
+                        "{}",
+                        messages::text("seed.root", &[&bundle.root, &bundle.size, &bundle.blocks])
+                    );
+                    if let (Some(key), Some(name)) = (&bundle.key, &bundle.name) {
+                        println!("{}", messages::text("seed.published", &[key, name]));
+                    }
+                    println!();
+                    println!("{}", messages::text("seed.commands", &[]));
+                    for command in bundle.commands() {
+                        println!("  {}", command);
+                    }
+                    if let Some(path) = &actor.pin_request {
+                        println!();
+                        println!(
+                            "{}",
+                            messages::text("seed.pin-request", &[&path.display(), &path.display()])
+                        );
+                    }
+                    actix::fut::ok(())
+                },
+            ),
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use actix::prelude::*;
+use chrono::Utc;
+use cid::ToCid;
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+use url::Url;
+
+use crate::error::{self, CliError};
+use crate::{history, messages, output};
+use git_lfs_ipfs_lib::{
+    config,
+    error::Error,
+    ipfs,
+    share::{self, ShareLink},
+    spec::ipfs::Path,
+    LfsIpfs,
+};
+
+/// The key links are signed with: the manifest signing key if there is one, so that readers
+/// who trust the repository's manifests can check links too, else `self`.
+fn signing_key_name() -> String {
+    config::get()
+        .signing_key
+        .clone()
+        .unwrap_or_else(|| "self".to_string())
+}
+
+/// Prints a link that hands out the object `oid` through a gateway until it expires, without
+/// revealing the repository's root or any other object.
+pub struct Create {
+    oid: String,
+    hours: i64,
+    gateway: Option<String>,
+    key: Option<String>,
+    json: bool,
+}
+
+impl Create {
+    pub fn new(
+        oid: String,
+        hours: Option<i64>,
+        gateway: Option<String>,
+        key: Option<String>,
+        json: bool,
+    ) -> Self {
+        Self {
+            oid,
+            hours: hours.unwrap_or(share::DEFAULT_EXPIRY_HOURS),
+            gateway,
+            key,
+            json,
+        }
+    }
+}
+
+impl Actor for Create {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Create as Actor>::Context) {
+        let gateway = match &self.gateway {
+            Some(gateway) => Some(
+                Url::parse(gateway)
+                    .unwrap_or_else(|_| error::exit(CliError::InvalidUrl(gateway.clone()))),
+            ),
+            None => config::get().gateway_urls.first().cloned(),
+        }
+        .unwrap_or_else(|| error::exit(CliError::NoGateway));
+        // The object is looked up in the configured root, else in the latest published one
+        let root = config::get().root.clone().or_else(|| {
+            history::load()
+                .unwrap_or_else(|err| error::exit(err))
+                .pop()
+                .and_then(|latest| latest.root.to_cid().ok())
+                .map(Path::ipfs)
+        });
+        let lfs = match root {
+            Some(root) => LfsIpfs::with_root(root),
+            None => LfsIpfs::new(),
+        };
+        let oid = self.oid.clone();
+        let key_name = self.key.clone().unwrap_or_else(signing_key_name);
+        let expires_at = Utc::now() + chrono::Duration::hours(self.hours);
+        let link = lfs
+            .locate(&self.oid)
+            .and_then(move |(cid, _)| share::create(&oid, cid, &gateway, expires_at, &key_name))
+            .map_err(CliError::IpfsApiError);
+        ctx.wait(actix::fut::wrap_future(link).then(
+            |result: Result<ShareLink, CliError>, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(link) if actor.json => output::print_json("share create", &link),
+                    Ok(link) => {
+                        println!("{}", link.url);
+                        println!(
+                            "{}",
+                            messages::text("share.expires", &[&link.expires_at.to_rfc3339()])
+                        );
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
+
+/// What `share check` reports about a link that checked out
+#[derive(Debug, Serialize)]
+pub struct Checked {
+    pub oid: String,
+    pub cid: String,
+    pub expires_at: chrono::DateTime<Utc>,
+}
+
+/// Checks that a link hasn't expired and was signed by the trusted key, exiting with an error
+/// otherwise, e.g. for a gateway's reverse proxy to call before serving it.
+pub struct Check {
+    url: String,
+    key: Option<String>,
+    json: bool,
+}
+
+impl Check {
+    pub fn new(url: String, key: Option<String>, json: bool) -> Self {
+        Self { url, key, json }
+    }
+}
+
+impl Actor for Check {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Check as Actor>::Context) {
+        // Without a trusted key, links are checked against this node's own signing key
+        let trusted_key = match self.key.clone().or_else(|| config::get().trusted_key.clone()) {
+            Some(key) => future::Either::A(future::ok(key)),
+            None => {
+                let key_name = signing_key_name();
+                future::Either::B(ipfs::key_list().and_then(move |key_list| {
+                    key_list
+                        .keys
+                        .into_iter()
+                        .find(|key| key.name == key_name)
+                        .map(|key| key.id.to_string())
+                        .ok_or(Error::IpfsUploadNotPossible)
+                }))
+            }
+        };
+        let url = Url::parse(&self.url).unwrap_or_else(|err| {
+            error::exit(CliError::IpfsApiError(Error::ShareLinkRejected(format!(
+                "it is not a valid URL: {}",
+                err
+            ))))
+        });
+        let checked = trusted_key
+            .and_then(move |trusted_key| share::check(&url, &trusted_key, Utc::now()))
+            .map(|grant| Checked {
+                oid: grant.oid,
+                cid: grant.cid.to_string(),
+                expires_at: grant.expires_at,
+            })
+            .map_err(CliError::IpfsApiError);
+        ctx.wait(actix::fut::wrap_future(checked).then(
+            |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(checked) if actor.json => output::print_json("share check", &checked),
+                    Ok(checked) => println!(
+                        "{}",
+                        messages::text(
+                            "share.valid",
+                            &[&checked.expires_at.to_rfc3339(), &checked.oid, &checked.cid]
+                        )
+                    ),
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
@@ -4,7 +4,7 @@ use actix::prelude::*;
 use actix_web::HttpMessage;
 use futures::{future, prelude::*};
 
-use crate::error::CliError;
+use crate::error::{self, CliError};
 use git_lfs_ipfs_lib::{ipfs, spec};
 
 pub struct Smudge {
@@ -24,9 +24,9 @@ impl Actor for Smudge {
     type Context = Context<Self>;
     fn started(&mut self, ctx: &mut <Smudge as Actor>::Context) {
         let mut raw_object = Vec::with_capacity(8192);
-        io::stdin()
-            .read_to_end(&mut raw_object)
-            .expect("could not read raw object from stdin");
+        if let Err(err) = io::stdin().read_to_end(&mut raw_object) {
+            error::exit(CliError::Io(err));
+        }
         ctx.wait(
             actix::fut::wrap_stream(
                 future::ok(multihash::encode(multihash::Hash::SHA2256, &raw_object).unwrap())
@@ -44,8 +44,11 @@ impl Actor for Smudge {
                 actix::fut::result(actor.stdout.write(&b).map_err(CliError::Io))
             })
             .finish()
-            .then(|x, _, _| {
+            .then(|result, _, _| {
                 System::current().stop();
+                if let Err(err) = result {
+                    error::exit(err);
+                }
                 actix::fut::ok(())
             }),
         );
@@ -0,0 +1,447 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{self, CliError};
+use crate::{history, lock, messages, output, prune, receipt, repo};
+use git_lfs_ipfs_lib::{
+    config,
+    error::Error,
+    ipfs,
+    spec::{manifest::Manifest, Object},
+};
+
+/// Publishes under this key when neither `--key` nor an earlier publish names one
+const DEFAULT_KEY: &str = "self";
+
+/// An object uploaded since the last publish
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Staged {
+    #[serde(flatten)]
+    pub object: Object,
+    pub cid: String,
+    /// Whether `cid` is a UnixFS file of the object's content, rather than its root block
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unixfs: bool,
+}
+
+/// Uploaded objects are appended one JSON entry per line to `.git/lfs/ipfs/staged`, until
+/// `publish` links them into a new root
+fn staged_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("staged"))
+}
+
+/// An object pushed under other content than what its OID is already linked to, which never
+/// makes it into a published root. Conflicts are appended one JSON entry per line to
+/// `.git/lfs/ipfs/conflicts`, which keeps what the object was pushed as.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Conflict {
+    #[serde(flatten)]
+    pub staged: Staged,
+    /// What the OID is already linked to
+    pub linked: String,
+    pub rejected_at: DateTime<Utc>,
+}
+
+fn conflicts_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("conflicts"))
+}
+
+fn record_conflict(conflict: &Conflict) -> Result<(), CliError> {
+    warn!(
+        "Rejected object {} as {}, it is already linked to {}",
+        conflict.staged.object.oid, conflict.staged.cid, conflict.linked
+    );
+    lock::append_json_line(&conflicts_path()?, conflict)
+}
+
+fn insert(manifest: &mut Manifest, staged: &Staged) -> Result<(), Error> {
+    if staged.unixfs {
+        manifest.insert_unixfs(&staged.object, staged.cid.clone())
+    } else {
+        manifest.insert(&staged.object, staged.cid.clone())
+    }
+}
+
+/// Links `staged` into `manifest`, unless the manifest already links its OID to other content
+fn link(manifest: &mut Manifest, staged: Staged) -> Result<Staged, Conflict> {
+    match insert(manifest, &staged) {
+        Ok(()) => Ok(staged),
+        Err(Error::OidConflict { linked, .. }) => Err(Conflict {
+            staged,
+            linked,
+            rejected_at: Utc::now(),
+        }),
+        Err(err) => unreachable!("inserting into a manifest only fails on conflicts: {}", err),
+    }
+}
+
+/// Links every staged object into `manifest`, and returns those that conflict with it
+fn link_all(manifest: &mut Manifest, staged: Vec<Staged>) -> (Vec<Staged>, Vec<Conflict>) {
+    let mut linked = vec![];
+    let mut conflicts = vec![];
+    for staged in staged {
+        match link(manifest, staged) {
+            Ok(staged) => linked.push(staged),
+            Err(conflict) => conflicts.push(conflict),
+        }
+    }
+    (linked, conflicts)
+}
+
+pub fn stage(object: &Object, cid: &Cid) -> impl Future<Item = (), Error = CliError> {
+    admit(Staged {
+        object: object.clone(),
+        cid: cid.to_string(),
+        unixfs: false,
+    })
+}
+
+/// Stages an object imported as a UnixFS file of its content, like those added from a URL
+pub fn stage_unixfs(object: &Object, cid: &Cid) -> impl Future<Item = (), Error = CliError> {
+    admit(Staged {
+        object: object.clone(),
+        cid: cid.to_string(),
+        unixfs: true,
+    })
+}
+
+/// Stages `staged`, unless the latest published root or an earlier upload already links its
+/// OID to other content. Such an upload is recorded as a [`Conflict`] and fails. The staging
+/// area stays locked from reading the earlier uploads to appending this one, so that two
+/// uploads of the same OID can't both pass the check. It is changed off the event loop, since
+/// it may have to wait for another process' lock.
+fn admit(staged: Staged) -> impl Future<Item = (), Error = CliError> {
+    future::result(history::load())
+        .and_then(|history| latest_manifest(history.last()))
+        .and_then(move |mut manifest| {
+            lock::off_reactor(move || {
+                let path = staged_path()?;
+                let lock = lock::StateLock::acquire(&path)?;
+                link_all(&mut manifest, load()?);
+                let linked = link(&mut manifest, staged);
+                if let Ok(staged) = &linked {
+                    lock::append_json_line_held(&lock, &path, staged)?;
+                }
+                drop(lock);
+                match linked {
+                    // Objects uploaded again are no longer pruned
+                    Ok(staged) => prune::unprune(&[staged.object.oid]),
+                    Err(conflict) => {
+                        record_conflict(&conflict)?;
+                        Err(CliError::IpfsApiError(Error::OidConflict {
+                            oid: conflict.staged.object.oid,
+                            linked: conflict.linked,
+                            pushed: conflict.staged.cid,
+                        }))
+                    }
+                }
+            })
+        })
+}
+
+/// Every staged object, once each, by OID
+pub fn load() -> Result<Vec<Staged>, CliError> {
+    let path = staged_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let mut staged = BTreeMap::new();
+    for line in BufReader::new(std::fs::File::open(path).map_err(CliError::Io)?).lines() {
+        let entry: Staged =
+            serde_json::from_str(&line.map_err(CliError::Io)?).map_err(CliError::SerdeJsonError)?;
+        staged.insert(entry.object.oid.clone(), entry);
+    }
+    Ok(staged.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Removes the objects that were just published from the staging area, keeping any that
+/// a concurrent push staged in the meantime, or staged again under another CID. It waits for
+/// the staging area's lock, so on the event loop it runs through [`lock::off_reactor`].
+fn unstage(published: &[Staged]) -> Result<(), CliError> {
+    let path = staged_path()?;
+    let _lock = lock::StateLock::acquire(&path)?;
+    let remaining: Vec<Staged> = load()?
+        .into_iter()
+        .filter(|staged| {
+            !published.iter().any(|published| {
+                published.object.oid == staged.object.oid && published.cid == staged.cid
+            })
+        })
+        .collect();
+    if remaining.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path).map_err(CliError::Io)?;
+        }
+        return Ok(());
+    }
+    let mut lines = vec![];
+    for staged in &remaining {
+        serde_json::to_writer(&mut lines, staged).map_err(CliError::SerdeJsonError)?;
+        lines.push(b'\n');
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, lines).map_err(CliError::Io)?;
+    std::fs::rename(tmp, path).map_err(CliError::Io)
+}
+
+/// The manifest of the latest root published from this repository, empty if there is none yet.
+/// Fails if that root's manifest can't be read, rather than building on an empty one, which
+/// would leave every object published so far out of the next root.
+fn latest_manifest(
+    latest: Option<&history::Entry>,
+) -> impl Future<Item = Manifest, Error = CliError> {
+    match latest {
+        Some(latest) => future::Either::A(
+            future::result(
+                latest
+                    .root
+                    .to_cid()
+                    .map_err(|_| CliError::InvalidCid(latest.root.clone())),
+            )
+            .and_then(|root| ipfs::manifest_get(root).map_err(CliError::IpfsApiError)),
+        ),
+        None => future::Either::B(future::ok(Manifest::new())),
+    }
+}
+
+/// Outcome of a publish
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub key: String,
+    /// The published root, unless this was a dry run
+    pub root: Option<String>,
+    pub staged: Vec<Staged>,
+    /// Staged objects left out, since the latest root links their OIDs to other content
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Links every staged object into a root on top of the latest published one, and publishes
+/// it under an IPNS key once, for all of them. With `dry_run`, only lists what would go public.
+pub struct Publish {
+    key: Option<String>,
+    dry_run: bool,
+    json: bool,
+}
+
+impl Publish {
+    pub fn new(key: Option<String>, dry_run: bool, json: bool) -> Self {
+        Self { key, dry_run, json }
+    }
+}
+
+impl Actor for Publish {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Publish as Actor>::Context) {
+        let staged = load().unwrap_or_else(|err| error::exit(err));
+        let latest = history::load()
+            .unwrap_or_else(|err| error::exit(err))
+            .pop();
+        let key = self
+            .key
+            .clone()
+            .or_else(|| latest.as_ref().map(|latest| latest.key.clone()))
+            .unwrap_or_else(|| DEFAULT_KEY.to_string());
+        if self.dry_run || staged.is_empty() {
+            System::current().stop();
+            let report = Report {
+                key,
+                root: None,
+                staged,
+                conflicts: vec![],
+            };
+            if self.json {
+                output::print_json("publish", &report);
+            } else if report.staged.is_empty() {
+                println!("{}", messages::text("publish.nothing-staged", &[]));
+            } else {
+                println!(
+                    "{}",
+                    messages::text(
+                        "publish.would-publish",
+                        &[&report.staged.len(), &report.key]
+                    )
+                );
+                for staged in &report.staged {
+                    println!("{} {} {}", staged.object.oid, staged.object.size, staged.cid);
+                }
+            }
+            return;
+        }
+        // Conflicting objects leave the staging area, so that they don't hold up every later
+        // publish. They can be pushed again once fixed, and the conflict log keeps what they
+        // were pushed as.
+        let key_name = key.clone();
+        let publish = latest_manifest(latest.as_ref())
+            .and_then(move |mut manifest| {
+                let (staged, conflicts) = link_all(&mut manifest, staged);
+                lock::off_reactor(move || {
+                    for conflict in &conflicts {
+                        record_conflict(conflict)?;
+                    }
+                    unstage(
+                        &conflicts
+                            .iter()
+                            .map(|conflict| conflict.staged.clone())
+                            .collect::<Vec<_>>(),
+                    )?;
+                    Ok(conflicts)
+                })
+                .map(move |conflicts| (manifest, staged, conflicts))
+            })
+            .and_then(move |(manifest, staged, conflicts)| {
+                if staged.is_empty() {
+                    return future::Either::A(future::ok((None, staged, conflicts)));
+                }
+                future::Either::B(
+                    publish_root(manifest, key, staged.clone())
+                        .map(move |entry| (Some(entry), staged, conflicts)),
+                )
+            });
+        ctx.wait(
+            actix::fut::wrap_future(publish).then(move |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                let (entry, staged, conflicts) = result.unwrap_or_else(|err| error::exit(err));
+                if actor.json {
+                    let (key, root) = match entry {
+                        Some(entry) => (entry.key, Some(entry.root)),
+                        None => (key_name, None),
+                    };
+                    output::print_json(
+                        "publish",
+                        &Report {
+                            key,
+                            root,
+                            staged,
+                            conflicts,
+                        },
+                    );
+                    return actix::fut::ok(());
+                }
+                for conflict in &conflicts {
+                    eprintln!(
+                        "{}",
+                        messages::text(
+                            "publish.conflict",
+                            &[
+                                &conflict.staged.object.oid,
+                                &conflict.linked,
+                                &conflict.staged.cid
+                            ]
+                        )
+                    );
+                }
+                match entry {
+                    Some(entry) => println!(
+                        "{}",
+                        messages::text(
+                            "publish.published",
+                            &[&entry.root, &staged.len(), &entry.key]
+                        )
+                    ),
+                    None => println!("{}", messages::text("publish.all-conflicted", &[])),
+                }
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
+
+/// Puts `manifest` into a new root, publishes it under `key` and writes receipts for `staged`,
+/// which is then unstaged
+fn publish_root(
+    manifest: Manifest,
+    key: String,
+    staged: Vec<Staged>,
+) -> impl Future<Item = history::Entry, Error = CliError> {
+    ipfs::root_put(&manifest, config::get().layout)
+        .map_err(CliError::IpfsApiError)
+        .and_then(move |root| history::publish(root, key))
+        .and_then({
+            let staged = staged.clone();
+            move |entry| match config::get().receipts_dir.clone() {
+                Some(dir) => {
+                    // Receipts are signed like the manifest, else with the publishing key
+                    let key_name = config::get()
+                        .signing_key
+                        .clone()
+                        .unwrap_or_else(|| entry.key.clone());
+                    future::Either::A(
+                        future::result(
+                            entry
+                                .root
+                                .to_cid()
+                                .map_err(|_| CliError::InvalidCid(entry.root.clone())),
+                        )
+                        .and_then(move |root| receipt::write_receipts(dir, staged, root, key_name))
+                        .map(move |_| entry),
+                    )
+                }
+                None => future::Either::B(future::ok(entry)),
+            }
+        })
+        .and_then(move |entry| lock::off_reactor(move || unstage(&staged)).map(|_| entry))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unreadable_roots_are_not_built_on() {
+        assert!(latest_manifest(None).wait().unwrap().objects.is_empty());
+        let latest = history::Entry {
+            root: "not a root".to_string(),
+            key: DEFAULT_KEY.to_string(),
+            published_at: Utc::now(),
+        };
+        match latest_manifest(Some(&latest)).wait() {
+            Err(CliError::InvalidCid(_)) => {}
+            result => panic!("expected the root to be rejected, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn conflicting_objects_are_left_out() {
+        let staged = |oid: &str, cid: &str| Staged {
+            object: Object {
+                oid: oid.repeat(64),
+                size: 1,
+            },
+            cid: cid.to_string(),
+            unixfs: false,
+        };
+        let (published, other) = (
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+            "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR",
+        );
+        let mut manifest = Manifest::new();
+        link(&mut manifest, staged("a", published)).unwrap();
+        let (linked, conflicts) = link_all(
+            &mut manifest,
+            vec![
+                staged("a", published),
+                staged("b", other),
+                staged("a", other),
+            ],
+        );
+        assert_eq!(
+            vec!["a".repeat(64), "b".repeat(64)],
+            linked
+                .into_iter()
+                .map(|staged| staged.object.oid)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, conflicts.len());
+        assert_eq!("a".repeat(64), conflicts[0].staged.object.oid);
+        assert_eq!(published, conflicts[0].linked);
+        assert_eq!(other, conflicts[0].staged.cid);
+        assert_eq!(published, manifest.objects[&"a".repeat(64)].cid.cid);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+
+use actix::prelude::*;
+use chrono::{DateTime, Utc};
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::status::{self, TargetPins};
+use crate::{history, messages, output, repo};
+use git_lfs_ipfs_lib::{dag, error::Error, ipfs, spec::Object};
+
+/// How many of the largest objects to list
+const LARGEST_COUNT: usize = 10;
+
+/// Objects added to the local store in one month
+#[derive(Debug, Serialize)]
+pub struct Growth {
+    /// `YYYY-MM`, or `unknown`
+    pub month: String,
+    pub objects: usize,
+    pub size: u64,
+}
+
+/// The last root published in one month, and how much it held
+#[derive(Debug, Serialize)]
+pub struct Published {
+    /// `YYYY-MM`
+    pub month: String,
+    pub root: String,
+    /// `None` if its manifest couldn't be read
+    pub objects: Option<usize>,
+    pub size: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// What `stats` reports
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub objects: usize,
+    pub total_size: u64,
+    /// What the objects take up on the local node, with blocks they share counted once.
+    /// `None` if the node couldn't tell.
+    pub deduplicated_size: Option<u64>,
+    pub deduplicated_error: Option<String>,
+    pub growth: Vec<Growth>,
+    /// Growth of the published roots, from the publish history, oldest first
+    pub published: Vec<Published>,
+    pub largest: Vec<Object>,
+    /// `None` if the node couldn't be asked
+    pub pinned: Option<usize>,
+    pub pinned_error: Option<String>,
+    pub pin_targets: Vec<TargetPins>,
+}
+
+/// The last entry of every month in `history`, oldest first
+fn monthly(history: Vec<history::Entry>) -> Vec<(String, history::Entry)> {
+    let mut months = BTreeMap::new();
+    for entry in history {
+        months.insert(entry.published_at.format("%Y-%m").to_string(), entry);
+    }
+    months.into_iter().collect()
+}
+
+/// Reads the manifest of the last root published in every month
+fn published(history: Vec<history::Entry>) -> impl Future<Item = Vec<Published>, Error = Error> {
+    future::join_all(monthly(history).into_iter().map(|(month, entry)| {
+        let root = entry.root.clone();
+        entry
+            .root
+            .to_cid()
+            .map_err(|_| Error::IpfsPathParseError("Invalid root CID"))
+            .into_future()
+            .and_then(ipfs::manifest_get)
+            .then(move |result| {
+                Ok::<_, Error>(match result {
+                    Ok(manifest) => Published {
+                        month,
+                        root,
+                        objects: Some(manifest.objects.len()),
+                        size: Some(manifest.objects.values().map(|entry| entry.size).sum()),
+                        error: None,
+                    },
+                    Err(err) => Published {
+                        month,
+                        root,
+                        objects: None,
+                        size: None,
+                        error: Some(err.to_string()),
+                    },
+                })
+            })
+    }))
+}
+
+/// Summarizes the local LFS store: totals, deduplicated size, the largest objects, growth per
+/// month locally and in published roots, and how many objects the node and pin targets have.
+#[derive(Default)]
+pub struct Stats {
+    json: bool,
+}
+
+impl Stats {
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+}
+
+impl Actor for Stats {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Stats as Actor>::Context) {
+        let load = || -> Result<_, CliError> { Ok((repo::local_objects()?, history::load()?)) };
+        let (mut objects, history) = load().unwrap_or_else(|err| error::exit(err));
+
+        let mut growth: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+        for object in &objects {
+            let month = repo::lfs_object_path(&object.oid)
+                .ok()
+                .and_then(|path| std::fs::metadata(path).ok())
+                .and_then(|metadata| metadata.modified().ok())
+                .map(|modified| DateTime::<Utc>::from(modified).format("%Y-%m").to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            let entry = growth.entry(month).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += object.size;
+        }
+
+        objects.sort_by(|a, b| b.size.cmp(&a.size));
+        let cids: Vec<Cid> = objects
+            .iter()
+            .filter_map(|object| {
+                ipfs::oid_to_cid(cid::Codec::DagProtobuf, &object.oid)
+                    .wait()
+                    .ok()
+            })
+            .collect();
+        let mut report = Report {
+            objects: objects.len(),
+            total_size: objects.iter().map(|object| object.size).sum(),
+            deduplicated_size: None,
+            deduplicated_error: None,
+            growth: growth
+                .into_iter()
+                .map(|(month, (objects, size))| Growth {
+                    month,
+                    objects,
+                    size,
+                })
+                .collect(),
+            published: vec![],
+            largest: objects.iter().take(LARGEST_COUNT).cloned().collect(),
+            pinned: None,
+            pinned_error: None,
+            pin_targets: vec![],
+        };
+
+        let deduplicated = if cids.is_empty() {
+            future::Either::A(future::ok(Ok(Some(0))))
+        } else {
+            future::Either::B(
+                dag::stat_all(&cids)
+                    .map(|stat| stat.deduplicated_size())
+                    .then(Ok),
+            )
+        };
+        let strings: Vec<String> = cids.iter().map(Cid::to_string).collect();
+        let pins = ipfs::pin_ls().then(Ok::<_, Error>);
+        ctx.wait(
+            actix::fut::wrap_future(pins.join4(
+                deduplicated,
+                published(history),
+                status::target_pins(strings.clone()),
+            ))
+            .then(move |result: Result<_, Error>, actor: &mut Self, _ctx| {
+                let (pins, deduplicated, published, pin_targets) =
+                    result.unwrap_or_else(|err| error::exit(CliError::IpfsApiError(err)));
+                match pins {
+                    Ok(pins) => {
+                        report.pinned = Some(
+                            strings
+                                .iter()
+                                .filter(|cid| pins.keys.contains_key(*cid))
+                                .count(),
+                        );
+                    }
+                    Err(err) => report.pinned_error = Some(err.to_string()),
+                }
+                match deduplicated {
+                    Ok(Some(size)) => report.deduplicated_size = Some(size),
+                    Ok(None) => {
+                        report.deduplicated_error =
+                            Some("the IPFS daemon is too old to report it".to_string())
+                    }
+                    Err(err) => report.deduplicated_error = Some(err.to_string()),
+                }
+                report.published = published;
+                report.pin_targets = pin_targets;
+                if actor.json {
+                    output::print_json("stats", &report);
+                } else {
+                    print(&report);
+                }
+                System::current().stop();
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
+
+fn print(report: &Report) {
+    println!("{}", messages::text("stats.objects", &[&report.objects]));
+    println!(
+        "{}",
+        messages::text("stats.total-size", &[&report.total_size])
+    );
+    println!(
+        "{}",
+        match (report.deduplicated_size, &report.deduplicated_error) {
+            (Some(size), _) => messages::text("stats.deduplicated", &[&size]),
+            (None, Some(err)) => messages::text("stats.deduplicated-unavailable", &[err]),
+            (None, None) => messages::text("stats.deduplicated-unknown", &[]),
+        }
+    );
+    println!("{}", messages::text("stats.growth", &[]));
+    for growth in &report.growth {
+        println!(
+            "{}",
+            messages::text(
+                "stats.growth-month",
+                &[&growth.month, &growth.objects, &growth.size]
+            )
+        );
+    }
+    println!("{}", messages::text("stats.published", &[]));
+    for published in &report.published {
+        println!(
+            "{}",
+            match (published.objects, published.size) {
+                (Some(objects), Some(size)) => messages::text(
+                    "stats.published-month",
+                    &[&published.month, &objects, &size, &published.root]
+                ),
+                _ => messages::text(
+                    "stats.published-unreadable",
+                    &[
+                        &published.month,
+                        &published.error.as_ref().map_or("", String::as_str),
+                        &published.root
+                    ]
+                ),
+            }
+        );
+    }
+    println!("{}", messages::text("stats.largest", &[]));
+    for object in &report.largest {
+        println!(
+            "{}",
+            messages::text("stats.largest-object", &[&object.oid, &object.size])
+        );
+    }
+    println!(
+        "{}",
+        match (report.pinned, &report.pinned_error) {
+            (Some(pinned), _) => messages::text("pins.local", &[&pinned]),
+            (None, Some(err)) => messages::text("pins.local-unavailable", &[err]),
+            (None, None) => messages::text("pins.local-unknown", &[]),
+        }
+    );
+    status::print_target_pins(&report.pin_targets);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn growth_is_published_per_month() {
+        let entry = |root: &str, published_at: &str| history::Entry {
+            root: root.to_string(),
+            key: "self".to_string(),
+            published_at: published_at.parse().unwrap(),
+        };
+        let months = monthly(vec![
+            entry("a", "2024-01-03T00:00:00Z"),
+            entry("b", "2024-01-30T00:00:00Z"),
+            entry("c", "2024-03-01T00:00:00Z"),
+        ]);
+        assert_eq!(
+            vec![("2024-01", "b"), ("2024-03", "c")],
+            months
+                .iter()
+                .map(|(month, entry)| (month.as_str(), entry.root.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+}
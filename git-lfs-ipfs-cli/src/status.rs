@@ -0,0 +1,323 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use actix::prelude::*;
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{history, messages, output, prune, repo, stage};
+use git_lfs_ipfs_lib::{
+    config,
+    error::Error,
+    ipfs, pin,
+    spec::{ipfs::Path, manifest::Manifest, Object},
+};
+
+/// How many of a pin target's pins are local objects
+#[derive(Debug, Serialize)]
+pub struct TargetPins {
+    pub target: String,
+    /// `None` if the target couldn't be asked
+    pub pinned: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// Where the local objects stand, as printed by `status`
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// The API that was asked, or `None` if none could be reached
+    pub api: Option<String>,
+    pub api_error: Option<String>,
+    pub objects: usize,
+    pub total_size: u64,
+    /// `None` if no API could be reached
+    pub pinned: Option<usize>,
+    pub unpinned: Vec<Object>,
+    pub pin_targets: Vec<TargetPins>,
+    /// Objects uploaded since the last publish, waiting for the next
+    pub staged: usize,
+    /// Objects marked for unpinning, waiting out their grace period
+    pub pending_delete: usize,
+    pub last_published: Option<history::Entry>,
+    /// What the IPNS key of the last publish points at now, `None` if it couldn't be resolved
+    pub ipns_root: Option<String>,
+    /// Whether the IPNS key no longer points at the last root published from here
+    pub ipns_drift: bool,
+    /// Local objects that are neither in the last published root nor staged for the next,
+    /// `None` if its manifest couldn't be read
+    pub unpublished: Option<Vec<Object>>,
+    pub manifest_error: Option<String>,
+}
+
+/// Local objects that `manifest` doesn't list and that aren't staged either
+fn unpublished(
+    objects: &[Object],
+    manifest: &Manifest,
+    staged: &HashSet<String>,
+) -> Vec<Object> {
+    objects
+        .iter()
+        .filter(|object| {
+            !manifest.objects.contains_key(&object.oid) && !staged.contains(&object.oid)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Asks every pin target which of `cids` it has pinned
+pub fn target_pins(cids: Vec<String>) -> impl Future<Item = Vec<TargetPins>, Error = Error> {
+    future::join_all(config::get().pin_target_urls.clone().into_iter().map(move |target| {
+        let cids = cids.clone();
+        pin::ls_at(&target, pin::PinType::Recursive).then(move |result| {
+            Ok::<_, Error>(match result {
+                Ok(pins) => TargetPins {
+                    target: target.to_string(),
+                    pinned: Some(cids.iter().filter(|cid| pins.keys.contains_key(*cid)).count()),
+                    error: None,
+                },
+                Err(err) => TargetPins {
+                    target: target.to_string(),
+                    pinned: None,
+                    error: Some(err.to_string()),
+                },
+            })
+        })
+    }))
+}
+
+/// What the IPNS key named `key_name` currently resolves to
+fn resolve_key(key_name: String) -> impl Future<Item = Cid, Error = Error> {
+    ipfs::key_list()
+        .and_then(move |key_list| {
+            key_list
+                .keys
+                .into_iter()
+                .find(|key| key.name == key_name)
+                .ok_or(Error::IpfsUploadNotPossible)
+        })
+        .and_then(|key| Path::from_str(&format!("/ipns/{}", key.id)))
+        .and_then(ipfs::resolve)
+}
+
+/// Reports how the local LFS store compares to the local node, the pin targets and the last
+/// published root: which objects are pinned where, which still wait to be published or
+/// unpinned, and whether the published root still matches what was recorded here.
+#[derive(Default)]
+pub struct Status {
+    json: bool,
+}
+
+impl Status {
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+}
+
+impl Actor for Status {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Status as Actor>::Context) {
+        let load = || -> Result<_, CliError> {
+            Ok((
+                repo::local_objects()?,
+                stage::load()?,
+                prune::load_pending()?.len(),
+                history::load()?.pop(),
+            ))
+        };
+        let (objects, staged, pending_delete, last_published) =
+            load().unwrap_or_else(|err| error::exit(err));
+        let cids: Vec<Option<String>> = objects
+            .iter()
+            .map(|object| {
+                ipfs::oid_to_cid(cid::Codec::DagProtobuf, &object.oid)
+                    .wait()
+                    .map(|cid| cid.to_string())
+                    .ok()
+            })
+            .collect();
+        let staged: HashSet<String> = staged.into_iter().map(|staged| staged.object.oid).collect();
+        let local = ipfs::ipfs_api_url()
+            .and_then(|url| ipfs::pin_ls().map(move |pins| (url, pins)))
+            .then(Ok::<_, Error>);
+        let targets = target_pins(cids.iter().flatten().cloned().collect());
+        let published = match last_published.clone() {
+            Some(entry) => future::Either::A(
+                entry
+                    .root
+                    .to_cid()
+                    .map_err(|_| Error::IpfsPathParseError("Invalid root CID"))
+                    .into_future()
+                    .and_then(ipfs::manifest_get)
+                    .then(Ok::<_, Error>)
+                    .join(resolve_key(entry.key).then(Ok))
+                    .map(|(manifest, root)| (Some(manifest), root.ok())),
+            ),
+            None => future::Either::B(future::ok((None, None))),
+        };
+        ctx.wait(
+            actix::fut::wrap_future(local.join3(targets, published)).then(
+                move |result: Result<_, Error>, actor: &mut Self, _ctx| {
+                    let (local, pin_targets, (manifest, ipns_root)) =
+                        result.unwrap_or_else(|err| error::exit(CliError::IpfsApiError(err)));
+                    let mut report = Report {
+                        api: None,
+                        api_error: None,
+                        objects: objects.len(),
+                        total_size: objects.iter().map(|object| object.size).sum(),
+                        pinned: None,
+                        unpinned: vec![],
+                        pin_targets,
+                        staged: staged.len(),
+                        pending_delete,
+                        ipns_drift: match (&ipns_root, &last_published) {
+                            (Some(root), Some(entry)) => root.to_string() != entry.root,
+                            _ => false,
+                        },
+                        ipns_root: ipns_root.map(|root| root.to_string()),
+                        last_published,
+                        unpublished: None,
+                        manifest_error: None,
+                    };
+                    match manifest {
+                        Some(Ok(manifest)) => {
+                            report.unpublished = Some(unpublished(&objects, &manifest, &staged))
+                        }
+                        Some(Err(err)) => report.manifest_error = Some(err.to_string()),
+                        None => {
+                            report.unpublished =
+                                Some(unpublished(&objects, &Manifest::new(), &staged))
+                        }
+                    }
+                    match local {
+                        Ok((url, pins)) => {
+                            report.unpinned = objects
+                                .into_iter()
+                                .zip(cids)
+                                .filter(|(_, cid)| {
+                                    cid.as_ref()
+                                        .map_or(true, |cid| !pins.keys.contains_key(cid))
+                                })
+                                .map(|(object, _)| object)
+                                .collect();
+                            report.api = Some(url.to_string());
+                            report.pinned = Some(report.objects - report.unpinned.len());
+                        }
+                        Err(err) => report.api_error = Some(err.to_string()),
+                    }
+                    if actor.json {
+                        output::print_json("status", &report);
+                    } else {
+                        print(&report);
+                    }
+                    System::current().stop();
+                    actix::fut::ok(())
+                },
+            ),
+        );
+    }
+}
+
+/// Prints how many local objects every pin target has, for `status` and `stats`
+pub fn print_target_pins(pin_targets: &[TargetPins]) {
+    for target in pin_targets {
+        println!(
+            "{}",
+            match (target.pinned, &target.error) {
+                (Some(pinned), _) => messages::text("pins.target", &[&target.target, &pinned]),
+                (None, Some(err)) => {
+                    messages::text("pins.target-unavailable", &[&target.target, err])
+                }
+                (None, None) => messages::text("pins.target-unknown", &[&target.target]),
+            }
+        );
+    }
+}
+
+fn print(report: &Report) {
+    println!(
+        "{}",
+        match (&report.api, &report.api_error) {
+            (Some(api), _) => messages::text("status.api", &[api]),
+            (None, Some(err)) => messages::text("status.api-unavailable", &[err]),
+            (None, None) => messages::text("status.api-unknown", &[]),
+        }
+    );
+    println!(
+        "{}",
+        messages::text("status.objects", &[&report.objects, &report.total_size])
+    );
+    if let Some(pinned) = report.pinned {
+        println!("{}", messages::text("pins.local", &[&pinned]));
+        for object in &report.unpinned {
+            println!(
+                "{}",
+                messages::text("status.not-pinned", &[&object.oid, &object.size])
+            );
+        }
+    }
+    print_target_pins(&report.pin_targets);
+    println!("{}", messages::text("status.staged", &[&report.staged]));
+    println!(
+        "{}",
+        messages::text("status.pending-delete", &[&report.pending_delete])
+    );
+    println!(
+        "{}",
+        match &report.last_published {
+            Some(entry) => messages::text(
+                "status.last-published",
+                &[&entry.root, &entry.key, &entry.published_at.to_rfc3339()]
+            ),
+            None => messages::text("status.never-published", &[]),
+        }
+    );
+    if report.ipns_drift {
+        println!(
+            "{}",
+            messages::text(
+                "status.ipns-drift",
+                &[&report.ipns_root.as_ref().map_or("", String::as_str)]
+            )
+        );
+    }
+    match (&report.unpublished, &report.manifest_error) {
+        (Some(unpublished), _) => {
+            for object in unpublished {
+                println!(
+                    "{}",
+                    messages::text("status.not-published", &[&object.oid, &object.size])
+                );
+            }
+        }
+        (None, Some(err)) => println!("{}", messages::text("status.manifest-unreadable", &[err])),
+        (None, None) => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn objects_are_unpublished_unless_listed_or_staged() {
+        let object = |oid: &str| Object {
+            oid: oid.repeat(64),
+            size: 1,
+        };
+        let (listed, staged, missing) = (object("a"), object("b"), object("c"));
+        let mut manifest = Manifest::new();
+        manifest
+            .insert(&listed, "QmPZ9gcCEpqKTo6aq61g2nXGUhM4iCL3ewB6LDXZCtioEB".to_string())
+            .unwrap();
+        let staged: HashSet<String> = vec![staged.oid.clone()].into_iter().collect();
+        assert_eq!(
+            vec![missing.oid.clone()],
+            unpublished(&[listed, object("b"), missing], &manifest, &staged)
+                .into_iter()
+                .map(|object| object.oid)
+                .collect::<Vec<_>>()
+        );
+    }
+}
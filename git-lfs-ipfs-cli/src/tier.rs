@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use cid::Cid;
+use futures::{future, prelude::*};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{self, CliError};
+use crate::lock::{self, StateLock};
+use crate::{messages, output, repo};
+use git_lfs_ipfs_lib::{config, ipfs, pin};
+
+/// How long objects stay on the local node after their last use, unless overridden
+const DEFAULT_COLD_AFTER_DAYS: u32 = 90;
+
+/// When an object was last uploaded or downloaded, and whether it was unpinned locally since
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Tier {
+    pub accessed_at: DateTime<Utc>,
+    /// Only pinned on the pin targets
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cold: bool,
+}
+
+/// Tiers are kept as a JSON object from OID to tier in `.git/lfs/ipfs/tiers`
+fn tiers_path() -> Result<PathBuf, CliError> {
+    repo::ipfs_state_dir().map(|dir| dir.join("tiers"))
+}
+
+pub fn load_tiers() -> Result<BTreeMap<String, Tier>, CliError> {
+    let path = tiers_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    serde_json::from_reader(std::fs::File::open(path).map_err(CliError::Io)?)
+        .map_err(CliError::SerdeJsonError)
+}
+
+fn save_tiers(tiers: &BTreeMap<String, Tier>) -> Result<(), CliError> {
+    let path = tiers_path()?;
+    let tmp = path.with_extension("tmp");
+    serde_json::to_writer(std::fs::File::create(&tmp).map_err(CliError::Io)?, tiers)
+        .map_err(CliError::SerdeJsonError)?;
+    std::fs::rename(tmp, path).map_err(CliError::Io)
+}
+
+/// OIDs of the objects that are only pinned on the pin targets
+pub fn cold_oids() -> Result<BTreeSet<String>, CliError> {
+    Ok(load_tiers()?
+        .into_iter()
+        .filter(|(_, tier)| tier.cold)
+        .map(|(oid, _)| oid)
+        .collect())
+}
+
+/// Records that `oid` is used now, returning whether it was cold. Uses are only recorded once
+/// tiering is enabled, or for objects that are cold.
+fn touch(oid: &str) -> Result<bool, CliError> {
+    let _lock = StateLock::acquire(&tiers_path()?)?;
+    let mut tiers = load_tiers()?;
+    let cold = tiers.get(oid).map_or(false, |tier| tier.cold);
+    if !cold && config::get().cold_after_days.is_none() {
+        return Ok(false);
+    }
+    tiers.insert(
+        oid.to_string(),
+        Tier {
+            accessed_at: Utc::now(),
+            cold: false,
+        },
+    );
+    save_tiers(&tiers)?;
+    Ok(cold)
+}
+
+/// Records a use of `oid`, stored as `cid`, and pins it on the local node again if it was
+/// cold. Failures are only logged, so that tiering never fails a transfer; an object that
+/// couldn't be pinned again is no longer cold, so `pin verify --repair` and `daemon` retry.
+pub fn accessed(oid: String, cid: Cid) -> impl Future<Item = (), Error = ()> {
+    let touched = {
+        let oid = oid.clone();
+        lock::off_reactor(move || touch(&oid))
+    };
+    touched.then(move |touched| match touched {
+        Ok(true) => future::Either::A(pin::add(cid).then(move |result| {
+            match result {
+                Ok(_) => info!("Pinned {} on the local node again", oid),
+                Err(err) => error!("Could not pin cold object {} again: {}", oid, err),
+            }
+            Ok(())
+        })),
+        Ok(false) => future::Either::B(future::ok(())),
+        Err(err) => {
+            error!("Could not record the use of {}: {}", oid, error::describe(&err));
+            future::Either::B(future::ok(()))
+        }
+    })
+}
+
+/// When `oid` was last used: its last recorded use, else when it entered the local store
+fn last_used(oid: &str, tiers: &BTreeMap<String, Tier>) -> Option<DateTime<Utc>> {
+    tiers.get(oid).map(|tier| tier.accessed_at).or_else(|| {
+        repo::lfs_object_path(oid)
+            .ok()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .and_then(|metadata| metadata.modified().ok())
+            .map(DateTime::<Utc>::from)
+    })
+}
+
+/// Moves every object that wasn't used for `after_days` to the cold tier: once the pin targets
+/// confirm they have it, it is unpinned from the local node. Returns the OIDs moved. Objects
+/// that fail to move stay on the local node for the next run.
+pub fn demote(after_days: u32) -> impl Future<Item = Vec<String>, Error = CliError> {
+    let stale = || -> Result<Vec<(String, DateTime<Utc>)>, CliError> {
+        if config::get().pin_target_urls.is_empty() {
+            return Err(CliError::NoPinTargets);
+        }
+        let tiers = load_tiers()?;
+        let cutoff = Utc::now() - Duration::days(i64::from(after_days));
+        Ok(repo::local_objects()?
+            .into_iter()
+            .filter(|object| !tiers.get(&object.oid).map_or(false, |tier| tier.cold))
+            .filter_map(|object| {
+                last_used(&object.oid, &tiers)
+                    .filter(|used| *used <= cutoff)
+                    .map(|used| (object.oid, used))
+            })
+            .collect())
+    };
+    future::result(stale()).and_then(|stale| {
+        future::join_all(stale.into_iter().map(|(oid, used)| {
+            ipfs::oid_to_cid(cid::Codec::DagProtobuf, &oid)
+                .and_then(|cid| pin::add_to_targets(cid.clone()).map(|_| cid))
+                .and_then(ipfs::pin_rm)
+                .then(move |result| Ok::<_, CliError>((oid, used, result)))
+        }))
+        .and_then(|results| {
+            let _lock = StateLock::acquire(&tiers_path()?)?;
+            let mut tiers = load_tiers()?;
+            let mut demoted = vec![];
+            for (oid, used, result) in results {
+                let used_since = tiers
+                    .get(&oid)
+                    .map_or(false, |tier| tier.accessed_at > used);
+                match result {
+                    // A transfer touched it while it was being unpinned, so it stays in the hot
+                    // tier and `pin verify --repair` pins it again
+                    Ok(_) if used_since => warn!("{} was used while moving to the cold tier", oid),
+                    Ok(_) => {
+                        tiers.insert(
+                            oid.clone(),
+                            Tier {
+                                accessed_at: used,
+                                cold: true,
+                            },
+                        );
+                        demoted.push(oid);
+                    }
+                    Err(err) => error!("Could not move {} to the cold tier: {}", oid, err),
+                }
+            }
+            save_tiers(&tiers)?;
+            Ok(demoted)
+        })
+    })
+}
+
+/// Moves objects that weren't used for a while to the cold tier once
+pub struct Demote {
+    after_days: u32,
+    json: bool,
+}
+
+impl Demote {
+    pub fn new(after_days: Option<u32>, json: bool) -> Self {
+        Self {
+            after_days: after_days
+                .or(config::get().cold_after_days)
+                .unwrap_or(DEFAULT_COLD_AFTER_DAYS),
+            json,
+        }
+    }
+}
+
+impl Actor for Demote {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Demote as Actor>::Context) {
+        ctx.wait(actix::fut::wrap_future(demote(self.after_days)).then(
+            |result, actor: &mut Self, _ctx| {
+                System::current().stop();
+                match result {
+                    Ok(demoted) if actor.json => output::print_json("tier", &demoted),
+                    Ok(demoted) => {
+                        for oid in &demoted {
+                            println!("{}", messages::text("tier.moved", &[oid]));
+                        }
+                    }
+                    Err(err) => error::exit(err),
+                }
+                actix::fut::ok(())
+            },
+        ));
+    }
+}
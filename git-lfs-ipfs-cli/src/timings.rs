@@ -0,0 +1,21 @@
+//! Writing the stage timings that [`git_lfs_ipfs_lib::timing`] records to
+//! `GIT_LFS_IPFS_TIMINGS`. Git runs the filters and transfer agent of many objects at once,
+//! so every run adds to the file under its lock.
+use git_lfs_ipfs_lib::{config, timing};
+
+use crate::lock::StateLock;
+
+/// Adds this run's timings to the configured file, if there is one. Failures are only logged,
+/// since the run itself is done by now.
+pub fn write() {
+    let path = match &config::get().timings_file {
+        Some(path) => path.clone(),
+        None => return,
+    };
+    let written = StateLock::acquire(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|_lock| timing::write(&path).map_err(|err| err.to_string()));
+    if let Err(err) = written {
+        warn!("Could not write timings to {}: {}", path.display(), err);
+    }
+}
@@ -1,12 +1,18 @@
+use std::collections::HashSet;
 use std::io::BufRead;
 
 use actix::prelude::*;
 use futures::{future, prelude::*, stream};
 
 use crate::error::CliError;
+use crate::hooks::{self, HookEvent};
+use crate::messages;
+use crate::stage;
+use crate::tier;
 use git_lfs_ipfs_lib::{
-    ipfs,
+    config, error, ipfs, pin, routing,
     spec::{self, transfer::custom},
+    LfsIpfs,
 };
 
 #[derive(Debug, Clone)]
@@ -58,13 +64,40 @@ impl StreamHandler<Input, CliError> for Transfer {
         match (self.engine.clone(), event) {
             (None, Input(custom::Event::Init(init))) => {
                 self.engine = Some(Engine::new(ctx.address(), init).start());
-                println!("{{}}");
+                ctx.wait(actix::fut::wrap_future(ipfs::capabilities()).then(
+                    |result, _actor: &mut Self, _ctx| {
+                        let response = match result {
+                            Ok(capabilities) => {
+                                debug!("Detected IPFS daemon {:?}", capabilities);
+                                if config::get().fast_resolve && !capabilities.ipns_pubsub {
+                                    warn!("Fast resolution is enabled, but the daemon does not use IPNS over pubsub, so names may resolve to stale roots");
+                                }
+                                custom::InitResponse::default()
+                            }
+                            Err(err @ error::Error::DaemonTooOld(_, _)) => custom::InitResponse {
+                                error: Some(custom::Error {
+                                    code: 1,
+                                    message: crate::error::describe(&CliError::IpfsApiError(err)),
+                                }),
+                            },
+                            Err(err) => {
+                                warn!("Could not detect IPFS daemon capabilities: {}", err);
+                                custom::InitResponse::default()
+                            }
+                        };
+                        println!(
+                            "{}",
+                            serde_json::to_string(&response).expect("Failed to serialize an event")
+                        );
+                        actix::fut::ok(())
+                    },
+                ));
             }
             (None, event) => {
-                panic!(CliError::UnexpectedEvent(event.0));
+                crate::error::exit(CliError::UnexpectedEvent(event.0));
             }
             (Some(_), Input(custom::Event::Init(init))) => {
-                panic!(CliError::UnexpectedEvent(custom::Event::Init(init)));
+                crate::error::exit(CliError::UnexpectedEvent(custom::Event::Init(init)));
             }
             (Some(_), Input(custom::Event::Terminate)) => {
                 debug!("Stopping system");
@@ -78,15 +111,12 @@ impl StreamHandler<Input, CliError> for Transfer {
                             debug!("Received response {:?}", response);
                             println!(
                                 "{}",
-                                serde_json::to_string(&response.0s)
+                                serde_json::to_string(&response.0)
                                     .expect("Failed to serialize an event")
                             );
                             actix::fut::ok(())
                         }
-                        Err(err) => {
-                            panic!("{:?}", err);
-                            actix::fut::err(())
-                        }
+                        Err(err) => crate::error::exit(err),
                     },
                 ));
             }
@@ -94,7 +124,7 @@ impl StreamHandler<Input, CliError> for Transfer {
     }
 
     fn error(&mut self, err: CliError, ctx: &mut Context<Self>) -> Running {
-        panic!("{:?}", err);
+        crate::error::exit(err);
     }
 }
 
@@ -118,87 +148,369 @@ impl Handler<Output> for Transfer {
 struct Engine {
     transfer: actix::Addr<Transfer>,
     init: custom::Init,
+    /// OIDs of objects found as UnixFS files, which are downloaded whole instead of as a block
+    unixfs: HashSet<String>,
 }
 
 impl Engine {
     fn new(transfer: actix::Addr<Transfer>, init: custom::Init) -> Self {
-        Self { transfer, init }
+        Self {
+            transfer,
+            init,
+            unixfs: HashSet::new(),
+        }
     }
-}
-
-impl Actor for Engine {
-    type Context = Context<Self>;
-}
 
-impl Handler<Input> for Engine {
-    type Result = ResponseActFuture<Self, Output, CliError>;
-    fn handle(&mut self, event: Input, ctx: &mut <Self as Actor>::Context) -> Self::Result {
-        match (event.0, &self.init.operation) {
-            (custom::Event::Download(download), custom::Operation::Download) => {
-                let cid = ipfs::sha256_to_cid(cid::Codec::DagProtobuf, &download.object.oid).wait().ok();
-                if let Some(cid) = cid {
-                    let oid = download.object.oid.clone();
-                    let mut output = std::env::current_dir().unwrap();
-                    output.push(&download.object.oid);
-                    Box::new(
-                        actix::fut::wrap_stream(
-                            ipfs::block_get_to_fs(spec::ipfs::Path::ipfs(cid.clone()), output.clone())
-                                .map_err(CliError::IpfsApiError),
-                        )
-                        .fold(0, move |mut bytes_so_far, x, actor: &mut Self, ctx| {
-                            bytes_so_far += x as u64;
+    /// Downloads an object through the gateways in read-through mode, see
+    /// [`LfsIpfs::read_through`]. Nothing is written before the object checks out.
+    fn read_through(
+        &self,
+        download: custom::Download,
+    ) -> ResponseActFuture<Self, Output, CliError> {
+        let oid = download.object.oid;
+        let mut output = std::env::current_dir().unwrap();
+        output.push(&oid);
+        let lfs = match config::get().root.clone() {
+            Some(root) => LfsIpfs::with_root(root),
+            None => LfsIpfs::new(),
+        };
+        Box::new(actix::fut::wrap_future::<_, Self>(
+            lfs.read_through(&oid)
+                .map_err(CliError::IpfsApiError)
+                .and_then({
+                    let output = output.clone();
+                    move |(cid, content)| {
+                        std::fs::write(&output, &content)
+                            .map(|_| (cid, content.len() as u64))
+                            .map_err(CliError::Io)
+                    }
+                })
+                .then(move |result| {
+                    Ok(match result {
+                        Ok((cid, len)) => {
                             println!(
                                 "{}",
                                 serde_json::to_string(&custom::Event::Progress(custom::Progress {
                                     oid: oid.clone(),
-                                    bytes_so_far,
-                                    bytes_since_last: x as u64,
+                                    bytes_so_far: len,
+                                    bytes_since_last: len,
                                 }))
                                 .expect("Failed to serialize an event")
                             );
-                            // TODO: Don't disobey actix style and just print events here, there must be a better way...
-                            // ctx.spawn(actix::fut::wrap_future(actor.transfer.send(
-                            //     Output(custom::Event::Progress(custom::Progress {
-                            //         oid: oid.clone(),
-                            //         bytes_so_far,
-                            //         bytes_since_last: x,
-                            //     })),
-                            // ).then(|_| {
-                            //     future::ok(())
-                            // })));
-                            actix::fut::ok(bytes_so_far)
-                        })
-                        .map(move |_, _, _| {
-                            Output(custom::Event::Complete(custom::Complete {
-                                oid: download.object.oid.clone(),
-                                error: None,
-                                path: Some(output),
-                            }))
-                        }),
-                    )
-                } else {
-                    Box::new(actix::fut::wrap_future::<_, Self>(future::ok(Output(
-                        custom::Event::Complete(custom::Complete {
-                            oid: download.object.oid.clone(),
+                            download_complete(oid, &cid, output)
+                        }
+                        Err(err) => Output(custom::Event::Complete(custom::Complete {
+                            oid,
+                            error: Some(custom::Error {
+                                code: 1,
+                                message: crate::error::describe(&err),
+                            }),
+                            path: None,
+                        })),
+                    })
+                }),
+        ))
+    }
+
+    /// With a provider check configured, fails a download right away when no node provides
+    /// the object, unless a mirror can. The download goes ahead if the check itself fails.
+    fn download_if_provided(
+        &self,
+        download: custom::Download,
+        cid: cid::Cid,
+    ) -> ResponseActFuture<Self, Output, CliError> {
+        let timeout = match config::get().provider_check {
+            Some(timeout) => timeout,
+            None => return self.download_or_retry(download, cid),
+        };
+        Box::new(
+            actix::fut::wrap_future(
+                routing::find_providers(&cid, 1, timeout)
+                    .then(Ok::<_, CliError>)
+                    .and_then({
+                        let cid = cid.clone();
+                        move |providers| match providers {
+                            Ok(ref providers) if providers.is_empty() => future::Either::A(
+                                ipfs::provide_from_mirrors(cid)
+                                    .then(|provided| Ok(provided.unwrap_or(0) > 0)),
+                            ),
+                            Ok(_) => future::Either::B(future::ok(true)),
+                            Err(err) => {
+                                debug!("Could not look for providers of {}: {}", cid, err);
+                                future::Either::B(future::ok(true))
+                            }
+                        }
+                    }),
+            )
+            .and_then(
+                move |provided, actor: &mut Self, _ctx| -> ResponseActFuture<Self, Output, CliError> {
+                    if provided {
+                        return actor.download_or_retry(download, cid);
+                    }
+                    Box::new(actix::fut::ok(Output(custom::Event::Complete(
+                        custom::Complete {
+                            oid: download.object.oid,
                             error: Some(custom::Error {
                                 code: 404,
-                                message: "Object not found".to_string(),
+                                message: messages::text("transfer.no-providers", &[&cid]),
                             }),
                             path: None,
-                        }),
+                        },
                     ))))
+                },
+            ),
+        )
+    }
+
+    /// Downloads an object, and if that fails, asks the configured mirrors to provide it and
+    /// tries once more.
+    fn download_or_retry(
+        &self,
+        download: custom::Download,
+        cid: cid::Cid,
+    ) -> ResponseActFuture<Self, Output, CliError> {
+        let retry = (download.clone(), cid.clone());
+        Box::new(self.download(download, cid).then(
+            move |result, _actor: &mut Self, _ctx| -> ResponseActFuture<Self, Output, CliError> {
+                match result {
+                    Err(err) if !config::get().mirror_api_urls.is_empty() => {
+                        let (download, cid) = retry;
+                        warn!(
+                            "Could not download {}, asking mirrors to provide it: {}",
+                            download.object.oid, err
+                        );
+                        Box::new(
+                            actix::fut::wrap_future(
+                                ipfs::provide_from_mirrors(cid.clone())
+                                    .then(|_| Ok::<_, CliError>(())),
+                            )
+                            .and_then(move |_, actor: &mut Self, _ctx| {
+                                actor.download(download, cid)
+                            }),
+                        )
+                    }
+                    result => Box::new(actix::fut::result(result)),
                 }
+            },
+        ))
+    }
+
+    /// Downloads an object, stored under `cid`, into the current directory.
+    fn download(
+        &self,
+        download: custom::Download,
+        cid: cid::Cid,
+    ) -> ResponseActFuture<Self, Output, CliError> {
+        let oid = download.object.oid.clone();
+        let mut output = std::env::current_dir().unwrap();
+        output.push(&download.object.oid);
+        let unixfs = self.unixfs.contains(&oid);
+        if config::get().trustless && !unixfs {
+            return Box::new(
+                actix::fut::wrap_future(
+                    ipfs::gateway_block_get(cid.clone())
+                        .map_err(CliError::IpfsApiError)
+                        .and_then({
+                            let output = output.clone();
+                            move |block| {
+                                std::fs::write(&output, &block)
+                                    .map(|_| block.len() as u64)
+                                    .map_err(CliError::Io)
+                            }
+                        }),
+                )
+                .map(move |len, _, _| {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&custom::Event::Progress(custom::Progress {
+                            oid: oid.clone(),
+                            bytes_so_far: len,
+                            bytes_since_last: len,
+                        }))
+                        .expect("Failed to serialize an event")
+                    );
+                    download_complete(oid, &cid, output)
+                }),
+            );
+        }
+        Box::new(
+            actix::fut::wrap_stream(
+                if unixfs {
+                    future::Either::A(ipfs::cat_to_fs(
+                        spec::ipfs::Path::ipfs(cid.clone()),
+                        output.clone(),
+                    ))
+                } else {
+                    future::Either::B(ipfs::block_get_to_fs(
+                        spec::ipfs::Path::ipfs(cid.clone()),
+                        output.clone(),
+                    ))
+                }
+                .map_err(CliError::IpfsApiError),
+            )
+            .fold(0, move |mut bytes_so_far, x, actor: &mut Self, ctx| {
+                bytes_so_far += x as u64;
+                println!(
+                    "{}",
+                    serde_json::to_string(&custom::Event::Progress(custom::Progress {
+                        oid: oid.clone(),
+                        bytes_so_far,
+                        bytes_since_last: x as u64,
+                    }))
+                    .expect("Failed to serialize an event")
+                );
+                // TODO: Don't disobey actix style and just print events here, there must be a better way...
+                // ctx.spawn(actix::fut::wrap_future(actor.transfer.send(
+                //     Output(custom::Event::Progress(custom::Progress {
+                //         oid: oid.clone(),
+                //         bytes_so_far,
+                //         bytes_since_last: x,
+                //     })),
+                // ).then(|_| {
+                //     future::ok(())
+                // })));
+                actix::fut::ok(bytes_so_far)
+            })
+            .map(move |_, _, _| {
+                download_complete(download.object.oid.clone(), &cid, output)
+            }),
+        )
+    }
+}
+
+/// Reports an object that couldn't be located. Only an object missing from the root is a 404,
+/// anything else, such as the root failing to resolve, is reported as it is.
+fn locate_failed(oid: String, err: error::Error) -> custom::Complete {
+    let error = if err.kind() == error::ErrorKind::NotFound {
+        debug!("Could not find {}: {}", oid, err);
+        custom::Error {
+            code: 404,
+            message: messages::text("transfer.not-found", &[&oid]),
+        }
+    } else {
+        custom::Error {
+            code: 1,
+            message: crate::error::describe(&CliError::IpfsApiError(err)),
+        }
+    };
+    custom::Complete {
+        oid,
+        error: Some(error),
+        path: None,
+    }
+}
+
+/// Reports a finished download, unless the post-download hook rejects it.
+fn download_complete(oid: String, cid: &cid::Cid, output: std::path::PathBuf) -> Output {
+    let hook = hooks::post_download(&HookEvent {
+        oid: Some(&oid),
+        cid: Some(cid.to_string()),
+        path: Some(&output),
+    });
+    Output(custom::Event::Complete(match hook {
+        Ok(()) => custom::Complete {
+            oid,
+            error: None,
+            path: Some(output),
+        },
+        Err(err) => custom::Complete {
+            oid,
+            error: Some(custom::Error {
+                code: 1,
+                message: crate::error::describe(&err),
+            }),
+            path: None,
+        },
+    }))
+}
+
+impl Actor for Engine {
+    type Context = Context<Self>;
+}
+
+impl Handler<Input> for Engine {
+    type Result = ResponseActFuture<Self, Output, CliError>;
+    fn handle(&mut self, event: Input, ctx: &mut <Self as Actor>::Context) -> Self::Result {
+        match (event.0, &self.init.operation) {
+            (custom::Event::Download(download), custom::Operation::Download) => {
+                if config::get().read_through {
+                    return self.read_through(download);
+                }
+                let oid = download.object.oid.clone();
+                let cid: Box<Future<Item = (cid::Cid, bool), Error = error::Error>> =
+                    match config::get().root.clone() {
+                        Some(root) => Box::new(LfsIpfs::with_root(root).locate(&oid)),
+                        None => Box::new(
+                            ipfs::oid_to_cid(cid::Codec::DagProtobuf, &oid).map(|cid| (cid, false)),
+                        ),
+                    };
+                Box::new(
+                    actix::fut::wrap_future(cid.then(Ok::<_, CliError>)).and_then(
+                        move |cid, actor: &mut Self, ctx| match cid {
+                            Ok((cid, unixfs)) => {
+                                ctx.spawn(actix::fut::wrap_future(tier::accessed(
+                                    oid.clone(),
+                                    cid.clone(),
+                                )));
+                                if unixfs {
+                                    actor.unixfs.insert(oid);
+                                }
+                                actor.download_if_provided(download, cid)
+                            }
+                            Err(err) => Box::new(actix::fut::wrap_future::<_, Self>(
+                                future::ok(Output(custom::Event::Complete(locate_failed(
+                                    oid, err,
+                                )))),
+                            )),
+                        },
+                    ),
+                )
             }
-            // Upload transfer is dummy, the smudge filter adds files to IPFS already
-            // TODO: just check the sha256 hash with a /api/v0/block/get
+            // The clean filter adds files to IPFS already, so uploads only stage the object for
+            // the next publish, once enough pin targets have it if any are configured, and once
+            // it reads back intact through a gateway in paranoid mode
             (custom::Event::Upload(upload), custom::Operation::Upload) => {
-                Box::new(actix::fut::wrap_future::<_, Self>(future::ok(Output(
-                    custom::Event::Complete(custom::Complete {
-                        oid: upload.object.oid,
-                        error: None,
-                        path: None,
-                    }),
-                ))))
+                let object = upload.object;
+                let oid = object.oid.clone();
+                let read_back = if config::get().paranoid {
+                    future::Either::A(LfsIpfs::new().read_back(&object.oid))
+                } else {
+                    future::Either::B(future::ok(()))
+                };
+                Box::new(actix::fut::wrap_future::<_, Self>(
+                    ipfs::oid_to_cid(cid::Codec::DagProtobuf, &object.oid)
+                        .and_then(|cid| {
+                            if config::get().pin_target_urls.is_empty() {
+                                future::Either::A(future::ok(cid))
+                            } else {
+                                future::Either::B(
+                                    pin::add_to_targets(cid.clone()).map(move |_| cid),
+                                )
+                            }
+                        })
+                        .and_then(|cid| read_back.map(|_| cid))
+                        .map_err(CliError::IpfsApiError)
+                        .and_then(move |cid| stage::stage(&object, &cid).map(|_| cid))
+                        .and_then({
+                            let oid = oid.clone();
+                            move |cid| tier::accessed(oid, cid).then(|_| Ok::<_, CliError>(()))
+                        })
+                        .then(move |result| {
+                            Ok(Output(custom::Event::Complete(custom::Complete {
+                                oid,
+                                error: result.err().map(|err| custom::Error {
+                                    code: match err {
+                                        CliError::IpfsApiError(error::Error::OidConflict {
+                                            ..
+                                        }) => 409,
+                                        _ => 1,
+                                    },
+                                    message: crate::error::describe(&err),
+                                }),
+                                path: None,
+                            })))
+                        }),
+                ))
             }
             (event, _) => Box::new(actix::fut::wrap_future::<_, Self>(future::err(
                 CliError::UnexpectedEvent(event),
@@ -0,0 +1,172 @@
+use std::collections::BTreeSet;
+
+use actix::prelude::*;
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{messages, output, prune, repo, tier};
+use git_lfs_ipfs_lib::{error::Error, ipfs, spec::Object};
+
+/// Outcome of cross-checking the local LFS store against the local node's pins
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub pinned: usize,
+    /// Objects left unpinned on purpose, in the cold tier
+    pub cold: usize,
+    /// Objects pending delete or pruned, which are left as prune wants them
+    pub removed: usize,
+    pub repinned: Vec<String>,
+    pub missing: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Splits `objects` into those that should be pinned, and how many are cold or removed on
+/// purpose
+fn to_check(
+    objects: Vec<Object>,
+    cold: &BTreeSet<String>,
+    removed: &BTreeSet<String>,
+) -> (Vec<Object>, usize, usize) {
+    let (mut check, mut cold_count, mut removed_count) = (vec![], 0, 0);
+    for object in objects {
+        if cold.contains(&object.oid) {
+            cold_count += 1;
+        } else if removed.contains(&object.oid) {
+            removed_count += 1;
+        } else {
+            check.push(object);
+        }
+    }
+    (check, cold_count, removed_count)
+}
+
+/// Checks every local LFS object against `pin/ls` and, when `repair` is set, re-pins the missing
+/// ones. Objects in the cold tier, and objects prune is removing or removed, are skipped, so
+/// that neither is undone.
+pub fn reconcile(repair: bool) -> impl Future<Item = Report, Error = CliError> {
+    let local = repo::local_objects().and_then(|objects| {
+        Ok(to_check(objects, &tier::cold_oids()?, &prune::removed_oids()?))
+    });
+    future::result(local).and_then(move |(objects, cold, removed)| {
+        ipfs::pin_ls()
+            .map_err(CliError::IpfsApiError)
+            .and_then(move |pins| {
+                let mut report = Report {
+                    cold,
+                    removed,
+                    ..Report::default()
+                };
+                let mut missing: Vec<Object> = vec![];
+                for object in objects {
+                    let pinned = ipfs::oid_to_cid(cid::Codec::DagProtobuf, &object.oid)
+                        .wait()
+                        .map(|cid| pins.keys.contains_key(&cid.to_string()))
+                        .unwrap_or(false);
+                    if pinned {
+                        report.pinned += 1;
+                    } else {
+                        missing.push(object);
+                    }
+                }
+                if !repair {
+                    report.missing = missing.into_iter().map(|object| object.oid).collect();
+                    return future::Either::A(future::ok(report));
+                }
+                future::Either::B(
+                    future::join_all(missing.into_iter().map(|object| {
+                        ipfs::oid_to_cid(cid::Codec::DagProtobuf, &object.oid)
+                            .and_then(ipfs::pin_add)
+                            .then(move |result: Result<_, Error>| {
+                                Ok::<_, CliError>((object.oid, result))
+                            })
+                    }))
+                    .map(move |results| {
+                        for (oid, result) in results {
+                            match result {
+                                Ok(_) => report.repinned.push(oid),
+                                Err(err) => report.failed.push((oid, err.to_string())),
+                            }
+                        }
+                        report
+                    }),
+                )
+            })
+    })
+}
+
+pub struct Verify {
+    repair: bool,
+    json: bool,
+}
+
+impl Verify {
+    pub fn new(repair: bool, json: bool) -> Self {
+        Self { repair, json }
+    }
+}
+
+impl Actor for Verify {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Verify as Actor>::Context) {
+        ctx.wait(
+            actix::fut::wrap_future(reconcile(self.repair)).then(
+                |result, actor: &mut Self, _ctx| {
+                    System::current().stop();
+                    match result {
+                        Ok(report) if actor.json => output::print_json("pin verify", &report),
+                        Ok(report) => {
+                            println!("{}", messages::text("pin-verify.pinned", &[&report.pinned]));
+                            if report.cold > 0 {
+                                println!("{}", messages::text("pin-verify.cold", &[&report.cold]));
+                            }
+                            if report.removed > 0 {
+                                println!(
+                                    "{}",
+                                    messages::text("pin-verify.removed", &[&report.removed])
+                                );
+                            }
+                            for oid in &report.missing {
+                                println!("{}", messages::text("pin-verify.missing", &[oid]));
+                            }
+                            for oid in &report.repinned {
+                                println!("{}", messages::text("pin-verify.repinned", &[oid]));
+                            }
+                            for (oid, err) in &report.failed {
+                                println!("{}", messages::text("pin-verify.failed", &[oid, err]));
+                            }
+                        }
+                        Err(err) => error::exit(err),
+                    }
+                    actix::fut::ok(())
+                },
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pruned_and_cold_objects_are_not_pinned_again() {
+        let object = |oid: &str| Object {
+            oid: oid.repeat(64),
+            size: 1,
+        };
+        let set = |oids: &[&str]| -> BTreeSet<String> {
+            oids.iter().map(|oid| oid.repeat(64)).collect()
+        };
+        let (check, cold, removed) = to_check(
+            vec![object("a"), object("b"), object("c"), object("d")],
+            &set(&["b"]),
+            &set(&["c", "d", "e"]),
+        );
+        assert_eq!(
+            vec!["a".repeat(64)],
+            check.into_iter().map(|object| object.oid).collect::<Vec<_>>()
+        );
+        assert_eq!((1, 2), (cold, removed));
+    }
+}
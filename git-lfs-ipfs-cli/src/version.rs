@@ -0,0 +1,126 @@
+use actix::prelude::*;
+use futures::prelude::*;
+use serde_derive::Serialize;
+
+use crate::{messages, output};
+use git_lfs_ipfs_lib::{ipfs, pointer};
+
+/// Operations of the custom transfer protocol that the transfer agent handles
+const TRANSFER_OPERATIONS: &[&str] = &["download", "upload"];
+
+/// What `version` reports
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    /// `None` if built outside of a git checkout
+    pub commit: Option<&'static str>,
+    pub lfs_pointer_versions: Vec<&'static str>,
+    pub lfs_transfer_operations: &'static [&'static str],
+    pub ipfs_api: &'static str,
+    pub ipfs_minimum_version: String,
+    /// Version of the daemon behind the API, `None` if none could be reached
+    pub ipfs_daemon_version: Option<String>,
+    pub ipfs_daemon_error: Option<String>,
+    pub features: Vec<&'static str>,
+}
+
+/// Prints the version, and with `verbose` everything a bug report needs about this build and
+/// the IPFS daemon it talks to.
+pub struct Version {
+    verbose: bool,
+    json: bool,
+}
+
+impl Version {
+    pub fn new(verbose: bool, json: bool) -> Self {
+        Self { verbose, json }
+    }
+}
+
+impl Actor for Version {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Version as Actor>::Context) {
+        let (major, minor, patch) = ipfs::MINIMUM_VERSION;
+        let mut report = Report {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: option_env!("GIT_LFS_IPFS_COMMIT"),
+            lfs_pointer_versions: std::iter::once(pointer::VERSION)
+                .chain(pointer::LEGACY_VERSIONS.iter().cloned())
+                .collect(),
+            lfs_transfer_operations: TRANSFER_OPERATIONS,
+            ipfs_api: "v0",
+            ipfs_minimum_version: format!("{}.{}.{}", major, minor, patch),
+            ipfs_daemon_version: None,
+            ipfs_daemon_error: None,
+            features: git_lfs_ipfs_lib::features(),
+        };
+        if !self.verbose {
+            if self.json {
+                output::print_json("version", &report);
+            } else {
+                println!("git-lfs-ipfs-cli {}", report.version);
+            }
+            System::current().stop();
+            return;
+        }
+        ctx.wait(
+            actix::fut::wrap_future(ipfs::version()).then(move |result, actor: &mut Self, _ctx| {
+                match result {
+                    Ok(version) => report.ipfs_daemon_version = Some(version.version),
+                    Err(err) => report.ipfs_daemon_error = Some(err.to_string()),
+                }
+                if actor.json {
+                    output::print_json("version", &report);
+                } else {
+                    print(&report);
+                }
+                System::current().stop();
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
+
+fn print(report: &Report) {
+    println!(
+        "git-lfs-ipfs-cli {} ({})",
+        report.version,
+        report.commit.map_or_else(
+            || messages::text("version.commit-unknown", &[]),
+            String::from
+        )
+    );
+    println!(
+        "{}",
+        messages::text(
+            "version.pointers",
+            &[&report.lfs_pointer_versions.join(", ")]
+        )
+    );
+    println!(
+        "{}",
+        messages::text(
+            "version.operations",
+            &[&report.lfs_transfer_operations.join(", ")]
+        )
+    );
+    println!(
+        "{}",
+        messages::text(
+            "version.api",
+            &[&report.ipfs_api, &report.ipfs_minimum_version]
+        )
+    );
+    println!(
+        "{}",
+        match (&report.ipfs_daemon_version, &report.ipfs_daemon_error) {
+            (Some(version), _) => messages::text("version.daemon", &[version]),
+            (None, Some(err)) => messages::text("version.daemon-unavailable", &[err]),
+            (None, None) => messages::text("version.daemon-unknown", &[]),
+        }
+    );
+    println!(
+        "{}",
+        messages::text("version.features", &[&report.features.join(", ")])
+    );
+}
@@ -0,0 +1,80 @@
+use actix::prelude::*;
+use cid::ToCid;
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{messages, output};
+use git_lfs_ipfs_lib::ipfs;
+
+/// Subscribes to the roots announced for an IPNS name and pins each one as it arrives,
+/// so that mirrors replicate new objects without waiting for the IPNS record to propagate.
+pub struct Watch {
+    name: String,
+    /// Peer IDs whose announcements are trusted. Empty trusts everyone on the topic.
+    from: Vec<String>,
+    json: bool,
+}
+
+impl Watch {
+    pub fn new(name: String, from: Vec<String>, json: bool) -> Self {
+        Self { name, from, json }
+    }
+}
+
+/// A root pinned as it was announced. With `--json`, each is printed as a line of its own.
+#[derive(Serialize)]
+struct Pinned {
+    root: String,
+}
+
+impl Actor for Watch {
+    type Context = Context<Self>;
+    fn started(&mut self, ctx: &mut <Watch as Actor>::Context) {
+        let topic = ipfs::announce_topic(&self.name);
+        let from = self.from.clone();
+        let json = self.json;
+        info!("Watching {} for new roots", topic);
+        ctx.wait(
+            actix::fut::wrap_future(
+                ipfs::pubsub_sub(&topic)
+                    .map_err(CliError::IpfsApiError)
+                    .filter(move |message| {
+                        let trusted = from.is_empty() || from.contains(&message.from);
+                        if !trusted {
+                            warn!("Ignoring announcement from untrusted peer {}", message.from);
+                        }
+                        trusted
+                    })
+                    .filter_map(|message| {
+                        ipfs::multibase_decode(&message.data)
+                            .and_then(|data| String::from_utf8(data).ok())
+                            .and_then(|root| root.trim().to_cid().ok())
+                    })
+                    .for_each(move |root| {
+                        info!("Pinning announced root {}", root);
+                        ipfs::pin_add(root.clone()).then(move |result| {
+                            match result {
+                                Ok(_) if json => output::print_json(
+                                    "watch",
+                                    &Pinned {
+                                        root: root.to_string(),
+                                    },
+                                ),
+                                Ok(_) => println!("{}", messages::text("watch.pinned", &[&root])),
+                                Err(err) => error!("Could not pin {}: {}", root, err),
+                            }
+                            future::ok::<(), CliError>(())
+                        })
+                    }),
+            )
+            .then(|result, _actor: &mut Self, _ctx| {
+                System::current().stop();
+                if let Err(err) = result {
+                    error::exit(err);
+                }
+                actix::fut::ok(())
+            }),
+        );
+    }
+}
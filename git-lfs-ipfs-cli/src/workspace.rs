@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use actix::prelude::*;
+use serde_derive::Serialize;
+
+use crate::error::{self, CliError};
+use crate::{messages, output};
+
+/// Path of the workspace file, also read from `git-lfs-ipfs.workspace` in the git config
+const WORKSPACE_ENV: &str = "GIT_LFS_IPFS_WORKSPACE";
+
+/// The repositories of a workspace file: one path per line, relative to the file, with blank
+/// lines and lines starting with `#` ignored.
+fn repositories(file: &Path) -> Result<Vec<PathBuf>, CliError> {
+    let base = file.parent().unwrap_or_else(|| Path::new("."));
+    Ok(std::fs::read_to_string(file)
+        .map_err(CliError::Io)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect())
+}
+
+/// The arguments that run `command` in a single repository
+fn subcommand(command: &str) -> &'static [&'static str] {
+    match command {
+        "fetch" => &["fetch"],
+        "verify" => &["pin", "verify"],
+        "prune" => &["prune"],
+        "publish" => &["publish"],
+        _ => unreachable!("clap only accepts the commands above"),
+    }
+}
+
+/// Outcome of a command in one repository of the workspace
+#[derive(Debug, Serialize)]
+pub struct RepositoryReport {
+    pub path: PathBuf,
+    pub success: bool,
+    /// What the command printed with `--json`, if it succeeded
+    pub data: Option<serde_json::Value>,
+}
+
+/// Runs a command in every repository listed in a workspace file, one after another, each
+/// with its own configuration.
+pub struct Workspace {
+    file: Option<PathBuf>,
+    command: String,
+    args: Vec<String>,
+    /// Settings the git config of the current directory put into the environment, which must
+    /// not leak into the other repositories
+    applied: Vec<String>,
+    json: bool,
+}
+
+impl Workspace {
+    pub fn new(
+        file: Option<PathBuf>,
+        command: String,
+        args: Vec<String>,
+        applied: Vec<String>,
+        json: bool,
+    ) -> Self {
+        Self {
+            file,
+            command,
+            args,
+            applied,
+            json,
+        }
+    }
+
+    fn run(&self, cli: &Path, path: &Path) -> Result<RepositoryReport, CliError> {
+        let mut command = Command::new(cli);
+        command
+            .args(subcommand(&self.command))
+            .args(&self.args)
+            .current_dir(path);
+        for var in &self.applied {
+            command.env_remove(var);
+        }
+        if !self.json {
+            println!("==> {}", path.display());
+            let status = command.status().map_err(CliError::Io)?;
+            return Ok(RepositoryReport {
+                path: path.to_path_buf(),
+                success: status.success(),
+                data: None,
+            });
+        }
+        let output = command
+            .arg("--json")
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(CliError::Io)?;
+        Ok(RepositoryReport {
+            path: path.to_path_buf(),
+            success: output.status.success(),
+            data: serde_json::from_slice::<serde_json::Value>(&output.stdout)
+                .ok()
+                .and_then(|envelope| envelope.get("data").cloned()),
+        })
+    }
+}
+
+impl Actor for Workspace {
+    type Context = Context<Self>;
+    fn started(&mut self, _ctx: &mut <Workspace as Actor>::Context) {
+        System::current().stop();
+        let file = match self
+            .file
+            .clone()
+            .or_else(|| std::env::var_os(WORKSPACE_ENV).map(PathBuf::from))
+        {
+            Some(file) => file,
+            None => error::exit(CliError::NoWorkspace),
+        };
+        let cli = std::env::current_exe().unwrap_or_else(|err| error::exit(CliError::Io(err)));
+        let repositories = repositories(&file).unwrap_or_else(|err| error::exit(err));
+        let mut reports = vec![];
+        for path in &repositories {
+            match self.run(&cli, path) {
+                Ok(report) => reports.push(report),
+                Err(err) => {
+                    warn!("Could not run {} in {}: {}", self.command, path.display(), err);
+                    reports.push(RepositoryReport {
+                        path: path.clone(),
+                        success: false,
+                        data: None,
+                    });
+                }
+            }
+        }
+        let failed = reports.iter().filter(|report| !report.success).count();
+        if self.json {
+            output::print_json("workspace", &reports);
+        } else {
+            for report in reports.iter().filter(|report| !report.success) {
+                println!(
+                    "{}",
+                    messages::text("workspace.failed", &[&report.path.display()])
+                );
+            }
+        }
+        if failed > 0 {
+            error::exit(CliError::WorkspaceFailed(failed, reports.len()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn workspace_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "git-lfs-ipfs-workspace-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn repositories_are_relative_to_the_file() {
+        let dir = workspace_dir("file");
+        let file = dir.join("workspace");
+        std::fs::write(&file, "# assets\nmodels\n\n  ../datasets  \n#old\n").unwrap();
+        assert_eq!(
+            vec![dir.join("models"), dir.join("../datasets")],
+            repositories(&file).unwrap()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Runs every repository's command through a script that reports, as the data of its
+    /// JSON output, how it was run, and that fails in a repository named `failing`
+    #[cfg(unix)]
+    #[test]
+    fn commands_run_in_each_repository_with_its_own_configuration() {
+        use std::os::unix::fs::PermissionsExt;
+        const APPLIED: &str = "GIT_LFS_IPFS_WORKSPACE_TEST_APPLIED";
+        let dir = workspace_dir("run");
+        let cli = dir.join("cli");
+        std::fs::write(
+            &cli,
+            format!(
+                "#!/bin/sh\nprintf '{{\"command\":\"test\",\"data\":{{\"args\":\"%s\",\"dir\":\"%s\",\"leaked\":\"%s\"}}}}' \"$*\" \"$(basename \"$PWD\")\" \"${}\"\n[ \"$(basename \"$PWD\")\" != failing ]\n",
+                APPLIED
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&cli, std::fs::Permissions::from_mode(0o755)).unwrap();
+        for repository in &["models", "failing"] {
+            std::fs::create_dir_all(dir.join(repository)).unwrap();
+        }
+        std::env::set_var(APPLIED, "from the current repository");
+        let workspace = Workspace::new(
+            None,
+            "verify".to_string(),
+            vec!["--all".to_string()],
+            vec![APPLIED.to_string()],
+            true,
+        );
+
+        let report = workspace.run(&cli, &dir.join("models")).unwrap();
+        assert!(report.success);
+        assert_eq!(
+            serde_json::json!({
+                "args": "pin verify --all --json",
+                "dir": "models",
+                "leaked": "",
+            }),
+            report.data.unwrap()
+        );
+        let report = workspace.run(&cli, &dir.join("failing")).unwrap();
+        assert!(!report.success);
+        assert!(workspace.run(&cli, &dir.join("missing")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Drives a real `git lfs` client through the custom transfer agent and extension.
+//!
+//! These tests need `git`, `git-lfs`, and a running IPFS daemon, so they are ignored by
+//! default. Run them with `cargo test -p git-lfs-ipfs-cli -- --ignored`.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn cli_path() -> PathBuf {
+    let mut path = std::env::current_exe().unwrap();
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.push("git-lfs-ipfs-cli");
+    path
+}
+
+/// Every setting from the README, scoped to a single repository instead of `~/.gitconfig`.
+/// The standalone transfer agent lets push and clone go straight to IPFS without an LFS server.
+fn lfs_config() -> Vec<(&'static str, String)> {
+    let cli = cli_path().display().to_string();
+    vec![
+        ("lfs.customtransfer.ipfs.path", cli.clone()),
+        ("lfs.customtransfer.ipfs.args", "transfer".to_string()),
+        ("lfs.customtransfer.ipfs.concurrent", "true".to_string()),
+        ("lfs.customtransfer.ipfs.direction", "both".to_string()),
+        ("lfs.standalonetransferagent", "ipfs".to_string()),
+        ("lfs.extension.ipfs.clean", format!("{} clean %f", cli)),
+        ("lfs.extension.ipfs.smudge", format!("{} smudge %f", cli)),
+        ("lfs.extension.ipfs.priority", "0".to_string()),
+        ("user.name", "git-lfs-ipfs".to_string()),
+        ("user.email", "git-lfs-ipfs@localhost".to_string()),
+    ]
+}
+
+fn git<P: AsRef<Path>>(dir: P, args: &[&str]) {
+    let status = Command::new("git")
+        .current_dir(dir.as_ref())
+        .args(args)
+        .status()
+        .expect("could not run git");
+    assert!(status.success(), "git {:?} failed with {}", args, status);
+}
+
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "git-lfs-ipfs-conformance-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Scratch(path)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+#[ignore]
+fn push_clone_and_fsck_round_trip() {
+    let scratch = Scratch::new("round-trip");
+    let remote = scratch.0.join("remote.git");
+    let work = scratch.0.join("work");
+    let clone = scratch.0.join("clone");
+    let contents: Vec<u8> = (0..1 << 20).map(|i: u32| (i * 7 % 251) as u8).collect();
+
+    git(&scratch.0, &["init", "--bare", remote.to_str().unwrap()]);
+    git(&scratch.0, &["init", work.to_str().unwrap()]);
+    git(&work, &["lfs", "install", "--local"]);
+    for (key, value) in lfs_config() {
+        git(&work, &["config", key, &value]);
+    }
+    git(&work, &["lfs", "track", "*.bin"]);
+    std::fs::write(work.join("asset.bin"), &contents).unwrap();
+    git(&work, &["add", ".gitattributes", "asset.bin"]);
+    git(&work, &["commit", "-m", "Add asset"]);
+    git(&work, &["remote", "add", "origin", remote.to_str().unwrap()]);
+    git(&work, &["push", "origin", "HEAD:refs/heads/master"]);
+
+    let mut clone_args: Vec<String> = vec!["clone".to_string()];
+    for (key, value) in lfs_config() {
+        clone_args.push("--config".to_string());
+        clone_args.push(format!("{}={}", key, value));
+    }
+    clone_args.push(remote.to_str().unwrap().to_string());
+    clone_args.push(clone.to_str().unwrap().to_string());
+    git(
+        &scratch.0,
+        &clone_args.iter().map(String::as_str).collect::<Vec<_>>(),
+    );
+
+    assert_eq!(contents, std::fs::read(clone.join("asset.bin")).unwrap());
+    git(&clone, &["lfs", "fsck"]);
+}
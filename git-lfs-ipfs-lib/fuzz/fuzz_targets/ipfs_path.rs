@@ -0,0 +1,4 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| git_lfs_ipfs_lib::fuzz::ipfs_path(data));
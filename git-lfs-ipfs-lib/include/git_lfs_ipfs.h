@@ -0,0 +1,32 @@
+/* C bindings for git-lfs-ipfs-lib, built with
+ * `cargo rustc -p git-lfs-ipfs-lib --release --features ffi --crate-type cdylib`. */
+#ifndef GIT_LFS_IPFS_H
+#define GIT_LFS_IPFS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define GIT_LFS_IPFS_OK 0
+#define GIT_LFS_IPFS_INVALID_ARGUMENT -1
+#define GIT_LFS_IPFS_FAILED -2
+
+typedef struct git_lfs_ipfs git_lfs_ipfs;
+
+git_lfs_ipfs *git_lfs_ipfs_new(void);
+void git_lfs_ipfs_free(git_lfs_ipfs *handle);
+void git_lfs_ipfs_string_free(char *s);
+
+int git_lfs_ipfs_upload(git_lfs_ipfs *handle, const char *oid, const uint8_t *data, size_t len,
+                        char **cid_out);
+int git_lfs_ipfs_download(git_lfs_ipfs *handle, const char *oid, const char *path);
+int git_lfs_ipfs_resolve(git_lfs_ipfs *handle, const char *oid, char **cid_out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
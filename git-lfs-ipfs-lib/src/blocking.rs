@@ -0,0 +1,68 @@
+//! Synchronous wrappers around [`LfsIpfs`](crate::LfsIpfs), for scripts and build tools that
+//! don't run an actix `System` of their own.
+//!
+//! Each [`LfsIpfs`] owns a `System` and drives it while a call is in progress, so it must not
+//! be used from a thread that is already running one.
+use actix::{System, SystemRunner};
+use cid::Cid;
+use futures::prelude::*;
+
+use std::io::{Read, Write};
+
+use crate::error::Error;
+use crate::spec::ipfs::Path;
+
+pub struct LfsIpfs {
+    inner: crate::LfsIpfs,
+    runner: SystemRunner,
+}
+
+impl LfsIpfs {
+    pub fn new() -> Self {
+        Self::from_async(crate::LfsIpfs::new())
+    }
+
+    pub fn with_root(root: Path) -> Self {
+        Self::from_async(crate::LfsIpfs::with_root(root))
+    }
+
+    fn from_async(inner: crate::LfsIpfs) -> Self {
+        let runner = System::new("git-lfs-ipfs-blocking");
+        crate::ipfs::install_client_connector();
+        Self { inner, runner }
+    }
+
+    /// Reads an object's root block from `reader` and stores it, see
+    /// [`LfsIpfs::upload_object`](crate::LfsIpfs::upload_object).
+    pub fn upload_object<R: Read>(&mut self, oid: &str, mut reader: R) -> Result<Cid, Error> {
+        let mut block = vec![];
+        reader.read_to_end(&mut block).map_err(Error::Io)?;
+        let size = block.len() as u64;
+        let stream = futures::stream::once::<_, std::io::Error>(Ok(block.into()));
+        self.runner
+            .block_on(self.inner.upload_object(oid, size, stream))
+    }
+
+    /// Writes an object's root block to `writer`, returning its size.
+    pub fn download_object<W: Write>(&mut self, oid: &str, mut writer: W) -> Result<u64, Error> {
+        let block = self
+            .runner
+            .block_on(self.inner.download_object(oid).concat2())?;
+        writer.write_all(&block).map_err(Error::Io)?;
+        Ok(block.len() as u64)
+    }
+
+    pub fn resolve(&mut self, oid: &str) -> Result<Cid, Error> {
+        self.runner.block_on(self.inner.resolve(oid))
+    }
+
+    pub fn publish(&mut self, root: Cid, key_name: &str) -> Result<Path, Error> {
+        self.runner.block_on(self.inner.publish(root, key_name))
+    }
+}
+
+impl Default for LfsIpfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
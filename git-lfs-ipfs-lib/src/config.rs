@@ -0,0 +1,750 @@
+use lazy_static::lazy_static;
+use url::Url;
+
+use crate::error::Error;
+use crate::spec::ipfs::Path;
+use crate::spec::manifest::Layout;
+use crate::storage::DiskGuard;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Comma-separated IPFS API endpoints tried after the local daemon, as URLs or multiaddrs
+const API_ENV: &str = "GIT_LFS_IPFS_API";
+/// Comma-separated API endpoints of mirror nodes asked to provide objects no one seems to have
+const MIRRORS_ENV: &str = "GIT_LFS_IPFS_MIRRORS";
+/// Comma-separated API endpoints of team nodes or clusters that pin every uploaded object
+#[cfg(feature = "cluster")]
+const PIN_TARGETS_ENV: &str = "GIT_LFS_IPFS_PIN_TARGETS";
+/// How many pin targets must have pinned an object for its upload to succeed
+#[cfg(feature = "cluster")]
+const PIN_QUORUM_ENV: &str = "GIT_LFS_IPFS_PIN_QUORUM";
+/// Comma-separated gateways tried for reads once every API endpoint failed
+const GATEWAY_ENV: &str = "GIT_LFS_IPFS_GATEWAY";
+/// `post` or `get`, the HTTP method of API requests without a body
+const API_METHOD_ENV: &str = "GIT_LFS_IPFS_API_METHOD";
+/// `path` or `subdomain`
+const GATEWAY_STYLE_ENV: &str = "GIT_LFS_IPFS_GATEWAY_STYLE";
+/// Set to `true` to download verified raw blocks from gateways instead of using an API
+const TRUSTLESS_ENV: &str = "GIT_LFS_IPFS_TRUSTLESS";
+/// Set to `true` to announce newly published roots over pubsub
+const ANNOUNCE_ENV: &str = "GIT_LFS_IPFS_ANNOUNCE";
+/// Set to `true` to resolve IPNS names from the first record found and cache the result
+const FAST_RESOLVE_ENV: &str = "GIT_LFS_IPFS_FAST_RESOLVE";
+/// PEM file of extra CA certificates to trust for HTTPS endpoints
+const CA_BUNDLE_ENV: &str = "GIT_LFS_IPFS_CA_BUNDLE";
+/// Shell command run before a file is added to IPFS, which can veto the upload
+const PRE_UPLOAD_HOOK_ENV: &str = "GIT_LFS_IPFS_PRE_UPLOAD_HOOK";
+/// Shell command run after an object was downloaded, which can reject the download
+const POST_DOWNLOAD_HOOK_ENV: &str = "GIT_LFS_IPFS_POST_DOWNLOAD_HOOK";
+/// Comma-separated file extensions that may not be uploaded
+const DENY_EXTENSIONS_ENV: &str = "GIT_LFS_IPFS_DENY_EXTENSIONS";
+/// Comma-separated MIME types, or `type/*`, of content that may not be uploaded
+const DENY_MIME_TYPES_ENV: &str = "GIT_LFS_IPFS_DENY_MIME_TYPES";
+/// Largest file, in bytes, that may be uploaded
+const MAX_UPLOAD_SIZE_ENV: &str = "GIT_LFS_IPFS_MAX_UPLOAD_SIZE";
+/// clamd socket to scan uploads with, as a Unix socket path or `host:port`
+const CLAMD_ENV: &str = "GIT_LFS_IPFS_CLAMD";
+/// Set to `true` to look up credentials for API endpoints and gateways with `git credential`
+const CREDENTIAL_HELPER_ENV: &str = "GIT_LFS_IPFS_CREDENTIAL_HELPER";
+/// Whitespace-separated `<origin>=<username>:<password>` credentials for remote endpoints
+const CREDENTIALS_ENV: &str = "GIT_LFS_IPFS_CREDENTIALS";
+/// `/ipfs/<root cid>` or `/ipns/<name>` path of the published root to download objects from
+const ROOT_ENV: &str = "GIT_LFS_IPFS_ROOT";
+/// Name of the local key that manifests of roots published from here are signed with
+const SIGNING_KEY_ENV: &str = "GIT_LFS_IPFS_SIGNING_KEY";
+/// ID of the IPNS key that manifests of published roots must be signed with
+const TRUSTED_KEY_ENV: &str = "GIT_LFS_IPFS_TRUSTED_KEY";
+/// Directory to write a signed receipt to for every object that is published
+const RECEIPTS_ENV: &str = "GIT_LFS_IPFS_RECEIPTS";
+/// Days after their last use that objects are unpinned locally, once the pin targets have them
+const COLD_AFTER_DAYS_ENV: &str = "GIT_LFS_IPFS_COLD_AFTER_DAYS";
+/// Set to `true` to read objects through the gateways, checked against the signed manifest
+const READ_THROUGH_ENV: &str = "GIT_LFS_IPFS_READ_THROUGH";
+/// How published roots link their objects: `flat`, `fanout` or `manifest`
+const LAYOUT_ENV: &str = "GIT_LFS_IPFS_LAYOUT";
+/// What to do when a large upload doesn't fit under the node's StorageMax: `off`, `warn`, `gc` or `refuse`
+const DISK_GUARD_ENV: &str = "GIT_LFS_IPFS_DISK_GUARD";
+/// Set to `true` to read every upload back through a gateway and check it before it succeeds
+const PARANOID_ENV: &str = "GIT_LFS_IPFS_PARANOID";
+/// File to add per-stage timing histograms to, in the Prometheus text format
+const TIMINGS_ENV: &str = "GIT_LFS_IPFS_TIMINGS";
+/// Seconds to look for providers of an object before downloading it, failing fast if none exist
+const PROVIDER_CHECK_ENV: &str = "GIT_LFS_IPFS_PROVIDER_CHECK";
+
+lazy_static! {
+    static ref IPFS_PUBLIC_GATEWAY_URL: Url = Url::parse("https://ipfs.io/").unwrap();
+    /// Read from the environment on first use, see [`try_get`], unless [`set`] before that
+    static ref CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+    static ref CREDENTIAL_HELPER: RwLock<Option<CredentialHelper>> = RwLock::new(None);
+    /// What the credential helper answered for every origin asked so far
+    static ref HELPED_CREDENTIALS: Mutex<BTreeMap<String, Option<Credentials>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// IPFS API endpoints in failover order, after the local daemon found through `$IPFS_PATH/api`
+    pub api_urls: Vec<Url>,
+    /// Read-only gateways in failover order, used once no API endpoint is reachable
+    pub gateway_urls: Vec<Url>,
+    /// APIs of nodes that replicate the repository, asked to announce an object to the DHT
+    /// when it can't be downloaded, before trying again
+    pub mirror_api_urls: Vec<Url>,
+    /// APIs of nodes, like teammates' or an ipfs-cluster's, asked to pin every uploaded object
+    #[cfg(feature = "cluster")]
+    pub pin_target_urls: Vec<Url>,
+    /// How many of the pin targets must confirm a pin before an upload succeeds, all of them
+    /// if unset
+    #[cfg(feature = "cluster")]
+    pub pin_quorum: Option<usize>,
+    /// How requests to API endpoints are sent. Requests with a body are always POSTs.
+    pub api_method: ApiMethod,
+    pub gateway_style: GatewayStyle,
+    /// Download raw blocks from gateways and verify them against their CID, skipping the API
+    pub trustless: bool,
+    /// Announce every newly published root on the repository's pubsub topic
+    pub announce: bool,
+    /// Trade IPNS freshness for latency: accept the first record found instead of waiting for
+    /// a quorum, and reuse resolved names for a minute. Works best with IPNS over pubsub enabled.
+    pub fast_resolve: bool,
+    /// CA certificates trusted for HTTPS endpoints, in addition to the system's
+    pub ca_bundle: Option<PathBuf>,
+    /// Run before uploading, e.g. to scan for viruses; a non-zero exit aborts the upload
+    pub pre_upload_hook: Option<String>,
+    /// Run after downloading, e.g. for telemetry; a non-zero exit fails the download
+    pub post_download_hook: Option<String>,
+    /// Extensions, without the leading dot and in lowercase, of files refused for upload
+    pub denied_extensions: Vec<String>,
+    /// MIME types, in lowercase, of content refused for upload, going by its first bytes.
+    /// `type/*` refuses every subtype.
+    pub denied_mime_types: Vec<String>,
+    /// Files larger than this many bytes are refused for upload
+    pub max_upload_size: Option<u64>,
+    /// Where clamd listens, as a Unix socket path or `host:port`. Uploads are refused unless
+    /// it reports them clean.
+    pub clamd: Option<String>,
+    /// Download objects only through this published root's manifest. An `/ipfs/` root pins
+    /// downloads to a fixed set of objects, so builds are unaffected by later publishes.
+    pub root: Option<Path>,
+    /// Name of the local key to sign the manifest of every root built here with, such as the
+    /// IPNS key it is published under, or a separate key kept for signing
+    pub signing_key: Option<String>,
+    /// ID of the key whose signature a root's manifest must carry to be trusted. Objects are
+    /// only looked up in manifests that carry it, which exposes hijacked IPNS records and
+    /// tampered roots.
+    pub trusted_key: Option<String>,
+    /// Where `publish` writes a receipt for every object it links into a root, signed like the
+    /// manifest, which auditors can check against the published root later
+    pub receipts_dir: Option<PathBuf>,
+    /// Days after which objects that weren't uploaded or downloaded are unpinned from the local
+    /// node, once the pin targets confirmed they have them. They are pinned again when used.
+    pub cold_after_days: Option<u32>,
+    /// Read objects from the gateways only, and hand them out only once they match the root's
+    /// manifest and the manifest's signature verifies against the trusted key. Meant for
+    /// read-only mirrors, which can then use public gateways without being poisoned by them.
+    pub read_through: bool,
+    /// How roots published from here link their objects. Readers handle every layout.
+    pub layout: Layout,
+    /// How long to look for providers of an object before downloading it. If none are found,
+    /// the download fails right away instead of waiting on content no one has.
+    pub provider_check: Option<Duration>,
+    /// What to do before a large upload that would take the node past its Datastore.StorageMax
+    pub disk_guard: DiskGuard,
+    /// Read every uploaded object back through a gateway, re-hash it and compare it with what
+    /// the API returns before the upload succeeds, for data that can't be recreated
+    pub paranoid: bool,
+    /// Where to add timing histograms of hashing, adding, pinning, linking and publishing to,
+    /// see [`timing`](crate::timing)
+    pub timings_file: Option<PathBuf>,
+    /// Basic auth credentials for hosted APIs and gateways, keyed by origin like
+    /// `https://ipfs.example.com`
+    pub credentials: BTreeMap<String, Credentials>,
+    /// Ask git's credential helpers for the credentials of remote endpoints
+    pub credential_helper: bool,
+}
+
+/// A username and password or token, sent as basic auth
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// The `Authorization` header value
+    pub fn basic_authorization(&self) -> String {
+        format!(
+            "Basic {}",
+            base64::encode(&format!("{}:{}", self.username, self.password))
+        )
+    }
+}
+
+/// Looks up the credentials of an endpoint that has none configured, such as git's credential
+/// helpers do
+pub type CredentialHelper = fn(&Url) -> Option<Credentials>;
+
+/// Keeps passwords out of logs
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// go-ipfs 0.5 and later reject GET requests to the API, while every release accepts POST.
+/// GET is only left for older daemons behind proxies that don't let POSTs through.
+/// https://github.com/ipfs/kubo/blob/master/CHANGELOG.md#050-2020-04-28
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiMethod {
+    Post,
+    Get,
+}
+
+impl FromStr for ApiMethod {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "post" => Ok(ApiMethod::Post),
+            "get" => Ok(ApiMethod::Get),
+            _ => Err(()),
+        }
+    }
+}
+
+/// https://docs.ipfs.io/concepts/ipfs-gateway/#gateway-types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayStyle {
+    /// `https://<gateway>/ipfs/<cid>`
+    Path,
+    /// `https://<cidv1>.ipfs.<gateway>`, which gives every root its own origin
+    Subdomain,
+}
+
+impl FromStr for GatewayStyle {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(GatewayStyle::Path),
+            "subdomain" => Ok(GatewayStyle::Subdomain),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_urls: vec![],
+            gateway_urls: vec![IPFS_PUBLIC_GATEWAY_URL.clone()],
+            mirror_api_urls: vec![],
+            #[cfg(feature = "cluster")]
+            pin_target_urls: vec![],
+            #[cfg(feature = "cluster")]
+            pin_quorum: None,
+            api_method: ApiMethod::Post,
+            gateway_style: GatewayStyle::Path,
+            trustless: false,
+            announce: false,
+            fast_resolve: false,
+            ca_bundle: None,
+            pre_upload_hook: None,
+            post_download_hook: None,
+            denied_extensions: vec![],
+            denied_mime_types: vec![],
+            max_upload_size: None,
+            clamd: None,
+            root: None,
+            signing_key: None,
+            trusted_key: None,
+            receipts_dir: None,
+            cold_after_days: None,
+            read_through: false,
+            layout: Layout::default(),
+            provider_check: None,
+            disk_guard: DiskGuard::default(),
+            paranoid: false,
+            timings_file: None,
+            credentials: BTreeMap::new(),
+            credential_helper: false,
+        }
+    }
+}
+
+impl Config {
+    /// Credentials configured for the origin of `url`, if any
+    pub fn credentials_for(&self, url: &Url) -> Option<&Credentials> {
+        self.credentials.get(&url.origin().ascii_serialization())
+    }
+
+    /// Reads every setting from its environment variable, or for a variable `X` that is unset,
+    /// from the file named by `X_FILE`, as with secrets mounted into containers.
+    /// Fails with [`Error::InvalidConfig`] on the first setting that can't be used.
+    pub fn from_env() -> Result<Self, Error> {
+        let mut config = Self::default();
+        if let Some(api) = var(API_ENV) {
+            config.api_urls = parse_endpoints(&api);
+        }
+        if let Some(gateway) = var(GATEWAY_ENV) {
+            config.gateway_urls = parse_endpoints(&gateway);
+        }
+        if let Some(mirrors) = var(MIRRORS_ENV) {
+            config.mirror_api_urls = parse_endpoints(&mirrors);
+        }
+        #[cfg(feature = "cluster")]
+        {
+            if let Some(targets) = var(PIN_TARGETS_ENV) {
+                config.pin_target_urls = parse_endpoints(&targets);
+            }
+            if let Some(quorum) = var(PIN_QUORUM_ENV) {
+                config.pin_quorum = Some(parse_pin_quorum(&quorum, config.pin_target_urls.len())?);
+            }
+        }
+        if let Some(method) = var(API_METHOD_ENV) {
+            config.api_method = parse_var(API_METHOD_ENV, &method, "post or get")?;
+        }
+        if let Some(style) = var(GATEWAY_STYLE_ENV) {
+            config.gateway_style = parse_var(GATEWAY_STYLE_ENV, &style, "path or subdomain")?;
+        }
+        if let Some(trustless) = var(TRUSTLESS_ENV) {
+            config.trustless = parse_bool(TRUSTLESS_ENV, &trustless)?;
+        }
+        if let Some(announce) = var(ANNOUNCE_ENV) {
+            config.announce = parse_bool(ANNOUNCE_ENV, &announce)?;
+        }
+        if let Some(fast_resolve) = var(FAST_RESOLVE_ENV) {
+            config.fast_resolve = parse_bool(FAST_RESOLVE_ENV, &fast_resolve)?;
+        }
+        if let Some(ca_bundle) = var(CA_BUNDLE_ENV) {
+            config.ca_bundle = Some(PathBuf::from(ca_bundle));
+        }
+        if let Some(hook) = var(PRE_UPLOAD_HOOK_ENV) {
+            config.pre_upload_hook = Some(hook).filter(|hook| !hook.trim().is_empty());
+        }
+        if let Some(hook) = var(POST_DOWNLOAD_HOOK_ENV) {
+            config.post_download_hook = Some(hook).filter(|hook| !hook.trim().is_empty());
+        }
+        if let Some(extensions) = var(DENY_EXTENSIONS_ENV) {
+            config.denied_extensions = extensions
+                .split(',')
+                .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                .filter(|extension| !extension.is_empty())
+                .collect();
+        }
+        if let Some(mime_types) = var(DENY_MIME_TYPES_ENV) {
+            config.denied_mime_types = mime_types
+                .split(',')
+                .map(|mime_type| mime_type.trim().to_lowercase())
+                .filter(|mime_type| !mime_type.is_empty())
+                .collect();
+        }
+        if let Some(max_upload_size) = var(MAX_UPLOAD_SIZE_ENV) {
+            config.max_upload_size = Some(parse_var(
+                MAX_UPLOAD_SIZE_ENV,
+                &max_upload_size,
+                "a number of bytes",
+            )?);
+        }
+        if let Some(clamd) = var(CLAMD_ENV) {
+            config.clamd = Some(clamd).filter(|clamd| !clamd.trim().is_empty());
+        }
+        if let Some(credential_helper) = var(CREDENTIAL_HELPER_ENV) {
+            config.credential_helper = parse_bool(CREDENTIAL_HELPER_ENV, &credential_helper)?;
+        }
+        if let Some(credentials) = var(CREDENTIALS_ENV) {
+            config.credentials = parse_credentials(&credentials);
+        }
+        if let Some(layout) = var(LAYOUT_ENV) {
+            config.layout = parse_var(LAYOUT_ENV, &layout, "flat, fanout or manifest")?;
+        }
+        if let Some(seconds) = var(PROVIDER_CHECK_ENV) {
+            config.provider_check = parse_provider_check(&seconds)?;
+        }
+        if let Some(disk_guard) = var(DISK_GUARD_ENV) {
+            config.disk_guard = parse_var(DISK_GUARD_ENV, &disk_guard, "off, warn, gc or refuse")?;
+        }
+        if let Some(paranoid) = var(PARANOID_ENV) {
+            config.paranoid = parse_bool(PARANOID_ENV, &paranoid)?;
+        }
+        if let Some(timings) = var(TIMINGS_ENV) {
+            config.timings_file =
+                Some(PathBuf::from(timings)).filter(|path| !path.as_os_str().is_empty());
+        }
+        if let Some(key) = var(SIGNING_KEY_ENV) {
+            config.signing_key = Some(key.trim().to_string()).filter(|key| !key.is_empty());
+        }
+        if let Some(key) = var(TRUSTED_KEY_ENV) {
+            config.trusted_key = Some(key.trim().to_string()).filter(|key| !key.is_empty());
+        }
+        if let Some(receipts) = var(RECEIPTS_ENV) {
+            config.receipts_dir =
+                Some(PathBuf::from(receipts)).filter(|path| !path.as_os_str().is_empty());
+        }
+        if let Some(days) = var(COLD_AFTER_DAYS_ENV) {
+            config.cold_after_days =
+                Some(parse_var(COLD_AFTER_DAYS_ENV, &days, "a number of days")?);
+        }
+        if let Some(read_through) = var(READ_THROUGH_ENV) {
+            config.read_through = parse_bool(READ_THROUGH_ENV, &read_through)?;
+        }
+        if let Some(root) = var(ROOT_ENV) {
+            config.root = Some(parse_var(
+                ROOT_ENV,
+                &root,
+                "an /ipfs/<cid> or /ipns/<name> path",
+            )?);
+        }
+        Ok(config)
+    }
+}
+
+/// The configuration used by every IPFS request, read from the environment the first time it
+/// is asked for. Fails with [`Error::InvalidConfig`] if a setting can't be used.
+pub fn try_get() -> Result<Arc<Config>, Error> {
+    if let Some(config) = CONFIG.read().unwrap().as_ref() {
+        return Ok(config.clone());
+    }
+    let config = Arc::new(Config::from_env()?);
+    Ok(CONFIG.write().unwrap().get_or_insert(config).clone())
+}
+
+/// Like [`try_get`], for programs that checked the configuration with it or [`load`] first.
+/// Past that, an invalid setting is fatal rather than silently replaced by its default.
+pub fn get() -> Arc<Config> {
+    try_get().unwrap_or_else(|err| panic!("Invalid configuration: {}", err))
+}
+
+/// Replaces the configuration for all subsequent IPFS requests.
+pub fn set(config: Config) {
+    *CONFIG.write().unwrap() = Some(Arc::new(config));
+}
+
+/// Asks `helper` for the credentials of endpoints that have none configured, if
+/// [`Config::credential_helper`] is set. It only runs once a request goes to an endpoint, and
+/// only once per origin, so commands that never reach a hosted endpoint never run it.
+pub fn set_credential_helper(helper: CredentialHelper) {
+    *CREDENTIAL_HELPER.write().unwrap() = Some(helper);
+}
+
+/// The credentials configured for the origin of `url`, or else those the credential helper
+/// has for it, see [`set_credential_helper`].
+pub fn credentials_for(url: &Url) -> Option<Credentials> {
+    let config = get();
+    if let Some(credentials) = config.credentials_for(url) {
+        return Some(credentials.clone());
+    }
+    if !config.credential_helper {
+        return None;
+    }
+    let helper = (*CREDENTIAL_HELPER.read().unwrap())?;
+    ask_once(&mut HELPED_CREDENTIALS.lock().unwrap(), helper, url)
+}
+
+/// Asks `helper` for the credentials of `url`, unless `answers` has its origin already
+fn ask_once(
+    answers: &mut BTreeMap<String, Option<Credentials>>,
+    helper: CredentialHelper,
+    url: &Url,
+) -> Option<Credentials> {
+    answers
+        .entry(url.origin().ascii_serialization())
+        .or_insert_with(|| helper(url))
+        .clone()
+}
+
+/// Reads the configuration from the environment again, see [`Config::from_env`], and uses it
+/// for all subsequent IPFS requests.
+pub fn load() -> Result<(), Error> {
+    set(Config::from_env()?);
+    Ok(())
+}
+
+/// Parses how many of `targets` pin targets must confirm a pin, which can't be none of them or
+/// more than there are.
+#[cfg(feature = "cluster")]
+fn parse_pin_quorum(value: &str, targets: usize) -> Result<usize, Error> {
+    let expected = "a number from 1 to how many pin targets GIT_LFS_IPFS_PIN_TARGETS lists";
+    let quorum = parse_var(PIN_QUORUM_ENV, value, expected)?;
+    if quorum == 0 || quorum > targets {
+        return Err(Error::InvalidConfig {
+            var: PIN_QUORUM_ENV,
+            value: value.to_string(),
+            expected,
+        });
+    }
+    Ok(quorum)
+}
+
+/// Parses `value`, the value of `var`, failing with [`Error::InvalidConfig`] if it isn't
+/// `expected`.
+fn parse_var<T: FromStr>(
+    var: &'static str,
+    value: &str,
+    expected: &'static str,
+) -> Result<T, Error> {
+    value.trim().parse().map_err(|_| Error::InvalidConfig {
+        var,
+        value: value.to_string(),
+        expected,
+    })
+}
+
+/// Parses the value of an on/off setting. Anything but `true`, `1`, `false`, `0` or nothing is
+/// refused, so that a setting like `yes` doesn't quietly leave the feature off.
+fn parse_bool(var: &'static str, value: &str) -> Result<bool, Error> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" | "" => Ok(false),
+        _ => Err(Error::InvalidConfig {
+            var,
+            value: value.to_string(),
+            expected: "true, 1, false or 0",
+        }),
+    }
+}
+
+/// The provider check timeout in `seconds`, with 0 turning the check off
+fn parse_provider_check(seconds: &str) -> Result<Option<Duration>, Error> {
+    let seconds: u64 = parse_var(PROVIDER_CHECK_ENV, seconds, "a number of seconds")?;
+    Ok(Some(Duration::from_secs(seconds)).filter(|_| seconds > 0))
+}
+
+fn var(name: &str) -> Option<String> {
+    if let Ok(value) = std::env::var(name) {
+        return Some(value);
+    }
+    let path = std::env::var_os(format!("{}_FILE", name))?;
+    match std::fs::read_to_string(&path) {
+        Ok(value) => Some(value.trim_end_matches(&['\r', '\n'][..]).to_string()),
+        Err(err) => {
+            warn!("Could not read {} from {}: {}", name, PathBuf::from(path).display(), err);
+            None
+        }
+    }
+}
+
+/// Whitespace-separated `<origin>=<username>:<password>` entries
+fn parse_credentials(s: &str) -> BTreeMap<String, Credentials> {
+    s.split_whitespace()
+        .filter_map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            let origin = Url::parse(parts.next()?).ok()?.origin().ascii_serialization();
+            let mut secret = parts.next()?.splitn(2, ':');
+            let credentials = Credentials {
+                username: secret.next()?.to_string(),
+                password: secret.next()?.to_string(),
+            };
+            Some((origin, credentials))
+        })
+        .collect()
+}
+
+fn parse_endpoints(s: &str) -> Vec<Url> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|endpoint| !endpoint.is_empty())
+        .filter_map(|endpoint| {
+            let url = parse_endpoint(endpoint);
+            if url.is_none() {
+                warn!("Ignoring unparseable IPFS endpoint {}", endpoint);
+            }
+            url
+        })
+        .collect()
+}
+
+/// Accepts either a URL or a multiaddr like `/ip4/127.0.0.1/tcp/5001`, as found in `$IPFS_PATH/api`.
+pub fn parse_endpoint(endpoint: &str) -> Option<Url> {
+    use multiaddr::{AddrComponent, ToMultiaddr};
+    use std::net::IpAddr;
+    if !endpoint.starts_with('/') {
+        return Url::parse(endpoint).ok();
+    }
+    let multiaddr = endpoint.to_multiaddr().ok()?;
+    let mut addr: Option<IpAddr> = None;
+    let mut port: Option<u16> = None;
+    for addr_component in multiaddr.iter() {
+        match addr_component {
+            AddrComponent::IP4(v4addr) => addr = Some(v4addr.into()),
+            AddrComponent::IP6(v6addr) => addr = Some(v6addr.into()),
+            AddrComponent::TCP(tcpport) => port = Some(tcpport),
+            _ => {
+                return None;
+            }
+        }
+    }
+    match (addr, port) {
+        (Some(IpAddr::V6(addr)), Some(port)) => {
+            Url::parse(&format!("http://[{}]:{}/", addr, port)).ok()
+        }
+        (Some(addr), Some(port)) => Url::parse(&format!("http://{}:{}/", addr, port)).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn endpoints_parse_from_urls_and_multiaddrs() {
+        assert_eq!(
+            vec![
+                Url::parse("http://127.0.0.1:5001/").unwrap(),
+                Url::parse("http://[::1]:5001/").unwrap(),
+                Url::parse("https://ipfs.example.com/").unwrap(),
+            ],
+            parse_endpoints(
+                "/ip4/127.0.0.1/tcp/5001, /ip6/::1/tcp/5001,https://ipfs.example.com/,,/dns4/x"
+            )
+        );
+    }
+
+    #[test]
+    fn credentials_are_matched_by_origin() {
+        let mut config = Config::default();
+        config.credentials.insert(
+            "https://ipfs.example.com".to_string(),
+            Credentials {
+                username: "Aladdin".to_string(),
+                password: "open sesame".to_string(),
+            },
+        );
+        let credentials = config
+            .credentials_for(&Url::parse("https://ipfs.example.com/api/v0/add").unwrap())
+            .unwrap();
+        assert_eq!(
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==",
+            credentials.basic_authorization()
+        );
+        assert!(config
+            .credentials_for(&Url::parse("http://ipfs.example.com/").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn provider_check_is_seconds_or_off() {
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            parse_provider_check("5").unwrap()
+        );
+        assert_eq!(None, parse_provider_check("0").unwrap());
+        match parse_provider_check("5s") {
+            Err(Error::InvalidConfig { var, .. }) => assert_eq!(PROVIDER_CHECK_ENV, var),
+            result => panic!("expected a config error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn credential_helper_is_asked_once_per_origin() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static ASKED: AtomicUsize = AtomicUsize::new(0);
+        fn helper(url: &Url) -> Option<Credentials> {
+            ASKED.fetch_add(1, Ordering::SeqCst);
+            if url.host_str() == Some("ipfs.example.com") {
+                Some(Credentials {
+                    username: "ci".to_string(),
+                    password: "token".to_string(),
+                })
+            } else {
+                None
+            }
+        }
+        let mut answers = BTreeMap::new();
+        let url = |url: &str| Url::parse(url).unwrap();
+        for path in &["api/v0/add", "api/v0/cat"] {
+            let credentials = ask_once(
+                &mut answers,
+                helper,
+                &url(&format!("https://ipfs.example.com/{}", path)),
+            );
+            assert_eq!("ci", credentials.unwrap().username);
+        }
+        assert!(ask_once(&mut answers, helper, &url("https://gateway.example.com/")).is_none());
+        assert!(ask_once(
+            &mut answers,
+            helper,
+            &url("https://gateway.example.com/ipfs")
+        )
+        .is_none());
+        assert_eq!(2, ASKED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn credentials_parse_from_origin_entries() {
+        let credentials = parse_credentials(
+            "https://ipfs.example.com/=ci:tok:en\n  http://[::1]:5001=admin:pw bad-entry",
+        );
+        assert_eq!(
+            vec!["http://[::1]:5001", "https://ipfs.example.com"],
+            credentials.keys().map(String::as_str).collect::<Vec<_>>()
+        );
+        assert_eq!("tok:en", credentials["https://ipfs.example.com"].password);
+    }
+
+    #[cfg(feature = "cluster")]
+    #[test]
+    fn pin_quorum_is_at_least_one_and_at_most_every_target() {
+        assert_eq!(1, parse_pin_quorum("1", 3).unwrap());
+        assert_eq!(3, parse_pin_quorum(" 3", 3).unwrap());
+        for (quorum, targets) in &[("0", 3), ("4", 3), ("1", 0), ("two", 3)] {
+            match parse_pin_quorum(quorum, *targets) {
+                Err(Error::InvalidConfig { var, .. }) => assert_eq!(PIN_QUORUM_ENV, var),
+                result => panic!("expected a config error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn booleans_are_true_or_false_and_nothing_else() {
+        assert!(parse_bool(TRUSTLESS_ENV, "true").unwrap());
+        assert!(parse_bool(TRUSTLESS_ENV, " 1").unwrap());
+        assert!(parse_bool(TRUSTLESS_ENV, "TRUE").unwrap());
+        assert!(!parse_bool(TRUSTLESS_ENV, "false").unwrap());
+        assert!(!parse_bool(TRUSTLESS_ENV, "0").unwrap());
+        assert!(!parse_bool(TRUSTLESS_ENV, "").unwrap());
+        for value in &["yes", "on", "no", "ture"] {
+            match parse_bool(TRUSTLESS_ENV, value) {
+                Err(Error::InvalidConfig { var, .. }) => assert_eq!(TRUSTLESS_ENV, var),
+                result => panic!("expected a config error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_config_errors() {
+        assert_eq!(
+            1024u64,
+            parse_var(MAX_UPLOAD_SIZE_ENV, " 1024", "a number of bytes").unwrap()
+        );
+        match parse_var::<u64>(MAX_UPLOAD_SIZE_ENV, "10MB", "a number of bytes") {
+            Err(Error::InvalidConfig { var, value, .. }) => {
+                assert_eq!(MAX_UPLOAD_SIZE_ENV, var);
+                assert_eq!("10MB", value);
+            }
+            result => panic!("expected a config error, got {:?}", result),
+        }
+        assert!(parse_var::<Path>(
+            ROOT_ENV,
+            "ipfs/QmPZ9gcCEpqKTo6aq61g2nXGUhM4iCL3ewB6LDXZCtioEB",
+            "a path"
+        )
+        .is_err());
+        assert_eq!(
+            Layout::Fanout,
+            parse_var(LAYOUT_ENV, "fanout", "a layout").unwrap()
+        );
+        assert!(parse_var::<Layout>(LAYOUT_ENV, "nested", "a layout").is_err());
+        assert_eq!(
+            DiskGuard::Gc,
+            parse_var(DISK_GUARD_ENV, "gc", "a disk guard").unwrap()
+        );
+        assert!(parse_var::<DiskGuard>(DISK_GUARD_ENV, "ask", "a disk guard").is_err());
+    }
+}
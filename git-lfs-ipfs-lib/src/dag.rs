@@ -0,0 +1,241 @@
+//! IPLD nodes on the daemon: structured data like the manifest, and CAR files of whole DAGs.
+//! https://docs.ipfs.io/reference/api/http/#api-v0-dag-put
+use actix_web::{client, http::header, HttpMessage};
+use bytes::Bytes;
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+use url::Url;
+
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::ipfs::{
+    api_post, error_for_status, ipfs_api_url, json_lines, multipart_begin, multipart_boundary,
+    multipart_encode, multipart_end, send, send_with_fallback, write_api_url, Access, Endpoint,
+    MAX_BLOCK_SIZE,
+};
+use crate::spec::ipfs::{DagImportEvent, DagPutResponse, DagStat, DagStatResponse, Path};
+use crate::spec::manifest::Link;
+
+/// How a node is encoded
+/// https://ipld.io/docs/codecs/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// Compact binary encoding, how structured data is stored
+    DagCbor,
+    /// Readable encoding, with links as `{"/": "<cid>"}`
+    DagJson,
+    /// UnixFS files and directories
+    DagPb,
+}
+
+impl Codec {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Codec::DagCbor => "dag-cbor",
+            Codec::DagJson => "dag-json",
+            Codec::DagPb => "dag-pb",
+        }
+    }
+}
+
+fn link_to_cid(link: &Link) -> Result<Cid, Error> {
+    link.cid
+        .to_cid()
+        .map_err(|_| Error::IpfsPathParseError("Expected a CID"))
+}
+
+/// Stores `document`, given in the `input` codec, as a node encoded with `store`, and pins it
+/// if `pin` is set.
+pub fn put(
+    document: Bytes,
+    input: Codec,
+    store: Codec,
+    pin: bool,
+) -> impl Future<Item = Cid, Error = Error> {
+    write_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/dag/put").unwrap();
+            url.query_pairs_mut()
+                .append_pair("store-codec", store.as_str())
+                .append_pair("input-codec", input.as_str())
+                .append_pair("pin", &pin.to_string());
+            debug!("Sending dag put request to {}", url);
+            let (boundary, body) = multipart_encode(&document);
+            api_post(url)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("{}; boundary={}", mime::MULTIPART_FORM_DATA, boundary),
+                )
+                .body(body)
+                .unwrap()
+        })
+        .and_then(|client| {
+            client
+                .send()
+                .timeout(Duration::from_secs(600))
+                .map_err(Error::IpfsApiSendRequestError)
+        })
+        .and_then(error_for_status)
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+        .and_then(|res: DagPutResponse| link_to_cid(&res.cid))
+}
+
+/// Stores `document` as DAG-CBOR, see [`put`].
+pub fn put_json<T>(document: &T, pin: bool) -> impl Future<Item = Cid, Error = Error>
+where
+    T: serde::Serialize,
+{
+    future::result(serde_json::to_vec(document).map_err(|_| Error::SerializeJsonError)).and_then(
+        move |document| put(Bytes::from(document), Codec::DagJson, Codec::DagCbor, pin),
+    )
+}
+
+/// A node is a single block, but DAG-JSON spells out its links and bytes, taking several
+/// times the size of the block. An answer larger than this isn't a node.
+pub(crate) const MAX_NODE_SIZE: usize = 4 * MAX_BLOCK_SIZE;
+
+/// Fetches the node at `path` encoded with `output`, from the first API that has it.
+pub fn get_raw(path: Path, output: Codec) -> impl Future<Item = Bytes, Error = Error> {
+    send_with_fallback(Access::Read, move |endpoint| match endpoint {
+        Endpoint::Api(url) => {
+            let mut url = url.join("api/v0/dag/get").unwrap();
+            url.query_pairs_mut()
+                .append_pair("arg", &path.to_string())
+                .append_pair("output-codec", output.as_str());
+            Some(url)
+        }
+        Endpoint::Gateway(_) => None,
+    })
+    .and_then(error_for_status)
+    .and_then(|res| {
+        res.body()
+            .limit(MAX_NODE_SIZE)
+            .map_err(Error::IpfsApiPayloadError)
+    })
+}
+
+/// Fetches the node at `path` as DAG-JSON and deserializes it.
+pub fn get<T>(path: Path) -> impl Future<Item = T, Error = Error>
+where
+    T: serde::de::DeserializeOwned + 'static,
+{
+    get_raw(path, Codec::DagJson).and_then(|body| {
+        serde_json::from_slice(&body).map_err(Error::IpfsApiJsonStreamError)
+    })
+}
+
+/// The size and block count of the DAG under `cid`.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-dag-stat
+pub fn stat(cid: &Cid) -> impl Future<Item = DagStat, Error = Error> {
+    let cid = cid.to_string();
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/dag/stat").unwrap();
+            url.query_pairs_mut()
+                .append_pair("arg", &cid)
+                .append_pair("progress", "false");
+            debug!("Sending dag stat request to {}", url);
+            url
+        })
+        .and_then(send)
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+        .map(|stat: DagStatResponse| stat.into())
+}
+
+/// Statistics over the DAGs under all of `cids` at once, from which current daemons also
+/// report their size with shared blocks counted once, see
+/// [`DagStatResponse::deduplicated_size`].
+pub fn stat_all(cids: &[Cid]) -> impl Future<Item = DagStatResponse, Error = Error> {
+    let cids: Vec<String> = cids.iter().map(Cid::to_string).collect();
+    // Every DAG gets a line of statistics in the response
+    let limit = 64 * 1024 + cids.len() * 1024;
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/dag/stat").unwrap();
+            {
+                let mut query = url.query_pairs_mut();
+                for cid in &cids {
+                    query.append_pair("arg", cid);
+                }
+                query.append_pair("progress", "false");
+            }
+            debug!("Sending dag stat request for {} DAGs to {}", cids.len(), url);
+            url
+        })
+        .and_then(send)
+        .and_then(move |res| {
+            res.json()
+                .limit(limit)
+                .map_err(Error::IpfsApiJsonPayloadError)
+        })
+}
+
+/// Streams the DAG under `cid` as a CAR file.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-dag-export
+pub fn export(cid: &Cid) -> impl Stream<Item = Bytes, Error = Error> {
+    let cid = cid.to_string();
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/dag/export").unwrap();
+            url.query_pairs_mut().append_pair("arg", &cid);
+            debug!("Sending dag export request to {}", url);
+            url
+        })
+        .and_then(send)
+        .map(|res| res.payload().map_err(Error::IpfsApiPayloadError))
+        .flatten_stream()
+}
+
+/// Imports the CAR file in `car`, returning its roots, which are pinned if `pin_roots` is set.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-dag-import
+pub fn import<P, E>(car: P, pin_roots: bool) -> impl Future<Item = Vec<Cid>, Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+{
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/dag/import").unwrap();
+            url.query_pairs_mut().append_pair("pin-roots", &pin_roots.to_string());
+            debug!("Sending dag import request to {}", url);
+            let boundary = multipart_boundary();
+            api_post(url)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("{}; boundary={}", mime::MULTIPART_FORM_DATA, boundary),
+                )
+                .streaming(
+                    future::ok(Bytes::from(multipart_begin(None, &boundary).as_bytes()))
+                        .into_stream()
+                        .chain(car)
+                        .chain(
+                            future::ok(Bytes::from(multipart_end(&boundary).as_bytes()))
+                                .into_stream(),
+                        ),
+                )
+                .unwrap()
+        })
+        .and_then(|client| {
+            client
+                .send()
+                .timeout(Duration::from_secs(600))
+                .map_err(Error::IpfsApiSendRequestError)
+        })
+        .and_then(error_for_status)
+        .and_then(|res| json_lines(res.payload()).collect())
+        .and_then(|events: Vec<DagImportEvent>| {
+            events
+                .into_iter()
+                .filter_map(|event| event.root)
+                .map(|root| match root.pin_error_msg {
+                    Some(ref message) if !message.is_empty() => {
+                        Err(Error::IpfsApiJsonStreamError(
+                            <serde_json::Error as serde::de::Error>::custom(message),
+                        ))
+                    }
+                    _ => link_to_cid(&root.cid),
+                })
+                .collect()
+        })
+}
@@ -1,9 +1,16 @@
-use actix_web::{
-    client::SendRequestError, error::JsonPayloadError, error::PayloadError, error::ResponseError,
-    http::StatusCode, HttpResponse,
-};
+#[cfg(feature = "client")]
+use actix_web::{client::SendRequestError, error::JsonPayloadError, error::PayloadError};
+#[cfg(feature = "server")]
+use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
+#[cfg(feature = "server")]
+use crate::spec::{lfs::LfsErrorResponse, GIT_LFS_CONTENT_TYPE};
 use failure::Fail;
 
+/// Where the errors a server responds with are explained, sent along as their
+/// `documentation_url`
+#[cfg(feature = "server")]
+pub const DOCUMENTATION_URL: &str = "https://github.com/sameer/git-lfs-ipfs#server-errors";
+
 #[derive(Fail, Debug)]
 pub enum Error {
     #[fail(display = "A bad SHA2-256 hash was provided")]
@@ -14,22 +21,30 @@ pub enum Error {
     LocalApiUnavailableError,
     #[fail(display = "An error was encountered in parsing an IPFS path {}", _0)]
     IpfsPathParseError(&'static str),
+    #[cfg(feature = "client")]
     #[fail(
-        display = "An error was encountered in receiving a response from the IPFS API {:?}",
+        display = "An error was encountered in receiving a response from the IPFS API: {}",
         _0
     )]
     IpfsApiPayloadError(PayloadError),
+    #[cfg(feature = "client")]
     #[fail(
-        display = "An error was encountered in receiving a JSON response from the IPFS API {:?}",
+        display = "An error was encountered in receiving a JSON response from the IPFS API: {}",
         _0
     )]
     IpfsApiJsonPayloadError(JsonPayloadError),
     #[fail(
-        display = "An error was encountered while sending a request to the IPFS API {:?}",
+        display = "An error was encountered in parsing a streamed JSON response from the IPFS API: {}",
+        _0
+    )]
+    IpfsApiJsonStreamError(serde_json::Error),
+    #[cfg(feature = "client")]
+    #[fail(
+        display = "An error was encountered while sending a request to the IPFS API: {}",
         _0
     )]
     IpfsApiSendRequestError(SendRequestError),
-    #[fail(display = "An error was received from the IPFS API {:?}", _0)]
+    #[fail(display = "An error was received from the IPFS API: {}", _0.message())]
     IpfsApiResponseError(crate::spec::ipfs::Error),
     #[fail(
         display = "An object upload is impossible with your current configuration. You must use IPNS and have the matching key available locally."
@@ -43,29 +58,377 @@ pub enum Error {
     VerifyFailed,
     #[fail(display = "An internal server error occurred while serializing data to a json.")]
     SerializeJsonError,
+    #[fail(
+        display = "The IPFS daemon is version {}, but at least {} is required",
+        _0, _1
+    )]
+    DaemonTooOld(String, String),
+    #[fail(display = "The block received for {} did not match its CID", _0)]
+    BlockVerificationFailed(String),
+    #[fail(
+        display = "Object {} was declared as {} bytes, but {} were received",
+        oid, expected, received
+    )]
+    SizeMismatch {
+        oid: String,
+        expected: u64,
+        received: u64,
+    },
+    #[fail(
+        display = "Object {} is already stored as {}, and can't be replaced by {}",
+        oid, linked, pushed
+    )]
+    OidConflict {
+        oid: String,
+        linked: String,
+        pushed: String,
+    },
+    #[fail(
+        display = "Only {} pin targets confirmed pinning {}, but {} are required",
+        pinned, cid, required
+    )]
+    PinQuorumNotMet {
+        cid: String,
+        pinned: usize,
+        required: usize,
+    },
+    #[fail(
+        display = "Object {} read back through a gateway differs from what the IPFS API stores",
+        _0
+    )]
+    ReadBackMismatch(String),
+    #[fail(display = "The manifest of {} can't be trusted: {}", root, reason)]
+    ManifestNotTrusted { root: String, reason: String },
+    #[fail(display = "Reading objects through gateways needs {}", _0)]
+    ReadThroughMisconfigured(&'static str),
+    #[fail(display = "The share link was rejected: {}", _0)]
+    ShareLinkRejected(String),
+    #[fail(display = "The share link expired at {}", _0)]
+    ShareLinkExpired(String),
+    #[fail(display = "The receipt for {} doesn't hold: {}", oid, reason)]
+    ReceiptInvalid { oid: String, reason: String },
+    #[fail(
+        display = "The manifest of {} objects takes {} bytes, more than the {} that fit in one block",
+        objects, size, limit
+    )]
+    ManifestTooLarge {
+        objects: usize,
+        size: usize,
+        limit: usize,
+    },
+    #[fail(
+        display = "The IPFS node has {} bytes left under its Datastore.StorageMax, but the upload needs {}",
+        available, needed
+    )]
+    StorageFull { needed: u64, available: u64 },
+    #[fail(display = "An error was encountered in parsing an LFS pointer {}", _0)]
+    PointerParseError(&'static str),
+    #[fail(display = "Object {} could not be found on IPFS as {}", oid, cid)]
+    ObjectNotFound { oid: String, cid: String },
+    #[fail(
+        display = "{} is rate limiting requests and asked to wait {}s before retrying",
+        _0, _1
+    )]
+    RateLimited(String, u64),
+    #[fail(display = "{} is set to {:?}, but must be {}", var, value, expected)]
+    InvalidConfig {
+        var: &'static str,
+        value: String,
+        expected: &'static str,
+    },
     #[fail(display = "{}", _0)]
     Io(std::io::Error),
 }
 
-impl ResponseError for Error {
-    fn error_response(&self) -> HttpResponse {
+/// Broad categories of errors, for deciding how to react to one and what to tell the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// No IPFS API could be reached
+    DaemonUnreachable,
+    /// The daemon refused the request, or a needed key is missing
+    Auth,
+    NotFound,
+    /// Data did not match its hash
+    Integrity,
+    /// The node ran out of storage
+    Quota,
+    /// A newer record was already published under the key
+    PublishConflict,
+    /// The daemon or configuration can't support the request
+    Unsupported,
+    Other,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::LocalApiUnavailableError => ErrorKind::DaemonUnreachable,
+            #[cfg(feature = "client")]
+            Error::IpfsApiSendRequestError(_) => ErrorKind::DaemonUnreachable,
+            Error::IpfsUploadNotPossible
+            | Error::ShareLinkRejected(_)
+            | Error::ShareLinkExpired(_) => ErrorKind::Auth,
+            Error::VerifyFailed | Error::ObjectNotFound { .. } => ErrorKind::NotFound,
+            Error::HashError
+            | Error::BlockVerificationFailed(_)
+            | Error::SizeMismatch { .. }
+            | Error::OidConflict { .. }
+            | Error::ReadBackMismatch(_)
+            | Error::ManifestNotTrusted { .. }
+            | Error::ReceiptInvalid { .. } => ErrorKind::Integrity,
+            Error::TransferUnavailable
+            | Error::DaemonTooOld(_, _)
+            | Error::ReadThroughMisconfigured(_)
+            | Error::ManifestTooLarge { .. } => ErrorKind::Unsupported,
+            Error::StorageFull { .. } => ErrorKind::Quota,
+            Error::IpfsApiResponseError(error) => {
+                let message = error.message().to_lowercase();
+                if message.contains("not found") {
+                    ErrorKind::NotFound
+                } else if message.contains("no space left") || message.contains("storagemax") {
+                    ErrorKind::Quota
+                } else if message.contains("unauthorized")
+                    || message.contains("forbidden")
+                    || message.contains("permission denied")
+                {
+                    ErrorKind::Auth
+                } else if message.contains("newer") || message.contains("sequence number") {
+                    ErrorKind::PublishConflict
+                } else {
+                    ErrorKind::Other
+                }
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// What the user could try to resolve the error
+    pub fn hint(&self) -> Option<&'static str> {
+        self.hint_entry().map(|(_, hint)| hint)
+    }
+
+    /// A stable ID for [`hint`](Error::hint), for looking up a translation of it
+    pub fn hint_id(&self) -> Option<&'static str> {
+        self.hint_entry().map(|(id, _)| id)
+    }
+
+    fn hint_entry(&self) -> Option<(&'static str, &'static str)> {
+        let hint = match (self, self.kind()) {
+            (Error::DaemonTooOld(_, _), _) => ("daemon-too-old", "Upgrade the IPFS daemon."),
+            (_, ErrorKind::DaemonUnreachable) => (
+                "daemon-unreachable",
+                "Start the IPFS daemon with `ipfs daemon`, check $IPFS_PATH, or list reachable endpoints in GIT_LFS_IPFS_API.",
+            ),
+            (Error::InvalidConfig { .. }, _) => (
+                "invalid-config",
+                "Fix or unset the variable, or the git-lfs-ipfs setting in the git config that it comes from.",
+            ),
+            (Error::RateLimited(_, _), _) => (
+                "rate-limited",
+                "Retry later, or list endpoints that aren't rate limited in GIT_LFS_IPFS_API and GIT_LFS_IPFS_GATEWAY.",
+            ),
+            (Error::IpfsUploadNotPossible, _) => (
+                "upload-not-possible",
+                "Check that the IPNS key exists on this node with `ipfs key list`.",
+            ),
+            (Error::ShareLinkRejected(_), _) | (Error::ShareLinkExpired(_), _) => (
+                "share-link-rejected",
+                "Ask whoever shared the object for a new link; links stop working once they expire.",
+            ),
+            (_, ErrorKind::Auth) => (
+                "auth",
+                "Check that the IPFS API allows this request, e.g. its API.Authorizations or reverse proxy settings.",
+            ),
+            (_, ErrorKind::NotFound) => (
+                "not-found",
+                "Make sure a node that has the object is online and reachable, or try another gateway in GIT_LFS_IPFS_GATEWAY.",
+            ),
+            (Error::PinQuorumNotMet { .. }, _) => (
+                "pin-quorum",
+                "Check that the nodes in GIT_LFS_IPFS_PIN_TARGETS are online and reachable, or lower GIT_LFS_IPFS_PIN_QUORUM.",
+            ),
+            (Error::SizeMismatch { .. }, _) => (
+                "size-mismatch",
+                "The file may have changed during the upload, or its pointer records the wrong size; add it again with `git add`.",
+            ),
+            (Error::OidConflict { .. }, _) => (
+                "oid-conflict",
+                "The pushed content doesn't hash to its OID; check the file and the clean filter that produced its pointer.",
+            ),
+            (Error::ManifestNotTrusted { .. }, _) => (
+                "manifest-not-trusted",
+                "Check that GIT_LFS_IPFS_TRUSTED_KEY is the ID of the key the repository signs its manifests with; if it is, the root or a gateway may have been tampered with.",
+            ),
+            (Error::ReceiptInvalid { .. }, _) => (
+                "receipt-invalid",
+                "Check that the receipt is unaltered and that the key it is checked against is the one the repository signs with; otherwise, the object isn't in the root it claims.",
+            ),
+            (Error::ManifestTooLarge { .. }, _) => (
+                "manifest-too-large",
+                "A root's manifest is a single block, which holds about 7,000 objects; keep further objects in another repository, which publishes a root of its own.",
+            ),
+            (Error::ReadThroughMisconfigured(_), _) => (
+                "read-through-misconfigured",
+                "Set GIT_LFS_IPFS_ROOT to the repository's published root and GIT_LFS_IPFS_TRUSTED_KEY to the ID of the key its manifests are signed with.",
+            ),
+            (_, ErrorKind::Integrity) => (
+                "integrity",
+                "The data may be corrupt or the gateway untrustworthy; retry, or switch gateways in GIT_LFS_IPFS_GATEWAY.",
+            ),
+            (Error::StorageFull { .. }, _) => (
+                "storage-full",
+                "Free up space with `ipfs repo gc` or by unpinning old roots, set GIT_LFS_IPFS_DISK_GUARD=gc to collect garbage before large uploads, or raise Datastore.StorageMax in the IPFS config.",
+            ),
+            (_, ErrorKind::Quota) => (
+                "quota",
+                "Free up space with `ipfs repo gc` or raise Datastore.StorageMax in the IPFS config.",
+            ),
+            (_, ErrorKind::PublishConflict) => (
+                "publish-conflict",
+                "Someone published a newer root under this key; fetch their changes before publishing again.",
+            ),
+            _ => return None,
+        };
+        Some(hint)
+    }
+}
+
+#[cfg(feature = "server")]
+impl Error {
+    /// The status a Git LFS server responds with, per
+    /// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#response-errors
+    pub fn status(&self) -> StatusCode {
         match self {
-            Error::HashError => HttpResponse::BadRequest().finish(),
-            Error::LocalApiUnavailableError => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
-            Error::IpfsPathParseError(_) => HttpResponse::BadRequest().finish(),
-            Error::IpfsApiPayloadError(payload_error) => payload_error.error_response(),
-            Error::IpfsApiJsonPayloadError(json_payload_error) => {
-                json_payload_error.error_response()
+            Error::HashError | Error::IpfsPathParseError(_) | Error::PointerParseError(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
             }
-            Error::IpfsApiSendRequestError(send_request_error) => {
-                send_request_error.error_response()
+            Error::OidConflict { .. } => StatusCode::CONFLICT,
+            Error::TransferUnavailable => StatusCode::NOT_IMPLEMENTED,
+            Error::RateLimited(_, _) => StatusCode::TOO_MANY_REQUESTS,
+            Error::ShareLinkExpired(_) => StatusCode::GONE,
+            // The daemon, or a proxy in front of it, wants credentials that weren't sent
+            Error::IpfsApiResponseError(error)
+                if error.message().to_lowercase().contains("unauthorized") =>
+            {
+                StatusCode::UNAUTHORIZED
             }
-            Error::IpfsApiResponseError(error) => HttpResponse::InternalServerError().json(error),
-            Error::IpfsUploadNotPossible => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
-            Error::TransferUnavailable => HttpResponse::new(StatusCode::NOT_IMPLEMENTED),
-            Error::VerifyFailed => HttpResponse::NotFound().finish(),
-            Error::SerializeJsonError => HttpResponse::InternalServerError().finish(),
-            Error::Io(io) => HttpResponse::InternalServerError().finish(),
+            _ => match self.kind() {
+                ErrorKind::Auth => StatusCode::FORBIDDEN,
+                ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                ErrorKind::Quota => StatusCode::INSUFFICIENT_STORAGE,
+                ErrorKind::Integrity | ErrorKind::PublishConflict | ErrorKind::Unsupported => {
+                    StatusCode::UNPROCESSABLE_ENTITY
+                }
+                ErrorKind::DaemonUnreachable | ErrorKind::Other => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            },
+        }
+    }
+}
+
+/// Every error is reported to git-lfs as an LFS error object, with the hint appended to the message.
+#[cfg(feature = "server")]
+impl ResponseError for Error {
+    fn error_response(&self) -> HttpResponse {
+        let mut message = self.to_string();
+        if let Some(hint) = self.hint() {
+            message.push(' ');
+            message.push_str(hint);
+        }
+        let status = self.status();
+        let response = LfsErrorResponse::new(status, &message)
+            .documentation_url(url::Url::parse(DOCUMENTATION_URL).unwrap());
+        match serde_json::to_string(&response) {
+            Ok(body) => HttpResponse::build(status)
+                .content_type(GIT_LFS_CONTENT_TYPE)
+                .body(body),
+            Err(_) => HttpResponse::new(status),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn errors_respond_with_lfs_error_objects() {
+        let res = Error::ObjectNotFound {
+            oid: "1111111".to_string(),
+            cid: "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string(),
+        }
+        .error_response();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let body = match res.body() {
+            actix_web::Body::Binary(binary) => binary.as_ref().to_vec(),
+            _ => panic!("expected a binary body"),
+        };
+        let error: LfsErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert!(error.message.starts_with("Object 1111111 could not be found"));
+        assert_eq!(
+            Some(DOCUMENTATION_URL),
+            error.documentation_url.as_ref().map(url::Url::as_str)
+        );
+        assert_eq!(
+            StatusCode::INSUFFICIENT_STORAGE,
+            Error::IpfsApiResponseError(
+                serde_json::from_str(
+                    r#"{"Message": "write: no space left on device", "Code": 0, "Type": "error"}"#
+                )
+                .unwrap()
+            )
+            .status()
+        );
+    }
+
+    #[test]
+    fn errors_map_to_lfs_status_codes() {
+        let api_error = |message: &str| {
+            Error::IpfsApiResponseError(
+                serde_json::from_str(&format!(
+                    r#"{{"Message": "{}", "Code": 0, "Type": "error"}}"#,
+                    message
+                ))
+                .unwrap(),
+            )
+        };
+        let cases = vec![
+            (api_error("401 Unauthorized"), StatusCode::UNAUTHORIZED),
+            (api_error("permission denied"), StatusCode::FORBIDDEN),
+            (Error::IpfsUploadNotPossible, StatusCode::FORBIDDEN),
+            (
+                Error::ObjectNotFound {
+                    oid: "1111111".to_string(),
+                    cid: "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string(),
+                },
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                Error::ShareLinkExpired("2024-01-01T00:00:00+00:00".to_string()),
+                StatusCode::GONE,
+            ),
+            (
+                Error::ShareLinkRejected("it isn't signed".to_string()),
+                StatusCode::FORBIDDEN,
+            ),
+            (Error::PointerParseError("bad"), StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                Error::RateLimited("https://ipfs.io/".to_string(), 5),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                Error::StorageFull {
+                    needed: 2,
+                    available: 1,
+                },
+                StatusCode::INSUFFICIENT_STORAGE,
+            ),
+            (Error::LocalApiUnavailableError, StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for (error, status) in cases {
+            assert_eq!(status, error.status(), "{}", error);
+            assert_eq!(status, error.error_response().status(), "{}", error);
         }
     }
 }
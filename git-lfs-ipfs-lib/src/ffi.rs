@@ -0,0 +1,186 @@
+//! C ABI over the [`blocking`](crate::blocking) API, declared in `include/git_lfs_ipfs.h`.
+//!
+//! The functions that take a handle return `GIT_LFS_IPFS_OK` on success or a negative error
+//! code, and fail with `GIT_LFS_IPFS_INVALID_ARGUMENT` without doing anything if the handle, a
+//! string or an out-parameter is null. Strings returned through out-parameters are owned by the
+//! caller and must be released with `git_lfs_ipfs_string_free`. A handle must only be used by
+//! one thread at a time.
+//!
+//! Panics never unwind into the caller: they are logged and reported as `GIT_LFS_IPFS_FAILED`,
+//! or as a null handle from `git_lfs_ipfs_new`.
+//!
+//! Build the shared library with
+//! `cargo rustc -p git-lfs-ipfs-lib --release --features ffi --crate-type cdylib`.
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::blocking::LfsIpfs;
+
+pub const GIT_LFS_IPFS_OK: c_int = 0;
+pub const GIT_LFS_IPFS_INVALID_ARGUMENT: c_int = -1;
+pub const GIT_LFS_IPFS_FAILED: c_int = -2;
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
+
+/// `out` must not be null, which callers check before doing any work.
+unsafe fn set_string_out(out: *mut *mut c_char, s: String) -> c_int {
+    match CString::new(s) {
+        Ok(s) => {
+            *out = s.into_raw();
+            GIT_LFS_IPFS_OK
+        }
+        Err(_) => GIT_LFS_IPFS_FAILED,
+    }
+}
+
+/// Runs `f`, turning a panic into `on_panic` instead of unwinding across the C boundary.
+fn guard<T, F: FnOnce() -> T>(name: &str, on_panic: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        error!("{} panicked", name);
+        on_panic
+    })
+}
+
+/// Returns a new handle, or null if one could not be created.
+#[no_mangle]
+pub extern "C" fn git_lfs_ipfs_new() -> *mut LfsIpfs {
+    guard("git_lfs_ipfs_new", ptr::null_mut(), || {
+        Box::into_raw(Box::new(LfsIpfs::new()))
+    })
+}
+
+/// Releases `handle`. Does nothing if it is null.
+#[no_mangle]
+pub unsafe extern "C" fn git_lfs_ipfs_free(handle: *mut LfsIpfs) {
+    guard("git_lfs_ipfs_free", (), || {
+        if !handle.is_null() {
+            drop(Box::from_raw(handle));
+        }
+    })
+}
+
+/// Releases a string returned by this library. Does nothing if it is null.
+#[no_mangle]
+pub unsafe extern "C" fn git_lfs_ipfs_string_free(s: *mut c_char) {
+    guard("git_lfs_ipfs_string_free", (), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+/// Stores the `len` bytes at `data` as the object `oid`, writing its CID to `cid_out`.
+/// `data` may only be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn git_lfs_ipfs_upload(
+    handle: *mut LfsIpfs,
+    oid: *const c_char,
+    data: *const u8,
+    len: usize,
+    cid_out: *mut *mut c_char,
+) -> c_int {
+    guard("git_lfs_ipfs_upload", GIT_LFS_IPFS_FAILED, || {
+        let (handle, oid) = match (handle.as_mut(), str_arg(oid)) {
+            (Some(handle), Some(oid)) if (!data.is_null() || len == 0) && !cid_out.is_null() => {
+                (handle, oid)
+            }
+            _ => return GIT_LFS_IPFS_INVALID_ARGUMENT,
+        };
+        let data: &[u8] = if len == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(data, len)
+        };
+        match handle.upload_object(oid, data) {
+            Ok(cid) => set_string_out(cid_out, cid.to_string()),
+            Err(err) => {
+                error!("Upload of {} failed: {}", oid, err);
+                GIT_LFS_IPFS_FAILED
+            }
+        }
+    })
+}
+
+/// Downloads the object `oid` into the file at `path`, replacing it.
+#[no_mangle]
+pub unsafe extern "C" fn git_lfs_ipfs_download(
+    handle: *mut LfsIpfs,
+    oid: *const c_char,
+    path: *const c_char,
+) -> c_int {
+    guard("git_lfs_ipfs_download", GIT_LFS_IPFS_FAILED, || {
+        let (handle, oid, path) = match (handle.as_mut(), str_arg(oid), str_arg(path)) {
+            (Some(handle), Some(oid), Some(path)) => (handle, oid, path),
+            _ => return GIT_LFS_IPFS_INVALID_ARGUMENT,
+        };
+        let result = std::fs::File::create(path)
+            .map_err(crate::error::Error::Io)
+            .and_then(|file| handle.download_object(oid, file));
+        match result {
+            Ok(_) => GIT_LFS_IPFS_OK,
+            Err(err) => {
+                error!("Download of {} failed: {}", oid, err);
+                GIT_LFS_IPFS_FAILED
+            }
+        }
+    })
+}
+
+/// Writes the CID the object `oid` is stored under to `cid_out`.
+#[no_mangle]
+pub unsafe extern "C" fn git_lfs_ipfs_resolve(
+    handle: *mut LfsIpfs,
+    oid: *const c_char,
+    cid_out: *mut *mut c_char,
+) -> c_int {
+    guard("git_lfs_ipfs_resolve", GIT_LFS_IPFS_FAILED, || {
+        let (handle, oid) = match (handle.as_mut(), str_arg(oid)) {
+            (Some(handle), Some(oid)) if !cid_out.is_null() => (handle, oid),
+            _ => return GIT_LFS_IPFS_INVALID_ARGUMENT,
+        };
+        *cid_out = ptr::null_mut();
+        match handle.resolve(oid) {
+            Ok(cid) => set_string_out(cid_out, cid.to_string()),
+            Err(err) => {
+                error!("Resolving {} failed: {}", oid, err);
+                GIT_LFS_IPFS_FAILED
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn null_arguments_are_rejected_and_panics_contained() {
+        let mut cid: *mut c_char = ptr::null_mut();
+        let oid = CString::new("a".repeat(64)).unwrap();
+        unsafe {
+            assert_eq!(
+                GIT_LFS_IPFS_INVALID_ARGUMENT,
+                git_lfs_ipfs_resolve(ptr::null_mut(), oid.as_ptr(), &mut cid)
+            );
+            assert_eq!(
+                GIT_LFS_IPFS_INVALID_ARGUMENT,
+                git_lfs_ipfs_upload(ptr::null_mut(), oid.as_ptr(), ptr::null(), 1, &mut cid)
+            );
+            git_lfs_ipfs_free(ptr::null_mut());
+            git_lfs_ipfs_string_free(ptr::null_mut());
+        }
+        assert!(cid.is_null());
+        assert_eq!(
+            GIT_LFS_IPFS_FAILED,
+            guard("test", GIT_LFS_IPFS_FAILED, || -> c_int { panic!("boom") })
+        );
+    }
+}
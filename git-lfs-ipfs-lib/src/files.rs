@@ -0,0 +1,144 @@
+//! The daemon's mutable file system (MFS), a writable tree of paths over immutable objects.
+//! https://docs.ipfs.io/concepts/file-systems/#mutable-file-system-mfs
+use actix_web::{client, http::header, HttpMessage};
+use bytes::Bytes;
+use futures::{future, prelude::*};
+use url::Url;
+
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::ipfs::{
+    api_post, error_for_status, ipfs_api_url, multipart_begin, multipart_boundary, multipart_end,
+    send,
+};
+use crate::spec::ipfs::{FilesStatResponse, Path};
+
+/// How [`write`] treats the file at its path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Create the file if it doesn't exist yet
+    pub create: bool,
+    /// Create missing parent directories
+    pub parents: bool,
+    /// Drop the previous content, instead of writing over its start
+    pub truncate: bool,
+}
+
+impl Default for WriteOptions {
+    /// Replaces the file, creating it and its parents as needed
+    fn default() -> Self {
+        Self {
+            create: true,
+            parents: true,
+            truncate: true,
+        }
+    }
+}
+
+/// `api/v0/files/<command>?arg=<path>` on the local API
+fn command_url(command: &'static str, path: &str) -> impl Future<Item = Url, Error = Error> {
+    let path = path.to_string();
+    ipfs_api_url().map(move |url| {
+        let mut url = url.join(&format!("api/v0/files/{}", command)).unwrap();
+        url.query_pairs_mut().append_pair("arg", &path);
+        debug!("Sending files {} request to {}", command, url);
+        url
+    })
+}
+
+/// Writes `payload` to the file at `path`.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-files-write
+pub fn write<P, E>(
+    path: &str,
+    payload: P,
+    length: Option<u64>,
+    options: WriteOptions,
+) -> impl Future<Item = (), Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+{
+    command_url("write", path)
+        .map(move |mut url| {
+            url.query_pairs_mut()
+                .append_pair("create", &options.create.to_string())
+                .append_pair("parents", &options.parents.to_string())
+                .append_pair("truncate", &options.truncate.to_string());
+            let boundary = multipart_boundary();
+            api_post(url)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("{}; boundary={}", mime::MULTIPART_FORM_DATA, boundary),
+                )
+                .streaming(
+                    future::ok(Bytes::from(multipart_begin(length, &boundary).as_bytes()))
+                        .into_stream()
+                        .chain(payload)
+                        .chain(
+                            future::ok(Bytes::from(multipart_end(&boundary).as_bytes()))
+                                .into_stream(),
+                        ),
+                )
+                .unwrap()
+        })
+        .and_then(|client| {
+            client
+                .send()
+                .timeout(Duration::from_secs(600))
+                .map_err(Error::IpfsApiSendRequestError)
+        })
+        .and_then(error_for_status)
+        .map(|_| ())
+}
+
+/// Creates the directory at `path`, and with `parents` any missing directories above it.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-files-mkdir
+pub fn mkdir(path: &str, parents: bool) -> impl Future<Item = (), Error = Error> {
+    command_url("mkdir", path)
+        .map(move |mut url| {
+            url.query_pairs_mut().append_pair("parents", &parents.to_string());
+            url
+        })
+        .and_then(send)
+        .map(|_| ())
+}
+
+/// What is at `path`, and the CID of its current content.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-files-stat
+pub fn stat(path: &str) -> impl Future<Item = FilesStatResponse, Error = Error> {
+    command_url("stat", path)
+        .and_then(send)
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+}
+
+/// Removes the file at `path`. Directories are only removed with `recursive`.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-files-rm
+pub fn rm(path: &str, recursive: bool) -> impl Future<Item = (), Error = Error> {
+    command_url("rm", path)
+        .map(move |mut url| {
+            url.query_pairs_mut().append_pair("recursive", &recursive.to_string());
+            url
+        })
+        .and_then(send)
+        .map(|_| ())
+}
+
+/// Copies `from`, another MFS path or an `/ipfs/` path, to the MFS path `to`. Copying from
+/// `/ipfs/` links the existing object without reading its content.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-files-cp
+pub fn cp(from: &str, to: &str) -> impl Future<Item = (), Error = Error> {
+    let to = to.to_string();
+    command_url("cp", from)
+        .map(move |mut url| {
+            url.query_pairs_mut().append_pair("arg", &to);
+            url
+        })
+        .and_then(send)
+        .map(|_| ())
+}
+
+/// Links the object at `path` into MFS at `to`, see [`cp`].
+pub fn cp_ipfs(path: &Path, to: &str) -> impl Future<Item = (), Error = Error> {
+    cp(&path.to_string(), to)
+}
@@ -0,0 +1,136 @@
+//! Properties of the parsers and encoders, checked on arbitrary input by the cargo-fuzz targets
+//! in `fuzz/`, and on their seed corpora by the tests below. Each panics when the property
+//! doesn't hold, which is how libFuzzer learns about it.
+use std::path::Component;
+use std::str::FromStr;
+
+use crate::pointer::{Mode, Pointer};
+use crate::spec::{
+    ipfs::Path,
+    lfs::{BatchRequest, BatchResponse},
+};
+
+/// A strictly parsed pointer is written back byte for byte, and whatever lenient parsing
+/// recovers is a pointer strict parsing accepts.
+pub fn pointer(data: &[u8]) {
+    let s = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if let Ok(pointer) = Pointer::parse(s, Mode::Strict) {
+        assert_eq!(s, pointer.to_string());
+    }
+    if let Ok(pointer) = Pointer::parse(s, Mode::Lenient) {
+        let written = pointer.to_string();
+        match Pointer::parse(&written, Mode::Strict) {
+            Ok(reparsed) => assert_eq!(pointer, reparsed),
+            Err(err) => panic!("recovered {:?}, which strict parsing rejects: {}", written, err),
+        }
+    }
+}
+
+/// A parsed path formats to one that parses the same, and its suffix stays below the root.
+pub fn ipfs_path(data: &[u8]) {
+    let s = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if let Ok(path) = Path::from_str(s) {
+        match Path::from_str(&path.to_string()) {
+            Ok(reparsed) => assert_eq!(path, reparsed),
+            Err(err) => panic!("{} doesn't parse again: {}", path, err),
+        }
+        if let Some(suffix) = &path.suffix {
+            assert!(suffix.is_relative());
+            assert!(!suffix.components().any(|component| component == Component::ParentDir));
+        }
+    }
+}
+
+/// Batch requests and responses survive being serialized again.
+pub fn batch_json(data: &[u8]) {
+    if let Ok(request) = serde_json::from_slice::<BatchRequest>(data) {
+        let json = serde_json::to_vec(&request).unwrap();
+        assert_eq!(request, serde_json::from_slice(&json).unwrap());
+    }
+    if let Ok(response) = serde_json::from_slice::<BatchResponse>(data) {
+        let json = serde_json::to_vec(&response).unwrap();
+        assert_eq!(response, serde_json::from_slice(&json).unwrap());
+    }
+}
+
+/// The payload comes back out of its multipart body whole, and the boundary only delimits it.
+#[cfg(feature = "client")]
+pub fn multipart(data: &[u8]) {
+    let (boundary, body) = crate::ipfs::multipart_encode(data);
+    let delimiter = format!("--{}", boundary);
+    let occurrences = body
+        .windows(delimiter.len())
+        .filter(|window| *window == delimiter.as_bytes())
+        .count();
+    // Before the payload and after it, never inside
+    assert_eq!(2, occurrences);
+    let begin = format!("{}\r\n\r\n", delimiter);
+    let start = body
+        .windows(begin.len())
+        .position(|window| window == begin.as_bytes())
+        .unwrap()
+        + begin.len();
+    let end = format!("\r\n{}--\r\n", delimiter);
+    assert!(body.ends_with(end.as_bytes()));
+    assert_eq!(data, &body[start..body.len() - end.len()]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Seed inputs of a fuzz target, along with any crashes that were added to them
+    fn corpus(target: &str) -> Vec<Vec<u8>> {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fuzz/corpus")
+            .join(target);
+        let inputs: Vec<Vec<u8>> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+            .collect();
+        assert!(!inputs.is_empty());
+        inputs
+    }
+
+    #[test]
+    fn pointer_corpus_holds() {
+        for input in corpus("pointer") {
+            pointer(&input);
+        }
+    }
+
+    #[test]
+    fn ipfs_path_corpus_holds() {
+        for input in corpus("ipfs_path") {
+            ipfs_path(&input);
+        }
+    }
+
+    #[test]
+    fn batch_json_corpus_holds() {
+        for input in corpus("batch_json") {
+            batch_json(&input);
+        }
+        for fixture in &[
+            include_str!("spec/test/batch_request.json"),
+            include_str!("spec/test/batch_response_success.json"),
+            include_str!("spec/test/batch_response_error.json"),
+        ] {
+            batch_json(fixture.as_bytes());
+        }
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn multipart_corpus_holds() {
+        for input in corpus("multipart") {
+            multipart(&input);
+        }
+    }
+}
@@ -1,53 +1,381 @@
-use actix_web::{client, dev::HttpResponseBuilder, http::header, HttpMessage, HttpResponse};
+#[cfg(feature = "server")]
+use actix_web::{dev::HttpResponseBuilder, HttpResponse};
+use actix_web::{client, http::header, http::StatusCode, HttpMessage};
 use bytes::Bytes;
 use cid::Cid;
 use futures::{future, prelude::*};
 use lazy_static::lazy_static;
 use rand::{distributions::Alphanumeric, rngs::SmallRng, FromEntropy, Rng};
+use tokio_timer::Delay;
 use url::Url;
 
+use std::collections::HashMap;
 use std::io::Write;
 use std::iter::FromIterator;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use crate::config::{ApiMethod, GatewayStyle};
 use crate::error::Error;
+use crate::pointer::HashAlgorithm;
 use crate::spec::ipfs::*;
+use crate::spec::manifest::{Layout, Manifest, MANIFEST_LINK_NAME, SIGNATURE_LINK_NAME};
 
-lazy_static! {
-    static ref IPFS_PUBLIC_API_URL: Url = Url::parse("https://ipfs.io/").unwrap();
+/// Where a request can be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// An IPFS HTTP API, which can serve reads and writes
+    Api(Url),
+    /// A read-only HTTP gateway
+    Gateway(Url),
+}
+
+/// Writes only go to API endpoints, reads may also fall back to gateways
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Every endpoint usable for `access`, in failover order: the local daemon, then configured
+/// API endpoints, then (for reads) configured gateways.
+pub fn endpoints(access: Access) -> impl Future<Item = Vec<Endpoint>, Error = Error> {
+    let config = crate::config::get();
+    let mut endpoints: Vec<Endpoint> = local_api_url()
+        .into_iter()
+        .chain(config.api_urls.iter().cloned())
+        .map(Endpoint::Api)
+        .collect();
+    if access == Access::Read {
+        endpoints.extend(config.gateway_urls.iter().cloned().map(Endpoint::Gateway));
+    }
+    future::result(if endpoints.is_empty() {
+        Err(Error::LocalApiUnavailableError)
+    } else {
+        Ok(endpoints)
+    })
+}
+
+/// Sends a request to each endpoint in turn until one of them can be reached: GETs to gateways,
+/// and to APIs with the configured method.
+/// `request_url` builds the URL for an endpoint, or returns `None` if it can't serve the request.
+pub(crate) fn send_with_fallback<F>(
+    access: Access,
+    request_url: F,
+) -> impl Future<Item = client::ClientResponse, Error = Error>
+where
+    F: Fn(&Endpoint) -> Option<Url> + 'static,
+{
+    endpoints(access).and_then(move |endpoints| {
+        future::loop_fn(
+            (endpoints.into_iter(), None),
+            move |(mut endpoints, last_err): (std::vec::IntoIter<Endpoint>, Option<Error>)| {
+                let next = endpoints.find_map(|endpoint| {
+                    let gateway = match endpoint {
+                        Endpoint::Api(_) => false,
+                        Endpoint::Gateway(_) => true,
+                    };
+                    request_url(&endpoint).map(|url| (url, gateway))
+                });
+                match next {
+                    Some((url, gateway)) => {
+                        debug!("Sending request to {}", url);
+                        future::Either::A(send_rate_limited(url.clone(), move |url| {
+                            let mut request = if gateway {
+                                gateway_get(url)
+                            } else {
+                                api_request(url)
+                            };
+                            request.finish().unwrap()
+                        })
+                        .then(move |result| match result {
+                            Ok(res) => Ok(future::Loop::Break(res)),
+                            Err(err) => {
+                                warn!("Could not reach {}, trying the next endpoint: {}", url, err);
+                                Ok(future::Loop::Continue((endpoints, Some(err))))
+                            }
+                        }))
+                    }
+                    None => future::Either::B(future::err(
+                        last_err.unwrap_or(Error::LocalApiUnavailableError),
+                    )),
+                }
+            },
+        )
+    })
+}
+
+/// The first API endpoint in failover order that can be reached, for content-addressed writes.
+/// Their body is streamed, so it can't be resent to the next endpoint once a request fails;
+/// instead each endpoint is asked for its version until one answers.
+pub(crate) fn write_api_url() -> impl Future<Item = Url, Error = Error> {
+    endpoints(Access::Write).and_then(|endpoints| {
+        future::loop_fn(
+            (endpoints.into_iter(), None),
+            |(mut endpoints, last_err): (std::vec::IntoIter<Endpoint>, Option<Error>)| {
+                let next = endpoints.find_map(|endpoint| match endpoint {
+                    Endpoint::Api(url) => Some(url),
+                    Endpoint::Gateway(_) => None,
+                });
+                match next {
+                    Some(url) => future::Either::A(
+                        send_rate_limited(url.join("api/v0/version").unwrap(), |url| {
+                            api_request(url).finish().unwrap()
+                        })
+                        .then(move |result| match result {
+                            Ok(_) => Ok(future::Loop::Break(url)),
+                            Err(err) => {
+                                warn!("Could not reach {}, trying the next endpoint: {}", url, err);
+                                Ok(future::Loop::Continue((endpoints, Some(err))))
+                            }
+                        }),
+                    ),
+                    None => future::Either::B(future::err(
+                        last_err.unwrap_or(Error::LocalApiUnavailableError),
+                    )),
+                }
+            },
+        )
+    })
+}
+
+/// Sends a request without a body to [`ipfs_api_url`] only, for calls bound to the node that
+/// answers them, like its keys and configuration. Failing over would act as another node, e.g.
+/// publish under a teammate's key of the same name, so these fail instead.
+fn send_to_node<F>(request_url: F) -> impl Future<Item = client::ClientResponse, Error = Error>
+where
+    F: FnOnce(Url) -> Url + 'static,
+{
+    ipfs_api_url().and_then(move |url| {
+        let url = request_url(url);
+        debug!("Sending request to {}", url);
+        send_rate_limited(url, |url| api_request(url).finish().unwrap())
+    })
+}
+
+/// Starts a request without a body to the API at `url`, with the configured method and the
+/// credentials configured for its origin.
+pub(crate) fn api_request(url: Url) -> client::ClientRequestBuilder {
+    let request = match crate::config::get().api_method {
+        ApiMethod::Post => client::ClientRequest::post(url.as_str()),
+        ApiMethod::Get => client::ClientRequest::get(url.as_str()),
+    };
+    authorized(request, &url)
+}
+
+/// Starts a GET request to the gateway at `url`, with the credentials configured for its origin.
+fn gateway_get(url: Url) -> client::ClientRequestBuilder {
+    authorized(client::ClientRequest::get(url.as_str()), &url)
+}
+
+/// Starts a POST request to `url`, with the credentials configured for its origin.
+pub(crate) fn api_post(url: Url) -> client::ClientRequestBuilder {
+    authorized(client::ClientRequest::post(url.as_str()), &url)
+}
+
+fn authorized(
+    mut request: client::ClientRequestBuilder,
+    url: &Url,
+) -> client::ClientRequestBuilder {
+    crate::proxy::route(url);
+    if let Some(credentials) = crate::config::credentials_for(url) {
+        request.header(header::AUTHORIZATION, credentials.basic_authorization());
+    }
+    request
+}
+
+/// Passes successful responses on, and turns the others into the error the daemon reported.
+pub(crate) fn error_for_status(
+    res: client::ClientResponse,
+) -> impl Future<Item = client::ClientResponse, Error = Error> {
+    if res.status().is_success() {
+        future::Either::A(future::ok(res))
+    } else {
+        future::Either::B(
+            res.json()
+                .map_err(Error::IpfsApiJsonPayloadError)
+                .and_then(|err| Err(Error::IpfsApiResponseError(err))),
+        )
+    }
+}
+
+/// Sends a request without a body to the API at `url`, see [`api_request`], passing
+/// successful responses on and turning the others into the error the daemon reported.
+pub(crate) fn send(url: Url) -> impl Future<Item = client::ClientResponse, Error = Error> {
+    send_within(url, Duration::from_secs(600))
+}
+
+/// Like [`send`], giving up once `timeout` passes
+pub(crate) fn send_within(
+    url: Url,
+    timeout: Duration,
+) -> impl Future<Item = client::ClientResponse, Error = Error> {
+    api_request(url)
+        .finish()
+        .unwrap()
+        .send()
+        .timeout(timeout)
+        .map_err(Error::IpfsApiSendRequestError)
+        .and_then(error_for_status)
+}
+
+/// How often a rate limited request is retried before giving up on the endpoint
+const RATE_LIMIT_RETRIES: usize = 5;
+/// Endpoints asking to be left alone for longer than this are skipped instead
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+/// The wait when a 429 response doesn't say how long to back off for
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Sends the request built by `request` to `url`, waiting out `429 Too Many Requests` responses
+/// as long as their `Retry-After` asks for. Only this request is paused, so other transfers
+/// carry on in the meantime.
+fn send_rate_limited<F>(
+    url: Url,
+    request: F,
+) -> impl Future<Item = client::ClientResponse, Error = Error>
+where
+    F: Fn(Url) -> client::ClientRequest + 'static,
+{
+    future::loop_fn(0, move |attempt| {
+        let url = url.clone();
+        request(url.clone())
+            .send()
+            .timeout(Duration::from_secs(600))
+            .map_err(Error::IpfsApiSendRequestError)
+            .and_then(move |res| {
+                if res.status() != StatusCode::TOO_MANY_REQUESTS {
+                    return future::Either::A(future::ok(future::Loop::Break(res)));
+                }
+                let retry_after = res
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| parse_retry_after(value, chrono::Utc::now()))
+                    .unwrap_or(DEFAULT_RETRY_AFTER);
+                if attempt >= RATE_LIMIT_RETRIES || retry_after > MAX_RETRY_AFTER {
+                    return future::Either::A(future::err(Error::RateLimited(
+                        url.to_string(),
+                        retry_after.as_secs(),
+                    )));
+                }
+                warn!(
+                    "Rate limited by {}, waiting {}s before retrying",
+                    url,
+                    retry_after.as_secs()
+                );
+                future::Either::B(
+                    Delay::new(Instant::now() + retry_after)
+                        .map_err(|err| {
+                            Error::Io(std::io::Error::new(std::io::ErrorKind::Other, err))
+                        })
+                        .map(move |_| future::Loop::Continue(attempt + 1)),
+                )
+            })
+    })
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+/// https://tools.ietf.org/html/rfc7231#section-7.1.3
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    chrono::DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| {
+            (date.with_timezone(&chrono::Utc) - now)
+                .to_std()
+                .unwrap_or_else(|_| Duration::from_secs(0))
+        })
+}
+
+/// Replaces the system's HTTP client connector with one that trusts the configured CA bundle
+/// and connects through the proxies configured in the environment.
+/// Must be called from within a running actix `System`, before any request is sent.
+pub fn install_client_connector() {
+    use actix::Actor;
+    use actix_web::client::ClientConnector;
+    use openssl::ssl::{SslConnector, SslMethod};
+
+    let proxies = crate::proxy::Proxies::from_env();
+    let ca_bundle = crate::config::get().ca_bundle.clone();
+    if proxies.is_empty() && ca_bundle.is_none() {
+        return;
+    }
+    let mut connector = match ca_bundle {
+        Some(ca_bundle) => {
+            let connector = SslConnector::builder(SslMethod::tls()).and_then(|mut builder| {
+                builder.set_ca_file(&ca_bundle)?;
+                Ok(builder.build())
+            });
+            match connector {
+                Ok(connector) => {
+                    debug!("Trusting CA certificates from {}", ca_bundle.display());
+                    ClientConnector::with_connector(connector)
+                }
+                Err(err) => {
+                    error!(
+                        "Could not load CA certificates from {}: {}",
+                        ca_bundle.display(),
+                        err
+                    );
+                    ClientConnector::default()
+                }
+            }
+        }
+        None => ClientConnector::default(),
+    };
+    if !proxies.is_empty() {
+        debug!("Connecting through {:?}", proxies);
+        connector = connector.resolver(
+            crate::proxy::ProxyResolver::new(proxies)
+                .start()
+                .recipient(),
+        );
+    }
+    actix::System::current().registry().set(connector.start());
+}
+
+/// Builds `api/v0/<command>?arg=<arg>` on API endpoints, skipping gateways.
+fn api_only(command: &'static str, arg: String) -> impl Fn(&Endpoint) -> Option<Url> {
+    move |endpoint| match endpoint {
+        Endpoint::Api(url) => {
+            let mut url = url.join(command).unwrap();
+            url.query_pairs_mut().append_pair("arg", &arg);
+            Some(url)
+        }
+        Endpoint::Gateway(_) => None,
+    }
 }
 
+/// Maps a hex SHA2-256 digest to its CID, see
+/// [`pointer::digest_to_cid`](crate::pointer::digest_to_cid).
 pub fn sha256_to_cid(
     codec: cid::Codec,
     sha256_str: &str,
 ) -> impl Future<Item = Cid, Error = Error> {
-    future::result(
-        hex::decode(sha256_str)
-            .ok()
-            .and_then(|digest| {
-                if digest.len() != 32 {
-                    None
-                } else {
-                    let mut mh = [0u8; 34];
-                    mh[0] = multihash::Hash::SHA2256.code();
-                    mh[1] = multihash::Hash::SHA2256.size();
-                    digest.iter().enumerate().for_each(|(i, x)| mh[i + 2] = *x);
-                    Some(Cid::new(codec, cid::Version::V0, &mh))
-                }
-            })
-            .ok_or(Error::HashError),
-    )
+    future::result(crate::pointer::digest_to_cid(
+        codec,
+        HashAlgorithm::Sha256,
+        sha256_str,
+    ))
+}
+
+/// Maps a bare hex OID to its CID, see [`pointer::oid_to_cid`](crate::pointer::oid_to_cid).
+pub fn oid_to_cid(codec: cid::Codec, oid: &str) -> impl Future<Item = Cid, Error = Error> {
+    future::result(crate::pointer::oid_to_cid(codec, oid))
 }
 
-fn multipart_boundary() -> String {
+pub(crate) fn multipart_boundary() -> String {
     format!(
         "------------------------{}",
         String::from_iter(SmallRng::from_entropy().sample_iter(&Alphanumeric).take(18))
     )
 }
 
-fn multipart_begin(length: Option<u64>, boundary: &str) -> String {
+pub(crate) fn multipart_begin(length: Option<u64>, boundary: &str) -> String {
     let mut begin = String::new();
     begin.push_str("POST /api/v0/add HTTP/1.1\r\nHost: localhost:5001\r\n");
     if let Some(length) = length {
@@ -61,10 +389,29 @@ fn multipart_begin(length: Option<u64>, boundary: &str) -> String {
     begin
 }
 
-fn multipart_end(boundary: &str) -> String {
+pub(crate) fn multipart_end(boundary: &str) -> String {
     format!("\r\n--{}--\r\n", boundary)
 }
 
+/// Frames `payload` as the only part of a multipart body, returning the boundary for the
+/// Content-Type header along with the body. Unlike streamed payloads, this one can be checked
+/// for the boundary, which is drawn again in the unlikely case that it occurs in it.
+pub fn multipart_encode(payload: &[u8]) -> (String, Vec<u8>) {
+    let boundary = loop {
+        let boundary = multipart_boundary();
+        if !payload
+            .windows(boundary.len())
+            .any(|window| window == boundary.as_bytes())
+        {
+            break boundary;
+        }
+    };
+    let mut body = multipart_begin(Some(payload.len() as u64), &boundary).into_bytes();
+    body.extend_from_slice(payload);
+    body.extend_from_slice(multipart_end(&boundary).as_bytes());
+    (boundary, body)
+}
+
 pub fn parse_ipfs_path<I>(
     prefix: Prefix,
     root: &str,
@@ -78,21 +425,129 @@ where
     }))
 }
 
+/// How `add` chunks and encodes content, instead of leaving it to the daemon, whose defaults
+/// could change between releases: the CID of every object published so far depends on them.
+const ADD_PARAMETERS: &[(&str, &str)] = &[
+    ("chunker", "size-262144"),
+    ("cid-version", "0"),
+    ("hash", "sha2-256"),
+    ("raw-leaves", "false"),
+];
+
+/// Adds `payload`, returning the root once the daemon is done.
 pub fn add<P, E>(payload: P, length: Option<u64>) -> impl Future<Item = AddResponse, Error = Error>
 where
     P: Stream<Item = Bytes, Error = E> + 'static,
     E: actix_web::error::ResponseError,
 {
-    ipfs_api_url()
-        .map(|url| {
+    add_reporting(payload, length, None, true)
+}
+
+/// Like [`add`], calling `on_progress` with how many bytes of `payload` the daemon has read
+/// every time it reports progress.
+pub fn add_with_progress<P, E, F>(
+    payload: P,
+    length: Option<u64>,
+    on_progress: F,
+) -> impl Future<Item = AddResponse, Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+    F: FnMut(u64) + 'static,
+{
+    add_reporting(payload, length, Some(Box::new(on_progress)), true)
+}
+
+/// Like [`add_with_progress`], but leaves the root unpinned, for content that is only pinned
+/// once it checks out.
+pub(crate) fn add_unpinned<P, E>(
+    payload: P,
+    length: Option<u64>,
+    on_progress: Option<Box<FnMut(u64)>>,
+) -> impl Future<Item = AddResponse, Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+{
+    add_reporting(payload, length, on_progress, false)
+}
+
+fn add_reporting<P, E>(
+    payload: P,
+    length: Option<u64>,
+    mut on_progress: Option<Box<FnMut(u64)>>,
+    pin: bool,
+) -> impl Future<Item = AddResponse, Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+{
+    crate::timing::timed(
+        "add",
+        add_events_pinning(payload, length, on_progress.is_some(), pin)
+            .fold(None, move |root, event| {
+                Ok::<_, Error>(match event {
+                    AddEvent::Added(added) => Some(added),
+                    AddEvent::Progress(progress) => {
+                        if let Some(on_progress) = on_progress.as_mut() {
+                            on_progress(progress.bytes);
+                        }
+                        root
+                    }
+                })
+            })
+            .and_then(|root| {
+                root.ok_or_else(|| {
+                    Error::IpfsApiJsonStreamError(
+                        <serde_json::Error as serde::de::Error>::custom(
+                            "the daemon did not report the added root",
+                        ),
+                    )
+                })
+            }),
+    )
+}
+
+/// Adds `payload`, streaming what the daemon reports as it goes: how much it has read if
+/// `progress` is set, and every node added, the root last.
+pub fn add_events<P, E>(
+    payload: P,
+    length: Option<u64>,
+    progress: bool,
+) -> impl Stream<Item = AddEvent, Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+{
+    add_events_pinning(payload, length, progress, true)
+}
+
+fn add_events_pinning<P, E>(
+    payload: P,
+    length: Option<u64>,
+    progress: bool,
+    pin: bool,
+) -> impl Stream<Item = AddEvent, Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+{
+    write_api_url()
+        .map(move |url| {
             let mut url = url.join("api/v0/add").unwrap();
-            // url.query_pairs_mut().append_pair("hash", "sha2-256");
+            url.query_pairs_mut().extend_pairs(ADD_PARAMETERS);
+            if progress {
+                url.query_pairs_mut().append_pair("progress", "true");
+            }
+            if !pin {
+                url.query_pairs_mut().append_pair("pin", "false");
+            }
             url
         })
         .map(move |url| {
             let boundary = multipart_boundary();
             debug!("Sending add request to {}", url);
-            client::post(url)
+            api_post(url)
                 .header(
                     header::CONTENT_TYPE,
                     format!("{}; boundary={}", mime::MULTIPART_FORM_DATA, boundary),
@@ -117,27 +572,24 @@ where
                 .map_err(|err| Error::IpfsApiSendRequestError(err))
         })
         .and_then(|res| {
-            res.json()
-                .map_err(|err| Error::IpfsApiJsonPayloadError(err))
+            if res.status().is_success() {
+                future::Either::A(future::ok(res))
+            } else {
+                future::Either::B(
+                    res.json()
+                        .map_err(Error::IpfsApiJsonPayloadError)
+                        .and_then(|err| Err(Error::IpfsApiResponseError(err))),
+                )
+            }
         })
+        .map(|res| json_lines(res.payload()))
+        .flatten_stream()
 }
 
+/// Proxies `api/v0/get` as a response that can be returned from a handler.
+#[cfg(feature = "server")]
 pub fn get(path: Path) -> impl Future<Item = HttpResponse, Error = Error> {
-    ipfs_api_url()
-        .map(move |url| {
-            let mut url = url.join("api/v0/get").unwrap();
-            url.query_pairs_mut().append_pair("arg", &path.to_string());
-            url
-        })
-        .and_then(|url| {
-            debug!("Sending get request to {}", url);
-            client::get(url)
-                .finish()
-                .unwrap()
-                .send()
-                .timeout(Duration::from_secs(600))
-                .map_err(|err| Error::IpfsApiSendRequestError(err))
-        })
+    send_with_fallback(Access::Read, api_only("api/v0/get", path.to_string()))
         // TODO: Handle json error responses
         .and_then(|res| {
             // if res.status().is_success() {
@@ -160,120 +612,528 @@ pub fn block_get_to_fs(
     path: Path,
     output: std::path::PathBuf,
 ) -> impl Stream<Item = usize, Error = Error> {
-    ipfs_api_url()
-        .map(move |url| {
-            let mut url = url.join("api/v0/block/get").unwrap();
-            url.query_pairs_mut().append_pair("arg", &path.to_string());
-            url
-        })
-        .and_then(|url| {
-            debug!("Sending block get to fs request to {}", url);
-            client::get(url)
-                .finish()
-                .unwrap()
-                .send()
-                .timeout(Duration::from_secs(600))
-                .map_err(Error::IpfsApiSendRequestError)
+    send_with_fallback(Access::Read, api_only("api/v0/block/get", path.to_string()))
+        .into_stream()
+        .and_then(move |res| {
+            std::fs::File::create(&output)
+                .map(|mut file| {
+                    res.payload()
+                        .map_err(Error::IpfsApiPayloadError)
+                        .and_then(move |b| file.write_all(&b).map(|_| b.len()).map_err(Error::Io))
+                })
+                .map_err(Error::Io)
         })
+        .flatten()
+}
+
+/// Writes the content of the UnixFS file at `path` to `output`, see [`block_get_to_fs`].
+pub fn cat_to_fs(
+    path: Path,
+    output: std::path::PathBuf,
+) -> impl Stream<Item = usize, Error = Error> {
+    cat(path)
         .into_stream()
-        .map(move |res| {
-            let mut file = std::fs::File::create(&output).unwrap();
-            res.payload()
-                .map_err(Error::IpfsApiPayloadError)
-                .and_then(move |b| file.write(&b).map_err(Error::Io))
+        .and_then(move |res| {
+            std::fs::File::create(&output)
+                .map(|mut file| {
+                    res.payload()
+                        .map_err(Error::IpfsApiPayloadError)
+                        .and_then(move |b| file.write_all(&b).map(|_| b.len()).map_err(Error::Io))
+                })
+                .map_err(Error::Io)
         })
         .flatten()
 }
 
 pub fn cat(path: Path) -> impl Future<Item = client::ClientResponse, Error = Error> {
-    ipfs_api_url()
-        .then(move |url| match url {
-            Ok(url) => {
-                let mut url = url.join("api/v0/cat").unwrap();
-                url.query_pairs_mut().append_pair("arg", &path.to_string());
-                Ok(url)
-            }
-            Err(_) => Ok(IPFS_PUBLIC_API_URL.clone().join(&path.to_string()).unwrap()),
-        })
-        .and_then(|url| {
-            debug!("Sending cat request to {}", url);
-            client::get(url)
-                .finish()
+    let gateway_style = crate::config::get().gateway_style;
+    send_with_fallback(Access::Read, move |endpoint| match endpoint {
+        Endpoint::Api(url) => {
+            let mut url = url.join("api/v0/cat").unwrap();
+            url.query_pairs_mut().append_pair("arg", &path.to_string());
+            Some(url)
+        }
+        Endpoint::Gateway(url) => gateway_url(url, &path, gateway_style),
+    })
+}
+
+/// The URL a gateway serves `path` at.
+/// Subdomain gateways need a case-insensitive root, so CIDs are converted to base32 CIDv1
+/// and DNSLink names are inlined into a single DNS label.
+pub fn gateway_url(gateway: &Url, path: &Path, style: GatewayStyle) -> Option<Url> {
+    match style {
+        GatewayStyle::Path => gateway.join(&path.to_string()).ok(),
+        GatewayStyle::Subdomain => {
+            let root = match &path.root {
+                Root::Cid(cid) => cid_to_base32(cid),
+                Root::DnsLink(domain) => domain.to_string().replace('-', "--").replace('.', "-"),
+            };
+            let mut url = gateway.clone();
+            url.set_host(Some(&format!(
+                "{}.{}.{}",
+                root,
+                path.prefix,
+                gateway.host_str()?
+            )))
+            .ok()?;
+            url.set_path(
+                &path
+                    .suffix
+                    .as_ref()
+                    .map(|suffix| suffix.display().to_string())
+                    .unwrap_or_default(),
+            );
+            Some(url)
+        }
+    }
+}
+
+/// Formats a CID as a multibase base32 CIDv1, converting CIDv0s.
+pub fn cid_to_base32(cid: &Cid) -> String {
+    crate::pointer::format_cid(cid, crate::pointer::Multibase::Base32)
+}
+
+/// Stores `payload` as a single dag-pb block, such as the root block of an LFS object.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-block-put
+pub fn block_put<P, E>(
+    payload: P,
+    length: Option<u64>,
+) -> impl Future<Item = BlockPutResponse, Error = Error>
+where
+    P: Stream<Item = Bytes, Error = E> + 'static,
+    E: actix_web::error::ResponseError,
+{
+    write_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/block/put").unwrap();
+            url.query_pairs_mut()
+                .append_pair("format", "v0")
+                .append_pair("pin", "true");
+            debug!("Sending block put request to {}", url);
+            let boundary = multipart_boundary();
+            api_post(url)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("{}; boundary={}", mime::MULTIPART_FORM_DATA, boundary),
+                )
+                .streaming(
+                    future::ok(Bytes::from(multipart_begin(length, &boundary).as_bytes()))
+                        .into_stream()
+                        .chain(payload)
+                        .chain(
+                            future::ok(Bytes::from(multipart_end(&boundary).as_bytes()))
+                                .into_stream(),
+                        ),
+                )
                 .unwrap()
+        })
+        .and_then(|client| {
+            client
                 .send()
                 .timeout(Duration::from_secs(600))
                 .map_err(Error::IpfsApiSendRequestError)
         })
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
 }
 
 pub fn block_get(cid: Cid) -> impl Future<Item = client::ClientResponse, Error = Error> {
-    ipfs_api_url()
-        .map(move |url| {
-            let mut url = url.join("api/v0/block/get").unwrap();
-            url.query_pairs_mut().append_pair("arg", &cid.to_string());
+    send_with_fallback(Access::Read, api_only("api/v0/block/get", cid.to_string()))
+}
+
+/// Blocks larger than this are refused by IPFS nodes, so a gateway sending more is misbehaving.
+pub(crate) const MAX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Fetches a raw block from the configured gateways per the trustless gateway spec and verifies it
+/// against its CID, so that untrusted public gateways can be used.
+/// https://specs.ipfs.tech/http-gateways/trustless-gateway/
+pub fn gateway_block_get(cid: Cid) -> impl Future<Item = Bytes, Error = Error> {
+    let gateways = crate::config::get().gateway_urls.clone();
+    future::loop_fn(
+        (gateways.into_iter(), None),
+        move |(mut gateways, last_err): (std::vec::IntoIter<Url>, Option<Error>)| {
+            match gateways.next() {
+                Some(gateway) => {
+                    let mut url = gateway.join(&format!("ipfs/{}", cid)).unwrap();
+                    url.query_pairs_mut().append_pair("format", "raw");
+                    debug!("Sending trustless block request to {}", url);
+                    let cid = cid.clone();
+                    future::Either::A(
+                        send_rate_limited(url, |url| {
+                            gateway_get(url)
+                                .header(header::ACCEPT, "application/vnd.ipld.raw")
+                                .finish()
+                                .unwrap()
+                        })
+                        .and_then(|res| {
+                            res.body()
+                                .limit(MAX_BLOCK_SIZE)
+                                .map_err(Error::IpfsApiPayloadError)
+                        })
+                        .and_then(move |block| verify_block(&cid, block))
+                        .then(move |result| match result {
+                            Ok(block) => Ok(future::Loop::Break(block)),
+                            Err(err) => {
+                                warn!("Gateway {} failed, trying the next one: {}", gateway, err);
+                                Ok(future::Loop::Continue((gateways, Some(err))))
+                            }
+                        }),
+                    )
+                }
+                None => future::Either::B(future::err(
+                    last_err.unwrap_or(Error::LocalApiUnavailableError),
+                )),
+            }
+        },
+    )
+}
+
+/// Fetches `path` from the configured gateways only, trying each in turn, so that content
+/// can be read back through a different route than the API it was added through.
+pub fn gateway_cat(path: Path) -> impl Future<Item = client::ClientResponse, Error = Error> {
+    gateway_send(path, None)
+}
+
+/// Fetches the node at `path` as DAG-JSON from the configured gateways only. Nothing checks
+/// it against its CID, so it can only be trusted if it is verified some other way.
+/// https://specs.ipfs.tech/http-gateways/path-gateway/#format-request-query-parameter
+pub fn gateway_dag_get<T>(path: Path) -> impl Future<Item = T, Error = Error>
+where
+    T: serde::de::DeserializeOwned + 'static,
+{
+    gateway_send(path, Some("dag-json")).and_then(|res| {
+        res.json()
+            .limit(crate::dag::MAX_NODE_SIZE)
+            .map_err(Error::IpfsApiJsonPayloadError)
+    })
+}
+
+/// Sends a GET for `path`, in the response `format` if one is given, to each gateway in turn
+/// until one of them answers successfully.
+fn gateway_send(
+    path: Path,
+    format: Option<&'static str>,
+) -> impl Future<Item = client::ClientResponse, Error = Error> {
+    let config = crate::config::get();
+    let urls: Vec<Url> = config
+        .gateway_urls
+        .iter()
+        .filter_map(|gateway| gateway_url(gateway, &path, config.gateway_style))
+        .map(|mut url| {
+            if let Some(format) = format {
+                url.query_pairs_mut().append_pair("format", format);
+            }
             url
         })
-        .and_then(|url| {
-            debug!("Sending block get request to {}", url);
-            client::get(url)
-                .finish()
-                .unwrap()
-                .send()
-                .timeout(Duration::from_secs(600))
-                .map_err(Error::IpfsApiSendRequestError)
-        })
+        .collect();
+    future::loop_fn(
+        (urls.into_iter(), None),
+        |(mut urls, last_err): (std::vec::IntoIter<Url>, Option<Error>)| match urls.next() {
+            Some(url) => {
+                debug!("Sending gateway request to {}", url);
+                future::Either::A(
+                    send_rate_limited(url.clone(), |url| gateway_get(url).finish().unwrap())
+                        .and_then(move |res| {
+                            if res.status().is_success() {
+                                Ok(res)
+                            } else {
+                                Err(Error::Io(std::io::Error::new(
+                                    std::io::ErrorKind::Other,
+                                    format!("{} responded with {}", url, res.status()),
+                                )))
+                            }
+                        })
+                        .then(|result| match result {
+                            Ok(res) => Ok(future::Loop::Break(res)),
+                            Err(err) => {
+                                warn!("Gateway request failed, trying the next one: {}", err);
+                                Ok(future::Loop::Continue((urls, Some(err))))
+                            }
+                        }),
+                )
+            }
+            None => future::Either::B(future::err(
+                last_err.unwrap_or(Error::LocalApiUnavailableError),
+            )),
+        },
+    )
+}
+
+/// Checks that `block` hashes to the multihash in `cid`.
+pub fn verify_block(cid: &Cid, block: Bytes) -> Result<Bytes, Error> {
+    multihash::decode(&cid.hash)
+        .ok()
+        .and_then(|mh| multihash::encode(mh.alg, &block).ok())
+        .filter(|hash| *hash == cid.hash)
+        .map(|_| block)
+        .ok_or_else(|| Error::BlockVerificationFailed(cid.to_string()))
+}
+
+/// How long names resolved in fast mode are reused
+const FAST_RESOLVE_TTL: Duration = Duration::from_secs(60);
+
+/// Resolutions are cached per path and options, since the options change what a path
+/// resolves to: a single step instead of all, or within a shorter DHT timeout
+type ResolveKey = (String, ResolveOptions);
+
+lazy_static! {
+    static ref RESOLVE_CACHE: Mutex<HashMap<ResolveKey, (Instant, Path)>> =
+        Mutex::new(HashMap::new());
+}
+
+fn cached_resolution(key: &ResolveKey) -> Option<Path> {
+    RESOLVE_CACHE
+        .lock()
+        .unwrap()
+        .get(key)
+        .filter(|(resolved_at, _)| resolved_at.elapsed() < FAST_RESOLVE_TTL)
+        .map(|(_, resolved)| resolved.clone())
+}
+
+fn cache_resolution(key: ResolveKey, resolved: Path) {
+    RESOLVE_CACHE
+        .lock()
+        .unwrap()
+        .insert(key, (Instant::now(), resolved));
+}
+
+/// Options for [`resolve_with`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResolveOptions {
+    /// Resolve until the result is an `/ipfs/` path, instead of a single step
+    pub recursive: bool,
+    /// Skip the daemon's name cache, and ours
+    pub nocache: bool,
+    /// Stop looking for IPNS records after this long
+    pub dht_timeout: Option<Duration>,
+    /// Stop looking for IPNS records once this many were found
+    pub dht_record_count: Option<u32>,
+}
+
+impl Default for ResolveOptions {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            nocache: false,
+            dht_timeout: None,
+            dht_record_count: None,
+        }
+    }
+}
+
+impl ResolveOptions {
+    /// The options used by [`resolve`]: the defaults, or the fast settings when
+    /// [`Config::fast_resolve`](crate::config::Config::fast_resolve) is on.
+    pub fn from_config() -> Self {
+        if crate::config::get().fast_resolve {
+            Self {
+                dht_timeout: Some(Duration::from_secs(10)),
+                dht_record_count: Some(1),
+                ..Self::default()
+            }
+        } else {
+            Self::default()
+        }
+    }
 }
 
 pub fn resolve(path: Path) -> impl Future<Item = Cid, Error = Error> {
-    ipfs_api_url()
-        .then(move |url| match url {
-            Ok(url) => {
-                let mut url = url.join("api/v0/resolve").unwrap();
-                url.query_pairs_mut().append_pair("arg", &path.to_string());
-                debug!("Sending resolve request to {}", url);
-                Ok(url)
+    resolve_with(path, ResolveOptions::from_config()).and_then(|path| match path {
+        Path {
+            prefix: Prefix::Ipfs,
+            root: Root::Cid(cid),
+            ..
+        } => Ok(cid),
+        _ => Err(Error::IpfsPathParseError("Expected CID")),
+    })
+}
+
+/// Resolves `path`, down to an `/ipfs/` path unless `options.recursive` is off.
+/// IPNS names go through `name/resolve`, which understands `nocache`.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-resolve
+/// https://docs.ipfs.io/reference/api/http/#api-v0-name-resolve
+pub fn resolve_with(
+    path: Path,
+    options: ResolveOptions,
+) -> impl Future<Item = Path, Error = Error> {
+    let cached = crate::config::get().fast_resolve && !options.nocache && path.prefix == Prefix::Ipns;
+    let key = (path.to_string(), options.clone());
+    if cached {
+        if let Some(resolved) = cached_resolution(&key) {
+            debug!("Using cached resolution of {}", key.0);
+            return future::Either::A(future::ok(resolved));
+        }
+    }
+    future::Either::B(
+        send_with_fallback(Access::Read, move |endpoint| match endpoint {
+            Endpoint::Api(url) => {
+                let command = match path.prefix {
+                    Prefix::Ipns => "api/v0/name/resolve",
+                    Prefix::Ipfs => "api/v0/resolve",
+                };
+                let mut url = url.join(command).unwrap();
+                url.query_pairs_mut()
+                    .append_pair("arg", &path.to_string())
+                    .append_pair("recursive", &options.recursive.to_string());
+                if path.prefix == Prefix::Ipns {
+                    url.query_pairs_mut()
+                        .append_pair("nocache", &options.nocache.to_string());
+                }
+                if let Some(dht_timeout) = options.dht_timeout {
+                    url.query_pairs_mut()
+                        .append_pair("dht-timeout", &format!("{}s", dht_timeout.as_secs()));
+                }
+                if let Some(dht_record_count) = options.dht_record_count {
+                    url.query_pairs_mut()
+                        .append_pair("dht-record-count", &dht_record_count.to_string());
+                }
+                Some(url)
             }
-            Err(_) => Ok(IPFS_PUBLIC_API_URL.clone().join(&path.to_string()).unwrap()),
+            Endpoint::Gateway(_) => None,
         })
-        .map(|url| client::get(url).finish().unwrap())
-        .and_then(|client| {
-            client
-                .send()
-                .timeout(Duration::from_secs(600))
-                .map_err(|err| Error::IpfsApiSendRequestError(err))
-                .and_then(|res| {
-                    res.json().map_err(|err| {
-                        error!("{:?}", err);
-                        Error::IpfsApiJsonPayloadError(err)
-                    })
-                })
-                // .and_then(|res: Result<ResolveResponse>| match res {
-                //     Result::Ok(res) => Ok(res),
-                //     Result::Err(err) => Err(Error::IpfsApiResponseError(err)),
-                // })
-                .and_then(|res: ResolveResponse| match res.path.root {
-                    Root::Cid(cid) => Ok(cid),
-                    Root::DnsLink(_link) => Err(Error::IpfsPathParseError("Expected CID")),
-                })
+        .and_then(|res| {
+            res.json().map_err(|err| {
+                error!("{:?}", err);
+                Error::IpfsApiJsonPayloadError(err)
+            })
         })
+        // .and_then(|res: Result<ResolveResponse>| match res {
+        //     Result::Ok(res) => Ok(res),
+        //     Result::Err(err) => Err(Error::IpfsApiResponseError(err)),
+        // })
+        .map(move |res: ResolveResponse| {
+            if cached {
+                cache_resolution(key, res.path.clone());
+            }
+            res.path
+        }),
+    )
 }
 
-pub fn ls(path: Path) -> impl Future<Item = LsResponse, Error = Error> {
-    ipfs_api_url()
-        .map(move |url| {
-            let mut url = url.join("api/v0/ls").unwrap();
-            url.query_pairs_mut().append_pair("arg", &path.to_string());
-            debug!("Sending ls request to {}", url);
-            url
-        })
-        .and_then(|url| {
-            client::get(url)
-                .finish()
-                .unwrap()
-                .send()
-                .timeout(Duration::from_secs(600))
-                .map_err(Error::IpfsApiSendRequestError)
+/// Stores a DAG-JSON document as DAG-CBOR and pins it, see [`dag::put`](crate::dag::put).
+pub fn dag_put(document: Bytes) -> impl Future<Item = Cid, Error = Error> {
+    crate::dag::put(
+        document,
+        crate::dag::Codec::DagJson,
+        crate::dag::Codec::DagCbor,
+        true,
+    )
+}
+
+/// Fetches the node at `path` as DAG-JSON, see [`dag::get`](crate::dag::get).
+pub fn dag_get<T>(path: Path) -> impl Future<Item = T, Error = Error>
+where
+    T: serde::de::DeserializeOwned + 'static,
+{
+    crate::dag::get(path)
+}
+
+/// The manifest linked from a published root
+pub fn manifest_get(root: Cid) -> impl Future<Item = Manifest, Error = Error> {
+    dag_get(Path {
+        prefix: Prefix::Ipfs,
+        root: Root::Cid(root),
+        suffix: Some(MANIFEST_LINK_NAME.into()),
+    })
+}
+
+/// Stores `manifest` and a root linking it and its objects as laid out by `layout`, and pins
+/// the root, and with it every object. With a signing key configured, the root also holds a
+/// signature of the manifest. Fails with [`Error::ManifestTooLarge`] before storing anything
+/// if the manifest doesn't fit in one block.
+pub fn root_put(manifest: &Manifest, layout: Layout) -> impl Future<Item = Cid, Error = Error> {
+    if let Err(err) = manifest.check_size() {
+        return future::Either::A(future::err(err));
+    }
+    let objects = manifest
+        .objects
+        .iter()
+        .map(|(oid, entry)| {
+            Ok(layout
+                .object_path(oid)?
+                .map(|path| (path, entry.cid.cid.clone())))
         })
+        .collect::<Result<Vec<_>, Error>>();
+    let signature = match crate::config::get().signing_key.clone() {
+        Some(key_name) => future::Either::A(crate::signature::sign(manifest, &key_name).map(Some)),
+        None => future::Either::B(future::ok(None)),
+    };
+    let link = future::result(objects)
+        .join3(signature, crate::dag::put_json(manifest, false))
+        .and_then(move |(objects, signature, manifest)| {
+            let mut root = serde_json::Map::new();
+            root.insert(
+                MANIFEST_LINK_NAME.to_string(),
+                serde_json::json!({ "/": manifest.to_string() }),
+            );
+            if let Some(signature) = signature {
+                root.insert(
+                    SIGNATURE_LINK_NAME.to_string(),
+                    serde_json::to_value(signature).expect("signatures always serialize"),
+                );
+            }
+            for (path, cid) in objects.into_iter().flatten() {
+                let mut components: Vec<&str> = path.split('/').collect();
+                let name = components.pop().unwrap_or_default();
+                let mut directory = &mut root;
+                for component in components {
+                    directory = directory
+                        .entry(component)
+                        .or_insert_with(|| serde_json::json!({}))
+                        .as_object_mut()
+                        .expect("fanout directories are maps");
+                }
+                directory.insert(name.to_string(), serde_json::json!({ "/": cid }));
+            }
+            crate::dag::put_json(&root, true)
+        });
+    future::Either::B(crate::timing::timed("link", link))
+}
+
+/// Finds the CID of an object in a published root, see [`locate_object`].
+pub fn resolve_object(root: Cid, oid: String) -> impl Future<Item = Cid, Error = Error> {
+    locate_object(root, oid).map(|(cid, _)| cid)
+}
+
+/// Finds the CID of an object in a published root, and whether it is a UnixFS file rather
+/// than the object's root block. Prefers the root's manifest, falling back to the object's
+/// path in a flat, then a fanout, layout if it has none.
+pub fn locate_object(root: Cid, oid: String) -> impl Future<Item = (Cid, bool), Error = Error> {
+    manifest_get(root.clone()).then(move |result| {
+        use cid::ToCid;
+        match result
+            .ok()
+            .and_then(|manifest| manifest.objects.get(&oid).cloned())
+        {
+            Some(entry) => future::Either::A(future::result(
+                entry
+                    .cid
+                    .cid
+                    .to_cid()
+                    .map(|cid| (cid, entry.is_unixfs()))
+                    .map_err(|_| Error::IpfsPathParseError("Expected a CID")),
+            )),
+            None => {
+                let object_path = |layout: Layout| -> Result<Path, Error> {
+                    Ok(Path {
+                        prefix: Prefix::Ipfs,
+                        root: Root::Cid(root.clone()),
+                        suffix: layout.object_path(&oid)?.map(Into::into),
+                    })
+                };
+                let paths = object_path(Layout::Flat)
+                    .and_then(|flat| Ok((flat, object_path(Layout::Fanout)?)));
+                future::Either::B(
+                    future::result(paths)
+                        .and_then(|(flat, fanout)| resolve(flat).or_else(move |_| resolve(fanout)))
+                        .map(|cid| (cid, false)),
+                )
+            }
+        }
+    })
+}
+
+pub fn ls(path: Path) -> impl Future<Item = LsResponse, Error = Error> {
+    send_with_fallback(Access::Read, api_only("api/v0/ls", path.to_string()))
         .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
     // .and_then(|res: Result<LsResponse>| match res {
     //     Result::Ok(res) => Ok(res),
@@ -287,8 +1147,8 @@ pub fn object_patch_link(
     add_cid: Cid,
     create: bool,
 ) -> impl Future<Item = ObjectResponse, Error = Error> {
-    ipfs_api_url()
-        .map(move |url| {
+    send_with_fallback(Access::Write, move |endpoint| match endpoint {
+        Endpoint::Api(url) => {
             let mut url = url.join("api/v0/object/patch/add-link").unwrap();
             url.query_pairs_mut()
                 .append_pair("arg", &modify_cid.to_string());
@@ -297,20 +1157,14 @@ pub fn object_patch_link(
                 .append_pair("arg", &add_cid.to_string());
             url.query_pairs_mut()
                 .append_pair("create", &create.to_string());
-            debug!("Sending object patch link request to {}", url);
-
-            url
-        })
-        .map(|url| client::get(url).finish().unwrap())
-        .and_then(|client| {
-            client
-                .send()
-                .map_err(|err| Error::IpfsApiSendRequestError(err))
-        })
-        .and_then(|res| {
-            res.json()
-                .map_err(|err| Error::IpfsApiJsonPayloadError(err))
-        })
+            Some(url)
+        }
+        Endpoint::Gateway(_) => None,
+    })
+    .and_then(|res| {
+        res.json()
+            .map_err(|err| Error::IpfsApiJsonPayloadError(err))
+    })
     // .and_then(|res: Result<ObjectResponse>| match res {
     //     Result::Ok(res) => Ok(res),
     //     Result::Err(err) => Err(Error::IpfsApiResponseError(err)),
@@ -318,90 +1172,442 @@ pub fn object_patch_link(
 }
 
 pub fn name_publish(cid: Cid, key: Key) -> impl Future<Item = String, Error = Error> {
+    name_publish_path(Path::ipfs(cid), key)
+}
+
+/// Publishes `path` under `key`, which may also be an `/ipns/` path, so that resolving the
+/// key leads on to another name.
+pub fn name_publish_path(path: Path, key: Key) -> impl Future<Item = String, Error = Error> {
     debug!("Publishing with key {:?}", key);
+    let publish = send_to_node(move |url| {
+        let mut url = url.join("api/v0/name/publish").unwrap();
+        url.query_pairs_mut()
+            .append_pair("arg", &path.to_string())
+            .append_pair("key", &key.name);
+        url
+    })
+    .and_then(|res| res.body().map_err(|err| Error::IpfsApiPayloadError(err)))
+    .map(|bytes: Bytes| String::from_utf8_lossy(&bytes).to_string());
+    crate::timing::timed("publish", publish)
+}
+
+pub fn key_list() -> impl Future<Item = KeyListResponse, Error = Error> {
+    send_to_node(|url| url.join("api/v0/key/list").unwrap()).and_then(|res| {
+        res.json()
+            .map_err(|err| Error::IpfsApiJsonPayloadError(err))
+    })
+    // .and_then(|res: Result<KeyListResponse>| match res {
+    //     Result::Ok(res) => Ok(res),
+    //     Result::Err(err) => {
+    //         error!("{:?}", err);
+    //         Err(Error::IpfsApiResponseError(err))
+    //     }
+    // })
+}
+
+/// Generates an IPNS key named `name` on the local node.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-key-gen
+pub fn key_gen(name: &str) -> impl Future<Item = Key, Error = Error> {
+    let name = name.to_string();
     ipfs_api_url()
-        .then(move |url| match url {
-            Ok(url) => {
-                let mut url = url.join("api/v0/name/publish").unwrap();
-                url.query_pairs_mut()
-                    .append_pair("arg", &format!("/ipfs/{}", cid))
-                    .append_pair("key", &key.name);
-                debug!("Sending name publish request to {}", url);
-                Ok(url)
-            }
-            Err(_) => Ok(IPFS_PUBLIC_API_URL.clone().join(&cid.to_string()).unwrap()),
+        .map(move |url| {
+            let mut url = url.join("api/v0/key/gen").unwrap();
+            url.query_pairs_mut()
+                .append_pair("arg", &name)
+                .append_pair("type", "ed25519");
+            debug!("Sending key gen request to {}", url);
+            url
         })
-        .map(|url| client::get(url).finish().unwrap())
-        .and_then(|client| {
-            client
+        .and_then(|url| {
+            api_request(url)
+                .finish()
+                .unwrap()
                 .send()
                 .timeout(Duration::from_secs(600))
-                .map_err(|err| Error::IpfsApiSendRequestError(err))
+                .map_err(Error::IpfsApiSendRequestError)
+        })
+        .and_then(|res| {
+            if res.status().is_success() {
+                future::Either::A(res.json().map_err(Error::IpfsApiJsonPayloadError))
+            } else {
+                future::Either::B(
+                    res.json()
+                        .map_err(Error::IpfsApiJsonPayloadError)
+                        .and_then(|err| Err(Error::IpfsApiResponseError(err))),
+                )
+            }
         })
-        .and_then(|res| res.body().map_err(|err| Error::IpfsApiPayloadError(err)))
-        .map(|bytes: Bytes| String::from_utf8_lossy(&bytes).to_string())
 }
 
-pub fn key_list() -> impl Future<Item = KeyListResponse, Error = Error> {
+/// Sends `data` as the file argument of a request to the API at `url`.
+fn post_data(url: Url, data: &[u8]) -> impl Future<Item = client::ClientResponse, Error = Error> {
+    let (boundary, body) = multipart_encode(data);
+    api_post(url)
+        .header(
+            header::CONTENT_TYPE,
+            format!("{}; boundary={}", mime::MULTIPART_FORM_DATA, boundary),
+        )
+        .body(body)
+        .unwrap()
+        .send()
+        .timeout(Duration::from_secs(600))
+        .map_err(Error::IpfsApiSendRequestError)
+        .and_then(error_for_status)
+}
+
+/// Signs `data` with the local key named `key_name`. Needs Kubo 0.25 or later.
+/// https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-key-sign
+pub fn key_sign(key_name: &str, data: Bytes) -> impl Future<Item = String, Error = Error> {
+    let key_name = key_name.to_string();
     ipfs_api_url()
-        .map(|url| {
-            let mut url = url.join("api/v0/key/list").unwrap();
-            debug!("Sending key list request to {}", url);
-            url
+        .and_then(move |url| {
+            let mut url = url.join("api/v0/key/sign").unwrap();
+            url.query_pairs_mut().append_pair("key", &key_name);
+            debug!("Sending key sign request to {}", url);
+            post_data(url, &data)
+        })
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+        .map(|res: KeySignResponse| res.signature)
+}
+
+/// Whether `signature` is a signature of `data` by the key with the ID `key`, which doesn't
+/// have to be on the node. Needs Kubo 0.25 or later.
+/// https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-key-verify
+pub fn key_verify(
+    key: &str,
+    signature: &str,
+    data: Bytes,
+) -> impl Future<Item = bool, Error = Error> {
+    let (key, signature) = (key.to_string(), signature.to_string());
+    ipfs_api_url()
+        .and_then(move |url| {
+            let mut url = url.join("api/v0/key/verify").unwrap();
+            url.query_pairs_mut()
+                .append_pair("key", &key)
+                .append_pair("signature", &signature);
+            debug!("Sending key verify request to {}", url);
+            post_data(url, &data)
+        })
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+        .map(|res: KeyVerifyResponse| res.signature_valid)
+}
+
+pub fn version() -> impl Future<Item = VersionResponse, Error = Error> {
+    send_to_node(|url| url.join("api/v0/version").unwrap())
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-config-show
+pub fn config_show() -> impl Future<Item = serde_json::Value, Error = Error> {
+    send_to_node(|url| url.join("api/v0/config/show").unwrap()).and_then(|res| {
+        res.json()
+            .limit(usize::max_value())
+            .map_err(Error::IpfsApiJsonPayloadError)
+    })
+}
+
+/// The oldest daemon whose API this crate is known to work with
+pub const MINIMUM_VERSION: (u64, u64, u64) = (0, 4, 18);
+
+/// What the daemon behind the API supports, detected from its version and configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: (u64, u64, u64),
+    /// go-ipfs 0.5 and later reject GET requests to the API
+    pub post_only: bool,
+    pub pubsub: bool,
+    /// https://github.com/ipfs/kubo/blob/master/docs/experimental-features.md#ipns-pubsub
+    pub ipns_pubsub: bool,
+}
+
+/// Detects the daemon's capabilities, failing with [`Error::DaemonTooOld`] below [`MINIMUM_VERSION`].
+pub fn capabilities() -> impl Future<Item = Capabilities, Error = Error> {
+    version()
+        .join(config_show())
+        .and_then(|(version, config)| {
+            let parsed = parse_version(&version.version);
+            if parsed < MINIMUM_VERSION {
+                return Err(Error::DaemonTooOld(
+                    version.version,
+                    format!(
+                        "{}.{}.{}",
+                        MINIMUM_VERSION.0, MINIMUM_VERSION.1, MINIMUM_VERSION.2
+                    ),
+                ));
+            }
+            let flag = |pointer: &str| {
+                config
+                    .pointer(pointer)
+                    .and_then(serde_json::Value::as_bool)
+                    .unwrap_or(false)
+            };
+            Ok(Capabilities {
+                version: parsed,
+                post_only: crate::rpc::post_only(parsed),
+                pubsub: flag("/Pubsub/Enabled"),
+                ipns_pubsub: flag("/Ipns/UsePubsub"),
+            })
+        })
+}
+
+/// Parses versions like `0.23.0-dev`, treating missing or unparseable components as 0.
+pub(crate) fn parse_version(version: &str) -> (u64, u64, u64) {
+    let mut it = version
+        .split('-')
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|component| component.parse().unwrap_or(0));
+    (
+        it.next().unwrap_or(0),
+        it.next().unwrap_or(0),
+        it.next().unwrap_or(0),
+    )
+}
+
+/// Every pin on the local daemon, see [`pin::ls`](crate::pin::ls).
+pub fn pin_ls() -> impl Future<Item = PinLsResponse, Error = Error> {
+    crate::pin::ls(crate::pin::PinType::All)
+}
+
+/// See [`pin::add`](crate::pin::add).
+pub fn pin_add(cid: Cid) -> impl Future<Item = PinResponse, Error = Error> {
+    crate::pin::add(cid)
+}
+
+/// Asks every configured mirror to announce `cid` to the DHT, for when a download failed
+/// because no provider could be found. Returns how many mirrors agreed; failures are logged,
+/// since a mirror without the object can't help anyway.
+/// https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-routing-provide
+pub fn provide_from_mirrors(cid: Cid) -> impl Future<Item = usize, Error = Error> {
+    let mirrors = crate::config::get().mirror_api_urls.clone();
+    future::join_all(mirrors.into_iter().map(move |mirror| {
+        crate::routing::provide_at(&mirror, &cid).then(move |result| {
+            if let Err(err) = &result {
+                warn!("Mirror {} could not provide: {}", mirror, err);
+            }
+            Ok::<_, Error>(result.is_ok())
+        })
+    }))
+    .map(|provided| provided.into_iter().filter(|provided| *provided).count())
+}
+
+/// See [`pin::rm`](crate::pin::rm).
+pub fn pin_rm(cid: Cid) -> impl Future<Item = PinResponse, Error = Error> {
+    crate::pin::rm(cid)
+}
+
+/// The pubsub topic new roots published under the IPNS name `name` are announced on
+pub fn announce_topic(name: &str) -> String {
+    format!("git-lfs-ipfs/{}", name)
+}
+
+/// Publishes `data` on `topic`.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pubsub-pub
+pub fn pubsub_pub(topic: &str, data: Bytes) -> impl Future<Item = (), Error = Error> {
+    let topic = multibase_encode(topic.as_bytes());
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/pubsub/pub").unwrap();
+            url.query_pairs_mut().append_pair("arg", &topic);
+            debug!("Sending pubsub pub request to {}", url);
+            let (boundary, body) = multipart_encode(&data);
+            api_post(url)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("{}; boundary={}", mime::MULTIPART_FORM_DATA, boundary),
+                )
+                .body(body)
+                .unwrap()
         })
-        .map(|url| client::get(url).finish().unwrap())
         .and_then(|client| {
             client
                 .send()
-                .map_err(|err| Error::IpfsApiSendRequestError(err))
+                .timeout(Duration::from_secs(600))
+                .map_err(Error::IpfsApiSendRequestError)
         })
         .and_then(|res| {
-            res.json()
-                .map_err(|err| Error::IpfsApiJsonPayloadError(err))
+            if res.status().is_success() {
+                future::Either::A(future::ok(()))
+            } else {
+                future::Either::B(
+                    res.json()
+                        .map_err(Error::IpfsApiJsonPayloadError)
+                        .and_then(|err| Err(Error::IpfsApiResponseError(err))),
+                )
+            }
         })
-    // .and_then(|res: Result<KeyListResponse>| match res {
-    //     Result::Ok(res) => Ok(res),
-    //     Result::Err(err) => {
-    //         error!("{:?}", err);
-    //         Err(Error::IpfsApiResponseError(err))
-    //     }
-    // })
 }
 
+/// Streams every message received on `topic` until the daemon closes the connection.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pubsub-sub
+pub fn pubsub_sub(topic: &str) -> impl Stream<Item = PubsubMessage, Error = Error> {
+    let topic = multibase_encode(topic.as_bytes());
+    ipfs_api_url()
+        .and_then(move |url| {
+            let mut url = url.join("api/v0/pubsub/sub").unwrap();
+            url.query_pairs_mut().append_pair("arg", &topic);
+            debug!("Sending pubsub sub request to {}", url);
+            api_post(url)
+                .finish()
+                .unwrap()
+                .send()
+                .timeout(Duration::from_secs(600))
+                .map_err(Error::IpfsApiSendRequestError)
+        })
+        .map(|res| json_lines(res.payload()))
+        .flatten_stream()
+}
+
+/// Splits a streamed response into lines of JSON, as sent by streaming API commands.
+pub(crate) fn json_lines<S, T>(payload: S) -> impl Stream<Item = T, Error = Error>
+where
+    S: Stream<Item = Bytes, Error = actix_web::error::PayloadError>,
+    T: serde::de::DeserializeOwned,
+{
+    let mut buffer: Vec<u8> = vec![];
+    payload
+        .map_err(Error::IpfsApiPayloadError)
+        // Ends the last line even if the daemon didn't
+        .chain(futures::stream::once(Ok(Bytes::from_static(b"\n"))))
+        .map(move |chunk| {
+            buffer.extend_from_slice(&chunk);
+            let mut lines = vec![];
+            while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                lines.push(buffer.drain(..=end).collect::<Vec<u8>>());
+            }
+            futures::stream::iter_ok(lines)
+        })
+        .flatten()
+        .filter(|line| line.iter().any(|b| !b.is_ascii_whitespace()))
+        .and_then(|line| serde_json::from_slice(&line).map_err(Error::IpfsApiJsonStreamError))
+}
+
+/// Encodes as multibase base64url, which is how the API expects pubsub topics.
+fn multibase_encode(data: &[u8]) -> String {
+    format!("u{}", base64::encode_config(data, base64::URL_SAFE_NO_PAD))
+}
+
+/// Decodes pubsub payloads, which are multibase base64url since go-ipfs 0.11 and plain base64 before.
+pub fn multibase_decode(data: &str) -> Option<Vec<u8>> {
+    if data.starts_with('u') {
+        if let Ok(decoded) = base64::decode_config(&data[1..], base64::URL_SAFE_NO_PAD) {
+            return Some(decoded);
+        }
+    }
+    base64::decode(data).ok()
+}
+
+/// The first API endpoint: the local daemon if there is one, otherwise the first configured endpoint.
+/// Used for requests that concern one node, like its keys, configuration or pins.
 pub fn ipfs_api_url() -> impl Future<Item = Url, Error = Error> + Send {
-    use multiaddr::{AddrComponent, ToMultiaddr};
-    use std::fs;
-    use std::net::IpAddr;
     future::result(
-        dirs::home_dir()
-            .map(|mut home_dir| {
-                home_dir.push(".ipfs");
-                home_dir.push("api");
-                home_dir
-            })
-            .and_then(|multiaddr_path| fs::read_to_string(&multiaddr_path).ok())
-            .and_then(|multiaddr_str| multiaddr_str.to_multiaddr().ok())
-            .and_then(|multiaddr| {
-                let mut addr: Option<IpAddr> = None;
-                let mut port: Option<u16> = None;
-                for addr_component in multiaddr.iter() {
-                    match addr_component {
-                        AddrComponent::IP4(v4addr) => addr = Some(v4addr.into()),
-                        AddrComponent::IP6(v6addr) => addr = Some(v6addr.into()),
-                        AddrComponent::TCP(tcpport) => port = Some(tcpport),
-                        _ => {
-                            return None;
-                        }
-                    }
-                }
-                if let (Some(addr), Some(port)) = (addr, port) {
-                    Url::parse(&format!("http://{}:{}/", addr, port))
-                        .map_err(|_| ())
-                        .ok()
-                } else {
-                    None
-                }
-            })
+        local_api_url()
+            .or_else(|| crate::config::get().api_urls.first().cloned())
             .ok_or(Error::LocalApiUnavailableError),
     )
 }
+
+/// The local daemon's API, as written to `$IPFS_PATH/api` when it starts
+fn local_api_url() -> Option<Url> {
+    ipfs_repo_path()
+        .map(|mut ipfs_path| {
+            ipfs_path.push("api");
+            ipfs_path
+        })
+        .and_then(|multiaddr_path| std::fs::read_to_string(&multiaddr_path).ok())
+        .and_then(|multiaddr_str| crate::config::parse_endpoint(multiaddr_str.trim()))
+}
+
+/// The local IPFS repository: `$IPFS_PATH`, or `.ipfs` in the home directory
+/// (`%USERPROFILE%` on Windows). Like the daemon, expands a leading `~` in `$IPFS_PATH`.
+pub fn ipfs_repo_path() -> Option<std::path::PathBuf> {
+    match std::env::var_os("IPFS_PATH") {
+        Some(ipfs_path) => {
+            let ipfs_path = std::path::PathBuf::from(ipfs_path);
+            match ipfs_path.strip_prefix("~") {
+                Ok(rest) => dirs::home_dir().map(|home_dir| home_dir.join(rest)),
+                Err(_) => Some(ipfs_path),
+            }
+        }
+        None => dirs::home_dir().map(|mut home_dir| {
+            home_dir.push(".ipfs");
+            home_dir
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn multibase_round_trips() {
+        let topic = "git-lfs-ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        assert!(multibase_encode(topic.as_bytes()).starts_with('u'));
+        assert_eq!(
+            topic.as_bytes(),
+            &multibase_decode(&multibase_encode(topic.as_bytes())).unwrap()[..]
+        );
+        assert_eq!(b"hello", &multibase_decode("aGVsbG8=").unwrap()[..]);
+    }
+
+    #[test]
+    fn retry_after_parses_seconds_and_dates() {
+        let now = chrono::DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            parse_retry_after(" 120", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(0)),
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now)
+        );
+        assert_eq!(None, parse_retry_after("soon", now));
+    }
+
+    #[test]
+    fn resolutions_are_only_reused_with_the_same_options() {
+        let name = "/ipns/k51qzi5uqu5dlvj2baxnqndepeb86cbk3ng7n3i46uzyxzyqj2xjonzllnv0v8".to_string();
+        let resolved = Path::from_str(&format!("/ipfs/{}", EMPTY_FOLDER_HASH)).unwrap();
+        let recursive = ResolveOptions::default();
+        let single_step = ResolveOptions {
+            recursive: false,
+            ..ResolveOptions::default()
+        };
+        cache_resolution((name.clone(), recursive.clone()), resolved.clone());
+        assert_eq!(Some(resolved), cached_resolution(&(name.clone(), recursive)));
+        assert_eq!(None, cached_resolution(&(name, single_step)));
+    }
+
+    #[test]
+    fn versions_parse_with_suffixes() {
+        assert_eq!((0, 4, 18), parse_version("0.4.18"));
+        assert_eq!((0, 23, 0), parse_version("0.23.0-dev"));
+        assert_eq!((1, 0, 0), parse_version("1"));
+    }
+
+    #[test]
+    fn gateway_urls_are_generated_in_both_styles() {
+        let gateway = Url::parse("https://ipfs.io/").unwrap();
+        let path = Path::from_str(&format!("/ipfs/{}/a.bin", EMPTY_FOLDER_HASH)).unwrap();
+        assert_eq!(
+            format!("https://ipfs.io/ipfs/{}/a.bin", EMPTY_FOLDER_HASH),
+            gateway_url(&gateway, &path, GatewayStyle::Path)
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "https://bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354.ipfs.ipfs.io/a.bin",
+            gateway_url(&gateway, &path, GatewayStyle::Subdomain)
+                .unwrap()
+                .to_string()
+        );
+    }
+}
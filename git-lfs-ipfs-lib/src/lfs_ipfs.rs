@@ -0,0 +1,558 @@
+//! A high-level API for storing Git LFS objects on IPFS, for tools that want to move objects
+//! without going through git-lfs or the transfer agent.
+//!
+//! An LFS object is the root block of a file added to IPFS, so its SHA2-256 OID is also the
+//! digest of its CIDv0 and objects can be found without any lookup table.
+//!
+//! ```no_run
+//! # use futures::prelude::*;
+//! use git_lfs_ipfs_lib::LfsIpfs;
+//!
+//! let mut sys = actix::System::new("example");
+//! let lfs = LfsIpfs::new();
+//! let oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+//! let block = sys
+//!     .block_on(lfs.download_object(oid).concat2())
+//!     .unwrap();
+//! ```
+use actix_web::error::{PayloadError, ResponseError};
+use bytes::Bytes;
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+
+use openssl::sha::Sha256;
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::ipfs;
+use crate::pin;
+use crate::signature;
+use crate::spec::ipfs::{Path, Prefix, Root};
+use crate::spec::{manifest::Manifest, Object};
+use crate::storage;
+use crate::timing;
+
+/// Entry point to the high-level API. Endpoints come from the global [`config`](crate::config).
+#[derive(Debug, Clone, Default)]
+pub struct LfsIpfs {
+    /// Published root that [`resolve`](LfsIpfs::resolve) looks objects up in, if any
+    root: Option<Path>,
+}
+
+impl LfsIpfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks objects up in the manifest of `root`, an `/ipfs/` or `/ipns/` path, before
+    /// falling back to deriving their CID from the OID.
+    pub fn with_root(root: Path) -> Self {
+        Self { root: Some(root) }
+    }
+
+    /// Stores an object's root block and pins it. Fails with [`Error::SizeMismatch`] if
+    /// `stream` isn't `size` bytes long, aborting the request before the daemon stores
+    /// anything, with [`Error::HashError`] if the stored block does not hash to `oid`, and with
+    /// [`Error::StorageFull`] up front if the node has no room for it and is configured to refuse.
+    pub fn upload_object<S, E>(
+        &self,
+        oid: &str,
+        size: u64,
+        stream: S,
+    ) -> impl Future<Item = Cid, Error = Error>
+    where
+        S: Stream<Item = Bytes, Error = E> + 'static,
+        E: ResponseError,
+    {
+        let oid = oid.to_string();
+        storage::ensure_space(size)
+            .and_then(move |_| {
+                ipfs::oid_to_cid(cid::Codec::DagProtobuf, &oid).map(|expected| (oid, expected))
+            })
+            .and_then(move |(oid, expected)| {
+                let stream = SizeChecked::new(stream, size);
+                let (received, mismatched) = (stream.received.clone(), stream.mismatched.clone());
+                timing::timed("add", ipfs::block_put(stream, Some(size))).then(move |res| {
+                    let res = check_size(&received, &mismatched, &oid, size, res)?;
+                    if res.key == expected {
+                        Ok(res.key)
+                    } else {
+                        Err(Error::HashError)
+                    }
+                })
+            })
+    }
+
+    /// Adds `stream`, the content of `object` as a regular LFS server stores it, as a UnixFS
+    /// file and pins it. Fails like [`upload_object`](LfsIpfs::upload_object) if the content
+    /// doesn't match the object's size or hash. It is only pinned once it checks out, so that
+    /// content someone else pinned is never unpinned because of a mismatch.
+    pub fn import_file<S, E>(
+        &self,
+        object: &Object,
+        stream: S,
+    ) -> impl Future<Item = Cid, Error = Error>
+    where
+        S: Stream<Item = Bytes, Error = E> + 'static,
+        E: ResponseError,
+    {
+        self.import(object, stream, None)
+    }
+
+    /// Like [`import_file`](LfsIpfs::import_file), calling `on_progress` with how many bytes of
+    /// the object the daemon has read every time it reports progress.
+    pub fn import_file_with_progress<S, E, F>(
+        &self,
+        object: &Object,
+        stream: S,
+        on_progress: F,
+    ) -> impl Future<Item = Cid, Error = Error>
+    where
+        S: Stream<Item = Bytes, Error = E> + 'static,
+        E: ResponseError,
+        F: FnMut(u64) + 'static,
+    {
+        self.import(object, stream, Some(Box::new(on_progress)))
+    }
+
+    fn import<S, E>(
+        &self,
+        object: &Object,
+        stream: S,
+        on_progress: Option<Box<FnMut(u64)>>,
+    ) -> impl Future<Item = Cid, Error = Error>
+    where
+        S: Stream<Item = Bytes, Error = E> + 'static,
+        E: ResponseError,
+    {
+        let object = object.clone();
+        storage::ensure_space(object.size).and_then(move |_| {
+            let hasher = Rc::new(RefCell::new(Sha256::new()));
+            let stream = SizeChecked::new(stream, object.size);
+            let (received, mismatched) = (stream.received.clone(), stream.mismatched.clone());
+            let stream = stream.inspect({
+                let hasher = hasher.clone();
+                move |chunk| hasher.borrow_mut().update(chunk)
+            });
+            ipfs::add_unpinned(stream, Some(object.size), on_progress).then(move |res| {
+                let checked = check_size(&received, &mismatched, &object.oid, object.size, res);
+                let cid = match checked {
+                    Ok(added) => added.hash,
+                    Err(err) => return future::Either::A(future::err(err)),
+                };
+                let digest = hasher.replace(Sha256::new()).finish();
+                if hex::encode(digest) == object.oid {
+                    future::Either::B(pin::add(cid.clone()).map(move |_| cid))
+                } else {
+                    future::Either::A(future::err(Error::HashError))
+                }
+            })
+        })
+    }
+
+    /// Streams an object: its root block, from a trustless gateway if so configured, or the
+    /// content of the UnixFS file its root's manifest points to. In read-through mode, the
+    /// object is checked as described in [`read_through`](LfsIpfs::read_through) first.
+    pub fn download_object(&self, oid: &str) -> impl Stream<Item = Bytes, Error = Error> {
+        let config = crate::config::get();
+        if config.read_through {
+            return Box::new(self.read_through(oid).map(|(_, content)| content).into_stream())
+                as Box<Stream<Item = Bytes, Error = Error>>;
+        }
+        let trustless = config.trustless;
+        let oid = oid.to_string();
+        let stream = self
+            .locate(&oid)
+            .and_then(move |(cid, unixfs)| {
+                if unixfs {
+                    future::Either::A(ipfs::cat(Path::ipfs(cid)).map(
+                        |res| -> Box<Stream<Item = Bytes, Error = Error>> {
+                            Box::new(res.payload().map_err(Error::IpfsApiPayloadError))
+                        },
+                    ))
+                } else if trustless {
+                    future::Either::B(future::Either::A(
+                        ipfs::gateway_block_get(cid)
+                            .map(|block| -> Box<Stream<Item = Bytes, Error = Error>> {
+                                Box::new(futures::stream::once(Ok(block)))
+                            }),
+                    ))
+                } else {
+                    future::Either::B(future::Either::B(ipfs::block_get(cid.clone()).and_then(
+                        move |res| -> Result<Box<Stream<Item = Bytes, Error = Error>>, Error> {
+                            if res.status().is_success() {
+                                Ok(Box::new(res.payload().map_err(Error::IpfsApiPayloadError)))
+                            } else {
+                                Err(Error::ObjectNotFound {
+                                    oid,
+                                    cid: cid.to_string(),
+                                })
+                            }
+                        },
+                    )))
+                }
+            })
+            .flatten_stream();
+        Box::new(stream)
+    }
+
+    /// Fetches an object through the gateways only, and hands it out along with its CID once it
+    /// matches its entry in the root's manifest, whose signature must verify against the
+    /// trusted key. The object is held in memory until then, so that nothing unverified is
+    /// ever served.
+    pub fn read_through(&self, oid: &str) -> impl Future<Item = (Cid, Bytes), Error = Error> {
+        let oid = oid.to_string();
+        let trusted_key = crate::config::get().trusted_key.clone();
+        let (root, trusted_key) = match (self.root.clone(), trusted_key) {
+            (Some(root), Some(trusted_key)) => (root, trusted_key),
+            (None, _) => {
+                return future::Either::A(future::err(Error::ReadThroughMisconfigured(
+                    "a root to read objects from",
+                )))
+            }
+            (_, None) => {
+                return future::Either::A(future::err(Error::ReadThroughMisconfigured(
+                    "a trusted key to verify manifests with",
+                )))
+            }
+        };
+        future::Either::B(
+            signature::verified_manifest(root, trusted_key, true)
+                .and_then({
+                    let oid = oid.clone();
+                    move |manifest| manifest_entry(&manifest, &oid)
+                })
+                .and_then(|(cid, size, unixfs)| {
+                    let content = if unixfs {
+                        future::Either::A(ipfs::gateway_cat(Path::ipfs(cid.clone())).and_then(
+                            move |res| {
+                                res.body()
+                                    .limit(size as usize)
+                                    .map_err(Error::IpfsApiPayloadError)
+                            },
+                        ))
+                    } else {
+                        future::Either::B(ipfs::gateway_block_get(cid.clone()))
+                    };
+                    content.map(move |content| (cid, size, content))
+                })
+                .and_then(move |(cid, size, content)| {
+                    if content.len() as u64 != size {
+                        return Err(Error::SizeMismatch {
+                            oid,
+                            expected: size,
+                            received: content.len() as u64,
+                        });
+                    }
+                    let mut hasher = Sha256::new();
+                    hasher.update(&content);
+                    if hex::encode(hasher.finish()) == oid {
+                        Ok((cid, content))
+                    } else {
+                        Err(Error::HashError)
+                    }
+                }),
+        )
+    }
+
+    /// Reads an uploaded object back through the configured gateways instead of the API:
+    /// its root block must hash to `oid`, and the file it roots must hash the same as when
+    /// read through the API. Fails with [`Error::ReadBackMismatch`] otherwise.
+    pub fn read_back(&self, oid: &str) -> impl Future<Item = (), Error = Error> {
+        let oid = oid.to_string();
+        ipfs::oid_to_cid(cid::Codec::DagProtobuf, &oid)
+            .and_then(|cid| ipfs::gateway_block_get(cid.clone()).map(|_| cid))
+            .and_then(|cid| {
+                let api = ipfs::cat(Path::ipfs(cid.clone()))
+                    .and_then(|res| sha256(res.payload().map_err(Error::IpfsApiPayloadError)));
+                let gateway = ipfs::gateway_cat(Path::ipfs(cid))
+                    .and_then(|res| sha256(res.payload().map_err(Error::IpfsApiPayloadError)));
+                api.join(gateway)
+            })
+            .and_then(move |(api, gateway)| {
+                if api == gateway {
+                    Ok(())
+                } else {
+                    Err(Error::ReadBackMismatch(oid))
+                }
+            })
+    }
+
+    /// Finds the CID an object is stored under.
+    pub fn resolve(&self, oid: &str) -> impl Future<Item = Cid, Error = Error> {
+        self.locate(oid).map(|(cid, _)| cid)
+    }
+
+    /// Finds the CID an object is stored under, and whether it is a UnixFS file holding the
+    /// object rather than its root block, see [`ipfs::locate_object`]. With a trusted key
+    /// configured, only the root's manifest is consulted, once its signature verified.
+    pub fn locate(&self, oid: &str) -> impl Future<Item = (Cid, bool), Error = Error> {
+        let oid = oid.to_string();
+        match (self.root.clone(), crate::config::get().trusted_key.clone()) {
+            (Some(root), Some(trusted_key)) => future::Either::A(future::Either::A(
+                signature::verified_manifest(root, trusted_key, false).and_then(move |manifest| {
+                    manifest_entry(&manifest, &oid).map(|(cid, _, unixfs)| (cid, unixfs))
+                }),
+            )),
+            (Some(root), None) => future::Either::A(future::Either::B(
+                ipfs::resolve(root).and_then(move |root| ipfs::locate_object(root, oid)),
+            )),
+            (None, _) => future::Either::B(
+                ipfs::oid_to_cid(cid::Codec::DagProtobuf, &oid).map(|cid| (cid, false)),
+            ),
+        }
+    }
+
+    /// Publishes `root` under the local IPNS key named `key_name`, returning the IPNS path.
+    pub fn publish(&self, root: Cid, key_name: &str) -> impl Future<Item = Path, Error = Error> {
+        let key_name = key_name.to_string();
+        ipfs::key_list()
+            .and_then(move |key_list| {
+                key_list
+                    .keys
+                    .into_iter()
+                    .find(|key| key.name == key_name)
+                    .ok_or(Error::IpfsUploadNotPossible)
+            })
+            .and_then(move |key| {
+                let path = Path {
+                    prefix: Prefix::Ipns,
+                    root: Root::Cid(key.id.clone()),
+                    suffix: None,
+                };
+                ipfs::name_publish(root, key).map(move |_| path)
+            })
+    }
+}
+
+/// The CID, size and UnixFS flag of the object `oid` in `manifest`, failing with
+/// [`Error::VerifyFailed`] if it isn't listed.
+fn manifest_entry(manifest: &Manifest, oid: &str) -> Result<(Cid, u64, bool), Error> {
+    let entry = manifest.objects.get(oid).ok_or(Error::VerifyFailed)?;
+    let cid = entry
+        .cid
+        .cid
+        .to_cid()
+        .map_err(|_| Error::IpfsPathParseError("Expected a CID"))?;
+    Ok((cid, entry.size, entry.is_unixfs()))
+}
+
+/// The SHA2-256 digest of everything in `stream`
+fn sha256<S>(stream: S) -> impl Future<Item = [u8; 32], Error = Error>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    // Only the time spent hashing counts, not the time spent waiting for chunks
+    stream
+        .fold((Sha256::new(), Duration::from_secs(0)), |(mut hasher, spent), chunk| {
+            let start = Instant::now();
+            hasher.update(&chunk);
+            Ok::<_, Error>((hasher, spent + start.elapsed()))
+        })
+        .map(|(hasher, spent)| {
+            timing::observe("hash", spent);
+            hasher.finish()
+        })
+}
+
+/// Passes an upload through, failing it as soon as it runs past `expected` bytes or ends
+/// short of them. `received` counts what went through, and `mismatched` is set once it fails
+/// the upload, for telling the failure apart from others once the request is aborted.
+struct SizeChecked<S> {
+    inner: S,
+    expected: u64,
+    received: Rc<Cell<u64>>,
+    mismatched: Rc<Cell<bool>>,
+}
+
+impl<S> SizeChecked<S> {
+    fn new(inner: S, expected: u64) -> Self {
+        Self {
+            inner,
+            expected,
+            received: Rc::new(Cell::new(0)),
+            mismatched: Rc::new(Cell::new(false)),
+        }
+    }
+}
+
+/// Fails with [`Error::SizeMismatch`] if the [`SizeChecked`] stream counting `received`
+/// failed the upload, and otherwise with `result`'s own error first, so that a request that
+/// failed for another reason isn't mistaken for a short upload.
+fn check_size<T>(
+    received: &Cell<u64>,
+    mismatched: &Cell<bool>,
+    oid: &str,
+    expected: u64,
+    result: Result<T, Error>,
+) -> Result<T, Error> {
+    let mismatch = || Error::SizeMismatch {
+        oid: oid.to_string(),
+        expected,
+        received: received.get(),
+    };
+    if mismatched.get() {
+        return Err(mismatch());
+    }
+    let item = result?;
+    if received.get() != expected {
+        return Err(mismatch());
+    }
+    Ok(item)
+}
+
+impl<S, E> Stream for SizeChecked<S>
+where
+    S: Stream<Item = Bytes, Error = E>,
+    E: ResponseError,
+{
+    type Item = Bytes;
+    type Error = PayloadError;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, PayloadError> {
+        match self.inner.poll() {
+            Ok(Async::Ready(Some(chunk))) => {
+                self.received.set(self.received.get() + chunk.len() as u64);
+                if self.received.get() > self.expected {
+                    self.mismatched.set(true);
+                    Err(PayloadError::Overflow)
+                } else {
+                    Ok(Async::Ready(Some(chunk)))
+                }
+            }
+            Ok(Async::Ready(None)) if self.received.get() < self.expected => {
+                self.mismatched.set(true);
+                Err(PayloadError::Incomplete)
+            }
+            Ok(polled) => Ok(polled),
+            Err(err) => Err(PayloadError::Io(io::Error::new(
+                io::ErrorKind::Other,
+                err.to_string(),
+            ))),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod test {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::testing::{lock_ipfs_path, MockIpfs};
+
+    #[test]
+    fn upload_then_download_round_trips() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("lfs-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let block = Bytes::from_static(b"not really a dag-pb node, but the mock doesn't mind");
+        let oid = hex::encode(&multihash::encode(multihash::Hash::SHA2256, &block).unwrap()[2..]);
+        let lfs = LfsIpfs::new();
+        let cid = sys
+            .block_on(lfs.upload_object(
+                &oid,
+                block.len() as u64,
+                futures::stream::once::<_, actix_web::error::PayloadError>(Ok(block.clone())),
+            ))
+            .unwrap();
+        assert!(mock.is_pinned(&cid));
+        let downloaded = sys.block_on(lfs.download_object(&oid).concat2()).unwrap();
+        assert_eq!(block, downloaded);
+    }
+
+    #[test]
+    fn uploads_of_the_wrong_size_are_rejected() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("lfs-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let block = Bytes::from_static(b"an object that isn't the size its pointer says");
+        let oid = hex::encode(&multihash::encode(multihash::Hash::SHA2256, &block).unwrap()[2..]);
+        let cid = sys
+            .block_on(ipfs::oid_to_cid(cid::Codec::DagProtobuf, &oid))
+            .unwrap();
+        let lfs = LfsIpfs::new();
+        for declared in &[block.len() as u64 - 1, block.len() as u64 + 1] {
+            let err = sys
+                .block_on(lfs.upload_object(
+                    &oid,
+                    *declared,
+                    futures::stream::once::<_, PayloadError>(Ok(block.clone())),
+                ))
+                .unwrap_err();
+            match err {
+                Error::SizeMismatch { expected, .. } => assert_eq!(*declared, expected),
+                err => panic!("expected a size mismatch, got {}", err),
+            }
+        }
+        assert!(!mock.is_pinned(&cid));
+    }
+
+    #[test]
+    fn failed_uploads_are_not_mistaken_for_size_mismatches() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("lfs-ipfs-test");
+        let ipfs_path =
+            std::env::temp_dir().join(format!("git-lfs-ipfs-unreachable-{}", std::process::id()));
+        std::fs::create_dir_all(&ipfs_path).unwrap();
+        // Nothing listens on the discard port
+        std::fs::write(ipfs_path.join("api"), "/ip4/127.0.0.1/tcp/9").unwrap();
+        std::env::set_var("IPFS_PATH", &ipfs_path);
+        let block = Bytes::from_static(b"never reaches a daemon");
+        let oid = hex::encode(&multihash::encode(multihash::Hash::SHA2256, &block).unwrap()[2..]);
+        let err = sys
+            .block_on(LfsIpfs::new().upload_object(
+                &oid,
+                block.len() as u64,
+                futures::stream::once::<_, PayloadError>(Ok(block)),
+            ))
+            .unwrap_err();
+        std::fs::remove_dir_all(&ipfs_path).unwrap();
+        assert_eq!(ErrorKind::DaemonUnreachable, err.kind(), "got {}", err);
+    }
+
+    #[test]
+    fn imports_are_pinned_once_they_check_out() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("lfs-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let object = |content: &[u8]| Object {
+            oid: hex::encode(&multihash::encode(multihash::Hash::SHA2256, content).unwrap()[2..]),
+            size: content.len() as u64,
+        };
+        let content = Bytes::from_static(b"content that something else pinned already");
+        let pinned = sys
+            .block_on(ipfs::add(
+                futures::stream::once::<_, PayloadError>(Ok(content.clone())),
+                Some(content.len() as u64),
+            ))
+            .unwrap()
+            .hash;
+
+        // The same content, but under the OID of another object of the same size
+        let mut forged = object(&content);
+        forged.oid = object(&b"other content".repeat(4)[..content.len()]).oid;
+        match sys.block_on(LfsIpfs::new().import_file(
+            &forged,
+            futures::stream::once::<_, PayloadError>(Ok(content.clone())),
+        )) {
+            Err(Error::HashError) => {}
+            result => panic!("expected a hash mismatch, got {:?}", result),
+        }
+        assert!(mock.is_pinned(&pinned));
+
+        let imported = Bytes::from_static(b"content that checks out");
+        let cid = sys
+            .block_on(LfsIpfs::new().import_file(
+                &object(&imported),
+                futures::stream::once::<_, PayloadError>(Ok(imported)),
+            ))
+            .unwrap();
+        assert!(mock.is_pinned(&cid));
+    }
+}
@@ -1,18 +1,31 @@
+#[cfg(feature = "client")]
+extern crate base64;
 extern crate cid;
+#[cfg(feature = "client")]
 extern crate dirs;
-extern crate env_logger;
 extern crate failure;
+#[cfg(feature = "client")]
 extern crate futures;
 extern crate hex;
 extern crate lazy_static;
+#[cfg(feature = "client")]
 extern crate mime;
+#[cfg(feature = "client")]
 extern crate multiaddr;
 extern crate multihash;
-extern crate publicsuffix;
+#[cfg(feature = "client")]
+extern crate openssl;
+#[cfg(feature = "client")]
 extern crate rand;
 extern crate serde;
 extern crate serde_derive;
 extern crate serde_json;
+#[cfg(feature = "client")]
+extern crate tokio_io;
+#[cfg(feature = "client")]
+extern crate tokio_tcp;
+#[cfg(feature = "client")]
+extern crate tokio_timer;
 extern crate url;
 extern crate url_serde;
 #[macro_use]
@@ -22,7 +35,74 @@ extern crate log;
 #[macro_use]
 extern crate pretty_assertions;
 
+#[cfg(feature = "client")]
+pub mod blocking;
+#[cfg(feature = "client")]
+pub mod config;
+#[cfg(feature = "client")]
+pub mod dag;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "client")]
+pub mod files;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
+#[cfg(feature = "client")]
 pub mod ipfs;
+#[cfg(feature = "client")]
+mod lfs_ipfs;
+#[cfg(feature = "client")]
+pub mod pin;
 pub mod pointer;
+#[cfg(feature = "client")]
+pub mod proxy;
+#[cfg(feature = "cli-support")]
+pub mod receipt;
+#[cfg(feature = "client")]
+pub mod routing;
+#[cfg(feature = "client")]
+pub mod rpc;
+#[cfg(feature = "cli-support")]
+pub mod share;
+#[cfg(feature = "client")]
+pub mod signature;
 pub mod spec;
+#[cfg(feature = "client")]
+pub mod storage;
+#[cfg(feature = "cli-support")]
+pub mod swarm;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "client")]
+pub mod timing;
+
+#[cfg(feature = "client")]
+pub use crate::lfs_ipfs::LfsIpfs;
+
+/// The cargo features this library was built with
+pub fn features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature = "client") {
+        features.push("client");
+    }
+    if cfg!(feature = "server") {
+        features.push("server");
+    }
+    if cfg!(feature = "cli-support") {
+        features.push("cli-support");
+    }
+    if cfg!(feature = "cluster") {
+        features.push("cluster");
+    }
+    if cfg!(feature = "ffi") {
+        features.push("ffi");
+    }
+    if cfg!(feature = "testing") {
+        features.push("testing");
+    }
+    if cfg!(feature = "fuzzing") {
+        features.push("fuzzing");
+    }
+    features
+}
@@ -0,0 +1,247 @@
+//! Pins on the local daemon, which keep objects from being garbage collected.
+//! https://docs.ipfs.io/reference/api/http/#api-v0-pin-ls
+use actix_web::HttpMessage;
+use cid::Cid;
+use futures::{future, prelude::*, stream};
+use url::Url;
+
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::ipfs::{ipfs_api_url, json_lines, send};
+use crate::rpc;
+use crate::spec::ipfs::{PinInfo, PinLsEntry, PinLsResponse, PinResponse};
+
+/// The first release that can stream `pin/ls`, instead of building the whole pinset in memory
+const STREAM_SINCE: rpc::DaemonVersion = (0, 5, 0);
+
+/// Which pins to list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinType {
+    /// Pinned by itself only
+    Direct,
+    /// Pinned with everything it links to, like added objects
+    Recursive,
+    /// Pinned because something linking to it is pinned recursively
+    Indirect,
+    All,
+}
+
+impl PinType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PinType::Direct => "direct",
+            PinType::Recursive => "recursive",
+            PinType::Indirect => "indirect",
+            PinType::All => "all",
+        }
+    }
+}
+
+impl FromStr for PinType {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(PinType::Direct),
+            "recursive" => Ok(PinType::Recursive),
+            "indirect" => Ok(PinType::Indirect),
+            "all" => Ok(PinType::All),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Every pin of `pin_type`, read in one response. See [`ls_stream`] for large pinsets.
+pub fn ls(pin_type: PinType) -> impl Future<Item = PinLsResponse, Error = Error> {
+    ipfs_api_url().and_then(move |url| ls_at(&url, pin_type))
+}
+
+/// Like [`ls`], for the daemon behind the API at `api`, like a pin target
+pub fn ls_at(api: &Url, pin_type: PinType) -> impl Future<Item = PinLsResponse, Error = Error> {
+    let mut url = api.join("api/v0/pin/ls").unwrap();
+    url.query_pairs_mut().append_pair("type", pin_type.as_str());
+    debug!("Sending pin ls request to {}", url);
+    send(url).and_then(|res| {
+        res.json()
+            .limit(usize::max_value())
+            .map_err(Error::IpfsApiJsonPayloadError)
+    })
+}
+
+/// Every pin of `pin_type`, as the daemon finds them. Daemons before 0.5 can't stream, so
+/// their pinset is read in one response and then streamed from memory.
+pub fn ls_stream(pin_type: PinType) -> impl Stream<Item = PinLsEntry, Error = Error> {
+    ipfs_api_url()
+        .and_then(|url| rpc::daemon_version(&url).map(move |version| (url, version)))
+        .map(move |(url, version)| {
+            if version < STREAM_SINCE {
+                return future::Either::A(
+                    ls(pin_type)
+                        .map(|pins| {
+                            stream::iter_ok(pins.keys.into_iter().map(|(cid, info)| {
+                                PinLsEntry {
+                                    cid,
+                                    pin_type: info.pin_type,
+                                }
+                            }))
+                        })
+                        .flatten_stream(),
+                );
+            }
+            let mut url = url.join("api/v0/pin/ls").unwrap();
+            url.query_pairs_mut()
+                .append_pair("type", pin_type.as_str())
+                .append_pair("stream", "true");
+            debug!("Sending pin ls request to {}", url);
+            future::Either::B(
+                send(url)
+                    .map(|res| json_lines(res.payload()))
+                    .flatten_stream(),
+            )
+        })
+        .flatten_stream()
+}
+
+/// Whether `cid` is pinned, and how. Only `cid` is looked up, rather than the whole pinset.
+pub fn ls_cid(cid: &Cid) -> impl Future<Item = Option<PinInfo>, Error = Error> {
+    let cid = cid.to_string();
+    ipfs_api_url().and_then(move |url| {
+        let mut url = url.join("api/v0/pin/ls").unwrap();
+        url.query_pairs_mut()
+            .append_pair("arg", &cid)
+            .append_pair("type", PinType::All.as_str());
+        debug!("Sending pin ls request to {}", url);
+        send(url)
+            .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+            .map({
+                let cid = cid.clone();
+                move |mut pins: PinLsResponse| pins.keys.remove(&cid)
+            })
+            // The daemon fails the request for a CID that isn't pinned
+            .or_else(move |err| match err {
+                Error::IpfsApiResponseError(ref response)
+                    if response.message().contains("not pinned") =>
+                {
+                    debug!("{} is not pinned", cid);
+                    Ok(None)
+                }
+                err => Err(err),
+            })
+    })
+}
+
+/// Pins `cid` and everything it links to.
+pub fn add(cid: Cid) -> impl Future<Item = PinResponse, Error = Error> {
+    crate::timing::timed("pin", ipfs_api_url().and_then(move |url| add_at(&url, &cid)))
+}
+
+/// Asks the daemon behind the API at `api` to pin `cid` and everything it links to, fetching
+/// whatever it doesn't have yet.
+pub fn add_at(api: &Url, cid: &Cid) -> impl Future<Item = PinResponse, Error = Error> {
+    let mut url = api.join("api/v0/pin/add").unwrap();
+    url.query_pairs_mut().append_pair("arg", &cid.to_string());
+    debug!("Sending pin add request to {}", url);
+    send(url).and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+}
+
+/// Whether a pin target confirmed pinning an object, and why not if it didn't
+#[cfg(feature = "cluster")]
+#[derive(Debug)]
+pub struct TargetStatus {
+    pub target: Url,
+    pub error: Option<String>,
+}
+
+/// Asks every configured pin target to pin `cid`, failing with [`Error::PinQuorumNotMet`]
+/// unless at least the configured quorum of them confirm it. Returns each target's status.
+#[cfg(feature = "cluster")]
+pub fn add_to_targets(cid: Cid) -> impl Future<Item = Vec<TargetStatus>, Error = Error> {
+    let config = crate::config::get();
+    let targets = config.pin_target_urls.clone();
+    let required = config.pin_quorum.unwrap_or_else(|| targets.len());
+    future::join_all(targets.into_iter().map({
+        let cid = cid.clone();
+        move |target| {
+            add_at(&target, &cid).then(move |result| {
+                let error = result.err().map(|err| err.to_string());
+                match &error {
+                    None => info!("Pin target {} pinned the object", target),
+                    Some(err) => warn!("Pin target {} could not pin the object: {}", target, err),
+                }
+                Ok::<_, Error>(TargetStatus { target, error })
+            })
+        }
+    }))
+    .and_then(move |statuses| {
+        let pinned = statuses
+            .iter()
+            .filter(|status| status.error.is_none())
+            .count();
+        info!(
+            "{} of {} pin targets pinned {}, {} required",
+            pinned,
+            statuses.len(),
+            cid,
+            required
+        );
+        if pinned >= required {
+            Ok(statuses)
+        } else {
+            Err(Error::PinQuorumNotMet {
+                cid: cid.to_string(),
+                pinned,
+                required,
+            })
+        }
+    })
+}
+
+/// Moves the recursive pin on `from` to `to`, fetching only the blocks of `to` that `from`
+/// doesn't share, and keeps `from` pinned unless `unpin` is set. `from` must be pinned
+/// recursively.
+/// https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-pin-update
+pub fn update(from: Cid, to: Cid, unpin: bool) -> impl Future<Item = PinResponse, Error = Error> {
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/pin/update").unwrap();
+            url.query_pairs_mut()
+                .append_pair("arg", &from.to_string())
+                .append_pair("arg", &to.to_string())
+                .append_pair("unpin", &unpin.to_string());
+            debug!("Sending pin update request to {}", url);
+            url
+        })
+        .and_then(send)
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+}
+
+/// Removes the recursive pin on `cid`, leaving it to the garbage collector.
+pub fn rm(cid: Cid) -> impl Future<Item = PinResponse, Error = Error> {
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/pin/rm").unwrap();
+            url.query_pairs_mut().append_pair("arg", &cid.to_string());
+            debug!("Sending pin rm request to {}", url);
+            url
+        })
+        .and_then(send)
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pin_types_round_trip() {
+        for pin_type in &[
+            PinType::Direct,
+            PinType::Recursive,
+            PinType::Indirect,
+            PinType::All,
+        ] {
+            assert_eq!(Ok(*pin_type), pin_type.as_str().parse());
+        }
+        assert_eq!(Err(()), "everything".parse::<PinType>());
+    }
+}
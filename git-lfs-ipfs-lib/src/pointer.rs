@@ -1,150 +1,625 @@
+use cid::Cid;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 use std::fmt;
+use std::str::FromStr;
 
-const VERSION: &str = "https://git-lfs.github.com/spec/v1";
-const KEY_REGEX_STR: &str = "[a-z0-9.-]+";
+use crate::error::Error;
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/spec.md#the-pointer
+pub const VERSION: &str = "https://git-lfs.github.com/spec/v1";
+/// Versions written by pre-release git-lfs clients, only accepted in lenient mode
+pub const LEGACY_VERSIONS: &[&str] = &["https://hawser.github.com/spec/v1", "http://git-media.io/v/2"];
+const MAX_POINTER_SIZE: usize = 1024;
+const MAX_EXTENSION_PRIORITY: u8 = 9;
 
 lazy_static! {
-    static ref KEY_REGEX: Regex = Regex::new(KEY_REGEX_STR).unwrap();
+    static ref KEY_REGEX: Regex = Regex::new("^[a-z0-9.-]+$").unwrap();
+    static ref OID_REGEX: Regex = Regex::new("^[0-9a-f]+$").unwrap();
+    static ref SIZE_REGEX: Regex = Regex::new("^[0-9]+$").unwrap();
+    static ref EXTENSION_KEY_REGEX: Regex = Regex::new("^ext-([0-9])-([a-z0-9]+)$").unwrap();
+    static ref EXTENSION_NAME_REGEX: Regex = Regex::new("^[a-z0-9]+$").unwrap();
 }
 
-#[derive(Debug)]
-pub struct Pointer {
-    lines: Vec<Line>,
+/// How forgiving pointer parsing should be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Exactly what the spec allows, as written by git-lfs. Used by clean and smudge.
+    Strict,
+    /// Tolerates CRLF line endings, stray whitespace, unordered or unknown keys, a missing
+    /// trailing newline, and legacy versions. Used by `migrate` to recover damaged pointers.
+    Lenient,
 }
 
-impl Default for Pointer {
+/// The hash an OID was computed with, named by its prefix in pointers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256:",
+            HashAlgorithm::Sha512 => "sha512:",
+        }
+    }
+
+    /// Length of a hex-encoded digest
+    pub fn hex_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 64,
+            HashAlgorithm::Sha512 => 128,
+        }
+    }
+
+    pub fn multihash(self) -> multihash::Hash {
+        match self {
+            HashAlgorithm::Sha256 => multihash::Hash::SHA2256,
+            HashAlgorithm::Sha512 => multihash::Hash::SHA2512,
+        }
+    }
+
+    /// The algorithm of a multihash, if it is one LFS OIDs can be computed with
+    pub fn from_multihash(hash: multihash::Hash) -> Option<Self> {
+        [HashAlgorithm::Sha256, HashAlgorithm::Sha512]
+            .iter()
+            .cloned()
+            .find(|algorithm| algorithm.multihash() == hash)
+    }
+
+    /// Guesses the algorithm of a bare hex OID, as passed by the transfer protocol, from its length.
+    pub fn from_hex(oid: &str) -> Option<Self> {
+        [HashAlgorithm::Sha256, HashAlgorithm::Sha512]
+            .iter()
+            .cloned()
+            .find(|algorithm| algorithm.hex_len() == oid.len())
+    }
+
+    /// Splits a prefixed OID like `sha256:<hex>` into its algorithm and hex digest.
+    fn parse_prefixed(value: &str) -> Option<(Self, &str)> {
+        [HashAlgorithm::Sha256, HashAlgorithm::Sha512]
+            .iter()
+            .cloned()
+            .find(|algorithm| value.starts_with(algorithm.prefix()))
+            .map(|algorithm| (algorithm, &value[algorithm.prefix().len()..]))
+            .filter(|(algorithm, hex)| hex.len() == algorithm.hex_len() && OID_REGEX.is_match(hex))
+    }
+}
+
+impl Default for HashAlgorithm {
     fn default() -> Self {
-        Self {
-            lines: vec![Line {
-                key: "version".to_string(),
-                value: VERSION.to_string(),
-            }],
+        HashAlgorithm::Sha256
+    }
+}
+
+/// Whether `oid` is a bare hex OID of one of the algorithms LFS computes OIDs with
+pub fn is_oid(oid: &str) -> bool {
+    HashAlgorithm::from_hex(oid).is_some() && OID_REGEX.is_match(oid)
+}
+
+/// Maps a bare hex OID to its CID, picking the hash algorithm from the OID's length.
+/// SHA2-256 dag-pb OIDs map to CIDv0s, everything else needs a CIDv1.
+pub fn oid_to_cid(codec: cid::Codec, oid: &str) -> Result<Cid, Error> {
+    HashAlgorithm::from_hex(oid)
+        .ok_or(Error::HashError)
+        .and_then(|algorithm| digest_to_cid(codec, algorithm, oid))
+}
+
+/// Maps a hex digest computed with `algorithm` to its CID, checking that it has the length
+/// the algorithm produces.
+pub fn digest_to_cid(
+    codec: cid::Codec,
+    algorithm: HashAlgorithm,
+    hex_str: &str,
+) -> Result<Cid, Error> {
+    hex::decode(hex_str)
+        .ok()
+        .filter(|digest| digest.len() * 2 == algorithm.hex_len())
+        .map(|digest| {
+            let hash = algorithm.multihash();
+            let mut mh = vec![hash.code(), hash.size()];
+            mh.extend_from_slice(&digest);
+            let version = match (algorithm, codec) {
+                (HashAlgorithm::Sha256, cid::Codec::DagProtobuf) => cid::Version::V0,
+                _ => cid::Version::V1,
+            };
+            Cid::new(codec, version, &mh)
+        })
+        .ok_or(Error::HashError)
+}
+
+/// Maps a CID back to the OID of the object it stores, the inverse of [`oid_to_cid`]. Fails
+/// with [`Error::HashError`] unless its multihash is a well-formed SHA2-256 or SHA2-512 digest.
+pub fn cid_to_oid(cid: &Cid) -> Result<(HashAlgorithm, String), Error> {
+    let mh = multihash::decode(&cid.hash).map_err(|_| Error::HashError)?;
+    HashAlgorithm::from_multihash(mh.alg)
+        .filter(|algorithm| mh.digest.len() * 2 == algorithm.hex_len())
+        .map(|algorithm| (algorithm, hex::encode(mh.digest)))
+        .ok_or(Error::HashError)
+}
+
+/// Multibase encodings a CID can be formatted in.
+/// https://github.com/multiformats/multibase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multibase {
+    /// `z`, how CIDv1s are usually shown
+    Base58Btc,
+    /// `b`, lowercase and so safe in DNS labels and case-insensitive file systems
+    Base32,
+    /// `f`, lowercase hex
+    Base16,
+}
+
+impl FromStr for Multibase {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base58btc" => Ok(Multibase::Base58Btc),
+            "base32" => Ok(Multibase::Base32),
+            "base16" => Ok(Multibase::Base16),
+            _ => Err(Error::IpfsPathParseError("Unknown multibase")),
         }
     }
 }
 
-impl Serialize for Pointer {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(
-            self.lines
-                .iter()
-                .fold(String::new(), |mut acc, line| {
-                    acc.push_str(format!("{} {}\n", line.key, line.value).as_str());
-                    acc
-                })
-                .as_str(),
-        )
+/// Formats `cid` as a CIDv1 in `base`, converting CIDv0s, which can only be base58btc.
+pub fn format_cid(cid: &Cid, base: Multibase) -> String {
+    let v1 = Cid::new(cid.codec, cid::Version::V1, &cid.hash);
+    match base {
+        Multibase::Base58Btc => v1.to_string(),
+        Multibase::Base32 => format!("b{}", base32_lower(&v1.to_bytes())),
+        Multibase::Base16 => format!("f{}", hex::encode(v1.to_bytes())),
     }
 }
 
-impl<'de> Deserialize<'de> for Pointer {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        struct PointerVisitor;
-        impl<'de> de::Visitor<'de> for PointerVisitor {
-            type Value = Pointer;
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                write!(formatter, "a string in the format <key> <value>")
+/// RFC 4648 base32 in lowercase, without padding
+fn base32_lower(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut encoded = String::new();
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u16::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/spec.md#the-pointer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    pub algorithm: HashAlgorithm,
+    /// Hex-encoded hash of the object, without the algorithm prefix
+    pub oid: String,
+    pub size: u64,
+    /// Sorted by priority
+    pub extensions: Vec<Extension>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/extensions.md#clean
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub priority: u8,
+    pub name: String,
+    pub algorithm: HashAlgorithm,
+    /// Hex-encoded hash of the input to this extension's clean filter
+    pub oid: String,
+}
+
+impl Pointer {
+    pub fn new(oid: String, size: u64) -> Self {
+        Self::with_algorithm(HashAlgorithm::default(), oid, size)
+    }
+
+    pub fn with_algorithm(algorithm: HashAlgorithm, oid: String, size: u64) -> Self {
+        Self {
+            algorithm,
+            oid,
+            size,
+            extensions: vec![],
+        }
+    }
+
+    /// Records that the object passed through another extension's clean filter, whose input
+    /// hashed to `oid` with the pointer's algorithm. Extensions must be pushed in the order their clean filters ran, so that
+    /// smudge can reverse them deterministically on any machine.
+    pub fn push_extension(&mut self, name: &str, oid: String) -> Result<(), Error> {
+        if !EXTENSION_NAME_REGEX.is_match(name) {
+            return Err(Error::PointerParseError("Invalid extension name"));
+        }
+        if self.extension(name).is_some() {
+            return Err(Error::PointerParseError("Extension names must be unique"));
+        }
+        let priority = self.extensions.last().map(|ext| ext.priority + 1).unwrap_or(0);
+        if priority > MAX_EXTENSION_PRIORITY {
+            return Err(Error::PointerParseError("Too many extensions"));
+        }
+        self.extensions.push(Extension {
+            priority,
+            name: name.to_string(),
+            algorithm: self.algorithm,
+            oid,
+        });
+        Ok(())
+    }
+
+    pub fn extension(&self, name: &str) -> Option<&Extension> {
+        self.extensions.iter().find(|ext| ext.name == name)
+    }
+
+    pub fn parse(s: &str, mode: Mode) -> Result<Self, Error> {
+        match mode {
+            Mode::Strict => Self::parse_strict(s),
+            Mode::Lenient => Self::parse_lenient(s),
+        }
+    }
+
+    fn parse_strict(s: &str) -> Result<Self, Error> {
+        if s.len() > MAX_POINTER_SIZE {
+            return Err(Error::PointerParseError("Pointer is too large"));
+        }
+        if !s.ends_with('\n') {
+            return Err(Error::PointerParseError("Pointer must end with a newline"));
+        }
+        let mut lines = s.split_terminator('\n').map(|line| {
+            let mut it = line.splitn(2, ' ');
+            match (it.next(), it.next()) {
+                (Some(key), Some(value))
+                    if KEY_REGEX.is_match(key) && !value.is_empty() && !value.contains('\r') =>
+                {
+                    Ok((key, value))
+                }
+                _ => Err(Error::PointerParseError("Line is not in the form <key> <value>")),
+            }
+        });
+        match lines.next() {
+            Some(Ok(("version", VERSION))) => {}
+            Some(Err(err)) => return Err(err),
+            _ => return Err(Error::PointerParseError("First line must be the spec version")),
+        }
+
+        let mut builder = PointerBuilder::default();
+        let mut previous_key: Option<&str> = None;
+        for line in lines {
+            let (key, value) = line?;
+            if previous_key.map(|previous| previous >= key).unwrap_or(false) {
+                return Err(Error::PointerParseError(
+                    "Keys must be unique and sorted alphabetically",
+                ));
+            }
+            previous_key = Some(key);
+            // git-lfs writes sizes without leading zeros, and a pointer only has one spelling
+            if key == "size" && value.len() > 1 && value.starts_with('0') {
+                return Err(Error::PointerParseError("Size has leading zeros"));
             }
+            if !builder.accept(key, value)? {
+                return Err(Error::PointerParseError("Unknown key"));
+            }
+        }
+        builder.build()
+    }
 
-            fn visit_str<E>(self, lines_str: &str) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                let lines: Vec<Line> =
-                    lines_str
-                        .split_terminator('\n')
-                        .try_fold(vec![], |mut acc, line_str| {
-                            let mut it = line_str.split_whitespace();
-                            let key = it.next().ok_or_else(|| de::Error::missing_field("key"))?;
-                            if !KEY_REGEX.is_match(key) {
-                                return Err(de::Error::invalid_value(
-                                    de::Unexpected::Str(key),
-                                    &format!("expected key to match regex {}", KEY_REGEX.as_str())
-                                        .as_str(),
-                                ));
-                            }
-                            let value =
-                                it.next().ok_or_else(|| de::Error::missing_field("value"))?;
-                            let count = it.count();
-                            if count != 2 {
-                                return Err(de::Error::invalid_length(
-                                    count,
-                                    &"expected 2 elements",
-                                ));
-                            }
-                            acc.push(Line {
-                                key: key.to_string(),
-                                value: value.to_string(),
-                            });
-                            Ok(acc)
-                        })?;
-                Ok(Pointer { lines })
+    fn parse_lenient(s: &str) -> Result<Self, Error> {
+        // Anything larger is file content rather than a damaged pointer
+        if s.len() > MAX_POINTER_SIZE {
+            return Err(Error::PointerParseError("Pointer is too large"));
+        }
+        let mut builder = PointerBuilder::default();
+        let mut version_seen = false;
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut it = line.split_whitespace();
+            let (key, value) = match (it.next(), it.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => {
+                    warn!("Skipping malformed pointer line {:?}", line);
+                    continue;
+                }
+            };
+            if key == "version" {
+                if value != VERSION && !LEGACY_VERSIONS.contains(&value) {
+                    return Err(Error::PointerParseError("Unrecognized spec version"));
+                }
+                version_seen = true;
+            } else if !builder.accept(key, &value.to_lowercase())? {
+                warn!("Ignoring unknown pointer key {:?}", key);
             }
         }
-        deserializer.deserialize_string(PointerVisitor)
+        if !version_seen {
+            return Err(Error::PointerParseError("Missing spec version"));
+        }
+        // Rewritten with the current version and one newline per line, a recovered pointer
+        // can grow past what strict parsing, and so smudge, accepts
+        builder.build().and_then(|pointer| {
+            if pointer.to_string().len() > MAX_POINTER_SIZE {
+                Err(Error::PointerParseError("Recovered pointer is too large"))
+            } else {
+                Ok(pointer)
+            }
+        })
     }
 }
 
-#[derive(Debug)]
-pub struct Line {
-    key: String,
-    value: String,
+#[derive(Default)]
+struct PointerBuilder {
+    oid: Option<(HashAlgorithm, String)>,
+    size: Option<u64>,
+    extensions: Vec<Extension>,
 }
 
-impl Serialize for Line {
+impl PointerBuilder {
+    /// Returns false if the key is not one that git-lfs knows about.
+    fn accept(&mut self, key: &str, value: &str) -> Result<bool, Error> {
+        match key {
+            "oid" => self.oid = Some(parse_oid(value)?),
+            "size" => {
+                if !SIZE_REGEX.is_match(value) {
+                    return Err(Error::PointerParseError("Size is not a number"));
+                }
+                self.size = Some(
+                    value
+                        .parse()
+                        .map_err(|_| Error::PointerParseError("Size is out of range"))?,
+                );
+            }
+            _ => match EXTENSION_KEY_REGEX.captures(key) {
+                Some(captures) => {
+                    let priority = captures[1].parse().unwrap();
+                    if self.extensions.iter().any(|ext| ext.priority == priority) {
+                        return Err(Error::PointerParseError(
+                            "Extension priorities must be unique",
+                        ));
+                    }
+                    if self.extensions.iter().any(|ext| ext.name == captures[2]) {
+                        return Err(Error::PointerParseError("Extension names must be unique"));
+                    }
+                    let (algorithm, oid) = parse_oid(value)?;
+                    self.extensions.push(Extension {
+                        priority,
+                        name: captures[2].to_string(),
+                        algorithm,
+                        oid,
+                    });
+                }
+                None => return Ok(false),
+            },
+        }
+        Ok(true)
+    }
+
+    fn build(mut self) -> Result<Pointer, Error> {
+        self.extensions.sort_by_key(|ext| ext.priority);
+        match (self.oid, self.size) {
+            (Some((algorithm, oid)), Some(size)) => Ok(Pointer {
+                algorithm,
+                oid,
+                size,
+                extensions: self.extensions,
+            }),
+            (None, _) => Err(Error::PointerParseError("Missing oid")),
+            (_, None) => Err(Error::PointerParseError("Missing size")),
+        }
+    }
+}
+
+fn parse_oid(value: &str) -> Result<(HashAlgorithm, String), Error> {
+    HashAlgorithm::parse_prefixed(value)
+        .map(|(algorithm, hex)| (algorithm, hex.to_string()))
+        .ok_or(Error::PointerParseError("Expected a sha256 or sha512 oid"))
+}
+
+impl fmt::Display for Pointer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version {}", VERSION)?;
+        for ext in &self.extensions {
+            writeln!(
+                f,
+                "ext-{}-{} {}{}",
+                ext.priority,
+                ext.name,
+                ext.algorithm.prefix(),
+                ext.oid
+            )?;
+        }
+        writeln!(f, "oid {}{}", self.algorithm.prefix(), self.oid)?;
+        writeln!(f, "size {}", self.size)
+    }
+}
+
+impl FromStr for Pointer {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, Mode::Strict)
+    }
+}
+
+impl Serialize for Pointer {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_str(format!("{} {}", self.key, self.value).as_str())
+        serializer.collect_str(self)
     }
 }
 
-impl<'de> Deserialize<'de> for Line {
+impl<'de> Deserialize<'de> for Pointer {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        struct LineVisitor;
-        impl<'de> de::Visitor<'de> for LineVisitor {
-            type Value = Line;
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                write!(formatter, "a string in the format <key> <value>")
-            }
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
 
-            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                let mut it = v.split_whitespace();
-                let key = it.next().ok_or_else(|| de::Error::missing_field("key"))?;
-                if !KEY_REGEX.is_match(key) {
-                    return Err(de::Error::invalid_value(
-                        de::Unexpected::Str(key),
-                        &format!("expected key to match regex {}", KEY_REGEX.as_str()).as_str(),
-                    ));
-                }
-                let value = it.next().ok_or_else(|| de::Error::missing_field("value"))?;
-                let count = it.count();
-                if count != 2 {
-                    return Err(de::Error::invalid_length(count, &"expected 2 elements"));
-                }
-                Ok(Line {
-                    key: key.to_string(),
-                    value: value.to_string(),
-                })
-            }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    fn canonical() -> String {
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+            OID
+        )
+    }
+
+    #[test]
+    fn strict_round_trips() {
+        let pointer = Pointer::parse(&canonical(), Mode::Strict).unwrap();
+        assert_eq!(Pointer::new(OID.to_string(), 12345), pointer);
+        assert_eq!(canonical(), pointer.to_string());
+    }
+
+    #[test]
+    fn strict_parses_extensions_in_order() {
+        let s = format!(
+            "version https://git-lfs.github.com/spec/v1\next-0-foo sha256:{oid}\next-1-bar sha256:{oid}\noid sha256:{oid}\nsize 1\n",
+            oid = OID
+        );
+        let pointer = Pointer::parse(&s, Mode::Strict).unwrap();
+        assert_eq!(
+            vec!["foo", "bar"],
+            pointer
+                .extensions
+                .iter()
+                .map(|ext| ext.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(s, pointer.to_string());
+    }
+
+    #[test]
+    fn pushed_extensions_are_recorded_in_order() {
+        let mut pointer = Pointer::new(OID.to_string(), 1);
+        pointer.push_extension("gzip", OID.to_string()).unwrap();
+        pointer.push_extension("ipfs", OID.to_string()).unwrap();
+        assert!(pointer.push_extension("gzip", OID.to_string()).is_err());
+        assert!(pointer.push_extension("Not-Valid", OID.to_string()).is_err());
+        assert_eq!(1, pointer.extension("ipfs").unwrap().priority);
+        assert_eq!(
+            pointer,
+            Pointer::parse(&pointer.to_string(), Mode::Strict).unwrap()
+        );
+    }
+
+    #[test]
+    fn strict_rejects_malformed_pointers() {
+        let crlf = canonical().replace("\n", "\r\n");
+        let unterminated = canonical().trim_end().to_string();
+        let unsorted = format!(
+            "version https://git-lfs.github.com/spec/v1\nsize 12345\noid sha256:{}\n",
+            OID
+        );
+        let unknown = format!("{}zzz value\n", canonical());
+        for s in &[crlf, unterminated, unsorted, unknown] {
+            assert!(Pointer::parse(s, Mode::Strict).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn sha512_oids_round_trip() {
+        let oid = format!("{}{}", OID, OID);
+        let s = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha512:{}\nsize 1\n",
+            oid
+        );
+        let pointer = Pointer::parse(&s, Mode::Strict).unwrap();
+        assert_eq!(
+            Pointer::with_algorithm(HashAlgorithm::Sha512, oid.clone(), 1),
+            pointer
+        );
+        assert_eq!(s, pointer.to_string());
+        assert_eq!(Some(HashAlgorithm::Sha512), HashAlgorithm::from_hex(&oid));
+        let truncated = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha512:{}\nsize 1\n",
+            OID
+        );
+        assert!(Pointer::parse(&truncated, Mode::Strict).is_err());
+    }
+
+    #[test]
+    fn cids_map_back_to_oids() {
+        let cid = oid_to_cid(cid::Codec::DagProtobuf, OID).unwrap();
+        assert_eq!(
+            "QmTZ67btJsoHaF2HzHzkKoSd8NLZxMzafgP19RgetKipX4",
+            cid.to_string()
+        );
+        assert_eq!(
+            (HashAlgorithm::Sha256, OID.to_string()),
+            cid_to_oid(&cid).unwrap()
+        );
+        let sha512 = format!("{}{}", OID, OID);
+        let cid = oid_to_cid(cid::Codec::Raw, &sha512).unwrap();
+        assert_eq!((HashAlgorithm::Sha512, sha512), cid_to_oid(&cid).unwrap());
+        assert!(oid_to_cid(cid::Codec::Raw, &OID[1..]).is_err());
+        assert!(digest_to_cid(cid::Codec::Raw, HashAlgorithm::Sha512, OID).is_err());
+    }
+
+    #[test]
+    fn cids_format_in_any_multibase() {
+        let cid = oid_to_cid(cid::Codec::DagProtobuf, OID).unwrap();
+        assert_eq!(
+            "zdj7WaeLV2xAMGtSxLDj2xNCBHBkaY1fuQiXznXpE5KhmHigz",
+            format_cid(&cid, Multibase::Base58Btc)
+        );
+        assert_eq!(
+            "bafybeicnpiqumfflfe24sq7z4d7wturovw5y6mvrewg2vjpczisnc7rdsm",
+            format_cid(&cid, "base32".parse().unwrap())
+        );
+        assert_eq!(
+            format!("f01701220{}", OID),
+            format_cid(&cid, Multibase::Base16)
+        );
+    }
+
+    #[test]
+    fn lenient_recovers_malformed_pointers() {
+        let s = format!(
+            "  size   12345\r\nversion https://hawser.github.com/spec/v1\r\noid sha256:{}\r\nx-custom yes",
+            OID.to_uppercase()
+        );
+        assert_eq!(
+            Pointer::new(OID.to_string(), 12345),
+            Pointer::parse(&s, Mode::Lenient).unwrap()
+        );
+        assert!(Pointer::parse("size 1\n", Mode::Lenient).is_err());
+    }
+
+    #[test]
+    fn pointers_have_one_spelling() {
+        let leading_zeros = canonical().replace("size 12345", "size 012345");
+        assert!(Pointer::parse(&leading_zeros, Mode::Strict).is_err());
+        let same_name = format!(
+            "version https://git-lfs.github.com/spec/v1\next-0-foo sha256:{oid}\next-1-foo sha256:{oid}\noid sha256:{oid}\nsize 1\n",
+            oid = OID
+        );
+        assert!(Pointer::parse(&same_name, Mode::Strict).is_err());
+        assert!(Pointer::parse(&same_name, Mode::Lenient).is_err());
+    }
+
+    #[test]
+    fn lenient_only_recovers_what_strict_parsing_accepts() {
+        let oid = format!("{}{}", OID, OID);
+        let mut s = "version http://git-media.io/v/2".to_string();
+        for priority in 0..5 {
+            s.push_str(&format!("\next-{}-{} sha512:{}", priority, "x".repeat(25), oid));
         }
-        deserializer.deserialize_string(LineVisitor)
+        s.push_str(&format!("\noid sha512:{}\nsize 1", oid));
+        assert!(s.len() <= MAX_POINTER_SIZE);
+        assert!(Pointer::parse(&s, Mode::Lenient).is_err());
+        assert!(Pointer::parse(&"x".repeat(MAX_POINTER_SIZE + 1), Mode::Lenient).is_err());
     }
 }
@@ -0,0 +1,192 @@
+//! Receipts that an object was published: its OID, CID and size, the root it was linked into
+//! and when, signed with an IPNS key. An auditor holding a receipt can check it later against
+//! the published root, which is immutable, and the ID of the key, without trusting whoever
+//! kept the receipt.
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::ipfs;
+use crate::signature;
+use crate::spec::Object;
+
+/// Prefixed to what is signed, so that a receipt signature can't pass for any other signature
+/// made with the same key, like that of a manifest.
+const RECEIPT_CONTEXT: &[u8] = b"git-lfs-ipfs receipt\n";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Receipt {
+    pub oid: String,
+    pub cid: String,
+    pub size: u64,
+    /// The root whose manifest lists the object
+    pub root: String,
+    pub issued_at: DateTime<Utc>,
+    /// ID of the key that signed the receipt
+    pub key: String,
+    pub signature: String,
+}
+
+/// What is signed: every field but the key and signature, one per line
+fn signed_bytes(
+    oid: &str,
+    cid: &str,
+    size: u64,
+    root: &str,
+    issued_at: &DateTime<Utc>,
+) -> Vec<u8> {
+    let mut bytes = RECEIPT_CONTEXT.to_vec();
+    bytes.extend_from_slice(
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            oid,
+            cid,
+            size,
+            root,
+            issued_at.to_rfc3339()
+        )
+        .as_bytes(),
+    );
+    bytes
+}
+
+impl Receipt {
+    fn signed_bytes(&self) -> Vec<u8> {
+        signed_bytes(&self.oid, &self.cid, self.size, &self.root, &self.issued_at)
+    }
+}
+
+/// Issues a receipt that `object`, stored as `cid`, was linked into `root`, signed with the
+/// local key named `key_name`.
+pub fn issue(
+    object: &Object,
+    cid: &str,
+    root: &Cid,
+    key_name: &str,
+) -> impl Future<Item = Receipt, Error = Error> {
+    let (oid, size) = (object.oid.clone(), object.size);
+    let (cid, root) = (cid.to_string(), root.to_string());
+    let issued_at = Utc::now();
+    let data = Bytes::from(signed_bytes(&oid, &cid, size, &root, &issued_at));
+    let key_name = key_name.to_string();
+    ipfs::key_list()
+        .and_then(move |key_list| {
+            key_list
+                .keys
+                .into_iter()
+                .find(|key| key.name == key_name)
+                .ok_or(Error::IpfsUploadNotPossible)
+        })
+        .and_then(move |key| {
+            ipfs::key_sign(&key.name, data).map(move |signature| Receipt {
+                oid,
+                cid,
+                size,
+                root,
+                issued_at,
+                key: key.id.to_string(),
+                signature,
+            })
+        })
+}
+
+/// Checks that `receipt` was signed by `trusted_key`, whatever key it claims to be signed by,
+/// and that the manifest of its root lists the object with the same CID and size. The manifest
+/// is verified too if a trusted key for manifests is configured. Fails with
+/// [`Error::ReceiptInvalid`] otherwise.
+pub fn verify(receipt: &Receipt, trusted_key: &str) -> impl Future<Item = (), Error = Error> {
+    let receipt = receipt.clone();
+    let invalid = {
+        let oid = receipt.oid.clone();
+        move |reason: String| Error::ReceiptInvalid {
+            oid: oid.clone(),
+            reason,
+        }
+    };
+    let root = receipt.root.to_cid().map_err({
+        let invalid = invalid.clone();
+        move |_| invalid("its root isn't a CID".to_string())
+    });
+    ipfs::key_verify(
+        trusted_key,
+        &receipt.signature,
+        Bytes::from(receipt.signed_bytes()),
+    )
+    .and_then({
+        let invalid = invalid.clone();
+        move |valid| {
+            if valid {
+                Ok(())
+            } else {
+                Err(invalid(
+                    "its signature doesn't verify against the trusted key".to_string(),
+                ))
+            }
+        }
+    })
+    .and_then(move |_| future::result(root))
+    .and_then(signature::trusted_manifest)
+    .and_then(move |manifest| match manifest.objects.get(&receipt.oid) {
+        None => Err(invalid(format!(
+            "the manifest of {} doesn't list it",
+            receipt.root
+        ))),
+        Some(entry) if entry.cid.cid != receipt.cid || entry.size != receipt.size => {
+            Err(invalid(format!(
+                "the manifest of {} lists it as {} of {} bytes",
+                receipt.root, entry.cid.cid, entry.size
+            )))
+        }
+        Some(_) => Ok(()),
+    })
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod test {
+    use super::*;
+    use crate::testing::{lock_ipfs_path, MockIpfs};
+
+    #[test]
+    fn receipts_only_verify_unaltered_and_by_the_trusted_key() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("receipt-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let object = Object {
+            oid: "22ab5f63670800cc7be06dbed816012b0dc411e774754c7579467d2536a9cf3e".to_string(),
+            size: 21245,
+        };
+        let cid = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+        let root = "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR".to_cid().unwrap();
+        let receipt = sys.block_on(issue(&object, cid, &root, "self")).unwrap();
+        assert_eq!(
+            (object.oid.as_str(), object.size, root.to_string()),
+            (receipt.oid.as_str(), receipt.size, receipt.root.clone())
+        );
+
+        let verifies = |sys: &mut actix::SystemRunner, receipt: &Receipt, key: &str| {
+            sys.block_on(ipfs::key_verify(
+                key,
+                &receipt.signature,
+                Bytes::from(receipt.signed_bytes()),
+            ))
+            .unwrap()
+        };
+        assert!(verifies(&mut sys, &receipt, &receipt.key));
+        let mut altered = receipt.clone();
+        altered.size += 1;
+        assert!(!verifies(&mut sys, &altered, &receipt.key));
+        assert!(!verifies(
+            &mut sys,
+            &receipt,
+            "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR"
+        ));
+        match sys.block_on(verify(&altered, &receipt.key)) {
+            Err(Error::ReceiptInvalid { .. }) => {}
+            result => panic!("expected an altered receipt to be rejected, got {:?}", result),
+        }
+    }
+}
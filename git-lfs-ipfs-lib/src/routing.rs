@@ -0,0 +1,63 @@
+//! Finding and announcing who provides content, through the DHT or whichever routers the
+//! daemon is configured with. Daemons before 0.14 call these commands `dht`, see [`crate::rpc`].
+//! https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-routing-findprovs
+use actix_web::HttpMessage;
+use cid::Cid;
+use futures::prelude::*;
+use url::Url;
+
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::ipfs::{ipfs_api_url, json_lines, send};
+use crate::rpc;
+use crate::spec::ipfs::{PeerInfo, RoutingEvent, ROUTING_EVENT_PROVIDER};
+
+/// Up to `max` providers of `cid`, or those found within `timeout`, which may be none.
+pub fn find_providers(
+    cid: &Cid,
+    max: usize,
+    timeout: Duration,
+) -> impl Future<Item = Vec<PeerInfo>, Error = Error> {
+    let cid = cid.to_string();
+    ipfs_api_url()
+        .and_then(|url| rpc::command_url(&url, "routing/findprovs"))
+        .map(move |mut url| {
+            url.query_pairs_mut()
+                .append_pair("arg", &cid)
+                .append_pair("num-providers", &max.to_string())
+                // Ends the search on the daemon, which then closes the stream
+                .append_pair("timeout", &format!("{}ms", timeout.as_millis()));
+            debug!("Sending find providers request to {}", url);
+            url
+        })
+        .and_then(send)
+        .and_then(move |res| {
+            json_lines(res.payload())
+                .filter(|event: &RoutingEvent| event.event_type == ROUTING_EVENT_PROVIDER)
+                .map(|event| futures::stream::iter_ok(event.responses.unwrap_or_default()))
+                .flatten()
+                .take(max as u64)
+                .collect()
+        })
+}
+
+/// Announces that the local daemon provides `cid`.
+pub fn provide(cid: &Cid) -> impl Future<Item = (), Error = Error> {
+    let cid = cid.clone();
+    ipfs_api_url().and_then(move |url| provide_at(&url, &cid))
+}
+
+/// Asks the daemon behind the API at `api` to announce that it provides `cid`, which fails if
+/// it doesn't have the block.
+pub fn provide_at(api: &Url, cid: &Cid) -> impl Future<Item = (), Error = Error> {
+    let cid = cid.to_string();
+    rpc::command_url(api, "routing/provide")
+        .map(move |mut url| {
+            url.query_pairs_mut().append_pair("arg", &cid);
+            debug!("Sending provide request to {}", url);
+            url
+        })
+        .and_then(send)
+        .and_then(|res| json_lines(res.payload()).for_each(|_: RoutingEvent| Ok(())))
+}
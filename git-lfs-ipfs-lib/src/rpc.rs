@@ -0,0 +1,122 @@
+//! Differences between daemon releases in the IPFS HTTP API.
+//!
+//! Callers name commands as the latest daemons do, and the shims here translate them for
+//! whichever release an endpoint runs, detected once per endpoint from `api/v0/version`.
+use actix_web::HttpMessage;
+use futures::{future, prelude::*};
+use lazy_static::lazy_static;
+use url::Url;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::ipfs::{api_request, parse_version};
+use crate::spec::ipfs::{self, VersionResponse};
+
+/// A daemon release, as `(major, minor, patch)`
+pub type DaemonVersion = (u64, u64, u64);
+
+/// The first release that rejects GET requests to the API
+/// https://github.com/ipfs/kubo/blob/master/CHANGELOG.md#050-2020-04-28
+pub const POST_ONLY_SINCE: DaemonVersion = (0, 5, 0);
+
+/// Commands renamed by a release, as `(command, its name before the release, release)`
+const RENAMED_COMMANDS: &[(&str, &str, DaemonVersion)] = &[
+    // https://github.com/ipfs/kubo/blob/master/docs/changelogs/v0.14.md
+    ("routing/provide", "dht/provide", (0, 14, 0)),
+    ("routing/findprovs", "dht/findprovs", (0, 14, 0)),
+];
+
+lazy_static! {
+    static ref VERSIONS: Mutex<HashMap<Url, DaemonVersion>> = Mutex::new(HashMap::new());
+}
+
+/// What `command` is called on a daemon of `version`.
+pub fn command(command: &'static str, version: DaemonVersion) -> &'static str {
+    RENAMED_COMMANDS
+        .iter()
+        .find(|(renamed, _, since)| *renamed == command && version < *since)
+        .map(|(_, old, _)| *old)
+        .unwrap_or(command)
+}
+
+/// Whether a daemon of `version` only accepts POST requests.
+pub fn post_only(version: DaemonVersion) -> bool {
+    version >= POST_ONLY_SINCE
+}
+
+/// The release of the daemon serving the API at `api`. Asked for once, then remembered for
+/// the rest of the process.
+pub fn daemon_version(api: &Url) -> impl Future<Item = DaemonVersion, Error = Error> {
+    if let Some(version) = VERSIONS.lock().unwrap().get(api) {
+        return future::Either::A(future::ok(*version));
+    }
+    let api = api.clone();
+    let url = api.join("api/v0/version").unwrap();
+    debug!("Sending version request to {}", url);
+    future::Either::B(
+        api_request(url)
+            .finish()
+            .unwrap()
+            .send()
+            .timeout(Duration::from_secs(60))
+            .map_err(Error::IpfsApiSendRequestError)
+            .and_then(|res| {
+                if res.status().is_success() {
+                    future::Either::A(
+                        res.json::<VersionResponse>()
+                            .map_err(Error::IpfsApiJsonPayloadError),
+                    )
+                } else {
+                    future::Either::B(
+                        res.json::<ipfs::Error>()
+                            .map_err(Error::IpfsApiJsonPayloadError)
+                            .and_then(|err| Err(Error::IpfsApiResponseError(err))),
+                    )
+                }
+            })
+            .map(move |version| {
+                let version = parse_version(&version.version);
+                debug!("{} runs IPFS {:?}", api, version);
+                VERSIONS.lock().unwrap().insert(api, version);
+                version
+            }),
+    )
+}
+
+/// `api/v0/<command>` on `api`, under the name the daemon behind it knows the command by.
+pub fn command_url(api: &Url, command: &'static str) -> impl Future<Item = Url, Error = Error> {
+    let api_url = api.clone();
+    daemon_version(api).map(move |version| {
+        api_url
+            .join(&format!("api/v0/{}", self::command(command, version)))
+            .unwrap()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commands_keep_their_name_on_current_daemons() {
+        assert_eq!("routing/provide", command("routing/provide", (0, 14, 0)));
+        assert_eq!("routing/provide", command("routing/provide", (0, 23, 0)));
+        assert_eq!("add", command("add", (0, 4, 18)));
+    }
+
+    #[test]
+    fn renamed_commands_use_their_old_name_on_older_daemons() {
+        assert_eq!("dht/provide", command("routing/provide", (0, 13, 1)));
+        assert_eq!("dht/findprovs", command("routing/findprovs", (0, 4, 18)));
+    }
+
+    #[test]
+    fn daemons_reject_get_since_0_5() {
+        assert!(!post_only((0, 4, 23)));
+        assert!(post_only((0, 5, 0)));
+        assert!(post_only((0, 23, 0)));
+    }
+}
@@ -0,0 +1,250 @@
+//! Links that hand out a single object until they expire, signed with an IPNS key so that a
+//! gateway or server that knows the key's ID can check them without any state of its own.
+//! Only the object's CID is in the link, so it doesn't reveal the root or any other object.
+//! Public gateways ignore the expiry; only gateways that check links with [`check`], or
+//! serve them with [`serve`], enforce it.
+#[cfg(feature = "server")]
+use actix_web::{http::header, HttpMessage, HttpResponse};
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use cid::{Cid, ToCid};
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+use url::Url;
+
+use crate::config::GatewayStyle;
+use crate::error::Error;
+use crate::ipfs;
+use crate::pointer::{format_cid, Multibase};
+use crate::spec::ipfs::Path;
+
+/// How long a link works unless told otherwise
+pub const DEFAULT_EXPIRY_HOURS: i64 = 24;
+
+/// Prefixed to what is signed, so that a share signature can't pass for any other signature
+/// made with the same key, like that of a manifest.
+const SHARE_CONTEXT: &[u8] = b"git-lfs-ipfs share\n";
+
+/// A link to one object, valid until `expires_at`
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareLink {
+    pub oid: String,
+    pub cid: String,
+    pub expires_at: DateTime<Utc>,
+    /// ID of the key that signed the link
+    pub key: String,
+    pub signature: String,
+    #[serde(with = "url_serde")]
+    pub url: Url,
+}
+
+/// The object a link that checked out gives access to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub oid: String,
+    pub cid: Cid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What is signed: the CID in a form that doesn't depend on its version or base, the OID,
+/// which becomes the download's file name, and the expiry in seconds since the epoch.
+fn signed_bytes(oid: &str, cid: &Cid, expires_at: &DateTime<Utc>) -> Vec<u8> {
+    let mut bytes = SHARE_CONTEXT.to_vec();
+    bytes.extend_from_slice(
+        format!(
+            "{}\n{}\n{}",
+            format_cid(cid, Multibase::Base16),
+            oid,
+            expires_at.timestamp()
+        )
+        .as_bytes(),
+    );
+    bytes
+}
+
+/// Mints a link to the object `oid`, stored as `cid`, through `gateway` until `expires_at`,
+/// signed with the local key named `key_name`.
+pub fn create(
+    oid: &str,
+    cid: Cid,
+    gateway: &Url,
+    expires_at: DateTime<Utc>,
+    key_name: &str,
+) -> impl Future<Item = ShareLink, Error = Error> {
+    let oid = oid.to_string();
+    let key_name = key_name.to_string();
+    // Subdomain gateways redirect path URLs to their subdomains, keeping the query
+    let url = ipfs::gateway_url(gateway, &Path::ipfs(cid.clone()), GatewayStyle::Path);
+    let data = Bytes::from(signed_bytes(&oid, &cid, &expires_at));
+    future::result(url.ok_or(Error::IpfsPathParseError("Expected a gateway URL")))
+        .join(ipfs::key_list().and_then(move |key_list| {
+            key_list
+                .keys
+                .into_iter()
+                .find(|key| key.name == key_name)
+                .ok_or(Error::IpfsUploadNotPossible)
+        }))
+        .and_then(move |(url, key)| {
+            ipfs::key_sign(&key.name, data).map(move |signature| {
+                let mut url = url;
+                url.query_pairs_mut()
+                    .append_pair("filename", &oid)
+                    .append_pair("expires", &expires_at.timestamp().to_string())
+                    .append_pair("key", &key.id.to_string())
+                    .append_pair("signature", &signature);
+                ShareLink {
+                    oid,
+                    cid: cid.to_string(),
+                    expires_at,
+                    key: key.id.to_string(),
+                    signature,
+                    url,
+                }
+            })
+        })
+}
+
+/// The object, expiry and signature a link claims, before anything is checked
+fn parse(url: &Url) -> Result<(Grant, String), Error> {
+    let rejected = |reason: &str| Error::ShareLinkRejected(reason.to_string());
+    let mut segments = url
+        .path_segments()
+        .ok_or_else(|| rejected("it doesn't have an /ipfs/ path"))?;
+    let cid = match (segments.next(), segments.next(), segments.next()) {
+        (Some("ipfs"), Some(cid), None) => cid
+            .to_cid()
+            .map_err(|_| rejected("its path doesn't hold a CID"))?,
+        _ => return Err(rejected("it doesn't have an /ipfs/<cid> path")),
+    };
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let oid = query("filename").ok_or_else(|| rejected("it doesn't name the object"))?;
+    let expires_at = query("expires")
+        .and_then(|expires| expires.parse::<i64>().ok())
+        .map(|expires| Utc.timestamp(expires, 0))
+        .ok_or_else(|| rejected("it doesn't say when it expires"))?;
+    let signature = query("signature").ok_or_else(|| rejected("it isn't signed"))?;
+    Ok((
+        Grant {
+            oid,
+            cid,
+            expires_at,
+        },
+        signature,
+    ))
+}
+
+/// Checks that the link `url` hasn't expired at `now` and was signed by `trusted_key`, whatever
+/// key it claims to be signed by. Fails with [`Error::ShareLinkExpired`] or
+/// [`Error::ShareLinkRejected`] otherwise.
+pub fn check(
+    url: &Url,
+    trusted_key: &str,
+    now: DateTime<Utc>,
+) -> impl Future<Item = Grant, Error = Error> {
+    let trusted_key = trusted_key.to_string();
+    future::result(parse(url))
+        .and_then(move |(grant, signature)| {
+            if grant.expires_at <= now {
+                return Err(Error::ShareLinkExpired(grant.expires_at.to_rfc3339()));
+            }
+            Ok((grant, signature))
+        })
+        .and_then(move |(grant, signature)| {
+            let data = Bytes::from(signed_bytes(&grant.oid, &grant.cid, &grant.expires_at));
+            ipfs::key_verify(&trusted_key, &signature, data).and_then(move |valid| {
+                if valid {
+                    Ok(grant)
+                } else {
+                    Err(Error::ShareLinkRejected(
+                        "its signature doesn't verify against the trusted key".to_string(),
+                    ))
+                }
+            })
+        })
+}
+
+/// Responds to a request for the link `url` with the object it shares, once it checked out,
+/// or with the error otherwise.
+#[cfg(feature = "server")]
+pub fn serve(url: &Url, trusted_key: &str) -> impl Future<Item = HttpResponse, Error = Error> {
+    check(url, trusted_key, Utc::now()).and_then(|grant| {
+        ipfs::cat(Path::ipfs(grant.cid)).map(move |res| {
+            HttpResponse::Ok()
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", grant.oid),
+                )
+                .header(
+                    header::EXPIRES,
+                    grant.expires_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+                )
+                .streaming(res.payload())
+        })
+    })
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod test {
+    use super::*;
+    use crate::testing::{lock_ipfs_path, MockIpfs};
+
+    const OID: &str = "22ab5f63670800cc7be06dbed816012b0dc411e774754c7579467d2536a9cf3e";
+
+    #[test]
+    fn links_only_check_out_unexpired_unaltered_and_by_the_trusted_key() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("share-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let cid = crate::pointer::oid_to_cid(cid::Codec::Raw, OID).unwrap();
+        let gateway = Url::parse("https://ipfs.io/").unwrap();
+        let now = Utc.timestamp(1_700_000_000, 0);
+        let expires_at = now + chrono::Duration::hours(DEFAULT_EXPIRY_HOURS);
+        let link = sys
+            .block_on(create(OID, cid.clone(), &gateway, expires_at, "self"))
+            .unwrap();
+        assert!(link
+            .url
+            .as_str()
+            .starts_with(&format!("https://ipfs.io/ipfs/{}?filename={}", cid, OID)));
+
+        let grant = sys.block_on(check(&link.url, &link.key, now)).unwrap();
+        assert_eq!(
+            Grant {
+                oid: OID.to_string(),
+                cid,
+                expires_at,
+            },
+            grant
+        );
+
+        let rejected = |result: Result<Grant, Error>| match result {
+            Err(Error::ShareLinkRejected(_)) => {}
+            result => panic!("expected the link to be rejected, got {:?}", result),
+        };
+        match sys.block_on(check(&link.url, &link.key, expires_at)) {
+            Err(Error::ShareLinkExpired(_)) => {}
+            result => panic!("expected the link to have expired, got {:?}", result),
+        }
+        let mut extended = link.url.clone();
+        let query: Vec<(String, String)> = link
+            .url
+            .query_pairs()
+            .map(|(key, value)| match &*key {
+                "expires" => (key.into_owned(), (expires_at.timestamp() + 3600).to_string()),
+                _ => (key.into_owned(), value.into_owned()),
+            })
+            .collect();
+        extended.query_pairs_mut().clear().extend_pairs(query);
+        rejected(sys.block_on(check(&extended, &link.key, now)));
+        rejected(sys.block_on(check(
+            &link.url,
+            "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR",
+            now,
+        )));
+    }
+}
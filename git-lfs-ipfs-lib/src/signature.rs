@@ -0,0 +1,244 @@
+//! Signatures over the manifests of published roots, made and checked by the daemon with IPNS
+//! keys, so that a manifest can be trusted however it was fetched, even through a gateway that
+//! could have made it up. Signing and verifying need Kubo 0.25 or later; verifying works with
+//! the ID of any key, without the key being on the node.
+use bytes::Bytes;
+use cid::Cid;
+use futures::{future, prelude::*};
+use lazy_static::lazy_static;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::error::Error;
+use crate::ipfs;
+use crate::spec::ipfs::{Path, Prefix, Root};
+use crate::spec::manifest::{Manifest, Signature, MANIFEST_LINK_NAME, SIGNATURE_LINK_NAME};
+
+lazy_static! {
+    /// Manifests whose signature verified, by root CID. Roots are immutable, so they stay valid.
+    static ref VERIFIED: Mutex<HashMap<String, Arc<Manifest>>> = Mutex::new(HashMap::new());
+}
+
+/// Signs `manifest` with the local key named `key_name`.
+pub fn sign(manifest: &Manifest, key_name: &str) -> impl Future<Item = Signature, Error = Error> {
+    let data = Bytes::from(manifest.signed_bytes());
+    let key_name = key_name.to_string();
+    ipfs::key_list()
+        .and_then(move |key_list| {
+            key_list
+                .keys
+                .into_iter()
+                .find(|key| key.name == key_name)
+                .ok_or(Error::IpfsUploadNotPossible)
+        })
+        .and_then(move |key| {
+            ipfs::key_sign(&key.name, data).map(move |signature| Signature {
+                key: key.id.to_string(),
+                signature,
+            })
+        })
+}
+
+/// Checks that `signature` is a signature of `manifest` by `trusted_key`, whatever key the
+/// signature claims to be from. Fails with [`Error::ManifestNotTrusted`] otherwise.
+pub fn verify(
+    root: &str,
+    manifest: &Manifest,
+    signature: &Signature,
+    trusted_key: &str,
+) -> impl Future<Item = (), Error = Error> {
+    let root = root.to_string();
+    let claimed = signature.key.clone();
+    ipfs::key_verify(
+        trusted_key,
+        &signature.signature,
+        Bytes::from(manifest.signed_bytes()),
+    )
+    .and_then(move |valid| {
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::ManifestNotTrusted {
+                root,
+                reason: format!(
+                    "its signature, which claims to be by {}, doesn't verify against the trusted key",
+                    claimed
+                ),
+            })
+        }
+    })
+}
+
+/// The manifest of `root`, read through the API or, if `gateways_only`, through the gateways,
+/// once its signature verified against `trusted_key`.
+pub fn verified_manifest(
+    root: Path,
+    trusted_key: String,
+    gateways_only: bool,
+) -> impl Future<Item = Arc<Manifest>, Error = Error> {
+    ipfs::resolve(root).and_then(move |root| {
+        let key = root.to_string();
+        if let Some(manifest) = VERIFIED.lock().unwrap().get(&key) {
+            return future::Either::A(future::ok(manifest.clone()));
+        }
+        let link = |name: &str| Path {
+            prefix: Prefix::Ipfs,
+            root: Root::Cid(root.clone()),
+            suffix: Some(name.into()),
+        };
+        let (manifest, signature): (
+            Box<Future<Item = Manifest, Error = Error>>,
+            Box<Future<Item = Signature, Error = Error>>,
+        ) = if gateways_only {
+            (
+                Box::new(ipfs::gateway_dag_get(link(MANIFEST_LINK_NAME))),
+                Box::new(ipfs::gateway_dag_get(link(SIGNATURE_LINK_NAME))),
+            )
+        } else {
+            (
+                Box::new(ipfs::dag_get(link(MANIFEST_LINK_NAME))),
+                Box::new(ipfs::dag_get(link(SIGNATURE_LINK_NAME))),
+            )
+        };
+        let signature = signature.map_err({
+            let key = key.clone();
+            move |err| Error::ManifestNotTrusted {
+                root: key,
+                reason: format!("its signature could not be fetched: {}", err),
+            }
+        });
+        future::Either::B(
+            manifest
+                .join(signature)
+                .and_then(move |(manifest, signature)| {
+                    verify(&key, &manifest, &signature, &trusted_key).map(move |_| {
+                        let manifest = Arc::new(manifest);
+                        VERIFIED.lock().unwrap().insert(key, manifest.clone());
+                        manifest
+                    })
+                }),
+        )
+    })
+}
+
+/// The manifest of `root`, verified first if a trusted key is configured.
+pub fn trusted_manifest(root: Cid) -> impl Future<Item = Manifest, Error = Error> {
+    match crate::config::get().trusted_key.clone() {
+        Some(trusted_key) => future::Either::A(
+            verified_manifest(Path::ipfs(root), trusted_key, false)
+                .map(|manifest| (*manifest).clone()),
+        ),
+        None => future::Either::B(ipfs::manifest_get(root)),
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod test {
+    use super::*;
+    use crate::spec::manifest::Layout;
+    use crate::spec::Object;
+    use crate::testing::{lock_ipfs_path, MockIpfs};
+
+    fn manifest() -> Manifest {
+        let mut manifest = Manifest::new();
+        manifest
+            .insert(
+                &Object {
+                    oid: "22ab5f63670800cc7be06dbed816012b0dc411e774754c7579467d2536a9cf3e"
+                        .to_string(),
+                    size: 21245,
+                },
+                "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string(),
+            )
+            .unwrap();
+        manifest
+    }
+
+    #[test]
+    fn signatures_only_verify_for_the_signed_manifest_and_key() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("signature-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let manifest = manifest();
+        let signature = sys.block_on(sign(&manifest, "self")).unwrap();
+        sys.block_on(verify("root", &manifest, &signature, &signature.key))
+            .unwrap();
+
+        let mut tampered = manifest.clone();
+        for entry in tampered.objects.values_mut() {
+            entry.cid.cid = "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR".to_string();
+        }
+        match sys.block_on(verify("root", &tampered, &signature, &signature.key)) {
+            Err(Error::ManifestNotTrusted { .. }) => {}
+            result => panic!("expected a tampered manifest to be rejected, got {:?}", result),
+        }
+        let other_key = "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR";
+        match sys.block_on(verify("root", &manifest, &signature, other_key)) {
+            Err(Error::ManifestNotTrusted { .. }) => {}
+            result => panic!("expected another key's signature to be rejected, got {:?}", result),
+        }
+    }
+
+    fn use_keys(signing_key: Option<&str>, trusted_key: Option<&str>) {
+        crate::config::set(crate::config::Config {
+            signing_key: signing_key.map(str::to_string),
+            trusted_key: trusted_key.map(str::to_string),
+            ..(*crate::config::get()).clone()
+        });
+    }
+
+    #[test]
+    fn published_manifests_are_only_trusted_with_a_signature_by_the_trusted_key() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("signature-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let manifest = manifest();
+        let signature = sys.block_on(sign(&manifest, "self")).unwrap();
+
+        use_keys(Some("self"), Some(&signature.key));
+        let signed = sys
+            .block_on(ipfs::root_put(&manifest, Layout::Flat))
+            .unwrap();
+        let signed = sys.block_on(trusted_manifest(signed));
+
+        // Another manifest, published under the signature of this one
+        let mut other = manifest.clone();
+        for entry in other.objects.values_mut() {
+            entry.cid.cid = "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR".to_string();
+        }
+        let other = sys.block_on(crate::dag::put_json(&other, false)).unwrap();
+        let mut root = serde_json::Map::new();
+        root.insert(
+            MANIFEST_LINK_NAME.to_string(),
+            serde_json::json!({ "/": other.to_string() }),
+        );
+        root.insert(
+            SIGNATURE_LINK_NAME.to_string(),
+            serde_json::to_value(&signature).unwrap(),
+        );
+        let tampered = sys.block_on(crate::dag::put_json(&root, true)).unwrap();
+        let tampered = sys.block_on(trusted_manifest(tampered));
+
+        use_keys(None, Some(&signature.key));
+        let unsigned = sys
+            .block_on(ipfs::root_put(&manifest, Layout::Flat))
+            .unwrap();
+        let unsigned = sys.block_on(trusted_manifest(unsigned));
+        use_keys(None, None);
+
+        assert_eq!(manifest, signed.unwrap());
+        match tampered {
+            Err(Error::ManifestNotTrusted { .. }) => {}
+            result => panic!("expected a tampered manifest to be rejected, got {:?}", result),
+        }
+        match unsigned {
+            Err(Error::ManifestNotTrusted { reason, .. }) => {
+                assert!(reason.contains("could not be fetched"))
+            }
+            result => panic!("expected an unsigned manifest to be rejected, got {:?}", result),
+        }
+    }
+}
@@ -3,6 +3,7 @@ use lazy_static::lazy_static;
 use serde::Deserialize;
 use serde_derive::{Deserialize, Serialize};
 
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -38,6 +39,12 @@ pub struct Error {
     Type: String,
 }
 
+impl Error {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
 /// https://docs.ipfs.io/reference/api/http/#api-v0-add
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -48,6 +55,25 @@ pub struct AddResponse {
     pub size: String,
 }
 
+/// A line of `api/v0/add` output: progress while the content is read if asked for, then an
+/// entry for every node added, ending with the root
+/// https://docs.ipfs.io/reference/api/http/#api-v0-add
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum AddEvent {
+    Added(AddResponse),
+    Progress(AddProgress),
+}
+
+/// How many bytes of the content the daemon has read so far
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AddProgress {
+    #[serde(default)]
+    pub name: String,
+    pub bytes: u64,
+}
+
 /// https://docs.ipfs.io/reference/api/http/#api-v0-key-list
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -64,6 +90,21 @@ pub struct Key {
     pub id: Cid,
 }
 
+/// https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-key-sign
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct KeySignResponse {
+    /// Multibase-encoded
+    pub signature: String,
+}
+
+/// https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-key-verify
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct KeyVerifyResponse {
+    pub signature_valid: bool,
+}
+
 /// https://docs.ipfs.io/reference/api/http/#api-v0-ls
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "PascalCase")]
@@ -116,6 +157,249 @@ impl Into<Path> for Link {
     }
 }
 
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pin-ls
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PinLsResponse {
+    pub keys: HashMap<String, PinInfo>,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pin-ls
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct PinInfo {
+    #[serde(rename = "Type")]
+    pub pin_type: String,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-files-stat
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FilesStatResponse {
+    #[serde(with = "string")]
+    pub hash: Cid,
+    pub size: u64,
+    pub cumulative_size: u64,
+    pub blocks: u64,
+    #[serde(rename = "Type")]
+    pub file_type: FileType,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-repo-stat
+/// Only the sizes, as `size-only=true` returns them without counting every block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RepoStatResponse {
+    pub repo_size: u64,
+    /// Datastore.StorageMax in bytes
+    pub storage_max: u64,
+}
+
+/// A line of `api/v0/repo/gc?stream-errors=true`, for one removed block or one error
+/// https://docs.ipfs.io/reference/api/http/#api-v0-repo-gc
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RepoGcEntry {
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+/// A line of `api/v0/pin/ls?stream=true`
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pin-ls
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct PinLsEntry {
+    pub cid: String,
+    #[serde(rename = "Type")]
+    pub pin_type: String,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pin-add
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pin-rm
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PinResponse {
+    pub pins: Vec<String>,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-version
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VersionResponse {
+    pub version: String,
+    #[serde(default)]
+    pub commit: String,
+    #[serde(default)]
+    pub repo: String,
+    #[serde(default)]
+    pub system: String,
+    #[serde(default)]
+    pub golang: String,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-block-put
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BlockPutResponse {
+    #[serde(with = "string")]
+    pub key: Cid,
+    pub size: u64,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-dag-put
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DagPutResponse {
+    pub cid: crate::spec::manifest::Link,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-dag-stat
+/// Older daemons report the totals of the one DAG asked for, Kubo 0.21 and later a list of DAGs.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum DagStatResponse {
+    #[serde(rename_all = "PascalCase")]
+    Current {
+        dag_stats: Vec<DagStat>,
+        /// Size of every block under any of the DAGs, counting shared blocks once
+        #[serde(default)]
+        total_size: Option<u64>,
+    },
+    Legacy(DagStat),
+}
+
+impl DagStatResponse {
+    /// How much the DAGs take up together, or `None` from daemons that stat a single DAG
+    pub fn deduplicated_size(&self) -> Option<u64> {
+        match self {
+            DagStatResponse::Current { total_size, .. } => *total_size,
+            DagStatResponse::Legacy(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DagStat {
+    pub size: u64,
+    pub num_blocks: u64,
+}
+
+impl From<DagStatResponse> for DagStat {
+    fn from(response: DagStatResponse) -> Self {
+        match response {
+            DagStatResponse::Current { dag_stats, .. } => dag_stats.into_iter().fold(
+                DagStat {
+                    size: 0,
+                    num_blocks: 0,
+                },
+                |total, stat| DagStat {
+                    size: total.size + stat.size,
+                    num_blocks: total.num_blocks + stat.num_blocks,
+                },
+            ),
+            DagStatResponse::Legacy(stat) => stat,
+        }
+    }
+}
+
+/// A line of `api/v0/dag/import` output, which reports each root, and statistics if asked for
+/// https://docs.ipfs.io/reference/api/http/#api-v0-dag-import
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DagImportEvent {
+    #[serde(default)]
+    pub root: Option<DagImportRoot>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DagImportRoot {
+    pub cid: crate::spec::manifest::Link,
+    /// Set if the root could not be pinned
+    #[serde(default)]
+    pub pin_error_msg: Option<String>,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-swarm-peers
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SwarmPeersResponse {
+    /// `null` rather than empty without any peers
+    #[serde(default)]
+    pub peers: Option<Vec<SwarmPeer>>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct SwarmPeer {
+    /// The multiaddr the peer is connected on
+    pub addr: String,
+    /// The peer ID
+    pub peer: String,
+    #[serde(default)]
+    pub latency: String,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-swarm-connect
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SwarmConnectResponse {
+    pub strings: Vec<String>,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-swarm-addrs-local
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SwarmAddrsLocalResponse {
+    /// `null` rather than empty while the node listens nowhere
+    #[serde(default)]
+    pub strings: Option<Vec<String>>,
+}
+
+/// The type of routing events that list providers
+/// https://github.com/libp2p/go-libp2p/blob/master/core/routing/query.go
+pub const ROUTING_EVENT_PROVIDER: u64 = 4;
+
+/// A line of `api/v0/routing/findprovs` and `api/v0/routing/provide` output
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RoutingEvent {
+    #[serde(rename = "Type")]
+    pub event_type: u64,
+    #[serde(default)]
+    pub responses: Option<Vec<PeerInfo>>,
+    #[serde(default)]
+    pub extra: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct PeerInfo {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(default)]
+    pub addrs: Option<Vec<String>>,
+}
+
+/// https://docs.ipfs.io/reference/api/http/#api-v0-pubsub-sub
+#[derive(Debug, Deserialize, Clone)]
+pub struct PubsubMessage {
+    pub from: String,
+    /// Multibase-encoded payload
+    pub data: String,
+    pub seqno: String,
+    #[serde(rename = "topicIDs")]
+    pub topic_ids: Vec<String>,
+}
+
 /// https://docs.ipfs.io/reference/api/http/#api-v0-resolve
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -162,10 +446,57 @@ impl Display for Prefix {
     }
 }
 
+/// A domain name with a DNSLink record, checked for syntax only. Looking names up in the
+/// public suffix list would mean fetching it at runtime, which the protocol core can't do.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct DnsName(String);
+
+impl DnsName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for DnsName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Names of at least two labels of letters, digits and inner hyphens, with a top-level label
+/// that isn't all digits, so that IP addresses aren't taken for names.
+/// https://tools.ietf.org/html/rfc1123#section-2.1
+impl FromStr for DnsName {
+    type Err = crate::error::Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.trim_end_matches('.').to_lowercase();
+        let labels: Vec<&str> = name.split('.').collect();
+        let valid_label = |label: &&str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        };
+        let top_level = labels.last().map_or("", |label| *label);
+        if name.len() <= 253
+            && labels.len() >= 2
+            && labels.iter().all(valid_label)
+            && !top_level.chars().all(|c| c.is_ascii_digit())
+        {
+            Ok(DnsName(name))
+        } else {
+            Err(crate::error::Error::IpfsPathParseError("Invalid DNS name"))
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Root {
     Cid(cid::Cid),
-    DnsLink(publicsuffix::Domain),
+    DnsLink(DnsName),
 }
 
 impl Display for Root {
@@ -177,17 +508,13 @@ impl Display for Root {
     }
 }
 
-lazy_static! {
-    static ref PUBLIC_SUFFIX_LIST: publicsuffix::List = publicsuffix::List::fetch().unwrap();
-}
-
 impl FromStr for Root {
     type Err = crate::error::Error;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         use cid::ToCid;
         if let Ok(cid) = s.to_cid() {
             Ok(Root::Cid(cid))
-        } else if let Ok(dns_link) = PUBLIC_SUFFIX_LIST.parse_domain(s) {
+        } else if let Ok(dns_link) = s.parse() {
             Ok(Root::DnsLink(dns_link))
         } else {
             Err(crate::error::Error::IpfsPathParseError(
@@ -249,12 +576,23 @@ impl Display for Path {
 impl FromStr for Path {
     type Err = crate::error::Error;
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if !s.starts_with('/') {
+            return Err(crate::error::Error::IpfsPathParseError(
+                "Path must start with a slash",
+            ));
+        }
         let mut it = s.split('/').into_iter();
         it.next();
         let prefix = it.next();
         let root = it.next();
-        let suffix: Option<String> =
-            Some(it.collect()).and_then(|s: String| if s.len() != 0 { Some(s) } else { None });
+        // Empty components are dropped, so the suffix stays relative to the root
+        let components: Vec<&str> = it.filter(|component| !component.is_empty()).collect();
+        if components.iter().any(|component| *component == "..") {
+            return Err(crate::error::Error::IpfsPathParseError(
+                "Path must not climb above its root",
+            ));
+        }
+        let suffix: Option<String> = Some(components.join("/")).filter(|s| !s.is_empty());
         if let (Some(prefix), Some(root)) = (prefix, root) {
             if let (Ok(prefix), Ok(root)) = (Prefix::from_str(prefix), Root::from_str(root)) {
                 Self::parse(
@@ -286,12 +624,124 @@ mod test {
             format!("{}", Root::from_str(EMPTY_FOLDER_HASH).unwrap())
         );
         assert_eq!("ipfs.io", format!("{}", Root::from_str("ipfs.io").unwrap()));
+        assert_eq!("ipfs.io", format!("{}", Root::from_str("IPFS.io.").unwrap()));
         let path_string = format!("/ipfs/{}", EMPTY_FOLDER_HASH);
         assert_eq!(
             path_string,
             format!("{}", Path::from_str(&path_string).unwrap())
         );
     }
+
+    #[test]
+    fn dns_names_are_checked_without_a_suffix_list() {
+        for name in &["docs.ipfs.tech", "en.wikipedia-on-ipfs.org", "x1.example"] {
+            assert!(DnsName::from_str(name).is_ok(), "{}", name);
+        }
+        for name in &[
+            "localhost",
+            "127.0.0.1",
+            "-bad.example",
+            "bad-.example",
+            "under_score.example",
+            "a..example",
+            &format!("{}.example", "a".repeat(64)),
+        ] {
+            assert!(DnsName::from_str(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn ipfs_path_suffixes_keep_their_separators() {
+        let path = Path::from_str(&format!("/ipfs/{}/a//b/", EMPTY_FOLDER_HASH)).unwrap();
+        assert_eq!(Some(PathBuf::from("a/b")), path.suffix);
+        assert_eq!(path, Path::from_str(&path.to_string()).unwrap());
+        for s in &[
+            format!("ipfs/{}", EMPTY_FOLDER_HASH),
+            format!("x/ipfs/{}", EMPTY_FOLDER_HASH),
+            format!("/ipfs/{}/a/../../b", EMPTY_FOLDER_HASH),
+        ] {
+            assert!(Path::from_str(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn dag_stat_parses_from_every_release() {
+        let legacy: DagStatResponse =
+            serde_json::from_str(r#"{"Size":1234,"NumBlocks":3}"#).unwrap();
+        let current: DagStatResponse = serde_json::from_str(&format!(
+            r#"{{"TotalSize":1234,"DagStats":[{{"Cid":{{"/":"{}"}},"Size":1234,"NumBlocks":3}}],"Ratio":1}}"#,
+            EMPTY_FOLDER_HASH
+        ))
+        .unwrap();
+        let expected = DagStat {
+            size: 1234,
+            num_blocks: 3,
+        };
+        assert_eq!(None, legacy.deduplicated_size());
+        assert_eq!(Some(1234), current.deduplicated_size());
+        assert_eq!(expected, legacy.into());
+        assert_eq!(expected, current.into());
+    }
+
+    #[test]
+    fn dag_import_events_parse() {
+        let root: DagImportEvent = serde_json::from_str(&format!(
+            r#"{{"Root":{{"Cid":{{"/":"{}"}},"PinErrorMsg":""}}}}"#,
+            EMPTY_FOLDER_HASH
+        ))
+        .unwrap();
+        assert_eq!(EMPTY_FOLDER_HASH, root.root.unwrap().cid.cid);
+        let stats: DagImportEvent =
+            serde_json::from_str(r#"{"Stats":{"BlockCount":3,"BlockBytesCount":1234}}"#).unwrap();
+        assert!(stats.root.is_none());
+    }
+
+    #[test]
+    fn routing_events_parse() {
+        let event: RoutingEvent = serde_json::from_str(
+            r#"{"Extra":"","ID":"","Responses":[{"Addrs":["/ip4/127.0.0.1/tcp/4001"],"ID":"QmPeer"}],"Type":4}"#,
+        )
+        .unwrap();
+        assert_eq!(ROUTING_EVENT_PROVIDER, event.event_type);
+        assert_eq!("QmPeer", event.responses.unwrap()[0].id);
+        let event: RoutingEvent =
+            serde_json::from_str(r#"{"Extra":"","ID":"QmPeer","Responses":null,"Type":0}"#)
+                .unwrap();
+        assert!(event.responses.is_none());
+    }
+
+    #[test]
+    fn swarm_peers_parse_without_peers() {
+        let peers: SwarmPeersResponse = serde_json::from_str(r#"{"Peers":null}"#).unwrap();
+        assert!(peers.peers.is_none());
+    }
+
+    #[test]
+    fn swarm_local_addresses_parse() {
+        let addrs: SwarmAddrsLocalResponse = serde_json::from_str(
+            r#"{"Strings":["/ip4/127.0.0.1/tcp/4001/p2p/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"]}"#,
+        )
+        .unwrap();
+        assert_eq!(1, addrs.strings.unwrap().len());
+        let addrs: SwarmAddrsLocalResponse = serde_json::from_str(r#"{"Strings":null}"#).unwrap();
+        assert!(addrs.strings.is_none());
+    }
+
+    #[test]
+    fn add_events_parse() {
+        match serde_json::from_str(r#"{"Name":"","Bytes":262144}"#).unwrap() {
+            AddEvent::Progress(progress) => assert_eq!(262144, progress.bytes),
+            AddEvent::Added(_) => panic!("progress parsed as an added node"),
+        }
+        let added = format!(
+            r#"{{"Name":"{0}","Hash":"{0}","Size":"4"}}"#,
+            EMPTY_FOLDER_HASH
+        );
+        match serde_json::from_str(&added).unwrap() {
+            AddEvent::Added(added) => assert_eq!(EMPTY_FOLDER_HASH, added.hash.to_string()),
+            AddEvent::Progress(_) => panic!("added node parsed as progress"),
+        }
+    }
 }
 
 // TODO: Refactor to implement serialize for IpfsPath
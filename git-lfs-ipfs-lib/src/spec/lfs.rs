@@ -0,0 +1,475 @@
+//! Types of the Git LFS HTTP APIs, shared by servers and clients.
+use chrono::{DateTime, FixedOffset};
+use http::StatusCode;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use url::Url;
+
+use crate::spec::Object;
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#requests
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct BatchRequest {
+    pub operation: Operation,
+    #[serde(default = "Transfer::default_vec")]
+    pub transfers: Vec<Transfer>,
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub ref_property: Option<Ref>,
+    pub objects: Vec<Object>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_algo: Option<String>,
+}
+
+impl BatchRequest {
+    pub fn new(operation: Operation, objects: Vec<Object>) -> Self {
+        Self {
+            operation,
+            transfers: Transfer::default_vec(),
+            ref_property: None,
+            objects,
+            hash_algo: None,
+        }
+    }
+
+    pub fn transfers(mut self, transfers: Vec<Transfer>) -> Self {
+        self.transfers = transfers;
+        self
+    }
+
+    pub fn ref_name(mut self, name: &str) -> Self {
+        self.ref_property = Some(Ref {
+            name: name.to_string(),
+        });
+        self
+    }
+
+    pub fn hash_algo(mut self, hash_algo: &str) -> Self {
+        self.hash_algo = Some(hash_algo.to_string());
+        self
+    }
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#successful-responses
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct BatchResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer: Option<Transfer>,
+    pub objects: Vec<ObjectResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_algo: Option<String>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#requests
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Download,
+    Upload,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/basic-transfers.md#basic-transfer-api
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transfer {
+    Basic,
+    Custom,
+}
+
+impl Transfer {
+    fn default_vec() -> Vec<Self> {
+        vec![Transfer::Basic]
+    }
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Transfer::Basic
+    }
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#ref-property
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct Ref {
+    pub name: String,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#successful-responses
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ObjectResponse {
+    Success {
+        #[serde(flatten)]
+        object: Object,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        authenticated: Option<bool>,
+        actions: Actions,
+    },
+    Error {
+        #[serde(flatten)]
+        object: Object,
+        error: ObjectError,
+    },
+}
+
+impl ObjectResponse {
+    pub fn success(object: Object, actions: Actions) -> Self {
+        ObjectResponse::Success {
+            object,
+            authenticated: None,
+            actions: actions,
+        }
+    }
+
+    pub fn error(object: Object, error: ObjectError) -> Self {
+        ObjectResponse::Error { object, error }
+    }
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#successful-responses
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ObjectSuccess {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authenticated: Option<bool>,
+    actions: Actions,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#response-errors
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ObjectError {
+    pub code: u16,
+    pub message: String,
+}
+
+impl ObjectError {
+    pub fn new(status: StatusCode, message: &str) -> Self {
+        Self {
+            code: status.as_u16(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn DoesNotExist() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "Object does not exist")
+    }
+
+    pub fn RemovedByOwner() -> Self {
+        Self::new(StatusCode::GONE, "Object removed by owner")
+    }
+    pub fn ValidationError() -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, "Validation error")
+    }
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/basic-transfers.md#basic-transfer-api
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Actions {
+    UploadAndVerify { upload: Action, verify: Action },
+    Upload { upload: Action },
+    Download { download: Action },
+    None,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/basic-transfers.md#basic-transfer-api
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct Action {
+    #[serde(with = "url_serde")]
+    pub href: Url,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+
+impl Action {
+    pub fn new(href: Url) -> Self {
+        Self {
+            href,
+            header: None,
+            expires_in: None,
+            expires_at: None,
+        }
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.header
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn expires_in(mut self, seconds: i32) -> Self {
+        self.expires_in = Some(seconds);
+        self
+    }
+
+    pub fn expires_at(mut self, expires_at: DateTime<FixedOffset>) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md#response-errors
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct LfsErrorResponse {
+    pub message: String,
+    #[serde(with = "url_serde", default)]
+    pub documentation_url: Option<Url>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(skip, default = "LfsErrorResponse::default_status")]
+    pub status: StatusCode,
+}
+
+impl LfsErrorResponse {
+    pub fn new(status: StatusCode, message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+            documentation_url: None,
+            request_id: None,
+            status,
+        }
+    }
+
+    pub fn documentation_url(mut self, documentation_url: Url) -> Self {
+        self.documentation_url = Some(documentation_url);
+        self
+    }
+
+    pub fn request_id(mut self, request_id: &str) -> Self {
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+
+    fn default_status() -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    pub fn accept_header_incorrect() -> Self {
+        Self::new(
+            StatusCode::NOT_ACCEPTABLE,
+            "The Accept header needs to be `application/vnd.git-lfs+json`.",
+        )
+    }
+
+    pub fn rate_limit_hit() -> Self {
+        Self::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "A rate limit has been hit with the server.",
+        )
+    }
+
+    pub fn not_implemented() -> Self {
+        Self::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "The server has not implemented the current method.",
+        )
+    }
+
+    pub fn insufficient_storage() -> Self {
+        Self::new(
+            StatusCode::INSUFFICIENT_STORAGE,
+            "The server has insufficient storage capacity to complete the request.",
+        )
+    }
+
+    pub fn bandwidth_limit_exceeded() -> Self {
+        Self::new(
+            StatusCode::from_u16(509).unwrap(),
+            "A bandwidth limit has been exceeded.",
+        )
+    }
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct Lock {
+    pub id: String,
+    pub path: String,
+    pub locked_at: DateTime<FixedOffset>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<LockOwner>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct LockOwner {
+    pub name: String,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#create-lock
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct CreateLockRequest {
+    pub path: String,
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub ref_property: Option<Ref>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#create-lock
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#delete-lock
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct LockResponse {
+    pub lock: Lock,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#create-lock
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct LockConflictResponse {
+    pub lock: Lock,
+    pub message: String,
+    #[serde(with = "url_serde", default)]
+    pub documentation_url: Option<Url>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#list-locks
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ListLocksResponse {
+    pub locks: Vec<Lock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#list-locks-for-verification
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Default)]
+pub struct VerifyLocksRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub ref_property: Option<Ref>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#list-locks-for-verification
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct VerifyLocksResponse {
+    pub ours: Vec<Lock>,
+    pub theirs: Vec<Lock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/api/locking.md#delete-lock
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Default)]
+pub struct DeleteLockRequest {
+    #[serde(default)]
+    pub force: bool,
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub ref_property: Option<Ref>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn batch_response_serializes_correctly() {
+        assert_eq!(
+            include_str!("test/batch_response_success.json"),
+            serde_json::to_string_pretty(&BatchResponse {
+                transfer: Some(Transfer::Basic),
+                objects: vec![ObjectResponse::Success {
+                    object: Object {
+                        oid: "1111111".to_string(),
+                        size: 123,
+                    },
+                    authenticated: Some(true),
+                    actions: Actions::Download {
+                        download: Action {
+                            href: Url::parse("https://some-download.com").unwrap(),
+                            header: Some(
+                                [("Key", "value")]
+                                    .iter()
+                                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                                    .collect()
+                            ),
+                            expires_in: None,
+                            expires_at: DateTime::parse_from_rfc3339("2016-11-10T15:29:07Z")
+                                .unwrap()
+                                .into()
+                        }
+                    }
+                }],
+                hash_algo: None,
+            })
+            .unwrap(),
+        );
+
+        assert_eq!(
+            include_str!("test/batch_response_error.json"),
+            serde_json::to_string_pretty(&BatchResponse {
+                transfer: Some(Transfer::Basic),
+                objects: vec![ObjectResponse::Error {
+                    error: ObjectError::DoesNotExist(),
+                    object: Object {
+                        oid: "1111111".to_string(),
+                        size: 123,
+                    },
+                }],
+                hash_algo: None,
+            })
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn lfs_error_serializes_correctly() {
+        assert_eq!(
+            include_str!("test/lfs_error.json"),
+            serde_json::to_string_pretty(
+                &LfsErrorResponse::new(StatusCode::NOT_FOUND, "Not found")
+                    .documentation_url(Url::parse("https://lfs-server.com/docs/errors").unwrap())
+                    .request_id("123")
+            )
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn batch_request_deserializes_correctly() {
+        assert_eq!(
+            BatchRequest::new(
+                Operation::Download,
+                vec![Object {
+                    oid: "12345678".to_string(),
+                    size: 123,
+                }],
+            )
+            .transfers(vec![Transfer::Basic])
+            .ref_name("refs/heads/main")
+            .hash_algo("sha256"),
+            serde_json::from_str(include_str!("test/batch_request.json")).unwrap()
+        );
+    }
+
+    #[test]
+    fn batch_response_round_trips() {
+        let response: BatchResponse =
+            serde_json::from_str(include_str!("test/batch_response_success.json")).unwrap();
+        assert_eq!(
+            include_str!("test/batch_response_success.json"),
+            serde_json::to_string_pretty(&response).unwrap()
+        );
+    }
+
+    #[test]
+    fn verify_locks_response_deserializes_correctly() {
+        let response: VerifyLocksResponse =
+            serde_json::from_str(include_str!("test/verify_locks_response.json")).unwrap();
+        assert_eq!(1, response.ours.len());
+        assert_eq!("some-uuid", response.ours[0].id);
+        assert_eq!(
+            Some(LockOwner {
+                name: "Jane Doe".to_string()
+            }),
+            response.ours[0].owner
+        );
+        assert!(response.theirs.is_empty());
+        assert_eq!(Some("optional next ID".to_string()), response.next_cursor);
+    }
+
+}
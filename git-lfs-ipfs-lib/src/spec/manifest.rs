@@ -0,0 +1,456 @@
+use cid::ToCid;
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::spec::Object;
+
+/// Name of the link to the manifest in a published root
+pub const MANIFEST_LINK_NAME: &str = "lfs-manifest";
+pub const MANIFEST_VERSION: u32 = 1;
+/// Flags an entry whose CID is a UnixFS file holding the object, like objects mirrored from
+/// another LFS server, rather than the object's root block
+pub const UNIXFS_FLAG: &str = "unixfs";
+/// Name of the link to the manifest's signature in a published root, if it was signed
+pub const SIGNATURE_LINK_NAME: &str = "lfs-manifest-signature";
+/// Largest manifest, in bytes of its DAG-JSON form, that can be published. Daemons refuse
+/// blocks over 1 MiB and bitswap doesn't send them, and a manifest is one block. Its DAG-CBOR
+/// form is a little smaller than its DAG-JSON, so this leaves room for about 7,000 objects.
+pub const MAX_MANIFEST_SIZE: usize = 1024 * 1024;
+/// Prefixed to what is signed, so that a manifest signature can't pass for a signature of
+/// anything else made with the same key
+const SIGNATURE_CONTEXT: &[u8] = b"git-lfs-ipfs manifest\n";
+
+/// Maps every object in a published root from its OID to where it is stored, so readers
+/// can find objects without relying on how the root lays them out.
+/// Stored as DAG-CBOR, but written here in its DAG-JSON form.
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct Manifest {
+    pub version: u32,
+    /// Keyed by OID. Readers build paths from the keys, so a manifest keyed by anything but
+    /// hex OIDs doesn't deserialize.
+    #[serde(deserialize_with = "oid_keys")]
+    pub objects: BTreeMap<String, Entry>,
+}
+
+fn oid_keys<'de, D>(deserializer: D) -> Result<BTreeMap<String, Entry>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let objects: BTreeMap<String, Entry> = serde::Deserialize::deserialize(deserializer)?;
+    match objects.keys().find(|oid| !crate::pointer::is_oid(oid)) {
+        Some(oid) => Err(serde::de::Error::custom(format!(
+            "the manifest lists {:?}, which is not an OID",
+            oid
+        ))),
+        None => Ok(objects),
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct Entry {
+    pub cid: Link,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<String>,
+}
+
+/// A DAG-JSON link, `{"/": "<cid>"}`
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct Link {
+    #[serde(rename = "/")]
+    pub cid: String,
+}
+
+/// A signature over a manifest, made with an IPNS key and linked from the root next to it,
+/// which lets readers trust a manifest however it reached them.
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
+pub struct Signature {
+    /// ID of the key that signed, as `ipfs key list -l` shows it
+    pub key: String,
+    /// Multibase-encoded, as the daemon's `key/sign` returns it
+    pub signature: String,
+}
+
+/// How a newer manifest differs from an older one, see [`Manifest::diff`]
+#[derive(PartialEq, Eq, Debug, Default, Serialize, Clone)]
+pub struct Diff {
+    /// Objects only the newer manifest lists, keyed by OID
+    pub added: BTreeMap<String, Entry>,
+    /// Objects only the older manifest lists
+    pub removed: BTreeMap<String, Entry>,
+    /// Objects both list, but stored under CIDs of different content, as the newer one links them
+    pub relinked: BTreeMap<String, Entry>,
+}
+
+impl Diff {
+    /// The entries a reader of the older manifest lacks to read every object of the newer one
+    pub fn delta(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.added.iter().chain(self.relinked.iter())
+    }
+}
+
+impl Entry {
+    pub fn is_unixfs(&self) -> bool {
+        self.flags.iter().any(|flag| flag == UNIXFS_FLAG)
+    }
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            objects: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `object`, stored as `cid`. An object that is already in the manifest keeps its
+    /// link: pushing it again under another CID means corrupt data or a forged object, so it
+    /// fails with [`Error::OidConflict`].
+    pub fn insert(&mut self, object: &Object, cid: String) -> Result<(), Error> {
+        if let Some(entry) = self.objects.get(&object.oid) {
+            if !same_content(&entry.cid.cid, &cid) {
+                warn!(
+                    "Rejected object {} as {}, the manifest already links it to {}",
+                    object.oid, cid, entry.cid.cid
+                );
+                return Err(Error::OidConflict {
+                    oid: object.oid.clone(),
+                    linked: entry.cid.cid.clone(),
+                    pushed: cid,
+                });
+            }
+        }
+        self.objects.insert(
+            object.oid.clone(),
+            Entry {
+                cid: Link { cid },
+                size: object.size,
+                flags: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    /// Fails with [`Error::ManifestTooLarge`] if the manifest no longer fits in one block,
+    /// see [`MAX_MANIFEST_SIZE`].
+    pub fn check_size(&self) -> Result<(), Error> {
+        let size = serde_json::to_vec(self)
+            .expect("manifests always serialize")
+            .len();
+        if size > MAX_MANIFEST_SIZE {
+            return Err(Error::ManifestTooLarge {
+                objects: self.objects.len(),
+                size,
+                limit: MAX_MANIFEST_SIZE,
+            });
+        }
+        Ok(())
+    }
+
+    /// What a [`Signature`] signs: the manifest as JSON, whose objects are sorted by OID, so
+    /// that it comes out the same whether the manifest was read as DAG-JSON or DAG-CBOR.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNATURE_CONTEXT.to_vec();
+        bytes.extend(serde_json::to_vec(self).expect("manifests always serialize"));
+        bytes
+    }
+
+    /// What changed from this manifest to `newer`. An object stored under another CID of the
+    /// same content, like a CIDv1 of its CIDv0, doesn't count as changed.
+    pub fn diff(&self, newer: &Manifest) -> Diff {
+        let mut diff = Diff::default();
+        for (oid, entry) in &newer.objects {
+            match self.objects.get(oid) {
+                None => {
+                    diff.added.insert(oid.clone(), entry.clone());
+                }
+                Some(older) if !same_content(&older.cid.cid, &entry.cid.cid) => {
+                    diff.relinked.insert(oid.clone(), entry.clone());
+                }
+                Some(_) => {}
+            }
+        }
+        for (oid, entry) in &self.objects {
+            if !newer.objects.contains_key(oid) {
+                diff.removed.insert(oid.clone(), entry.clone());
+            }
+        }
+        diff
+    }
+
+    /// Adds `object`, stored as the UnixFS file `cid`, see [`insert`](Manifest::insert).
+    pub fn insert_unixfs(&mut self, object: &Object, cid: String) -> Result<(), Error> {
+        self.insert(object, cid)?;
+        if let Some(entry) = self.objects.get_mut(&object.oid) {
+            entry.flags = vec![UNIXFS_FLAG.to_string()];
+        }
+        Ok(())
+    }
+}
+
+/// How a published root links its objects, besides the manifest that every root has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// `<root>/<oid>`. The root is then one block linking every object, a little smaller than
+    /// the manifest, so the manifest's [`MAX_MANIFEST_SIZE`] keeps it fetchable too.
+    Flat,
+    /// `<root>/<oid[0..2]>/<oid[2..4]>/<oid>`, like `.git/lfs/objects`, which keeps
+    /// directories small in repositories with many objects. The manifest still lists all of
+    /// them in one block.
+    Fanout,
+    /// Only `<root>/lfs-manifest`
+    ManifestOnly,
+}
+
+impl Layout {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Layout::Flat => "flat",
+            Layout::Fanout => "fanout",
+            Layout::ManifestOnly => "manifest",
+        }
+    }
+
+    /// Where the object `oid` is linked in a root of this layout, relative to the root, if it
+    /// is at all. Fails with [`Error::HashError`] if `oid` isn't a hex OID.
+    pub fn object_path(self, oid: &str) -> Result<Option<String>, Error> {
+        if !crate::pointer::is_oid(oid) {
+            return Err(Error::HashError);
+        }
+        Ok(match self {
+            Layout::Flat => Some(oid.to_string()),
+            Layout::Fanout => Some(format!("{}/{}/{}", &oid[0..2], &oid[2..4], oid)),
+            Layout::ManifestOnly => None,
+        })
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Flat
+    }
+}
+
+impl FromStr for Layout {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Layout::Flat),
+            "fanout" => Ok(Layout::Fanout),
+            "manifest" => Ok(Layout::ManifestOnly),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Whether two CIDs address the same content, even if one is CIDv0 and the other CIDv1.
+fn same_content(a: &str, b: &str) -> bool {
+    match (a.to_cid(), b.to_cid()) {
+        (Ok(a), Ok(b)) => a.codec == b.codec && a.hash == b.hash,
+        _ => a == b,
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn manifest_serializes_correctly() {
+        let mut manifest = Manifest::new();
+        manifest.insert(
+            &Object {
+                oid: "22ab5f63670800cc7be06dbed816012b0dc411e774754c7579467d2536a9cf3e".to_string(),
+                size: 21245,
+            },
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            include_str!("test/manifest.json"),
+            serde_json::to_string(&manifest).unwrap()
+        );
+        assert_eq!(
+            manifest,
+            serde_json::from_str(include_str!("test/manifest.json")).unwrap()
+        );
+    }
+
+    #[test]
+    fn manifests_must_fit_in_one_block() {
+        let mut manifest = Manifest::new();
+        for i in 0..1000u32 {
+            manifest
+                .insert(
+                    &Object {
+                        oid: format!("{:064x}", i),
+                        size: u64::from(i),
+                    },
+                    "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string(),
+                )
+                .unwrap();
+        }
+        assert!(manifest.check_size().is_ok());
+        for i in 1000..10_000u32 {
+            manifest
+                .insert(
+                    &Object {
+                        oid: format!("{:064x}", i),
+                        size: u64::from(i),
+                    },
+                    "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string(),
+                )
+                .unwrap();
+        }
+        match manifest.check_size() {
+            Err(Error::ManifestTooLarge { objects, size, .. }) => {
+                assert_eq!(10_000, objects);
+                assert!(size > MAX_MANIFEST_SIZE);
+            }
+            result => panic!("expected the manifest to be too large, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn objects_keep_their_link() {
+        let object = Object {
+            oid: "22ab5f63670800cc7be06dbed816012b0dc411e774754c7579467d2536a9cf3e".to_string(),
+            size: 21245,
+        };
+        let mut manifest = Manifest::new();
+        manifest
+            .insert(&object, "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string())
+            .unwrap();
+        manifest
+            .insert(&object, "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string())
+            .unwrap();
+        match manifest.insert(
+            &object,
+            "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR".to_string(),
+        ) {
+            Err(Error::OidConflict { linked, .. }) => {
+                assert_eq!("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn", linked)
+            }
+            result => panic!("expected a conflict, got {:?}", result),
+        }
+        assert_eq!(
+            "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn",
+            manifest.objects[&object.oid].cid.cid
+        );
+    }
+
+    #[test]
+    fn signed_bytes_ignore_how_the_manifest_was_read() {
+        let manifest: Manifest = serde_json::from_str(include_str!("test/manifest.json")).unwrap();
+        let reordered: Manifest = serde_json::from_str(
+            r#"{"objects": {"22ab5f63670800cc7be06dbed816012b0dc411e774754c7579467d2536a9cf3e": {"size": 21245, "cid": {"/": "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"}}}, "version": 1}"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.signed_bytes(), reordered.signed_bytes());
+        assert!(manifest.signed_bytes().starts_with(SIGNATURE_CONTEXT));
+    }
+
+    #[test]
+    fn manifests_keyed_by_anything_but_oids_are_rejected() {
+        let entry =
+            r#"{"cid": {"/": "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn"}, "size": 1}"#;
+        let not_hex = "g".repeat(64);
+        for key in &["ab/../../..", "a", "ab\u{e9}d", not_hex.as_str()] {
+            let json = format!(
+                r#"{{"version": 1, "objects": {{{}: {}}}}}"#,
+                serde_json::to_string(key).unwrap(),
+                entry
+            );
+            assert!(
+                serde_json::from_str::<Manifest>(&json).is_err(),
+                "{:?} was accepted",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn diffs_list_added_removed_and_relinked_objects() {
+        let object = |oid: &str| Object {
+            oid: oid.repeat(64),
+            size: 1,
+        };
+        let mut older = Manifest::new();
+        older
+            .insert(&object("a"), "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string())
+            .unwrap();
+        older
+            .insert(&object("b"), "QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR".to_string())
+            .unwrap();
+        older
+            .insert(&object("c"), "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string())
+            .unwrap();
+        let mut newer = Manifest::new();
+        // The same content as a CIDv1
+        newer
+            .insert(
+                &object("a"),
+                "zdj7WbTaiJT1fgatdet9Ei9iDB5hdCxkbVyhyh8YTUnXMiwYi".to_string(),
+            )
+            .unwrap();
+        newer
+            .insert(&object("b"), "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string())
+            .unwrap();
+        newer
+            .insert(&object("d"), "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn".to_string())
+            .unwrap();
+
+        let diff = older.diff(&newer);
+        let oids = |entries: &BTreeMap<String, Entry>| -> Vec<String> {
+            entries.keys().cloned().collect()
+        };
+        assert_eq!(vec!["d".repeat(64)], oids(&diff.added));
+        assert_eq!(vec!["c".repeat(64)], oids(&diff.removed));
+        assert_eq!(vec!["b".repeat(64)], oids(&diff.relinked));
+        assert_eq!(
+            vec!["d".repeat(64), "b".repeat(64)],
+            diff.delta().map(|(oid, _)| oid.clone()).collect::<Vec<_>>()
+        );
+        assert_eq!(Diff::default(), newer.diff(&newer));
+    }
+
+    #[test]
+    fn layouts_link_objects_at_their_path() {
+        let oid = "22ab5f63670800cc7be06dbed816012b0dc411e774754c7579467d2536a9cf3e";
+        assert_eq!(
+            Some(oid.to_string()),
+            Layout::Flat.object_path(oid).unwrap()
+        );
+        assert_eq!(
+            Some(format!("22/ab/{}", oid)),
+            Layout::Fanout.object_path(oid).unwrap()
+        );
+        assert_eq!(None, Layout::ManifestOnly.object_path(oid).unwrap());
+        // 64 bytes, but not hex
+        let (uppercase, accented) = (oid.to_uppercase(), "é".repeat(32));
+        for layout in &[Layout::Flat, Layout::Fanout, Layout::ManifestOnly] {
+            assert_eq!(Ok(*layout), layout.as_str().parse());
+            for invalid in &[
+                "",
+                "22a",
+                "../etc/passwd",
+                uppercase.as_str(),
+                accented.as_str(),
+            ] {
+                match layout.object_path(invalid) {
+                    Err(Error::HashError) => {}
+                    result => panic!("expected {:?} to be rejected, got {:?}", invalid, result),
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use serde_derive::{Deserialize, Serialize};
 
-pub mod batch;
 pub mod ipfs;
+pub mod lfs;
+pub mod manifest;
 pub mod transfer;
 
 #[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
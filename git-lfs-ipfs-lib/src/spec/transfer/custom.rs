@@ -19,6 +19,13 @@ pub struct Init {
     pub concurrenttransfers: Option<usize>,
 }
 
+/// https://github.com/git-lfs/git-lfs/blob/master/docs/custom-transfers.md#stage-1-intiation
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone, Default)]
+pub struct InitResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+}
+
 /// https://github.com/git-lfs/git-lfs/blob/master/docs/custom-transfers.md#uploads
 #[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone)]
 pub struct Upload {
@@ -0,0 +1,175 @@
+//! Disk usage of the local daemon, checked before large uploads so that they fail up front with
+//! a clear error instead of halfway through with whatever the datastore reports.
+//! https://docs.ipfs.io/reference/api/http/#api-v0-repo-stat
+use actix_web::HttpMessage;
+use futures::{future, prelude::*};
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::config;
+use crate::error::Error;
+use crate::ipfs::{ipfs_api_url, json_lines, send_within};
+use crate::spec::ipfs::{RepoGcEntry, RepoStatResponse};
+
+/// Uploads smaller than this are never checked, since a `repo/stat` per small file would cost
+/// more than it could save.
+pub const GUARDED_SIZE: u64 = 16 * 1024 * 1024;
+
+/// What to do when an upload would take the node past its Datastore.StorageMax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskGuard {
+    /// Don't check
+    Off,
+    /// Log a warning and upload anyway, since StorageMax is only a soft limit
+    Warn,
+    /// Collect garbage first, and refuse if that doesn't free enough
+    Gc,
+    /// Refuse with [`Error::StorageFull`]
+    Refuse,
+}
+
+impl Default for DiskGuard {
+    fn default() -> Self {
+        DiskGuard::Warn
+    }
+}
+
+impl FromStr for DiskGuard {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(DiskGuard::Off),
+            "warn" => Ok(DiskGuard::Warn),
+            "gc" => Ok(DiskGuard::Gc),
+            "refuse" => Ok(DiskGuard::Refuse),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How much the local daemon stores, and how much it may.
+pub fn stat() -> impl Future<Item = RepoStatResponse, Error = Error> {
+    ipfs_api_url()
+        .map(|url| {
+            let mut url = url.join("api/v0/repo/stat").unwrap();
+            url.query_pairs_mut().append_pair("size-only", "true");
+            debug!("Sending repo stat request to {}", url);
+            url
+        })
+        .and_then(|url| send_within(url, Duration::from_secs(60)))
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+}
+
+/// Removes every block the local daemon doesn't have pinned, returning how many. Blocks that
+/// couldn't be removed are logged.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-repo-gc
+pub fn gc() -> impl Future<Item = usize, Error = Error> {
+    ipfs_api_url()
+        .map(|url| {
+            let mut url = url.join("api/v0/repo/gc").unwrap();
+            url.query_pairs_mut().append_pair("stream-errors", "true");
+            debug!("Sending repo gc request to {}", url);
+            url
+        })
+        // Collecting a large repository takes a while
+        .and_then(|url| send_within(url, Duration::from_secs(3600)))
+        .and_then(|res| {
+            json_lines(res.payload()).fold(0, |removed, entry: RepoGcEntry| match entry.error {
+                Some(error) => {
+                    warn!("Garbage collection could not remove a block: {}", error);
+                    Ok::<_, Error>(removed)
+                }
+                None => Ok(removed + 1),
+            })
+        })
+}
+
+/// Bytes still available under StorageMax, or `None` if the daemon sets no limit
+fn available(stat: &RepoStatResponse) -> Option<u64> {
+    if stat.storage_max == 0 {
+        None
+    } else {
+        Some(stat.storage_max.saturating_sub(stat.repo_size))
+    }
+}
+
+/// How many bytes an upload of `size` lacks, if it doesn't fit
+fn shortfall(stat: &RepoStatResponse, size: u64) -> Option<u64> {
+    available(stat)
+        .filter(|available| *available < size)
+        .map(|available| size - available)
+}
+
+fn storage_full(stat: &RepoStatResponse, size: u64) -> Error {
+    Error::StorageFull {
+        needed: size,
+        available: available(stat).unwrap_or_default(),
+    }
+}
+
+/// Checks that an upload of `size` bytes fits under the local daemon's StorageMax, reacting as
+/// [`Config::disk_guard`](crate::config::Config::disk_guard) says if it doesn't. Uploads
+/// smaller than [`GUARDED_SIZE`] aren't checked. If the daemon can't report its usage, the
+/// upload goes ahead: the check is there to fail early, not to fail more.
+pub fn ensure_space(size: u64) -> impl Future<Item = (), Error = Error> {
+    let guard = config::get().disk_guard;
+    if guard == DiskGuard::Off || size < GUARDED_SIZE {
+        return future::Either::A(future::ok(()));
+    }
+    future::Either::B(stat().then(move |result| {
+        let usage = match result {
+            Ok(usage) => usage,
+            Err(err) => {
+                warn!("Could not check the disk usage of the IPFS node: {}", err);
+                return future::Either::A(future::ok(()));
+            }
+        };
+        let missing = match shortfall(&usage, size) {
+            Some(missing) => missing,
+            None => return future::Either::A(future::ok(())),
+        };
+        match guard {
+            DiskGuard::Off => future::Either::A(future::ok(())),
+            DiskGuard::Warn => {
+                warn!(
+                    "Uploading {} bytes takes the IPFS node {} bytes past its Datastore.StorageMax",
+                    size, missing
+                );
+                future::Either::A(future::ok(()))
+            }
+            DiskGuard::Refuse => future::Either::A(future::err(storage_full(&usage, size))),
+            DiskGuard::Gc => {
+                info!(
+                    "Collecting garbage to make room for {} bytes on the IPFS node",
+                    size
+                );
+                future::Either::B(gc().and_then(|_| stat()).and_then(move |usage| {
+                    match shortfall(&usage, size) {
+                        Some(_) => Err(storage_full(&usage, size)),
+                        None => Ok(()),
+                    }
+                }))
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uploads_only_fall_short_under_a_storage_max() {
+        let usage = |repo_size, storage_max| RepoStatResponse {
+            repo_size,
+            storage_max,
+        };
+        assert_eq!(None, shortfall(&usage(100, 0), 1000));
+        assert_eq!(None, shortfall(&usage(100, 1100), 1000));
+        assert_eq!(Some(1), shortfall(&usage(101, 1100), 1000));
+        assert_eq!(Some(1000), shortfall(&usage(2000, 1100), 1000));
+        assert_eq!(Ok(DiskGuard::Gc), "gc".parse());
+        assert_eq!(Err(()), "sometimes".parse::<DiskGuard>());
+    }
+}
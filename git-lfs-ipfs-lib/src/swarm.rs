@@ -0,0 +1,60 @@
+//! Connections to other peers.
+//! https://docs.ipfs.io/reference/api/http/#api-v0-swarm-peers
+use actix_web::HttpMessage;
+use futures::prelude::*;
+
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::ipfs::{ipfs_api_url, send_within};
+use crate::spec::ipfs::{
+    SwarmAddrsLocalResponse, SwarmConnectResponse, SwarmPeer, SwarmPeersResponse,
+};
+
+/// The peers the local daemon is connected to.
+pub fn peers() -> impl Future<Item = Vec<SwarmPeer>, Error = Error> {
+    ipfs_api_url()
+        .map(|url| {
+            let url = url.join("api/v0/swarm/peers").unwrap();
+            debug!("Sending swarm peers request to {}", url);
+            url
+        })
+        .and_then(|url| send_within(url, Duration::from_secs(60)))
+        .and_then(|res| {
+            res.json()
+                .limit(usize::max_value())
+                .map_err(Error::IpfsApiJsonPayloadError)
+        })
+        .map(|res: SwarmPeersResponse| res.peers.unwrap_or_default())
+}
+
+/// Connects the local daemon to the peer at `multiaddr`, which must end in `/p2p/<peer id>`.
+/// https://docs.ipfs.io/reference/api/http/#api-v0-swarm-connect
+pub fn connect(multiaddr: &str) -> impl Future<Item = SwarmConnectResponse, Error = Error> {
+    let multiaddr = multiaddr.to_string();
+    ipfs_api_url()
+        .map(move |url| {
+            let mut url = url.join("api/v0/swarm/connect").unwrap();
+            url.query_pairs_mut().append_pair("arg", &multiaddr);
+            debug!("Sending swarm connect request to {}", url);
+            url
+        })
+        .and_then(|url| send_within(url, Duration::from_secs(60)))
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+}
+
+/// The multiaddrs the local daemon listens on, each ending in `/p2p/<peer id>` so that others
+/// can connect to it with [`connect`].
+/// https://docs.ipfs.io/reference/api/http/#api-v0-swarm-addrs-local
+pub fn local_addrs() -> impl Future<Item = Vec<String>, Error = Error> {
+    ipfs_api_url()
+        .map(|url| {
+            let mut url = url.join("api/v0/swarm/addrs/local").unwrap();
+            url.query_pairs_mut().append_pair("id", "true");
+            debug!("Sending swarm addrs local request to {}", url);
+            url
+        })
+        .and_then(|url| send_within(url, Duration::from_secs(60)))
+        .and_then(|res| res.json().map_err(Error::IpfsApiJsonPayloadError))
+        .map(|res: SwarmAddrsLocalResponse| res.strings.unwrap_or_default())
+}
@@ -0,0 +1,1616 @@
+//! An in-process mock of the subset of the IPFS HTTP API used by this crate.
+//!
+//! Added payloads are chunked and wrapped in dag-pb UnixFS nodes as a daemon does with its
+//! default chunker and CIDv0, so their CIDs are those a real daemon would report. Only one level
+//! of links is built, which covers payloads up to 43.5 MiB. Blocks stored any other way are
+//! kept as they are, addressed by the CIDv0 of their SHA2-256 multihash, which matches how
+//! git-lfs OIDs map onto CIDs elsewhere in the crate.
+//!
+//! It can pretend to be any daemon release, for the differences handled in [`crate::rpc`]:
+//! releases since 0.5 refuse GET requests, and before 0.14 `routing` commands were under `dht`.
+//! It also serves its blocks like a trustless gateway, under `/ipfs/<cid>`.
+//!
+//! DAG nodes are kept as the DAG-JSON they were put as, under a CIDv1 of that JSON rather than
+//! of the DAG-CBOR a daemon would store, and `dag/get` walks paths through them and their links.
+//!
+//! [`Faults`] make it misbehave like an overloaded daemon or a bad gateway would, to check that
+//! retries, failover and verification hold up.
+use actix_web::{
+    dev::Resource,
+    http::header,
+    middleware::{Middleware, Response, Started},
+    server, App, AsyncResponder, Body, HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use bytes::Bytes;
+use cid::Cid;
+use futures::{prelude::*, stream};
+use serde_json::json;
+use tokio_timer::Delay;
+use url::Url;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::rpc;
+
+/// The release the mock pretends to be unless told otherwise
+const DEFAULT_VERSION: &str = "0.4.18";
+
+lazy_static::lazy_static! {
+    /// Tests pointing `IPFS_PATH` at their own mock can't run at the same time
+    static ref IPFS_PATH_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Keeps other tests from pointing `IPFS_PATH` elsewhere until the guard is dropped. Take it
+/// before [`MockIpfs::set_env`], and hold it for as long as the mock is used.
+pub fn lock_ipfs_path() -> MutexGuard<'static, ()> {
+    IPFS_PATH_LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[derive(Default)]
+struct Inner {
+    blocks: HashMap<String, Bytes>,
+    /// The content of UnixFS files added, by root CID, as `cat` returns it
+    contents: HashMap<String, Bytes>,
+    /// Nodes put through `dag/put`, as the JSON they were given in, by CID
+    nodes: HashMap<String, serde_json::Value>,
+    pins: HashSet<String>,
+    keys: Vec<(String, String)>,
+    names: HashMap<String, String>,
+    provided: HashSet<String>,
+    /// Connected peers, as `(multiaddr, peer ID)`
+    peers: Vec<(String, String)>,
+    /// MFS paths, mapped to the CID of a file's content or `None` for directories. The root
+    /// directory `/` always exists and isn't stored.
+    files: BTreeMap<String, Option<String>>,
+    version: String,
+    faults: Faults,
+}
+
+/// Ways the mock misbehaves, on every route, until they are cleared
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    /// Wait this long before answering each request
+    pub latency: Duration,
+    /// Answer this many of the next requests with `429 Too Many Requests`
+    pub rate_limited: usize,
+    /// `Retry-After` of those answers, in seconds; they have none if unset
+    pub retry_after: Option<u64>,
+    /// Drop the connection after sending this many bytes of a response body
+    pub reset_after: Option<usize>,
+    /// Cut JSON responses off halfway
+    pub malformed_json: bool,
+    /// Flip a bit of every block served, so it no longer matches its CID
+    pub corrupt: bool,
+}
+
+/// Injects the configured [`Faults`] into requests and responses.
+struct FaultInjection;
+
+impl Middleware<MockState> for FaultInjection {
+    fn start(&self, req: &HttpRequest<MockState>) -> actix_web::Result<Started> {
+        let mut inner = req.state().inner.lock().unwrap();
+        let rate_limited = if inner.faults.rate_limited > 0 {
+            inner.faults.rate_limited -= 1;
+            let mut res = HttpResponse::TooManyRequests();
+            if let Some(retry_after) = inner.faults.retry_after {
+                res.header(header::RETRY_AFTER, retry_after.to_string());
+            }
+            Some(res.finish())
+        } else {
+            None
+        };
+        let latency = inner.faults.latency;
+        if latency == Duration::from_secs(0) {
+            return Ok(rate_limited.map_or(Started::Done, Started::Response));
+        }
+        Ok(Started::Future(Box::new(
+            Delay::new(Instant::now() + latency)
+                .then(move |_| -> actix_web::Result<Option<HttpResponse>> { Ok(rate_limited) }),
+        )))
+    }
+
+    fn response(
+        &self,
+        req: &HttpRequest<MockState>,
+        mut res: HttpResponse,
+    ) -> actix_web::Result<Response> {
+        let faults = req.state().inner.lock().unwrap().faults.clone();
+        let mut body = match res.body() {
+            Body::Binary(binary) if !binary.is_empty() => binary.as_ref().to_vec(),
+            _ => return Ok(Response::Done(res)),
+        };
+        let json = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map_or(false, |content_type| content_type.starts_with("application/json"));
+        if json && faults.malformed_json {
+            let half = body.len() / 2;
+            body.truncate(half);
+        } else if !json && faults.corrupt {
+            body[0] ^= 1;
+        }
+        match faults.reset_after {
+            Some(reset_after) if reset_after < body.len() => {
+                body.truncate(reset_after);
+                res.set_body(Body::Streaming(Box::new(stream::iter_result(vec![
+                    Ok(Bytes::from(body)),
+                    Err(actix_web::error::ErrorInternalServerError("connection reset")),
+                ]))));
+            }
+            _ => res.set_body(body),
+        }
+        Ok(Response::Done(res))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MockState {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MockState {
+    fn new(version: &str) -> Self {
+        let state = Self::default();
+        state.inner.lock().unwrap().version = version.to_string();
+        let self_id = content_cid(b"self").to_string();
+        state
+            .inner
+            .lock()
+            .unwrap()
+            .keys
+            .push(("self".to_string(), self_id));
+        state
+    }
+}
+
+/// A running mock daemon. Its `IPFS_PATH`-style directory contains an `api` file
+/// pointing at the server, so the crate's API discovery finds it.
+pub struct MockIpfs {
+    pub url: Url,
+    pub ipfs_path: PathBuf,
+    state: MockState,
+}
+
+impl MockIpfs {
+    /// Starts the mock on an ephemeral port. Must be called from within a running actix `System`.
+    pub fn start() -> Self {
+        Self::start_version(DEFAULT_VERSION)
+    }
+
+    /// Starts the mock pretending to be the daemon release `release`, like `0.23.0`.
+    pub fn start_version(release: &str) -> Self {
+        let state = MockState::new(release);
+        let app_state = state.clone();
+        let parsed = crate::ipfs::parse_version(release);
+        let post_only = rpc::post_only(parsed);
+        let provide_path = format!("/api/v0/{}", rpc::command("routing/provide", parsed));
+        let findprovs_path = format!("/api/v0/{}", rpc::command("routing/findprovs", parsed));
+        let server = server::new(move || {
+            App::with_state(app_state.clone())
+                .middleware(FaultInjection)
+                .resource("/ipfs/{cid}", |r| r.get().f(gateway))
+                .resource("/api/v0/add", move |r| route(r, post_only, add))
+                .resource("/api/v0/cat", move |r| route(r, post_only, cat))
+                .resource("/api/v0/block/get", move |r| route(r, post_only, block_get))
+                .resource("/api/v0/block/put", move |r| route(r, post_only, block_put))
+                .resource("/api/v0/resolve", move |r| route(r, post_only, resolve))
+                .resource("/api/v0/dag/put", move |r| route(r, post_only, dag_put))
+                .resource("/api/v0/dag/get", move |r| route(r, post_only, dag_get))
+                .resource("/api/v0/key/list", move |r| route(r, post_only, key_list))
+                .resource("/api/v0/key/sign", move |r| route(r, post_only, key_sign))
+                .resource("/api/v0/key/verify", move |r| route(r, post_only, key_verify))
+                .resource("/api/v0/name/publish", move |r| {
+                    route(r, post_only, name_publish)
+                })
+                .resource("/api/v0/pin/add", move |r| route(r, post_only, pin_add))
+                .resource("/api/v0/pin/ls", move |r| route(r, post_only, pin_ls))
+                .resource("/api/v0/pin/rm", move |r| route(r, post_only, pin_rm))
+                .resource("/api/v0/files/write", move |r| route(r, post_only, files_write))
+                .resource("/api/v0/files/mkdir", move |r| route(r, post_only, files_mkdir))
+                .resource("/api/v0/files/stat", move |r| route(r, post_only, files_stat))
+                .resource("/api/v0/files/rm", move |r| route(r, post_only, files_rm))
+                .resource("/api/v0/files/cp", move |r| route(r, post_only, files_cp))
+                .resource("/api/v0/version", move |r| route(r, post_only, version))
+                .resource("/api/v0/config/show", move |r| route(r, post_only, config_show))
+                .resource(&provide_path, move |r| route(r, post_only, provide))
+                .resource(&findprovs_path, move |r| route(r, post_only, findprovs))
+                .resource("/api/v0/swarm/peers", move |r| route(r, post_only, swarm_peers))
+                .resource("/api/v0/swarm/connect", move |r| {
+                    route(r, post_only, swarm_connect)
+                })
+        })
+        .bind("127.0.0.1:0")
+        .expect("could not bind mock IPFS API");
+        let addr = server.addrs()[0];
+        server.start();
+
+        let ipfs_path = std::env::temp_dir().join(format!("git-lfs-ipfs-mock-{}", addr.port()));
+        std::fs::create_dir_all(&ipfs_path).expect("could not create mock IPFS_PATH");
+        std::fs::write(
+            ipfs_path.join("api"),
+            format!("/ip4/{}/tcp/{}", addr.ip(), addr.port()),
+        )
+        .expect("could not write mock api file");
+
+        Self {
+            url: Url::parse(&format!("http://{}/", addr)).unwrap(),
+            ipfs_path,
+            state,
+        }
+    }
+
+    /// Points the crate at this mock by setting `IPFS_PATH` for the current process.
+    pub fn set_env(&self) {
+        std::env::set_var("IPFS_PATH", &self.ipfs_path);
+    }
+
+    /// Makes the mock misbehave as described by `faults`, replacing any earlier ones.
+    pub fn set_faults(&self, faults: Faults) {
+        self.state.inner.lock().unwrap().faults = faults;
+    }
+
+    pub fn is_pinned(&self, cid: &Cid) -> bool {
+        self.state
+            .inner
+            .lock()
+            .unwrap()
+            .pins
+            .contains(&cid.to_string())
+    }
+
+    pub fn is_provided(&self, cid: &Cid) -> bool {
+        self.state
+            .inner
+            .lock()
+            .unwrap()
+            .provided
+            .contains(&cid.to_string())
+    }
+
+    pub fn insert(&self, data: Bytes) -> Cid {
+        let cid = content_cid(&data);
+        self.state
+            .inner
+            .lock()
+            .unwrap()
+            .blocks
+            .insert(cid.to_string(), data);
+        cid
+    }
+}
+
+impl Drop for MockIpfs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.ipfs_path);
+    }
+}
+
+/// Serves `handler` for POST requests only if `post_only`, like daemons since 0.5, or for any method.
+fn route<H, R>(resource: &mut Resource<MockState>, post_only: bool, handler: H)
+where
+    H: Fn(&HttpRequest<MockState>) -> R + 'static,
+    R: Responder + 'static,
+{
+    if post_only {
+        resource.post().f(handler);
+    } else {
+        resource.f(handler);
+    }
+}
+
+fn content_cid(data: &[u8]) -> Cid {
+    let mh = multihash::encode(multihash::Hash::SHA2256, data).unwrap();
+    Cid::new(cid::Codec::DagProtobuf, cid::Version::V0, &mh)
+}
+
+/// Every value of `arg` in the query, in order. `HttpRequest::query` only keeps the last one.
+fn args(req: &HttpRequest<MockState>) -> Vec<String> {
+    url::form_urlencoded::parse(req.query_string().as_bytes())
+        .filter(|(k, _)| k == "arg")
+        .map(|(_, v)| v.into_owned())
+        .collect()
+}
+
+fn query(req: &HttpRequest<MockState>, key: &str) -> Option<String> {
+    url::form_urlencoded::parse(req.query_string().as_bytes())
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.into_owned())
+}
+
+fn api_error(message: &str) -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({
+        "Message": message,
+        "Code": 0,
+        "Type": "error",
+    }))
+}
+
+/// Strips the `/ipfs/` prefix if present, leaving the root CID.
+fn cid_arg(arg: &str) -> &str {
+    arg.trim_start_matches("/ipfs/")
+        .split('/')
+        .next()
+        .unwrap_or_default()
+}
+
+/// Extracts the first part of a multipart/form-data body.
+fn multipart_content(content_type: &str, body: &[u8]) -> Option<Bytes> {
+    let boundary = content_type.split("boundary=").nth(1)?.trim();
+    let start_marker = format!("--{}\r\n", boundary);
+    let end_marker = format!("\r\n--{}--", boundary);
+    let start = find(body, start_marker.as_bytes())? + start_marker.len();
+    let headers_end = find(&body[start..], b"\r\n")
+        .filter(|i| *i == 0)
+        .map(|_| start + 2)
+        .or_else(|| find(&body[start..], b"\r\n\r\n").map(|i| start + i + 4))?;
+    let end = find(&body[headers_end..], end_marker.as_bytes())? + headers_end;
+    Some(Bytes::from(&body[headers_end..end]))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Content is cut into chunks of this size, as by the daemon's default `size-262144` chunker
+const CHUNK_SIZE: usize = 256 * 1024;
+/// Links per node in the daemon's balanced layout
+const MAX_LINKS: usize = 174;
+
+fn varint(mut n: u64, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Appends a length-delimited protobuf field.
+fn field(key: u8, bytes: &[u8], out: &mut Vec<u8>) {
+    out.push(key);
+    varint(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+}
+
+/// A dag-pb node holding a UnixFS file: a leaf with its `data`, or a node linking to leaves,
+/// each given as `(cid, size of the leaf block, size of its data)`.
+/// https://github.com/ipfs/specs/blob/main/UNIXFS.md
+fn unixfs_file(data: &[u8], filesize: u64, links: &[(Cid, u64, u64)]) -> Vec<u8> {
+    let mut unixfs = vec![0x08, 0x02];
+    if !data.is_empty() {
+        field(0x12, data, &mut unixfs);
+    }
+    unixfs.push(0x18);
+    varint(filesize, &mut unixfs);
+    for (_, _, blocksize) in links {
+        unixfs.push(0x20);
+        varint(*blocksize, &mut unixfs);
+    }
+    // Links are encoded before the data, like go-merkledag does
+    let mut node = vec![];
+    for (cid, tsize, _) in links {
+        let mut link = vec![];
+        field(0x0a, &cid.to_bytes(), &mut link);
+        field(0x12, b"", &mut link);
+        link.push(0x18);
+        varint(*tsize, &mut link);
+        field(0x12, &link, &mut node);
+    }
+    field(0x0a, &unixfs, &mut node);
+    node
+}
+
+/// Stores `data` as a UnixFS file, returning its root and the cumulative size of its blocks.
+fn add_unixfs(inner: &mut Inner, data: Bytes) -> Option<(Cid, u64)> {
+    let mut put = |block: Vec<u8>| {
+        let cid = content_cid(&block);
+        let size = block.len() as u64;
+        inner.blocks.insert(cid.to_string(), Bytes::from(block));
+        (cid, size)
+    };
+    let (root, cumulative) = if data.len() <= CHUNK_SIZE {
+        put(unixfs_file(&data, data.len() as u64, &[]))
+    } else {
+        if data.len() > CHUNK_SIZE * MAX_LINKS {
+            return None;
+        }
+        let links: Vec<(Cid, u64, u64)> = data
+            .chunks(CHUNK_SIZE)
+            .map(|chunk| {
+                let (cid, size) = put(unixfs_file(chunk, chunk.len() as u64, &[]));
+                (cid, size, chunk.len() as u64)
+            })
+            .collect();
+        let (root, size) = put(unixfs_file(&[], data.len() as u64, &links));
+        (root, size + links.iter().map(|(_, tsize, _)| tsize).sum::<u64>())
+    };
+    inner.contents.insert(root.to_string(), data);
+    Some((root, cumulative))
+}
+
+/// Whether the chunker, CID version and hash asked for are the daemon's defaults, the only
+/// ones the mock can encode
+fn default_encoding(req: &HttpRequest<MockState>) -> bool {
+    [
+        ("chunker", "size-262144"),
+        ("cid-version", "0"),
+        ("hash", "sha2-256"),
+        ("raw-leaves", "false"),
+    ]
+    .iter()
+    .all(|(key, default)| query(req, key).map_or(true, |value| value == *default))
+}
+
+/// Like a daemon, answers with a line of JSON per event: the progress if asked for, then the
+/// added node, which is pinned unless `pin=false`.
+fn add(req: &HttpRequest<MockState>) -> Box<Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let state = req.state().clone();
+    let progress = query(req, "progress").map_or(false, |progress| progress == "true");
+    let pin = query(req, "pin").map_or(true, |pin| pin != "false");
+    let default_encoding = default_encoding(req);
+    let content_type = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    req.body()
+        .limit(usize::max_value())
+        .from_err()
+        .and_then(move |body: Bytes| {
+            if !default_encoding {
+                return Ok(api_error("the mock only adds with the default chunker and CIDv0"));
+            }
+            Ok(match multipart_content(&content_type, &body) {
+                Some(data) => {
+                    let size = data.len();
+                    let mut inner = state.inner.lock().unwrap();
+                    let (cid, cumulative) = match add_unixfs(&mut inner, data) {
+                        Some(added) => added,
+                        None => return Ok(api_error("the mock only builds one level of links")),
+                    };
+                    if pin {
+                        inner.pins.insert(cid.to_string());
+                    }
+                    let mut events = String::new();
+                    if progress {
+                        events.push_str(&format!("{}\n", json!({ "Name": "", "Bytes": size })));
+                    }
+                    events.push_str(&format!(
+                        "{}\n",
+                        json!({
+                            "Name": cid.to_string(),
+                            "Hash": cid.to_string(),
+                            "Size": cumulative.to_string(),
+                        })
+                    ));
+                    HttpResponse::Ok()
+                        .content_type("application/json")
+                        .body(events)
+                }
+                None => api_error("file argument 'path' is required"),
+            })
+        })
+        .responder()
+}
+
+fn block_put(
+    req: &HttpRequest<MockState>,
+) -> Box<Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let state = req.state().clone();
+    let content_type = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    req.body()
+        .limit(usize::max_value())
+        .from_err()
+        .and_then(move |body: Bytes| {
+            Ok(match multipart_content(&content_type, &body) {
+                Some(data) => {
+                    let cid = content_cid(&data);
+                    let size = data.len();
+                    let mut inner = state.inner.lock().unwrap();
+                    inner.blocks.insert(cid.to_string(), data);
+                    inner.pins.insert(cid.to_string());
+                    HttpResponse::Ok().json(json!({
+                        "Key": cid.to_string(),
+                        "Size": size,
+                    }))
+                }
+                None => api_error("file argument 'data' is required"),
+            })
+        })
+        .responder()
+}
+
+fn dag_put(
+    req: &HttpRequest<MockState>,
+) -> Box<Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let state = req.state().clone();
+    let content_type = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let pin = query(req, "pin").map_or(false, |pin| pin == "true");
+    req.body()
+        .limit(usize::max_value())
+        .from_err()
+        .and_then(move |body: Bytes| {
+            let node = multipart_content(&content_type, &body).and_then(|data| {
+                serde_json::from_slice::<serde_json::Value>(&data)
+                    .ok()
+                    .map(|node| (data, node))
+            });
+            Ok(match node {
+                Some((data, node)) => {
+                    let mh = multihash::encode(multihash::Hash::SHA2256, &data).unwrap();
+                    let cid = Cid::new(cid::Codec::DagCBOR, cid::Version::V1, &mh).to_string();
+                    let mut inner = state.inner.lock().unwrap();
+                    inner.blocks.insert(cid.clone(), data);
+                    inner.nodes.insert(cid.clone(), node);
+                    if pin {
+                        inner.pins.insert(cid.clone());
+                    }
+                    HttpResponse::Ok().json(json!({ "Cid": { "/": cid } }))
+                }
+                None => api_error("failed to decode the node as dag-json"),
+            })
+        })
+        .responder()
+}
+
+/// Answers the node at `/ipfs/<cid>/<path>`, following links on the way and at its end.
+fn dag_get(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    let path = args(req).into_iter().next().unwrap_or_default();
+    let follow = |value: &serde_json::Value| match value.get("/").and_then(|link| link.as_str()) {
+        Some(cid) => inner.nodes.get(cid).cloned(),
+        None => Some(value.clone()),
+    };
+    let mut segments = path
+        .trim_start_matches("/ipfs/")
+        .split('/')
+        .filter(|segment| !segment.is_empty());
+    let root = segments
+        .next()
+        .and_then(|cid| inner.nodes.get(cid))
+        .cloned();
+    let node = segments.fold(root, |node, segment| {
+        node.and_then(|node| node.get(segment).and_then(|value| follow(value)))
+    });
+    match node {
+        Some(node) => HttpResponse::Ok().json(node),
+        None => api_error("merkledag: not found"),
+    }
+}
+
+/// Files added are served whole, other blocks as they were stored.
+fn cat(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    match args(req).first().and_then(|arg| {
+        let cid = cid_arg(arg);
+        inner.contents.get(cid).or_else(|| inner.blocks.get(cid))
+    }) {
+        Some(data) => HttpResponse::Ok().body(data.clone()),
+        None => api_error("merkledag: not found"),
+    }
+}
+
+fn block_get(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    match args(req)
+        .first()
+        .and_then(|arg| inner.blocks.get(cid_arg(arg)))
+    {
+        Some(data) => HttpResponse::Ok().body(data.clone()),
+        None => api_error("merkledag: not found"),
+    }
+}
+
+/// Serves a block as a trustless gateway does with `?format=raw`.
+fn gateway(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    match inner.blocks.get(req.match_info().get("cid").unwrap_or_default()) {
+        Some(data) => HttpResponse::Ok()
+            .content_type("application/vnd.ipld.raw")
+            .body(data.clone()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn resolve(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    let path = args(req).into_iter().next().unwrap_or_default();
+    if path.starts_with("/ipns/") {
+        let name = path.trim_start_matches("/ipns/");
+        match inner.names.get(name) {
+            Some(value) => HttpResponse::Ok().json(json!({ "Path": value })),
+            None => api_error("could not resolve name"),
+        }
+    } else {
+        HttpResponse::Ok().json(json!({ "Path": path }))
+    }
+}
+
+fn key_list(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    HttpResponse::Ok().json(json!({
+        "Keys": inner
+            .keys
+            .iter()
+            .map(|(name, id)| json!({ "Name": name, "Id": id }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+/// Stands in for a signature of `data` by the key `id`: anyone could forge it, but like a real
+/// one it changes with either.
+fn mock_signature(id: &str, data: &[u8]) -> String {
+    let mut signed = id.as_bytes().to_vec();
+    signed.extend_from_slice(data);
+    format!("f{}", hex::encode(&content_cid(&signed).hash))
+}
+
+/// The data in a request's multipart body, run through `respond` with the request's state.
+fn with_data<F>(
+    req: &HttpRequest<MockState>,
+    respond: F,
+) -> Box<Future<Item = HttpResponse, Error = actix_web::Error>>
+where
+    F: FnOnce(&MockState, Bytes) -> HttpResponse + 'static,
+{
+    let state = req.state().clone();
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    req.body()
+        .limit(usize::max_value())
+        .from_err()
+        .and_then(move |body: Bytes| {
+            Ok(match multipart_content(&content_type, &body) {
+                Some(data) => respond(&state, data),
+                None => api_error("file argument 'data' is required"),
+            })
+        })
+        .responder()
+}
+
+fn key_sign(
+    req: &HttpRequest<MockState>,
+) -> Box<Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let name = query(req, "key").unwrap_or_else(|| "self".to_string());
+    with_data(req, move |state, data| {
+        let inner = state.inner.lock().unwrap();
+        match inner.keys.iter().find(|(key, _)| *key == name) {
+            Some((name, id)) => HttpResponse::Ok().json(json!({
+                "Key": { "Name": name, "Id": id },
+                "Signature": mock_signature(id, &data),
+            })),
+            None => api_error("no key by the given name was found"),
+        }
+    })
+}
+
+/// Keys are given by name if they are on the node, or else by ID.
+fn key_verify(
+    req: &HttpRequest<MockState>,
+) -> Box<Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let key = query(req, "key").unwrap_or_else(|| "self".to_string());
+    let signature = query(req, "signature").unwrap_or_default();
+    with_data(req, move |state, data| {
+        let inner = state.inner.lock().unwrap();
+        let id = inner
+            .keys
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map_or(key.clone(), |(_, id)| id.clone());
+        HttpResponse::Ok().json(json!({
+            "Key": { "Name": "", "Id": id },
+            "SignatureValid": mock_signature(&id, &data) == signature,
+        }))
+    })
+}
+
+fn name_publish(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    let value = args(req).into_iter().next().unwrap_or_default();
+    let key = query(req, "key").unwrap_or_else(|| "self".to_string());
+    let id = inner
+        .keys
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, id)| id.clone());
+    match id {
+        Some(id) => {
+            inner.names.insert(id.clone(), value.clone());
+            HttpResponse::Ok().json(json!({ "Name": id, "Value": value }))
+        }
+        None => api_error("no key by the given name was found"),
+    }
+}
+
+fn pin_add(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    let cids: Vec<String> = args(req)
+        .iter()
+        .map(|arg| cid_arg(arg).to_string())
+        .collect();
+    if let Some(missing) = cids.iter().find(|cid| !inner.blocks.contains_key(*cid)) {
+        return api_error(&format!("pin: {} not found", missing));
+    }
+    cids.iter().for_each(|cid| {
+        inner.pins.insert(cid.clone());
+    });
+    HttpResponse::Ok().json(json!({ "Pins": cids }))
+}
+
+/// Every pin in the mock is recursive, like those made by `add`.
+fn pin_ls(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    let requested = args(req);
+    let listed = match query(req, "type").as_ref().map(String::as_str) {
+        None | Some("all") | Some("recursive") => true,
+        Some(_) => false,
+    };
+    let keys: HashMap<String, serde_json::Value> = inner
+        .pins
+        .iter()
+        .filter(|_| listed)
+        .filter(|cid| requested.is_empty() || requested.iter().any(|arg| cid_arg(arg) == cid.as_str()))
+        .map(|cid| (cid.clone(), json!({ "Type": "recursive" })))
+        .collect();
+    if !requested.is_empty() && keys.is_empty() {
+        return api_error("path is not pinned");
+    }
+    if query(req, "stream").map_or(false, |stream| stream == "true") {
+        let lines: String = keys
+            .keys()
+            .map(|cid| format!("{}\n", json!({ "Cid": cid, "Type": "recursive" })))
+            .collect();
+        return HttpResponse::Ok()
+            .content_type("application/json")
+            .body(lines);
+    }
+    HttpResponse::Ok().json(json!({ "Keys": keys }))
+}
+
+fn pin_rm(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    let cids: Vec<String> = args(req)
+        .iter()
+        .map(|arg| cid_arg(arg).to_string())
+        .collect();
+    cids.iter().for_each(|cid| {
+        inner.pins.remove(cid);
+    });
+    HttpResponse::Ok().json(json!({ "Pins": cids }))
+}
+
+fn mfs_parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+fn mfs_is_dir(inner: &Inner, path: &str) -> bool {
+    path == "/" || inner.files.get(path) == Some(&None)
+}
+
+/// Checks that the parent of `path` is a directory, creating it and its ancestors if `parents`.
+fn mfs_parents(inner: &mut Inner, path: &str, parents: bool) -> Result<(), HttpResponse> {
+    let parent = mfs_parent(path);
+    if mfs_is_dir(inner, parent) {
+        return Ok(());
+    }
+    if !parents || inner.files.contains_key(parent) {
+        return Err(api_error("file does not exist"));
+    }
+    mfs_parents(inner, parent, true)?;
+    inner.files.insert(parent.to_string(), None);
+    Ok(())
+}
+
+fn flag(req: &HttpRequest<MockState>, key: &str) -> bool {
+    query(req, key).map_or(false, |value| value == "true")
+}
+
+/// Writes replace the whole file, as with `truncate`.
+fn files_write(
+    req: &HttpRequest<MockState>,
+) -> Box<Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let state = req.state().clone();
+    let path = args(req).into_iter().next().unwrap_or_default();
+    let (create, parents) = (flag(req, "create"), flag(req, "parents"));
+    let content_type = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    req.body()
+        .limit(usize::max_value())
+        .from_err()
+        .and_then(move |body: Bytes| {
+            let data = match multipart_content(&content_type, &body) {
+                Some(data) => data,
+                None => return Ok(api_error("file argument 'data' is required")),
+            };
+            let mut inner = state.inner.lock().unwrap();
+            match inner.files.get(&path) {
+                Some(None) => return Ok(api_error(&format!("{} is a directory", path))),
+                None if !create => return Ok(api_error("file does not exist")),
+                _ => {}
+            }
+            if let Err(err) = mfs_parents(&mut inner, &path, parents) {
+                return Ok(err);
+            }
+            let cid = content_cid(&data).to_string();
+            inner.blocks.insert(cid.clone(), data);
+            inner.files.insert(path, Some(cid));
+            Ok(HttpResponse::Ok().finish())
+        })
+        .responder()
+}
+
+fn files_mkdir(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    let path = args(req).into_iter().next().unwrap_or_default();
+    let parents = flag(req, "parents");
+    if mfs_is_dir(&inner, &path) && parents {
+        return HttpResponse::Ok().finish();
+    }
+    if path == "/" || inner.files.contains_key(&path) {
+        return api_error("file already exists");
+    }
+    if let Err(err) = mfs_parents(&mut inner, &path, parents) {
+        return err;
+    }
+    inner.files.insert(path, None);
+    HttpResponse::Ok().finish()
+}
+
+fn files_stat(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    let path = args(req).into_iter().next().unwrap_or_default();
+    if mfs_is_dir(&inner, &path) {
+        let children = inner
+            .files
+            .keys()
+            .filter(|child| *child != &path && mfs_parent(child) == path)
+            .count();
+        return HttpResponse::Ok().json(json!({
+            "Hash": crate::spec::ipfs::EMPTY_FOLDER_HASH,
+            "Size": 0,
+            "CumulativeSize": 0,
+            "Blocks": children,
+            "Type": "directory",
+        }));
+    }
+    match inner.files.get(&path) {
+        Some(Some(cid)) => {
+            let size = inner.blocks.get(cid).map_or(0, Bytes::len);
+            HttpResponse::Ok().json(json!({
+                "Hash": cid,
+                "Size": size,
+                "CumulativeSize": size,
+                "Blocks": 0,
+                "Type": "file",
+            }))
+        }
+        _ => api_error("file does not exist"),
+    }
+}
+
+/// `path` and everything below it
+fn mfs_subtree(inner: &Inner, path: &str) -> Vec<String> {
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    inner
+        .files
+        .keys()
+        .filter(|entry| *entry == path || entry.starts_with(&prefix))
+        .cloned()
+        .collect()
+}
+
+fn files_rm(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    let path = args(req).into_iter().next().unwrap_or_default();
+    if !inner.files.contains_key(&path) {
+        return api_error("file does not exist");
+    }
+    let subtree = mfs_subtree(&inner, &path);
+    if inner.files[&path].is_none() && !flag(req, "recursive") {
+        return api_error(&format!("{} is a directory, use -r to remove directories", path));
+    }
+    for entry in subtree {
+        inner.files.remove(&entry);
+    }
+    HttpResponse::Ok().finish()
+}
+
+fn files_cp(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    let (from, to) = match args(req).as_slice() {
+        [from, to] => (from.clone(), to.clone()),
+        _ => return api_error("argument 'source' and 'dest' are required"),
+    };
+    if to == "/" || inner.files.contains_key(&to) {
+        return api_error("directory already has entry by that name");
+    }
+    if !mfs_is_dir(&inner, mfs_parent(&to)) {
+        return api_error("file does not exist");
+    }
+    if from.starts_with("/ipfs/") {
+        let cid = cid_arg(&from).to_string();
+        if !inner.blocks.contains_key(&cid) {
+            return api_error("merkledag: not found");
+        }
+        inner.files.insert(to, Some(cid));
+        return HttpResponse::Ok().finish();
+    }
+    if !inner.files.contains_key(&from) {
+        return api_error("file does not exist");
+    }
+    let copies: Vec<(String, Option<String>)> = mfs_subtree(&inner, &from)
+        .into_iter()
+        .map(|entry| {
+            let value = inner.files[&entry].clone();
+            (format!("{}{}", to, &entry[from.len()..]), value)
+        })
+        .collect();
+    inner.files.extend(copies);
+    HttpResponse::Ok().finish()
+}
+
+fn version(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    HttpResponse::Ok().json(json!({
+        "Version": inner.version,
+        "Commit": "",
+        "Repo": "7",
+        "System": "amd64/linux",
+        "Golang": "go1.11",
+    }))
+}
+
+fn config_show(_req: &HttpRequest<MockState>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "Pubsub": { "Enabled": false },
+        "Ipns": { "UsePubsub": false },
+    }))
+}
+
+fn provide(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    for arg in args(req) {
+        let cid = cid_arg(&arg).to_string();
+        if !inner.blocks.contains_key(&cid) {
+            return api_error("block was not found locally (offline)");
+        }
+        inner.provided.insert(cid);
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// The mock only knows about itself as a provider, of the blocks it holds.
+fn findprovs(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    let self_id = inner.keys[0].1.clone();
+    let lines: String = args(req)
+        .iter()
+        .filter(|arg| inner.blocks.contains_key(cid_arg(arg)))
+        .map(|_| {
+            format!(
+                "{}\n",
+                json!({
+                    "Extra": "",
+                    "ID": "",
+                    "Responses": [{ "ID": self_id, "Addrs": [] }],
+                    "Type": 4,
+                })
+            )
+        })
+        .collect();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(lines)
+}
+
+fn swarm_peers(req: &HttpRequest<MockState>) -> HttpResponse {
+    let inner = req.state().inner.lock().unwrap();
+    if inner.peers.is_empty() {
+        return HttpResponse::Ok().json(json!({ "Peers": null }));
+    }
+    HttpResponse::Ok().json(json!({
+        "Peers": inner
+            .peers
+            .iter()
+            .map(|(addr, peer)| json!({ "Addr": addr, "Peer": peer, "Latency": "" }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+fn swarm_connect(req: &HttpRequest<MockState>) -> HttpResponse {
+    let mut inner = req.state().inner.lock().unwrap();
+    let multiaddr = args(req).into_iter().next().unwrap_or_default();
+    match multiaddr.find("/p2p/") {
+        Some(i) => {
+            let peer = multiaddr[i + "/p2p/".len()..].to_string();
+            let strings = vec![format!("connect {} success", peer)];
+            inner.peers.push((multiaddr[..i].to_string(), peer));
+            HttpResponse::Ok().json(json!({ "Strings": strings }))
+        }
+        None => api_error("invalid peer address"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spec::ipfs::{AddEvent, FileType, Path};
+    use crate::{files, ipfs, pin, routing, swarm};
+
+    #[test]
+    fn add_then_cat_round_trips() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let data = Bytes::from_static(b"hello from the mock daemon");
+        let expected = data.clone();
+        let (cid, body) = sys
+            .block_on(
+                ipfs::add(
+                    futures::stream::once::<_, actix_web::error::PayloadError>(Ok(data)),
+                    None,
+                )
+                .and_then(|res| {
+                    let cid = res.hash;
+                    ipfs::cat(Path::ipfs(cid.clone())).and_then(move |res| {
+                        res.body()
+                            .map_err(crate::error::Error::IpfsApiPayloadError)
+                            .map(move |body| (cid, body))
+                    })
+                }),
+            )
+            .unwrap();
+        assert_eq!(expected, body);
+        assert!(mock.is_pinned(&cid));
+    }
+
+    #[test]
+    fn add_streams_progress_before_the_root() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let data = Bytes::from_static(b"reported as it is read");
+        let size = data.len() as u64;
+        let events = sys
+            .block_on(
+                ipfs::add_events(
+                    futures::stream::once::<_, actix_web::error::PayloadError>(Ok(data)),
+                    None,
+                    true,
+                )
+                .collect(),
+            )
+            .unwrap();
+        assert_eq!(2, events.len());
+        match &events[0] {
+            AddEvent::Progress(progress) => assert_eq!(size, progress.bytes),
+            AddEvent::Added(_) => panic!("expected progress first"),
+        }
+        match &events[1] {
+            AddEvent::Added(added) => assert!(mock.is_pinned(&added.hash)),
+            AddEvent::Progress(_) => panic!("expected the root last"),
+        }
+    }
+
+    #[test]
+    fn add_passes_progress_on() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let data = Bytes::from_static(b"reported to the caller");
+        let size = data.len() as u64;
+        let reported = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let added = sys
+            .block_on(ipfs::add_with_progress(
+                futures::stream::once::<_, actix_web::error::PayloadError>(Ok(data)),
+                None,
+                {
+                    let reported = reported.clone();
+                    move |bytes| reported.borrow_mut().push(bytes)
+                },
+            ))
+            .unwrap();
+        assert!(mock.is_pinned(&added.hash));
+        assert_eq!(vec![size], *reported.borrow());
+    }
+
+    #[test]
+    fn pins_stream_by_type_on_every_release() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        for release in &["0.4.18", "0.23.0"] {
+            let mock = MockIpfs::start_version(release);
+            mock.set_env();
+            let cid = mock.insert(Bytes::from_static(b"pinned recursively"));
+            sys.block_on(pin::add(cid.clone())).unwrap();
+            let recursive = sys
+                .block_on(pin::ls_stream(pin::PinType::Recursive).collect())
+                .unwrap();
+            assert_eq!(1, recursive.len());
+            assert_eq!(cid.to_string(), recursive[0].cid);
+            let direct = sys
+                .block_on(pin::ls_stream(pin::PinType::Direct).collect())
+                .unwrap();
+            assert!(direct.is_empty());
+            assert!(sys.block_on(pin::ls_cid(&cid)).unwrap().is_some());
+            sys.block_on(pin::rm(cid.clone())).unwrap();
+            assert!(!mock.is_pinned(&cid));
+            assert!(sys.block_on(pin::ls_cid(&cid)).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn files_write_stat_cp_and_rm() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let data = Bytes::from_static(b"written to MFS");
+        let expected = content_cid(&data);
+        sys.block_on(files::write(
+            "/repo/objects/a",
+            futures::stream::once::<_, actix_web::error::PayloadError>(Ok(data.clone())),
+            None,
+            files::WriteOptions::default(),
+        ))
+        .unwrap();
+        let stat = sys.block_on(files::stat("/repo/objects/a")).unwrap();
+        assert_eq!(expected, stat.hash);
+        assert_eq!(data.len() as u64, stat.size);
+        assert_eq!(FileType::File, stat.file_type);
+        assert_eq!(
+            FileType::Directory,
+            sys.block_on(files::stat("/repo")).unwrap().file_type
+        );
+
+        sys.block_on(files::mkdir("/copy", false)).unwrap();
+        assert!(sys.block_on(files::mkdir("/missing/copy", false)).is_err());
+        sys.block_on(files::cp("/repo/objects/a", "/copy/a")).unwrap();
+        sys.block_on(files::cp_ipfs(&Path::ipfs(expected.clone()), "/copy/b"))
+            .unwrap();
+        assert_eq!(expected, sys.block_on(files::stat("/copy/b")).unwrap().hash);
+
+        assert!(sys.block_on(files::rm("/repo", false)).is_err());
+        sys.block_on(files::rm("/repo", true)).unwrap();
+        assert!(sys.block_on(files::stat("/repo/objects/a")).is_err());
+        assert_eq!(expected, sys.block_on(files::stat("/copy/a")).unwrap().hash);
+    }
+
+    #[test]
+    fn providers_are_found_for_held_blocks_only() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        for release in &["0.13.1", "0.23.0"] {
+            let mock = MockIpfs::start_version(release);
+            mock.set_env();
+            let held = mock.insert(Bytes::from_static(b"held by the mock"));
+            let missing = content_cid(b"held by no one");
+            let timeout = std::time::Duration::from_secs(1);
+            let providers = sys
+                .block_on(routing::find_providers(&held, 5, timeout))
+                .unwrap();
+            assert_eq!(1, providers.len());
+            assert!(sys
+                .block_on(routing::find_providers(&missing, 5, timeout))
+                .unwrap()
+                .is_empty());
+            sys.block_on(routing::provide(&held)).unwrap();
+            assert!(mock.is_provided(&held));
+            assert!(sys.block_on(routing::provide(&missing)).is_err());
+        }
+    }
+
+    #[test]
+    fn swarm_connect_adds_a_peer() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        assert!(sys.block_on(swarm::peers()).unwrap().is_empty());
+        assert!(sys.block_on(swarm::connect("/ip4/127.0.0.1/tcp/4001")).is_err());
+        sys.block_on(swarm::connect("/ip4/127.0.0.1/tcp/4001/p2p/QmPeer"))
+            .unwrap();
+        let peers = sys.block_on(swarm::peers()).unwrap();
+        assert_eq!(1, peers.len());
+        assert_eq!("QmPeer", peers[0].peer);
+        assert_eq!("/ip4/127.0.0.1/tcp/4001", peers[0].addr);
+    }
+
+    #[test]
+    fn requests_reach_daemons_that_reject_get() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start_version("0.23.0");
+        mock.set_env();
+        let capabilities = sys.block_on(ipfs::capabilities()).unwrap();
+        assert_eq!((0, 23, 0), capabilities.version);
+        assert!(capabilities.post_only);
+        let cid = mock.insert(Bytes::from_static(b"pinned with a POST"));
+        sys.block_on(ipfs::pin_add(cid.clone())).unwrap();
+        assert!(mock.is_pinned(&cid));
+    }
+
+    #[test]
+    fn daemon_version_is_detected_per_endpoint() {
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let old = MockIpfs::start_version("0.4.18");
+        let current = MockIpfs::start_version("0.23.0");
+        assert_eq!((0, 4, 18), sys.block_on(rpc::daemon_version(&old.url)).unwrap());
+        assert_eq!((0, 23, 0), sys.block_on(rpc::daemon_version(&current.url)).unwrap());
+    }
+
+    #[test]
+    fn mirrors_provide_under_the_command_name_of_their_release() {
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mirrors: Vec<MockIpfs> = ["0.4.18", "0.13.1", "0.14.0", "0.23.0"]
+            .iter()
+            .map(|release| MockIpfs::start_version(release))
+            .collect();
+        let data = Bytes::from_static(b"held by every mirror");
+        let cid = content_cid(&data);
+        for mirror in &mirrors {
+            mirror.insert(data.clone());
+        }
+        crate::config::set(crate::config::Config {
+            mirror_api_urls: mirrors.iter().map(|mirror| mirror.url.clone()).collect(),
+            ..(*crate::config::get()).clone()
+        });
+        let provided = sys
+            .block_on(ipfs::provide_from_mirrors(cid.clone()))
+            .unwrap();
+        assert_eq!(mirrors.len(), provided);
+        assert!(mirrors.iter().all(|mirror| mirror.is_provided(&cid)));
+    }
+
+    #[cfg(feature = "cluster")]
+    #[test]
+    fn pin_targets_must_meet_the_quorum() {
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let targets: Vec<MockIpfs> = (0..2).map(|_| MockIpfs::start()).collect();
+        let data = Bytes::from_static(b"pinned by a team");
+        let cid = content_cid(&data);
+        for target in &targets {
+            target.insert(data.clone());
+        }
+        // Nothing listens on the discard port
+        let offline = Url::parse("http://127.0.0.1:9/").unwrap();
+        let use_quorum = |quorum: usize| {
+            crate::config::set(crate::config::Config {
+                pin_target_urls: targets
+                    .iter()
+                    .map(|target| target.url.clone())
+                    .chain(Some(offline.clone()))
+                    .collect(),
+                pin_quorum: Some(quorum),
+                ..(*crate::config::get()).clone()
+            })
+        };
+
+        use_quorum(2);
+        let statuses = sys.block_on(pin::add_to_targets(cid.clone())).unwrap();
+        assert_eq!(
+            vec![true, true, false],
+            statuses
+                .iter()
+                .map(|status| status.error.is_none())
+                .collect::<Vec<_>>()
+        );
+        assert!(targets.iter().all(|target| target.is_pinned(&cid)));
+
+        use_quorum(3);
+        match sys.block_on(pin::add_to_targets(cid)) {
+            Err(crate::error::Error::PinQuorumNotMet {
+                pinned, required, ..
+            }) => assert_eq!((2, 3), (pinned, required)),
+            result => panic!("expected the quorum to be missed, got {:?}", result),
+        }
+    }
+
+    /// Points the gateways at `gateways`, in order.
+    fn use_gateways(gateways: &[&MockIpfs]) {
+        crate::config::set(crate::config::Config {
+            gateway_urls: gateways.iter().map(|gateway| gateway.url.clone()).collect(),
+            ..(*crate::config::get()).clone()
+        });
+    }
+
+    fn block_get_body(cid: Cid) -> impl Future<Item = Bytes, Error = crate::error::Error> {
+        ipfs::block_get(cid).and_then(|res| {
+            res.body()
+                .limit(usize::max_value())
+                .map_err(crate::error::Error::IpfsApiPayloadError)
+        })
+    }
+
+    #[test]
+    fn rate_limit_storms_are_waited_out_until_they_last_too_long() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let data = Bytes::from_static(b"behind a rate limit");
+        let cid = mock.insert(data.clone());
+        mock.set_faults(Faults {
+            rate_limited: 3,
+            retry_after: Some(0),
+            ..Faults::default()
+        });
+        assert_eq!(data, sys.block_on(block_get_body(cid.clone())).unwrap());
+        mock.set_faults(Faults {
+            rate_limited: 100,
+            retry_after: Some(0),
+            ..Faults::default()
+        });
+        match sys.block_on(block_get_body(cid)) {
+            Err(crate::error::Error::RateLimited(_, 0)) => {}
+            other => panic!("expected to give up on the rate limit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn slow_responses_are_waited_for() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let data = Bytes::from_static(b"slow to arrive");
+        let cid = mock.insert(data.clone());
+        let latency = Duration::from_millis(200);
+        mock.set_faults(Faults {
+            latency,
+            ..Faults::default()
+        });
+        let start = Instant::now();
+        assert_eq!(data, sys.block_on(block_get_body(cid)).unwrap());
+        assert!(start.elapsed() >= latency);
+    }
+
+    #[test]
+    fn truncated_and_malformed_responses_are_errors() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let cid = mock.insert(Bytes::from_static(b"cut off in the middle"));
+        mock.set_faults(Faults {
+            reset_after: Some(4),
+            ..Faults::default()
+        });
+        assert!(sys.block_on(block_get_body(cid.clone())).is_err());
+        mock.set_faults(Faults {
+            malformed_json: true,
+            ..Faults::default()
+        });
+        match sys.block_on(pin::add_at(&mock.url, &cid)) {
+            Err(crate::error::Error::IpfsApiJsonPayloadError(_)) => {}
+            other => panic!("expected a JSON error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn writes_fail_over_but_key_calls_stay_on_the_local_node() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let remote = MockIpfs::start();
+        // A local daemon that isn't running: its api file is left behind, but nothing listens
+        let down = std::env::temp_dir().join("git-lfs-ipfs-mock-down");
+        std::fs::create_dir_all(&down).unwrap();
+        std::fs::write(down.join("api"), "/ip4/127.0.0.1/tcp/1").unwrap();
+        std::env::set_var("IPFS_PATH", &down);
+        let config = crate::config::get();
+        crate::config::set(crate::config::Config {
+            api_urls: vec![remote.url.clone()],
+            ..(*config).clone()
+        });
+
+        let data = Bytes::from_static(b"content-addressed, so any API node will do");
+        let added = sys.block_on(ipfs::add(
+            futures::stream::once::<_, actix_web::error::PayloadError>(Ok(data)),
+            None,
+        ));
+        let keys = sys.block_on(ipfs::key_list());
+        crate::config::set((*config).clone());
+        assert!(remote.is_pinned(&added.unwrap().hash));
+        assert!(
+            keys.is_err(),
+            "the key list must not come from another node"
+        );
+    }
+
+    #[test]
+    fn gateways_that_corrupt_or_reset_are_failed_over() {
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let corrupt = MockIpfs::start();
+        let reset = MockIpfs::start();
+        let healthy = MockIpfs::start();
+        let data = Bytes::from_static(b"verified whichever gateway it came from");
+        let cid = content_cid(&data);
+        for gateway in &[&corrupt, &reset, &healthy] {
+            gateway.insert(data.clone());
+        }
+        corrupt.set_faults(Faults {
+            corrupt: true,
+            ..Faults::default()
+        });
+        reset.set_faults(Faults {
+            reset_after: Some(4),
+            ..Faults::default()
+        });
+
+        use_gateways(&[&corrupt]);
+        match sys.block_on(ipfs::gateway_block_get(cid.clone())) {
+            Err(crate::error::Error::BlockVerificationFailed(_)) => {}
+            other => panic!("expected the corrupt block to be rejected, got {:?}", other),
+        }
+        use_gateways(&[&reset]);
+        assert!(sys.block_on(ipfs::gateway_block_get(cid.clone())).is_err());
+        use_gateways(&[&corrupt, &reset, &healthy]);
+        assert_eq!(data, sys.block_on(ipfs::gateway_block_get(cid)).unwrap());
+    }
+
+    /// Content of known bytes, with the object and CID it must always add as
+    #[derive(serde_derive::Deserialize)]
+    struct Golden {
+        name: String,
+        /// The content is `repeat` repeated and cut to `len` bytes
+        repeat: String,
+        len: usize,
+        oid: String,
+        size: u64,
+        cid: String,
+    }
+
+    impl Golden {
+        fn all() -> Vec<Golden> {
+            serde_json::from_str(include_str!("spec/test/golden_objects.json")).unwrap()
+        }
+
+        fn content(&self) -> Bytes {
+            let repeat = self.repeat.as_bytes();
+            Bytes::from(
+                repeat
+                    .iter()
+                    .cycle()
+                    .take(self.len)
+                    .cloned()
+                    .collect::<Vec<u8>>(),
+            )
+        }
+
+        fn object(&self) -> crate::spec::Object {
+            crate::spec::Object {
+                oid: self.oid.clone(),
+                size: self.size,
+            }
+        }
+    }
+
+    /// Goes through the steps of the clean filter: add the content, take its root block as the
+    /// object, and check it maps back to the CID it was added as.
+    #[test]
+    fn golden_contents_add_as_the_same_objects() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        for golden in Golden::all() {
+            let added = sys
+                .block_on(ipfs::add(
+                    futures::stream::once::<_, actix_web::error::PayloadError>(Ok(
+                        golden.content()
+                    )),
+                    None,
+                ))
+                .unwrap();
+            assert_eq!(golden.cid, added.hash.to_string(), "{}", golden.name);
+            let block = sys.block_on(block_get_body(added.hash)).unwrap();
+            let oid = hex::encode(&multihash::encode(multihash::Hash::SHA2256, &block).unwrap()[2..]);
+            assert_eq!(golden.oid, oid, "{}", golden.name);
+            assert_eq!(golden.size, block.len() as u64, "{}", golden.name);
+            assert_eq!(
+                golden.cid,
+                crate::pointer::oid_to_cid(cid::Codec::DagProtobuf, &oid)
+                    .unwrap()
+                    .to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn golden_objects_make_the_same_manifest_and_batch_response() {
+        use crate::spec::lfs::{Action, Actions, BatchResponse, ObjectResponse};
+        use crate::spec::manifest::Manifest;
+        use cid::ToCid;
+        let mut manifest = Manifest::new();
+        for golden in Golden::all() {
+            manifest.insert(&golden.object(), golden.cid.clone()).unwrap();
+        }
+        assert_eq!(
+            include_str!("spec/test/golden_manifest.json"),
+            serde_json::to_string(&manifest).unwrap()
+        );
+
+        let gateway = Url::parse("https://ipfs.io/").unwrap();
+        let response = BatchResponse {
+            transfer: None,
+            objects: Golden::all()
+                .iter()
+                .map(|golden| {
+                    let cid = golden.cid.to_cid().unwrap();
+                    let href = ipfs::gateway_url(
+                        &gateway,
+                        &Path::ipfs(cid),
+                        crate::config::GatewayStyle::Path,
+                    )
+                    .unwrap();
+                    ObjectResponse::success(
+                        golden.object(),
+                        Actions::Download {
+                            download: Action::new(href),
+                        },
+                    )
+                })
+                .collect(),
+            hash_algo: None,
+        };
+        assert_eq!(
+            include_str!("spec/test/golden_batch_response.json"),
+            serde_json::to_string_pretty(&response).unwrap()
+        );
+    }
+
+    #[test]
+    fn large_contents_are_linked_from_one_root() {
+        let _lock = lock_ipfs_path();
+        let mut sys = actix::System::new("mock-ipfs-test");
+        let mock = MockIpfs::start();
+        mock.set_env();
+        let data = Bytes::from(vec![7; CHUNK_SIZE * 2 + 1]);
+        let added = sys
+            .block_on(ipfs::add(
+                futures::stream::once::<_, actix_web::error::PayloadError>(Ok(data.clone())),
+                None,
+            ))
+            .unwrap();
+        // Two full chunks, which are the same block, a short one and the root
+        assert_eq!(3, mock.state.inner.lock().unwrap().blocks.len());
+        let body = sys
+            .block_on(ipfs::cat(Path::ipfs(added.hash)).and_then(|res| {
+                res.body()
+                    .limit(usize::max_value())
+                    .map_err(crate::error::Error::IpfsApiPayloadError)
+            }))
+            .unwrap();
+        assert_eq!(data, body);
+    }
+}
@@ -0,0 +1,242 @@
+//! Timing histograms of the stages objects pass through: hashing, adding, pinning, linking
+//! into a root and publishing. Every stage is also logged at debug level as it finishes, so
+//! slow stages show up in the log file too.
+//!
+//! The histograms are written in the Prometheus text format to `GIT_LFS_IPFS_TIMINGS`, adding
+//! to what earlier runs wrote there, as node_exporter's textfile collector expects.
+//! https://prometheus.io/docs/instrumenting/exposition_formats/
+use futures::prelude::*;
+use lazy_static::lazy_static;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const METRIC: &str = "git_lfs_ipfs_stage_seconds";
+
+/// Upper bounds of the buckets, in seconds. Adds and pins of large objects over slow links can
+/// take minutes, so the buckets reach further than usual.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+/// Durations of one stage, counted per bucket, the last for everything above the largest bound
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub buckets: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+lazy_static! {
+    static ref HISTOGRAMS: Mutex<BTreeMap<String, Histogram>> = Mutex::new(BTreeMap::new());
+}
+
+/// Records that `stage` took `elapsed`.
+pub fn observe(stage: &str, elapsed: Duration) {
+    debug!("{} took {:?}", stage, elapsed);
+    if let Ok(mut histograms) = HISTOGRAMS.lock() {
+        histograms
+            .entry(stage.to_string())
+            .or_default()
+            .observe(elapsed);
+    }
+}
+
+/// Times `future` as `stage`, from when it is first polled until it completes, successfully
+/// or not.
+pub fn timed<F>(stage: &'static str, future: F) -> impl Future<Item = F::Item, Error = F::Error>
+where
+    F: Future,
+{
+    futures::future::lazy(move || {
+        let start = Instant::now();
+        future.then(move |result| {
+            observe(stage, start.elapsed());
+            result
+        })
+    })
+}
+
+/// The histograms recorded by this process
+pub fn snapshot() -> BTreeMap<String, Histogram> {
+    HISTOGRAMS
+        .lock()
+        .map(|histograms| histograms.clone())
+        .unwrap_or_default()
+}
+
+/// Formats `histograms` with cumulative buckets, as Prometheus expects.
+pub fn render(histograms: &BTreeMap<String, Histogram>) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# HELP {} Time spent per stage of storing objects", METRIC);
+    let _ = writeln!(out, "# TYPE {} histogram", METRIC);
+    for (stage, histogram) in histograms {
+        let mut cumulative = 0;
+        for (i, count) in histogram.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = BUCKETS
+                .get(i)
+                .map(|bound| bound.to_string())
+                .unwrap_or_else(|| "+Inf".to_string());
+            let _ = writeln!(
+                out,
+                "{}_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                METRIC, stage, le, cumulative
+            );
+        }
+        let _ = writeln!(out, "{}_sum{{stage=\"{}\"}} {}", METRIC, stage, histogram.sum);
+        let _ = writeln!(out, "{}_count{{stage=\"{}\"}} {}", METRIC, stage, histogram.count);
+    }
+    out
+}
+
+/// Reads back histograms written by [`render`], skipping anything else.
+pub fn parse(text: &str) -> BTreeMap<String, Histogram> {
+    let mut histograms: BTreeMap<String, Histogram> = BTreeMap::new();
+    for line in text.lines().filter(|line| line.starts_with(METRIC)) {
+        let mut parts = line.rsplitn(2, ' ');
+        let value = match parts.next().and_then(|value| value.parse::<f64>().ok()) {
+            Some(value) => value,
+            None => continue,
+        };
+        let series = parts.next().unwrap_or("");
+        let label = |name: &str| {
+            let start = series.find(&format!("{}=\"", name))? + name.len() + 2;
+            let end = start + series[start..].find('"')?;
+            Some(series[start..end].to_string())
+        };
+        let stage = match label("stage") {
+            Some(stage) => stage,
+            None => continue,
+        };
+        let histogram = histograms.entry(stage).or_default();
+        if series.starts_with(&format!("{}_bucket", METRIC)) {
+            let bucket = match label("le").as_ref().map(String::as_str) {
+                Some("+Inf") => Some(BUCKETS.len()),
+                Some(le) => BUCKETS
+                    .iter()
+                    .position(|bound| le.parse::<f64>().ok() == Some(*bound)),
+                None => None,
+            };
+            if let Some(bucket) = bucket {
+                histogram.buckets[bucket] = value as u64;
+            }
+        } else if series.starts_with(&format!("{}_sum", METRIC)) {
+            histogram.sum = value;
+        } else if series.starts_with(&format!("{}_count", METRIC)) {
+            histogram.count = value as u64;
+        }
+    }
+    // Buckets were read cumulative
+    for histogram in histograms.values_mut() {
+        for i in (1..histogram.buckets.len()).rev() {
+            histogram.buckets[i] = histogram.buckets[i].saturating_sub(histogram.buckets[i - 1]);
+        }
+    }
+    histograms
+}
+
+/// Adds this process' histograms to those already in the file at `path`. The file is replaced
+/// at once through a temporary file of this process' own, so readers never see half of it.
+/// Processes that write the same file at the same time must hold a lock on it around this, or
+/// they may lose each other's runs.
+pub fn write(path: &Path) -> std::io::Result<()> {
+    let recorded = snapshot();
+    if recorded.is_empty() {
+        return Ok(());
+    }
+    let mut histograms = std::fs::read_to_string(path)
+        .map(|text| parse(&text))
+        .unwrap_or_default();
+    for (stage, histogram) in &recorded {
+        histograms
+            .entry(stage.clone())
+            .or_default()
+            .merge(histogram);
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    let temporary = PathBuf::from(temporary);
+    std::fs::write(&temporary, render(&histograms))
+        .and_then(|_| std::fs::rename(&temporary, path))
+        .map_err(|err| {
+            let _ = std::fs::remove_file(&temporary);
+            err
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histograms_round_trip_and_merge() {
+        let mut add = Histogram::default();
+        add.observe(Duration::from_millis(3));
+        add.observe(Duration::from_millis(700));
+        add.observe(Duration::from_secs(600));
+        let mut histograms = BTreeMap::new();
+        histograms.insert("add".to_string(), add.clone());
+        let text = render(&histograms);
+        assert!(text.contains("git_lfs_ipfs_stage_seconds_bucket{stage=\"add\",le=\"0.005\"} 1"));
+        assert!(text.contains("git_lfs_ipfs_stage_seconds_bucket{stage=\"add\",le=\"+Inf\"} 3"));
+        assert!(text.contains("git_lfs_ipfs_stage_seconds_count{stage=\"add\"} 3"));
+        let parsed = parse(&text);
+        assert_eq!(add, parsed["add"]);
+
+        let mut merged = parsed["add"].clone();
+        merged.merge(&add);
+        assert_eq!(6, merged.count);
+        assert_eq!(2, merged.buckets[0]);
+        assert_eq!(2, merged.buckets[BUCKETS.len()]);
+    }
+
+    #[test]
+    fn runs_add_up_in_the_file() {
+        observe("write-test", Duration::from_millis(1));
+        let dir = std::env::temp_dir().join(format!("timing-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("timings.prom");
+        write(&path).unwrap();
+        write(&path).unwrap();
+        let written = parse(&std::fs::read_to_string(&path).unwrap());
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(2, written["write-test"].count);
+        assert_eq!(1, files);
+    }
+}